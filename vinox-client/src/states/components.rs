@@ -6,6 +6,7 @@ use leafwing_input_manager::prelude::*;
 
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
+use vinox_common::world::chunks::storage::HORIZONTAL_DISTANCE;
 
 #[derive(Resource, Deref, DerefMut)]
 pub struct ProjectPath(pub PathBuf);
@@ -42,7 +43,9 @@ pub enum GameActions {
     Debug,
 }
 
+// Missing fields fall back to the defaults so older configs still load
 #[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GameOptions {
     pub input: InputMap<GameActions>,
     pub fov: f32,
@@ -53,6 +56,9 @@ pub struct GameOptions {
     pub vsync: bool,
     pub looking_at: bool,
     pub debug: bool,
+    pub view_distance: u32,
+    // How far behind the server other players are drawn, in milliseconds
+    pub interpolation_delay: u32,
 }
 
 impl Default for GameOptions {
@@ -82,6 +88,8 @@ impl Default for GameOptions {
             vsync: true,
             looking_at: true,
             debug: false,
+            view_distance: HORIZONTAL_DISTANCE as u32,
+            interpolation_delay: 100,
         }
     }
 }
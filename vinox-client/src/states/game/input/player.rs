@@ -16,17 +16,14 @@ use bevy_quinnet::client::Client;
 use vinox_common::{
     ecs::bundles::Inventory,
     networking::protocol::ClientMessage,
-    physics::{
-        collision::raycast::raycast_world,
-        simulate::{CollidesWithWorld, Velocity},
-    },
+    physics::collision::raycast::raycast_world,
     storage::blocks::descriptor::BlockGeometry,
     world::chunks::{
-        ecs::{ChunkManager, CurrentChunks},
+        ecs::ChunkManager,
         positions::{RelativeVoxelPos, VoxelPos},
         storage::{
-            self, name_to_identifier, trim_geo_identifier, BlockData, ItemTable, CHUNK_SIZE,
-            HORIZONTAL_DISTANCE,
+            self, name_to_identifier, trim_geo_identifier, BlockData, BlockRegistry, ItemTable,
+            PackedBlock, CHUNK_SIZE, HORIZONTAL_DISTANCE,
         },
     },
 };
@@ -36,7 +33,7 @@ use crate::states::{
     game::{
         networking::syncing::HighLightCube,
         ui::{dropdown::ConsoleOpen, plugin::InUi},
-        world::chunks::{ControlledPlayer, PlayerChunk, PlayerTargetedBlock},
+        world::chunks::{ControlledPlayer, PlayerTargetedBlock},
     },
     menu::ui::InOptions,
 };
@@ -189,26 +186,13 @@ pub fn spawn_camera(
 #[derive(Resource)]
 pub struct MouseSensitivity(pub f32);
 
-#[allow(clippy::too_many_arguments)]
+// Only the camera, moving the player is predicted in networking so it matches the server
 pub fn handle_movement(
     mut player: Query<&mut FPSCamera>,
-    mut player_position: Query<
-        (
-            &mut Transform,
-            &mut Velocity,
-            &ActionState<GameActions>,
-            Option<&CollidesWithWorld>,
-        ),
-        With<ControlledPlayer>,
-    >,
     mut camera_transform: Query<&mut Transform, (With<Camera>, Without<ControlledPlayer>)>,
     mut mouse_events: EventReader<MouseMotion>,
     mouse_sensitivity: Res<MouseSensitivity>,
     windows: Query<&Window, With<PrimaryWindow>>,
-    mut stationary_frames: Local<i32>,
-    current_chunks: Res<CurrentChunks>,
-    time: Res<Time>,
-    player_chunk: Res<PlayerChunk>,
 ) {
     let Ok(window) = windows.get_single() else {
         return;
@@ -232,84 +216,6 @@ pub fn handle_movement(
             transform.look_at(looking_at, Vec3::new(0.0, 1.0, 0.0));
         }
     }
-    // Update velocity with movement input
-    if let Ok((_translation, mut velocity, action_state, world_collide)) =
-        player_position.get_single_mut()
-    {
-        if world_collide.is_some() {
-            let mut movement = Vec3::ZERO;
-
-            if velocity.0.y.abs() < 0.001 && *stationary_frames < 10 {
-                *stationary_frames += 4;
-            } else if *stationary_frames >= 0 {
-                *stationary_frames -= 1;
-            }
-
-            let gravity = 35.0 * Vec3::NEG_Y;
-            velocity.0 += gravity * time.delta().as_secs_f32().clamp(0.0, 0.1);
-
-            if window.cursor.grab_mode == CursorGrabMode::Locked {
-                if current_chunks.get_entity(player_chunk.chunk_pos).is_none() {
-                    return;
-                }
-
-                if action_state.pressed(GameActions::Forward) {
-                    let mut fwd = transform.forward();
-                    fwd.y = 0.0;
-                    let fwd = fwd.normalize();
-                    movement += fwd;
-                }
-                if action_state.pressed(GameActions::Left) {
-                    movement += transform.left()
-                }
-                if action_state.pressed(GameActions::Right) {
-                    movement += transform.right()
-                }
-                if action_state.pressed(GameActions::Backward) {
-                    let mut back = transform.back();
-                    back.y = 0.0;
-                    let back = back.normalize();
-                    movement += back;
-                }
-                movement = movement.normalize_or_zero();
-                if action_state.pressed(GameActions::Run) {
-                    movement *= 10.0;
-                } else {
-                    movement *= 5.0;
-                }
-                if action_state.pressed(GameActions::Jump) && *stationary_frames > 8 {
-                    *stationary_frames = 0;
-                    velocity.0.y = 10.0;
-                }
-            }
-            velocity.0 = Vec3::new(movement.x, velocity.0.y, movement.z);
-        } else {
-            velocity.0 = Vec3::ZERO;
-            if window.cursor.grab_mode == CursorGrabMode::Locked {
-                if action_state.pressed(GameActions::Forward) {
-                    velocity.0 += transform.forward().normalize();
-                }
-                if action_state.pressed(GameActions::Left) {
-                    velocity.0 += transform.left()
-                }
-                if action_state.pressed(GameActions::Right) {
-                    velocity.0 += transform.right()
-                }
-                if action_state.pressed(GameActions::Backward) {
-                    velocity.0 += transform.back().normalize();
-                }
-                velocity.0 = velocity.0.normalize_or_zero();
-                if action_state.pressed(GameActions::Run) {
-                    velocity.0 *= 10.0;
-                } else {
-                    velocity.0 *= 5.0;
-                }
-                if action_state.pressed(GameActions::Jump) {
-                    velocity.0.y = 10.0;
-                }
-            }
-        }
-    }
 }
 
 fn norm_to_bar(item: usize) -> Option<(usize, usize)> {
@@ -324,7 +230,6 @@ fn norm_to_bar(item: usize) -> Option<(usize, usize)> {
 #[allow(clippy::too_many_arguments)]
 #[allow(clippy::type_complexity)]
 pub fn interact(
-    _commands: Commands,
     mut windows: Query<&mut Window, With<PrimaryWindow>>,
     camera_query: Query<&GlobalTransform, With<Camera>>,
     mut client: ResMut<Client>,
@@ -346,7 +251,7 @@ pub fn interact(
     // current_chunks: Res<CurrentChunks>,
     // block_table: Res<BlockTable>,
     mut chunk_manager: ChunkManager,
-    item_table: Res<ItemTable>,
+    (item_table, block_registry): (Res<ItemTable>, Res<BlockRegistry>),
     mut temp_bar: Local<Option<usize>>,
     mut item_type: Local<BlockGeometry>,
     mut norm_item: Local<usize>,
@@ -354,6 +259,8 @@ pub fn interact(
     keys: Res<Input<KeyCode>>,
     options: Res<GameOptions>,
     mut player_targeted: ResMut<PlayerTargetedBlock>,
+    time: Res<Time>,
+    mut breaking_block: Local<Option<(VoxelPos, f32)>>,
 ) {
     let window = windows.single_mut();
     if window.cursor.grab_mode != CursorGrabMode::Locked {
@@ -539,6 +446,10 @@ pub fn interact(
         let cur_item = inventory.clone().current_item;
         let cur_bar = inventory.clone().current_bar;
         let item_data = inventory.clone().hotbar[*cur_bar][*cur_item].clone();
+        let holding_item = item_data.is_some();
+        let held_identifier = item_data
+            .as_ref()
+            .map(|item| name_to_identifier(item.namespace.clone(), item.name.clone()));
         let place_item = if let Some(item) = item_data {
             if let Some(item_descriptor) = item_table.get(&name_to_identifier(
                 item.namespace.clone(),
@@ -556,7 +467,7 @@ pub fn interact(
             None
         };
 
-        let mouse_left = action_state.just_pressed(GameActions::PrimaryInteract);
+        let mouse_left = action_state.pressed(GameActions::PrimaryInteract);
         let mouse_right = action_state.just_pressed(GameActions::SecondaryInteract);
         if let Ok(camera_transform) = camera_query.get_single() {
             // Then cast the ray.
@@ -586,7 +497,48 @@ pub fn interact(
                     }
                     block_transform.translation = point + Vec3::splat(0.5);
                 }
-                if mouse_left || (mouse_right && place_item.is_some()) {
+                let interactable = chunk_manager
+                    .get_descriptor(VoxelPos::from((voxel_pos, chunk_pos)))
+                    .and_then(|descriptor| descriptor.interactable)
+                    .unwrap_or(false);
+                // Holding down break has to last as long as the server expects it to
+                let target = VoxelPos::from((voxel_pos, chunk_pos));
+                let break_ready = if mouse_left {
+                    if breaking_block.map(|(pos, _)| pos != target).unwrap_or(true) {
+                        *breaking_block = Some((target, time.elapsed_seconds()));
+                        client
+                            .connection_mut()
+                            .try_send_message(ClientMessage::StartBreak {
+                                chunk_pos: *chunk_pos,
+                                voxel_pos: [
+                                    voxel_pos.x as u8,
+                                    voxel_pos.y as u8,
+                                    voxel_pos.z as u8,
+                                ],
+                            });
+                    }
+                    let tool = held_identifier
+                        .as_ref()
+                        .and_then(|identifier| item_table.get(identifier));
+                    let break_time = chunk_manager
+                        .get_descriptor(target)
+                        .map(|descriptor| descriptor.break_time(tool))
+                        .unwrap_or_default();
+                    breaking_block
+                        .map(|(_, started)| time.elapsed_seconds() - started >= break_time)
+                        .unwrap_or(false)
+                } else {
+                    *breaking_block = None;
+                    false
+                };
+                if mouse_right && interactable {
+                    client
+                        .connection_mut()
+                        .try_send_message(ClientMessage::InteractBlock {
+                            chunk_pos: *chunk_pos,
+                            voxel_pos: [voxel_pos.x as u8, voxel_pos.y as u8, voxel_pos.z as u8],
+                        });
+                } else if break_ready || (mouse_right && place_item.is_some()) {
                     if mouse_right {
                         if (point.x <= player_transform.x as f32 - 0.5
                             || point.x >= player_transform.x as f32 + 0.5)
@@ -717,63 +669,47 @@ pub fn interact(
                                                     voxel_pos.y as u8,
                                                     voxel_pos.z as u8,
                                                 ],
-                                                block_type: modified_item,
+                                                block_type: modified_item.pack(&block_registry),
                                             },
                                         );
                                     }
                                 }
                             }
                         }
-                    } else if mouse_left {
+                    } else if break_ready {
+                        *breaking_block = None;
                         if let Some(identifier) =
                             chunk_manager.get_identifier(VoxelPos::from((voxel_pos, chunk_pos)))
                         {
-                            let identifier = trim_geo_identifier(identifier);
-                            if let Some(item_def) = item_table.get(&identifier) {
-                                if inventory.add_item(item_def).is_ok() {
-                                    chunk_manager.set_block(
-                                        VoxelPos::from((voxel_pos, chunk_pos)),
-                                        BlockData::new("vinox".to_string(), "air".to_string()),
-                                    );
-                                    client.connection_mut().try_send_message(
-                                        ClientMessage::SentBlock {
-                                            chunk_pos: *chunk_pos,
-                                            voxel_pos: [
-                                                voxel_pos.x as u8,
-                                                voxel_pos.y as u8,
-                                                voxel_pos.z as u8,
-                                            ],
-                                            block_type: BlockData::new(
-                                                "vinox".to_string(),
-                                                "air".to_string(),
-                                            ),
-                                        },
-                                    );
-                                }
-                            } else {
-                                chunk_manager.set_block(
-                                    VoxelPos::from((voxel_pos, chunk_pos)),
-                                    BlockData::new("vinox".to_string(), "air".to_string()),
-                                );
-                                client.connection_mut().try_send_message(
-                                    ClientMessage::SentBlock {
-                                        chunk_pos: *chunk_pos,
-                                        voxel_pos: [
-                                            voxel_pos.x as u8,
-                                            voxel_pos.y as u8,
-                                            voxel_pos.z as u8,
-                                        ],
-                                        block_type: BlockData::new(
-                                            "vinox".to_string(),
-                                            "air".to_string(),
-                                        ),
-                                    },
-                                );
+                            // A full inventory still breaks it, the server drops the item instead
+                            if let Some(item_def) = item_table.get(&trim_geo_identifier(identifier))
+                            {
+                                inventory.add_item(item_def).ok();
                             }
+                            chunk_manager.set_block(
+                                VoxelPos::from((voxel_pos, chunk_pos)),
+                                BlockData::new("vinox".to_string(), "air".to_string()),
+                            );
+                            client
+                                .connection_mut()
+                                .try_send_message(ClientMessage::SentBlock {
+                                    chunk_pos: *chunk_pos,
+                                    voxel_pos: [
+                                        voxel_pos.x as u8,
+                                        voxel_pos.y as u8,
+                                        voxel_pos.z as u8,
+                                    ],
+                                    block_type: PackedBlock::default(),
+                                });
                         }
                     }
+                } else if mouse_right && holding_item {
+                    client
+                        .connection_mut()
+                        .try_send_message(ClientMessage::UseItem);
                 }
             } else if let Ok((_, mut block_visibility)) = cube_position.get_single_mut() {
+                *breaking_block = None;
                 *block_visibility = Visibility::Hidden;
                 player_targeted.block = None;
                 player_targeted.pos = None;
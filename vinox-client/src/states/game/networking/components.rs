@@ -1,6 +1,16 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    f32::consts::{PI, TAU},
+};
 
 use bevy::prelude::*;
+use bevy_quinnet::client::Client;
+use vinox_common::{
+    ecs::bundles::{HeldItem, Inventory, InventoryOp},
+    networking::protocol::{ClientMessage, TICK_SECONDS},
+    physics::movement::{InputFrame, MoveState},
+    world::chunks::storage::ItemTable,
+};
 
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct Password(pub String);
@@ -8,6 +18,13 @@ pub struct Password(pub String);
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct ChatMessages(pub Vec<(String, String)>);
 
+// Whole lines the server says the console input could become
+#[derive(Resource, Default)]
+pub struct CommandCompletions {
+    pub input: String,
+    pub suggestions: Vec<String>,
+}
+
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct ClientData(pub u64);
 
@@ -24,3 +41,189 @@ pub struct ClientLobby {
 
 #[derive(Default, Resource, Deref, DerefMut)]
 pub struct NetworkMapping(pub HashMap<Entity, Entity>);
+
+// Inventory ops we already applied locally that the server hasn't confirmed yet
+#[derive(Resource, Default)]
+pub struct InventoryPrediction {
+    pub next_sequence: u32,
+    pub pending: Vec<(u32, InventoryOp)>,
+}
+
+impl InventoryPrediction {
+    // Does nothing if the op isn't valid locally since the server would reject it anyway
+    pub fn predict(
+        &mut self,
+        op: InventoryOp,
+        inventory: &mut Inventory,
+        held: &mut HeldItem,
+        item_table: &ItemTable,
+        client: &mut Client,
+    ) {
+        if inventory.apply_op(held, &op, item_table) {
+            let sequence = self.next_sequence;
+            self.next_sequence += 1;
+            client.connection_mut().try_send_message_on(
+                bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                ClientMessage::InventoryOp {
+                    sequence,
+                    op: op.clone(),
+                },
+            );
+            self.pending.push((sequence, op));
+        }
+    }
+
+    pub fn acknowledge(&mut self, sequence: u32) {
+        self.pending.retain(|(pending, _)| *pending > sequence);
+    }
+
+    // Take the servers contents and replay anything it hasn't seen yet on top
+    pub fn rollback(
+        &mut self,
+        sequence: u32,
+        server_inventory: Inventory,
+        server_held: HeldItem,
+        inventory: &mut Inventory,
+        held: &mut HeldItem,
+        item_table: &ItemTable,
+    ) {
+        self.acknowledge(sequence);
+        inventory.hotbar = server_inventory.hotbar;
+        inventory.slots = server_inventory.slots;
+        *held = server_held;
+        self.pending
+            .retain(|(_, op)| inventory.apply_op(held, op, item_table));
+    }
+}
+
+// Inputs we already moved with locally that the server hasn't run yet
+#[derive(Resource, Default)]
+pub struct MovementPrediction {
+    pub next_sequence: u32,
+    pub pending: VecDeque<InputFrame>,
+    pub last_ack: Option<u32>,
+    // A newer server state we still have to replay pending inputs on top of
+    pub server_state: Option<MoveState>,
+}
+
+impl MovementPrediction {
+    // Acks are unreliable so an older one can show up after a newer one
+    pub fn acknowledge(&mut self, sequence: u32, state: MoveState) {
+        if self.last_ack.map_or(true, |last| sequence > last) {
+            self.last_ack = Some(sequence);
+            self.server_state = Some(state);
+            self.pending.retain(|frame| frame.sequence > sequence);
+        }
+    }
+}
+
+// Samples further off than this are a new connection or a hitch, not drift
+const MAX_CLOCK_DRIFT: f64 = 1.0;
+
+// Our guess at the server's tick clock, eased towards each time sync so jitter doesn't shake it
+#[derive(Resource, Default)]
+pub struct ServerClock {
+    offset: Option<f64>,
+    latest_tick: Option<u32>,
+}
+
+impl ServerClock {
+    pub fn sync(&mut self, tick: u32, now: f64) {
+        let sample = tick as f64 * TICK_SECONDS - now;
+        self.offset = Some(match self.offset {
+            Some(offset) if (sample - offset).abs() < MAX_CLOCK_DRIFT => {
+                offset + (sample - offset) * 0.1
+            }
+            _ => sample,
+        });
+    }
+
+    // Fractional server tick we are at now, None until the first sync arrives
+    pub fn tick(&self, now: f64) -> Option<f64> {
+        self.offset.map(|offset| (now + offset) / TICK_SECONDS)
+    }
+
+    // Unreliable updates can arrive late, anything not newer than what we have is stale
+    pub fn is_newer(&mut self, tick: u32) -> bool {
+        if self.latest_tick.map_or(false, |latest| tick <= latest) {
+            return false;
+        }
+        self.latest_tick = Some(tick);
+        true
+    }
+}
+
+// Never guess further than this past the newest snapshot, a longer gap means we lost them
+const MAX_EXTRAPOLATION_TICKS: f64 = 15.0;
+const MAX_SNAPSHOTS: usize = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub translation: Vec3,
+    pub yaw: f32,
+    pub head_pitch: f32,
+}
+
+impl Snapshot {
+    // Past 1.0 this keeps going in the same direction
+    fn lerp(&self, other: &Snapshot, s: f32) -> Snapshot {
+        let yaw_delta = (other.yaw - self.yaw + PI).rem_euclid(TAU) - PI;
+        Snapshot {
+            tick: self.tick,
+            translation: self.translation.lerp(other.translation, s),
+            yaw: self.yaw + yaw_delta * s,
+            head_pitch: self.head_pitch + (other.head_pitch - self.head_pitch) * s,
+        }
+    }
+}
+
+// Server states of a remote entity, oldest first
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct SnapshotBuffer(pub VecDeque<Snapshot>);
+
+impl SnapshotBuffer {
+    pub fn new(snapshot: Snapshot) -> Self {
+        Self(VecDeque::from([snapshot]))
+    }
+
+    // Anything left out didn't change, without a spawn to build on there is nothing to apply to
+    pub fn push_delta(
+        &mut self,
+        tick: u32,
+        translation: Option<Vec3>,
+        yaw: Option<f32>,
+        head_pitch: Option<f32>,
+    ) {
+        let Some(last) = self.back().copied() else {
+            return;
+        };
+        if tick <= last.tick {
+            return;
+        }
+        self.push_back(Snapshot {
+            tick,
+            translation: translation.unwrap_or(last.translation),
+            yaw: yaw.unwrap_or(last.yaw),
+            head_pitch: head_pitch.unwrap_or(last.head_pitch),
+        });
+        if self.len() > MAX_SNAPSHOTS {
+            self.pop_front();
+        }
+    }
+
+    // Where the entity was at tick, past the newest snapshot we keep going for a little while
+    pub fn sample(&mut self, tick: f64) -> Option<Snapshot> {
+        // The last snapshot before tick is still needed to interpolate from
+        while self.len() > 2 && self[1].tick as f64 <= tick {
+            self.pop_front();
+        }
+        let from = *self.front()?;
+        let Some(to) = self.get(1).copied() else {
+            return Some(from);
+        };
+        let span = (to.tick - from.tick) as f64;
+        let elapsed = (tick - from.tick as f64).clamp(0.0, span + MAX_EXTRAPOLATION_TICKS);
+        Some(from.lerp(&to, (elapsed / span) as f32))
+    }
+}
@@ -1,12 +1,15 @@
 use bevy::prelude::*;
-use vinox_common::networking::protocol::EntityBuffer;
 
 use crate::states::components::GameState;
 
 use super::{
-    components::{ChatMessages, ClientLobby, NetworkMapping},
+    components::{
+        ChatMessages, ClientLobby, CommandCompletions, InventoryPrediction, MovementPrediction,
+        NetworkMapping, ServerClock,
+    },
     syncing::{
-        client_naive_inventory, client_send_naive_position, get_id, get_messages, lerp_new_location,
+        client_send_selected_item, get_messages, interpolate_entities, predict_movement, send_join,
+        send_view_distance,
     },
 };
 
@@ -16,19 +19,23 @@ impl Plugin for NetworkingPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(ClientLobby::default())
             .insert_resource(NetworkMapping::default())
-            .insert_resource(EntityBuffer::default())
+            .insert_resource(ServerClock::default())
             .insert_resource(ChatMessages::default())
+            .insert_resource(CommandCompletions::default())
+            .insert_resource(InventoryPrediction::default())
+            .insert_resource(MovementPrediction::default())
             .add_system(
-                client_send_naive_position
+                predict_movement
                     .in_schedule(CoreSchedule::FixedUpdate)
                     .in_set(OnUpdate(GameState::Game)),
             )
+            .add_system(send_join.in_schedule(OnEnter(GameState::Game)))
             .add_systems(
                 (
                     get_messages,
-                    lerp_new_location,
-                    get_id,
-                    client_naive_inventory,
+                    interpolate_entities,
+                    client_send_selected_item,
+                    send_view_distance,
                 )
                     .in_set(OnUpdate(GameState::Game)),
             );
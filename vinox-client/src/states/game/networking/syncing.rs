@@ -1,30 +1,36 @@
 use super::components::{
-    ChatMessages, ClientData, ClientLobby, NetworkMapping, Password, PlayerInfo,
+    ChatMessages, ClientData, ClientLobby, CommandCompletions, InventoryPrediction,
+    MovementPrediction, NetworkMapping, Password, PlayerInfo, ServerClock, Snapshot,
+    SnapshotBuffer,
 };
 use crate::states::{
     components::{GameActions, GameOptions},
     game::{
+        input::player::FPSCamera,
         ui::dropdown::Toast,
         world::chunks::{ControlledPlayer, CreateChunkEvent, SetBlockEvent},
     },
 };
-use bevy::prelude::*;
-use bevy_quinnet::client::*;
-use bevy_tweening::{
-    lens::{TransformPositionLens, TransformRotationLens},
-    *,
+use bevy::{
+    prelude::*,
+    render::primitives::Aabb,
+    window::{CursorGrabMode, PrimaryWindow},
 };
-use big_space::FloatingOrigin;
+use bevy_quinnet::client::*;
+use big_space::{FloatingOrigin, FloatingOriginSettings, GridCell};
 use leafwing_input_manager::prelude::*;
-use std::{io::Cursor, time::Duration};
+use std::{collections::HashMap, f32::consts::FRAC_PI_2, io::Cursor, time::Duration};
 use vinox_common::{
-    ecs::bundles::{Inventory, PlayerBundleBuilder},
-    networking::protocol::{ClientMessage, EntityBuffer, ServerMessage},
-    physics::simulate::{CollidesWithWorld, Velocity},
+    ecs::bundles::{HeldItem, Inventory, PlayerBundleBuilder},
+    networking::protocol::{ClientMessage, HeadPitch, ServerMessage, TICK_SECONDS},
+    physics::{
+        movement::{step_movement, InputFrame, MoveState},
+        simulate::CollisionWorld,
+    },
     world::chunks::{
-        ecs::{ChunkCell, LoadPoint},
+        ecs::{ChunkCell, LoadPoint, LoadableAssets},
         positions::{ChunkPos, RelativeVoxelPos, VoxelPos},
-        storage::RawChunk,
+        storage::{name_to_identifier, BlockRegistry, ItemTable, PackedChunk, VERTICAL_DISTANCE},
     },
 };
 use zstd::stream::copy_decode;
@@ -32,32 +38,51 @@ use zstd::stream::copy_decode;
 #[derive(Component)]
 pub struct HighLightCube;
 
-pub fn get_id(
+// Our id already came with the asset handshake while loading
+pub fn send_join(
     mut client: ResMut<Client>,
-    mut client_data: ResMut<ClientData>,
-    mut has_connected: Local<bool>,
+    client_data: Res<ClientData>,
     options: Res<GameOptions>,
     hashed_password: Res<Password>,
+    mut movement: ResMut<MovementPrediction>,
+    mut clock: ResMut<ServerClock>,
 ) {
-    if *has_connected {
-    } else {
-        while let Some(message) = client
-            .connection_mut()
-            .try_receive_message::<ServerMessage>()
-        {
-            if let ServerMessage::ClientId { id } = message {
-                **client_data = id;
-                client
-                    .connection_mut()
-                    .try_send_message(ClientMessage::Join {
-                        user_name: options.user_name.clone(),
-                        id,
-                        password: hashed_password.clone(),
-                    });
-                *has_connected = true;
-            }
-        }
+    // Sequences and ticks start over on the server so nothing from a previous game is newer
+    *movement = MovementPrediction::default();
+    *clock = ServerClock::default();
+    client
+        .connection_mut()
+        .try_send_message(ClientMessage::Join {
+            user_name: options.user_name.clone(),
+            id: **client_data,
+            password: hashed_password.clone(),
+        });
+}
+
+// Once our player exists and again whenever the option changes
+pub fn send_view_distance(
+    mut client: ResMut<Client>,
+    options: Res<GameOptions>,
+    mut load_point: Query<&mut LoadPoint, With<ControlledPlayer>>,
+    mut sent: Local<Option<u32>>,
+) {
+    let Ok(mut load_point) = load_point.get_single_mut() else {
+        return;
+    };
+    if *sent == Some(options.view_distance) && !load_point.is_added() {
+        return;
     }
+    let horizontal = options.view_distance;
+    let vertical = horizontal.min(VERTICAL_DISTANCE as u32);
+    load_point.horizontal = horizontal as i32;
+    load_point.vertical = vertical as i32;
+    client
+        .connection_mut()
+        .try_send_message(ClientMessage::ViewDistance {
+            horizontal,
+            vertical,
+        });
+    *sent = Some(horizontal);
 }
 
 #[allow(clippy::clone_on_copy)]
@@ -66,19 +91,34 @@ pub fn get_messages(
     mut cmd1: Commands,
     mut cmd2: Commands,
     mut client: ResMut<Client>,
-    (client_data, options): (Res<ClientData>, Res<GameOptions>),
+    (client_data, options, block_registry, loadable_assets): (
+        Res<ClientData>,
+        Res<GameOptions>,
+        Res<BlockRegistry>,
+        Res<LoadableAssets>,
+    ),
     mut lobby: ResMut<ClientLobby>,
     mut network_mapping: ResMut<NetworkMapping>,
-    mut entity_buffer: ResMut<EntityBuffer>,
+    (mut snapshots, mut clock, time): (
+        Query<(Entity, &mut SnapshotBuffer)>,
+        ResMut<ServerClock>,
+        Res<Time>,
+    ),
     player_builder: Res<PlayerBundleBuilder>,
     mut chunk_event: EventWriter<CreateChunkEvent>,
     mut block_event: EventWriter<SetBlockEvent>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     asset_server: Res<AssetServer>,
-    mut messages: ResMut<ChatMessages>,
+    (mut messages, mut completions): (ResMut<ChatMessages>, ResMut<CommandCompletions>),
     mut toast: ResMut<Toast>,
-    boiler_player: Query<Entity, With<FloatingOrigin>>,
+    (boiler_player, mut controlled_inventory, mut prediction, item_table, mut movement): (
+        Query<Entity, With<FloatingOrigin>>,
+        Query<(&mut Inventory, &mut HeldItem), With<ControlledPlayer>>,
+        ResMut<InventoryPrediction>,
+        Res<ItemTable>,
+        ResMut<MovementPrediction>,
+    ),
 ) {
     if **client_data != 0 {
         while let Some(message) = client
@@ -127,12 +167,12 @@ pub fn get_messages(
                                 input_map: options.input.clone(),
                             })
                             .insert(*inventory)
-                            .insert(CollidesWithWorld)
+                            .insert(HeldItem::default())
                             .insert(FloatingOrigin)
                             .insert(ChunkCell::default())
                             .insert(LoadPoint::default())
                             .insert(VoxelPos::default())
-                            .insert(Velocity(Vec3::ZERO));
+                            .insert(MoveState::new(translation));
                         if let Ok(boiler) = boiler_player.get_single() {
                             cmd2.entity(boiler).despawn_recursive();
                         }
@@ -148,12 +188,18 @@ pub fn get_messages(
                             false,
                             user_name,
                         ));
+                        // Hidden until the server tells us they are close enough to see
                         client_entity
                             .insert(
                                 Transform::from_translation(translation)
                                     .with_rotation(Quat::from_euler(EulerRot::XYZ, 0.0, yaw, 0.0)),
                             )
-                            .insert(*inventory);
+                            .insert((
+                                *inventory,
+                                HeadPitch::default(),
+                                Visibility::Hidden,
+                                SnapshotBuffer::default(),
+                            ));
                     }
 
                     let player_info = PlayerInfo {
@@ -185,19 +231,152 @@ pub fn get_messages(
                         voxel_pos[1] as u32,
                         voxel_pos[2] as u32,
                     )),
-                    block_type,
+                    block_type: block_type.unpack(&block_registry),
                 }),
+                ServerMessage::RejectBlock {
+                    chunk_pos,
+                    voxel_pos,
+                    block_type,
+                    inventory,
+                } => {
+                    block_event.send(SetBlockEvent {
+                        chunk_pos: ChunkPos(chunk_pos),
+                        voxel_pos: RelativeVoxelPos(UVec3::new(
+                            voxel_pos[0] as u32,
+                            voxel_pos[1] as u32,
+                            voxel_pos[2] as u32,
+                        )),
+                        block_type: block_type.unpack(&block_registry),
+                    });
+                    if let Ok((mut cur_inventory, _)) = controlled_inventory.get_single_mut() {
+                        // Keep what is selected locally, only the contents are the servers
+                        let (current_bar, current_item) = (
+                            cur_inventory.current_bar.clone(),
+                            cur_inventory.current_item.clone(),
+                        );
+                        *cur_inventory = *inventory;
+                        cur_inventory.current_bar = current_bar;
+                        cur_inventory.current_item = current_item;
+                    }
+                }
+                ServerMessage::InventoryDelta { delta } => {
+                    if let Ok((mut cur_inventory, _)) = controlled_inventory.get_single_mut() {
+                        cur_inventory.apply_delta(delta);
+                    }
+                }
+                ServerMessage::InventoryAck { sequence } => prediction.acknowledge(sequence),
+                ServerMessage::MoveAck { sequence, state } => movement.acknowledge(sequence, state),
+                ServerMessage::InventoryRollback {
+                    sequence,
+                    inventory,
+                    held,
+                } => {
+                    if let Ok((mut cur_inventory, mut cur_held)) =
+                        controlled_inventory.get_single_mut()
+                    {
+                        prediction.rollback(
+                            sequence,
+                            *inventory,
+                            held,
+                            &mut cur_inventory,
+                            &mut cur_held,
+                            &item_table,
+                        );
+                    }
+                }
                 ServerMessage::NetworkedEntities { networked_entities } => {
-                    let arr_len = entity_buffer.entities.len() - 1;
-                    entity_buffer.entities.rotate_left(1);
-                    entity_buffer.entities[arr_len] = networked_entities;
+                    let tick = networked_entities.tick;
+                    if !clock.is_newer(tick) {
+                        continue;
+                    }
+                    let deltas: HashMap<Entity, usize> = networked_entities
+                        .entities
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, entity)| network_mapping.get(entity).map(|e| (*e, i)))
+                        .collect();
+                    // Everything we know about that isn't listed stayed put this tick
+                    for (entity, mut buffer) in snapshots.iter_mut() {
+                        if let Some(&i) = deltas.get(&entity) {
+                            buffer.push_delta(
+                                tick,
+                                networked_entities.translations[i],
+                                networked_entities.yaws[i],
+                                networked_entities.head_pitchs[i],
+                            );
+                        } else {
+                            buffer.push_delta(tick, None, None, None);
+                        }
+                    }
+                }
+                ServerMessage::ServerTime { tick } => {
+                    clock.sync(tick, time.elapsed_seconds_f64());
+                }
+                ServerMessage::EntitySpawn {
+                    entity,
+                    tick,
+                    translation,
+                    yaw,
+                    head_pitch,
+                    item,
+                } => {
+                    if let Some(item) = item {
+                        // Items never move so they don't need snapshots
+                        let texture = loadable_assets
+                            .item_textures
+                            .get(&name_to_identifier(item.namespace, item.name))
+                            .cloned();
+                        let item_entity = cmd1
+                            .spawn(MaterialMeshBundle {
+                                mesh: meshes.add(Mesh::from(shape::Cube { size: 0.3 })),
+                                material: materials.add(StandardMaterial {
+                                    base_color_texture: texture,
+                                    alpha_mode: AlphaMode::Mask(0.5),
+                                    unlit: true,
+                                    ..default()
+                                }),
+                                transform: Transform::from_translation(translation),
+                                ..default()
+                            })
+                            .id();
+                        network_mapping.insert(entity, item_entity);
+                    } else if let Some(client_entity) = network_mapping.get(&entity) {
+                        cmd1.entity(*client_entity).insert((
+                            Transform::from_translation(translation)
+                                .with_rotation(Quat::from_euler(EulerRot::XYZ, 0.0, yaw, 0.0)),
+                            HeadPitch(head_pitch),
+                            Visibility::Inherited,
+                            SnapshotBuffer::new(Snapshot {
+                                tick,
+                                translation,
+                                yaw,
+                                head_pitch,
+                            }),
+                        ));
+                    }
+                }
+                ServerMessage::EntityDespawn { entity } => {
+                    let is_player = lobby
+                        .players
+                        .values()
+                        .any(|info| info.server_entity == entity);
+                    // Players stay around hidden, anything else is spawned again if it comes back
+                    if is_player {
+                        if let Some(client_entity) = network_mapping.get(&entity) {
+                            cmd1.entity(*client_entity)
+                                .insert((Visibility::Hidden, SnapshotBuffer::default()));
+                        }
+                    } else if let Some(client_entity) = network_mapping.remove(&entity) {
+                        cmd1.entity(client_entity).despawn();
+                    }
                 }
                 ServerMessage::LevelData { chunk_data, pos } => {
                     let mut temp_output = Cursor::new(Vec::new());
                     copy_decode(&chunk_data[..], &mut temp_output).unwrap();
-                    let level_data: RawChunk = bincode::deserialize(temp_output.get_ref()).unwrap();
+                    let level_data: PackedChunk =
+                        bincode::deserialize(temp_output.get_ref()).unwrap();
                     chunk_event.send(CreateChunkEvent {
-                        raw_chunk: level_data,
+                        raw_chunk: level_data.unpack(&block_registry),
                         pos: ChunkPos(pos),
                     });
                 }
@@ -213,102 +392,140 @@ pub fn get_messages(
                             .set_duration(Some(Duration::from_secs(3)));
                     }
                 }
+                ServerMessage::CommandCompletions { input, suggestions } => {
+                    *completions = CommandCompletions { input, suggestions };
+                }
+                // Kicked or banned, the server closes the connection shortly after
+                ServerMessage::Refused { reason } => {
+                    warn!("Disconnected by the server: {reason}");
+                    messages.push(("Server".to_string(), format!("Disconnected: {reason}")));
+                    toast
+                        .error(format!("Disconnected: {reason}"))
+                        .set_duration(None);
+                }
                 _ => {}
             }
         }
     }
 }
 
-pub fn lerp_new_location(
-    mut commands: Commands,
-    entity_buffer: ResMut<EntityBuffer>,
-    lobby: ResMut<ClientLobby>,
-    network_mapping: ResMut<NetworkMapping>,
-    client_data: ResMut<ClientData>,
-    transform_query: Query<&Transform>,
+// Remote entities are drawn a little in the past so there is usually a snapshot on either side
+pub fn interpolate_entities(
+    mut entities: Query<(&mut SnapshotBuffer, &mut Transform, &mut HeadPitch)>,
+    clock: Res<ServerClock>,
+    options: Res<GameOptions>,
+    time: Res<Time>,
 ) {
-    for i in 0..entity_buffer.entities[0].entities.len() {
-        if let Some(entity) = network_mapping.get(&entity_buffer.entities[0].entities[i]) {
-            let translation = entity_buffer.entities[0].translations[i];
-            let rotation =
-                Quat::from_euler(EulerRot::XYZ, 0.0, entity_buffer.entities[0].yaws[i], 0.0);
-            let transform = Transform {
-                translation,
-                ..Default::default()
-            }
-            .with_rotation(rotation);
-            if let Some(player_entity) = lobby.players.get(&client_data) {
-                if player_entity.client_entity != *entity {
-                    if let Ok(old_transform) = transform_query.get(*entity) {
-                        let tween = Tween::new(
-                            EaseFunction::QuadraticIn,
-                            Duration::from_millis(25),
-                            TransformPositionLens {
-                                start: old_transform.translation,
-                                end: transform.translation,
-                            },
-                        )
-                        .with_repeat_count(RepeatCount::Finite(1));
-                        let tween_rot = Tween::new(
-                            EaseFunction::QuadraticIn,
-                            Duration::from_millis(25),
-                            TransformRotationLens {
-                                start: old_transform.rotation,
-                                end: transform.rotation,
-                            },
-                        )
-                        .with_repeat_count(RepeatCount::Finite(1));
-                        let track = Tracks::new([tween, tween_rot]);
-                        commands
-                            .get_entity(*entity)
-                            .unwrap()
-                            .insert(Animator::new(track));
-                    }
-                } else {
-                }
-            }
+    let Some(tick) = clock.tick(time.elapsed_seconds_f64()) else {
+        return;
+    };
+    let render_tick = tick - options.interpolation_delay as f64 / 1000.0 / TICK_SECONDS;
+    for (mut buffer, mut transform, mut head_pitch) in entities.iter_mut() {
+        if let Some(snapshot) = buffer.sample(render_tick) {
+            transform.translation = snapshot.translation;
+            transform.rotation = Quat::from_rotation_y(snapshot.yaw);
+            **head_pitch = snapshot.head_pitch;
         }
     }
 }
 
-pub fn client_send_naive_position(
-    transform_query: Query<&VoxelPos, With<ControlledPlayer>>,
-    mut camera_query: Query<&mut Transform, (With<Camera>, Without<ControlledPlayer>)>,
+// How many unacknowledged inputs go out with each packet in case earlier ones were lost
+const RESENT_INPUTS: usize = 8;
+// Past this the server is gone or far behind so stop holding on to old inputs
+const MAX_PENDING_INPUTS: usize = 120;
+
+// Moves straight away with the same code the server runs, then fixes things up when it answers
+#[allow(clippy::type_complexity)]
+pub fn predict_movement(
     mut client: ResMut<Client>,
+    mut prediction: ResMut<MovementPrediction>,
+    mut player: Query<
+        (
+            &mut MoveState,
+            &mut Transform,
+            &mut GridCell<i32>,
+            &Aabb,
+            &ActionState<GameActions>,
+        ),
+        With<ControlledPlayer>,
+    >,
+    camera: Query<&FPSCamera>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    floating_settings: Res<FloatingOriginSettings>,
+    world: CollisionWorld,
 ) {
-    if let Ok(transform) = transform_query.get_single() {
-        if let Ok(camera_transform) = camera_query.get_single_mut() {
-            client.connection_mut().try_send_message_on(
-                bevy_quinnet::shared::channel::ChannelId::Unreliable,
-                ClientMessage::Position {
-                    player_pos: transform.as_vec3(),
-                    yaw: camera_transform.rotation.to_euler(EulerRot::XYZ).1,
-                    head_pitch: camera_transform.rotation.to_euler(EulerRot::XYZ).0,
-                },
-            );
+    let Ok((mut state, mut transform, mut grid_cell, aabb, action_state)) = player.get_single_mut()
+    else {
+        return;
+    };
+    let Ok(fps_camera) = camera.get_single() else {
+        return;
+    };
+
+    if let Some(server_state) = prediction.server_state.take() {
+        *state = server_state;
+        for frame in prediction.pending.iter() {
+            step_movement(&mut state, frame, aabb, &world);
         }
     }
+
+    let locked = windows.get_single().map_or(false, |window| {
+        window.cursor.grab_mode == CursorGrabMode::Locked
+    });
+    let pressed = |action| locked && action_state.pressed(action);
+    let frame = InputFrame {
+        sequence: prediction.next_sequence,
+        forward: pressed(GameActions::Forward),
+        backward: pressed(GameActions::Backward),
+        left: pressed(GameActions::Left),
+        right: pressed(GameActions::Right),
+        jump: pressed(GameActions::Jump),
+        run: pressed(GameActions::Run),
+        // Turn the camera angles into the yaw and pitch the rest of the game uses
+        yaw: -fps_camera.phi - FRAC_PI_2,
+        head_pitch: FRAC_PI_2 - fps_camera.theta,
+    };
+    prediction.next_sequence += 1;
+    step_movement(&mut state, &frame, aabb, &world);
+    prediction.pending.push_back(frame);
+    if prediction.pending.len() > MAX_PENDING_INPUTS {
+        prediction.pending.pop_front();
+    }
+
+    let frames = prediction
+        .pending
+        .iter()
+        .rev()
+        .take(RESENT_INPUTS)
+        .copied()
+        .collect();
+    client.connection_mut().try_send_message_on(
+        bevy_quinnet::shared::channel::ChannelId::Unreliable,
+        ClientMessage::Inputs { frames },
+    );
+
+    let (cell, translation) =
+        floating_settings.imprecise_translation_to_grid::<i32>(state.translation);
+    *grid_cell = cell;
+    transform.translation = translation;
 }
 
-// TODO: This is genuinely awful lol worse then position way more to do cheating wise plus bandwidth. Change this to be smarter asap
-pub fn client_naive_inventory(
-    player_query: Query<&Inventory, With<ControlledPlayer>>,
+// Only the selected hotbar slot, everything else in the inventory goes through the server
+pub fn client_send_selected_item(
+    player_query: Query<&Inventory, (With<ControlledPlayer>, Changed<Inventory>)>,
     mut client: ResMut<Client>,
-    mut timer: Local<Timer>,
-    time: Res<Time>,
+    mut last_selected: Local<(usize, usize)>,
 ) {
-    timer.set_mode(TimerMode::Repeating);
-    timer.set_duration(Duration::from_secs_f32(1.));
-
-    timer.tick(time.delta());
-    if timer.just_finished() {
-        if let Ok(inventory) = player_query.get_single() {
-            client.connection_mut().try_send_message_on(
-                bevy_quinnet::shared::channel::ChannelId::UnorderedReliable,
-                ClientMessage::Inventory {
-                    inventory: Box::new(inventory.clone()),
-                },
-            );
+    if let Ok(inventory) = player_query.get_single() {
+        let selected = (*inventory.current_bar, *inventory.current_item);
+        if selected != *last_selected {
+            *last_selected = selected;
+            client
+                .connection_mut()
+                .try_send_message(ClientMessage::SelectItem {
+                    bar: selected.0,
+                    item: selected.1,
+                });
         }
     }
 }
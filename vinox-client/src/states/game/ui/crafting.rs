@@ -6,86 +6,22 @@ use bevy::prelude::*;
 use bevy_egui::EguiContexts;
 use bevy_egui::{*};
 
-use vinox_common::world::chunks::storage::{identifier_to_name, name_to_identifier, ItemTable};
+use bevy_quinnet::client::Client;
+use vinox_common::networking::protocol::ClientMessage;
+use vinox_common::world::chunks::storage::{identifier_to_name, name_to_identifier};
 use vinox_common::{ecs::bundles::Inventory, world::chunks::storage::RecipeTable};
 
 use crate::states::{components::GameOptions, game::world::chunks::ControlledPlayer};
 
-//TODO: This is pretty jank rn. Many edge cases aren't accounted for
-pub fn craft(
-    inventory: &mut Inventory,
-    required_items: &HashMap<String, u32>,
-    output_item: (String, u32),
-    item_table: &ItemTable,
-) {
-    let mut modified_items = required_items.clone();
-    let mut new_inventory = inventory.clone();
-    for (row_num, bar_row) in new_inventory.clone().hotbar.0.iter().enumerate() {
-        for (item_num, bar_item) in bar_row.iter().cloned().enumerate() {
-            if let Some(bar_item) = bar_item {
-                let identifier =
-                    name_to_identifier(bar_item.clone().namespace, bar_item.clone().name);
-                if modified_items.contains_key(&identifier) {
-                    let amount_left = *modified_items.get(&identifier).unwrap();
-                    if amount_left < bar_item.stack_size {
-                        let mut new_item = bar_item.clone();
-                        new_item.stack_size -= amount_left;
-                        new_inventory.hotbar[row_num][item_num] = Some(new_item);
-                        modified_items.remove(&identifier);
-                    } else {
-                        new_inventory.hotbar[row_num][item_num] = None;
-                        if (amount_left as i32 - bar_item.stack_size as i32) <= 0 {
-                            modified_items.remove(&identifier);
-                        } else {
-                            modified_items
-                                .insert(identifier.clone(), amount_left - bar_item.stack_size);
-                        }
-                    }
-                }
-            }
-        }
-    }
-    for (row_num, inv_row) in new_inventory.clone().slots.iter().enumerate() {
-        for (item_num, inv_item) in inv_row.iter().cloned().enumerate() {
-            if let Some(inv_item) = inv_item {
-                let identifier =
-                    name_to_identifier(inv_item.clone().namespace, inv_item.clone().name);
-                if modified_items.contains_key(&identifier) {
-                    let amount_left = *modified_items.get(&identifier).unwrap();
-                    if amount_left < inv_item.stack_size {
-                        let mut new_item = inv_item.clone();
-                        new_item.stack_size -= amount_left;
-                        new_inventory.slots[row_num][item_num] = Some(new_item);
-                        modified_items.remove(&identifier);
-                    } else {
-                        new_inventory.slots[row_num][item_num] = None;
-                        if (amount_left as i32 - inv_item.stack_size as i32) <= 0 {
-                            modified_items.remove(&identifier);
-                        } else {
-                            modified_items
-                                .insert(identifier.clone(), amount_left - inv_item.stack_size);
-                        }
-                    }
-                }
-            }
-        }
-    }
-    if modified_items.is_empty() && new_inventory
-            .add_item(item_table.get(&output_item.0).unwrap())
-            .is_ok() {
-        *inventory = new_inventory.clone();
-    }
-}
-
 pub fn crafting_ui(
     recipe_table: Res<RecipeTable>,
-    item_table: Res<ItemTable>,
-    mut player_query: Query<&mut Inventory, With<ControlledPlayer>>,
+    player_query: Query<&Inventory, With<ControlledPlayer>>,
+    mut client: ResMut<Client>,
     mut contexts: EguiContexts,
     _options: Res<GameOptions>,
     mut current_search: Local<String>,
 ) {
-    if let Ok(mut inventory) = player_query.get_single_mut() {
+    if let Ok(inventory) = player_query.get_single() {
         if inventory.open {
             egui::SidePanel::left("crafting").show(contexts.ctx_mut(), |ui| {
                 ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
@@ -124,15 +60,14 @@ pub fn crafting_ui(
                                             }
                                         });
                                     if ui.button("Craft").clicked() {
-                                        if let Some(required_items) = recipe.required_items.clone()
-                                        {
-                                            craft(
-                                                &mut inventory,
-                                                &required_items,
-                                                recipe.output_item.clone(),
-                                                &item_table,
-                                            );
-                                        }
+                                        client.connection_mut().try_send_message(
+                                            ClientMessage::Craft {
+                                                recipe: name_to_identifier(
+                                                    recipe.namespace.clone(),
+                                                    recipe.name.clone(),
+                                                ),
+                                            },
+                                        );
                                     }
                                     ui.end_row();
                                 }
@@ -2,18 +2,18 @@ use bevy_quinnet::client::Client;
 
 use brigadier_rs::*;
 use egui_notify::Toasts;
-use std::{convert::Infallible};
-use vinox_common::{networking::protocol::ClientMessage, physics::simulate::CollidesWithWorld};
+use std::{cell::Cell, convert::Infallible};
+use vinox_common::networking::protocol::ClientMessage;
 
 use bevy::{pbr::wireframe::WireframeConfig, prelude::*};
-use bevy_egui::{
-    egui::{Align2},
-    *,
-};
+use bevy_egui::{egui::Align2, *};
 
 use crate::states::{
     components::GameOptions,
-    game::{networking::components::ChatMessages, world::chunks::ControlledPlayer},
+    game::{
+        networking::components::{ChatMessages, CommandCompletions},
+        world::chunks::ControlledPlayer,
+    },
 };
 
 #[derive(Resource, Default, Deref, DerefMut)]
@@ -22,11 +22,28 @@ pub struct ConsoleOpen(pub bool);
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct Toast(pub Toasts);
 
+// The only command the client runs itself since it only changes how the world is drawn, no
+// argument toggles it
+fn parse_wireframe(message: &str, current: bool) -> Option<bool> {
+    let enabled = Cell::new(None);
+    let parser = literal("/wireframe")
+        .then(boolean("bool").build_exec(|_ctx: (), bool| {
+            enabled.set(Some(bool));
+            Ok::<(), Infallible>(())
+        }))
+        .build_exec(|_ctx: ()| {
+            enabled.set(Some(!current));
+            Ok::<(), Infallible>(())
+        });
+    match parser.parse((), message) {
+        Ok((rest, _)) if rest.trim().is_empty() => enabled.get(),
+        _ => None,
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn create_ui(
-    mut commands: Commands,
-    mut player_query: Query<(Entity, &mut Transform, &GlobalTransform), With<ControlledPlayer>>,
-    collider_query: Query<&CollidesWithWorld>,
+    player_query: Query<Entity, With<ControlledPlayer>>,
     mut client: ResMut<Client>,
     is_open: Res<ConsoleOpen>, // mut username_res: ResMut<UserName>,
     mut current_message: Local<String>,
@@ -35,35 +52,10 @@ pub fn create_ui(
     mut toast: ResMut<Toast>,
     _options: Res<GameOptions>,
     mut wireframe_config: ResMut<WireframeConfig>,
+    completions: Res<CommandCompletions>,
 ) {
     toast.show(contexts.ctx_mut());
     if **is_open {
-        let parser = literal("/wireframe")
-            .then(boolean("bool").build_exec(|_ctx: (), bar| {
-                println!("Toggled wireframe to {bar}");
-                Ok::<(), Infallible>(())
-            }))
-            .build_exec(|_ctx: ()| {
-                println!("Called foo with no arguments");
-                Ok::<(), Infallible>(())
-            });
-        let parser_spec = literal("/spectator")
-            .then(boolean("bool").build_exec(|_ctx: (), bar| {
-                println!("Toggled spectator to {bar}");
-                Ok::<(), Infallible>(())
-            }))
-            .build_exec(|_ctx: ()| {
-                println!("Called foo with no arguments");
-                Ok::<(), Infallible>(())
-            });
-        let parser_tp = literal("/tp")
-            .then(integer_i64("x").build_exec(|_ctx: (), _bar| Ok::<(), Infallible>(())))
-            .then(integer_i64("y").build_exec(|_ctx: (), _bar| Ok::<(), Infallible>(())))
-            .then(integer_i64("z").build_exec(|_ctx: (), _bar| Ok::<(), Infallible>(())))
-            .build_exec(|_ctx: ()| {
-                println!("Called foo with no arguments");
-                Ok::<(), Infallible>(())
-            });
         egui::Window::new("Console")
             .anchor(Align2::CENTER_TOP, [0.0, 0.0])
             .default_width(1000.0)
@@ -75,63 +67,42 @@ pub fn create_ui(
                     egui::TopBottomPanel::bottom("text_box")
                         .resizable(false)
                         .show_inside(ui, |ui| {
+                            let suggestions = if completions.input == *current_message {
+                                completions.suggestions.as_slice()
+                            } else {
+                                &[]
+                            };
+                            let mut picked = None;
                             ui.horizontal(|ui| {
                                 ui.label("Type: ");
-                                let response = ui.text_edit_singleline(&mut *current_message);
+                                // Locked so tab completes instead of moving focus
+                                let response = ui.add(
+                                    egui::TextEdit::singleline(&mut *current_message)
+                                        .lock_focus(true),
+                                );
+                                if response.changed() {
+                                    picked = Some(current_message.clone());
+                                }
+                                if response.has_focus()
+                                    && ui.input(|input| input.key_pressed(egui::Key::Tab))
+                                {
+                                    picked = suggestions.first().cloned().or(picked.take());
+                                }
 
                                 // Pressing enter makes we lose focus
                                 let input_send = response.lost_focus()
                                     && ui.input(|input| input.key_pressed(egui::Key::Enter));
                                 if input_send {
                                     // TODO: Switch this to a better system
-                                    if let Ok((player, mut player_transform, global_transform)) =
-                                        player_query.get_single_mut()
-                                    {
-                                        if let Ok((result, _)) = parser.parse((), &current_message)
-                                        {
-                                            messages
-                                                .push(("Console".to_string(), result.to_string()));
-                                            wireframe_config.global = !wireframe_config.global;
-                                        } else if let Ok((result, _)) =
-                                            parser_spec.parse((), &current_message)
-                                        {
-                                            messages
-                                                .push(("Console".to_string(), result.to_string()));
-                                            if collider_query.get(player).is_ok() {
-                                                commands
-                                                    .entity(player)
-                                                    .remove::<CollidesWithWorld>();
-                                            } else {
-                                                commands.entity(player).insert(CollidesWithWorld);
-                                            }
-                                        } else if let Ok((result, _)) =
-                                            parser_tp.parse((), &current_message)
-                                        {
-                                            let (mut x, mut y, mut z) = (0, 0, 0);
-                                            for (idx, num) in result.split_whitespace().enumerate()
-                                            {
-                                                if let Ok(conv) = num.parse() {
-                                                    match idx {
-                                                        0 => {
-                                                            x = conv;
-                                                        }
-                                                        1 => {
-                                                            y = conv;
-                                                        }
-                                                        2 => {
-                                                            z = conv;
-                                                        }
-                                                        _ => {}
-                                                    }
-                                                }
-                                            }
-                                            let delta = Vec3::new(x as f32, y as f32, z as f32)
-                                                - global_transform.translation();
-
-                                            player_transform.translation += delta;
+                                    if player_query.get_single().is_ok() {
+                                        if let Some(enabled) = parse_wireframe(
+                                            &current_message,
+                                            wireframe_config.global,
+                                        ) {
+                                            wireframe_config.global = enabled;
                                             messages.push((
                                                 "Console".to_string(),
-                                                format!("{x}, {y}, {z}"),
+                                                format!("Wireframe set to {enabled}"),
                                             ));
                                         } else {
                                             client.connection_mut().try_send_message(
@@ -144,6 +115,24 @@ pub fn create_ui(
                                     }
                                 }
                             });
+                            ui.horizontal_wrapped(|ui| {
+                                for suggestion in suggestions {
+                                    if ui.small_button(suggestion).clicked() {
+                                        picked = Some(suggestion.clone());
+                                    }
+                                }
+                            });
+                            // The server knows what commands exist so it does the completing
+                            if let Some(input) = picked {
+                                if input.starts_with('/') {
+                                    client.connection_mut().try_send_message(
+                                        ClientMessage::CompleteCommand {
+                                            input: input.clone(),
+                                        },
+                                    );
+                                }
+                                *current_message = input;
+                            }
                         });
 
                     egui::ScrollArea::vertical()
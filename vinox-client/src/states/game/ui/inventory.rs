@@ -5,20 +5,30 @@ use bevy_egui::{
     egui::{Color32, Sense},
     *,
 };
+use bevy_quinnet::client::Client;
 use vinox_common::{
-    ecs::bundles::{CurrentInvBar, CurrentInvItem, Inventory},
-    storage::items::descriptor::ItemData,
-    world::chunks::{ecs::LoadableAssets, storage::name_to_identifier},
+    ecs::bundles::{
+        max_stack, same_item, CurrentInvBar, CurrentInvItem, HeldItem, Inventory, InventoryOp,
+        InventorySlot,
+    },
+    world::chunks::{
+        ecs::LoadableAssets,
+        storage::{name_to_identifier, ItemTable},
+    },
 };
 
-use crate::states::{components::GameOptions, game::world::chunks::ControlledPlayer};
+use crate::states::{
+    components::GameOptions,
+    game::{networking::components::InventoryPrediction, world::chunks::ControlledPlayer},
+};
 
 pub fn status_bar(
-    mut player_query: Query<&mut Inventory, With<ControlledPlayer>>,
+    mut player_query: Query<(&mut Inventory, &mut HeldItem), With<ControlledPlayer>>,
     mut contexts: EguiContexts,
     _options: Res<GameOptions>,
-    mut held_items: ResMut<CurrentItemsHeld>,
-    mut holding: ResMut<Holding>,
+    mut prediction: ResMut<InventoryPrediction>,
+    mut client: ResMut<Client>,
+    item_table: Res<ItemTable>,
     loadable_assets: Res<LoadableAssets>,
 ) {
     let ctx = contexts.ctx_mut().clone();
@@ -28,7 +38,7 @@ pub fn status_bar(
         .max_height(75.0)
         .show(&ctx, |ui| {
             ui.with_layout(egui::Layout::left_to_right(egui::Align::Center), |ui| {
-                if let Ok(mut inventory) = player_query.get_single_mut() {
+                if let Ok((mut inventory, mut held)) = player_query.get_single_mut() {
                     StripBuilder::new(ui)
                         .size(Size::exact(50.0))
                         .size(Size::exact(50.0))
@@ -103,18 +113,27 @@ pub fn status_bar(
                                                                 )));
                                                             });
                                                     });
-                                                    if image.clicked() {
-                                                        grab_stack(
-                                                            &mut held_items,
-                                                            &mut inventory,
-                                                            &mut holding,
-                                                            hotbar_num,
-                                                            item_num,
-                                                            true,
-                                                        );
+                                                    if let Some(click) = slot_click(&image) {
+                                                        if let Some(op) = grab_stack(
+                                                            &inventory,
+                                                            &held,
+                                                            InventorySlot::Hotbar(
+                                                                hotbar_num, item_num,
+                                                            ),
+                                                            click,
+                                                            &item_table,
+                                                        ) {
+                                                            prediction.predict(
+                                                                op,
+                                                                &mut inventory,
+                                                                &mut held,
+                                                                &item_table,
+                                                                &mut client,
+                                                            );
+                                                        }
                                                     }
-                                                } else if ui
-                                                    .add(
+                                                } else if let Some(click) = slot_click(
+                                                    &ui.add(
                                                         egui::widgets::Image::new(
                                                             contexts
                                                                 .image_id(
@@ -128,17 +147,23 @@ pub fn status_bar(
                                                         )
                                                         .tint(color)
                                                         .sense(Sense::click()),
-                                                    )
-                                                    .clicked()
-                                                {
-                                                    grab_stack(
-                                                        &mut held_items,
-                                                        &mut inventory,
-                                                        &mut holding,
-                                                        hotbar_num,
-                                                        item_num,
-                                                        true,
-                                                    );
+                                                    ),
+                                                ) {
+                                                    if let Some(op) = grab_stack(
+                                                        &inventory,
+                                                        &held,
+                                                        InventorySlot::Hotbar(hotbar_num, item_num),
+                                                        click,
+                                                        &item_table,
+                                                    ) {
+                                                        prediction.predict(
+                                                            op,
+                                                            &mut inventory,
+                                                            &mut held,
+                                                            &item_table,
+                                                            &mut client,
+                                                        );
+                                                    }
                                                 }
                                             },
                                         );
@@ -160,116 +185,116 @@ pub fn status_bar(
         });
 }
 
-#[derive(Resource, Default, Clone, Debug, Deref, DerefMut)]
-pub struct CurrentItemsHeld(pub Vec<(ItemData, &'static str, usize, usize)>);
-#[derive(Resource, Default, Clone, Debug, Deref, DerefMut)]
-pub struct Holding(pub bool);
-
-// Item data is the items we are moving importantly also how much we are moving. So to move half a stack we set the item data to half of the source item.
-// Right now we only ever have one held but this is a vec for the future for bulk moving
+// How a slot was clicked
+#[derive(Clone, Copy)]
+pub struct SlotClick {
+    pub right: bool,
+    pub shift: bool,
+    pub ctrl: bool,
+}
 
-// TODO: Change bar and inventory slots possible to be one big array instead of two seperate. Would make it cleaner to access items
+// Left click picks up or puts down the whole stack, right click picks up half or puts down one.
+// With nothing held shift click moves the stack over between the hotbar and the inventory, shift
+// right click splits it into an empty slot next to it and ctrl click swaps it with the selected
+// hotbar slot
 pub fn grab_stack(
-    held_items: &mut CurrentItemsHeld,
-    inventory: &mut Inventory,
-    holding: &mut Holding,
-    row_index: usize,
-    row_item: usize,
-    bar: bool,
-) {
-    if !**holding {
-        if bar {
-            if inventory.hotbar[row_index][row_item].is_some() {
-                held_items.insert(
-                    0,
-                    (
-                        inventory.hotbar[row_index][row_item].clone().unwrap(),
-                        "bar",
-                        row_index,
-                        row_item,
-                    ),
-                );
-                inventory.hotbar[row_index][row_item] = None;
-                **holding = true;
-            }
-        } else if inventory.slots[row_index][row_item].is_some() {
-            held_items.insert(
-                0,
-                (
-                    inventory.slots[row_index][row_item].clone().unwrap(),
-                    "inventory",
-                    row_index,
-                    row_item,
-                ),
-            );
-            inventory.slots[row_index][row_item] = None;
-            **holding = true;
+    inventory: &Inventory,
+    held: &HeldItem,
+    slot: InventorySlot,
+    click: SlotClick,
+    item_table: &ItemTable,
+) -> Option<InventoryOp> {
+    if let Some(held_item) = &held.0 {
+        return Some(InventoryOp::Drop {
+            to: slot,
+            amount: if click.right { 1 } else { held_item.stack_size },
+        });
+    }
+    let item = inventory.get_slot(slot).as_ref()?;
+    let hotbar = matches!(slot, InventorySlot::Hotbar(..));
+    let section = |other: bool| {
+        Inventory::all_slots().filter(move |to| matches!(to, InventorySlot::Hotbar(..)) == other)
+    };
+    if click.ctrl {
+        let to = InventorySlot::Hotbar(*inventory.current_bar, *inventory.current_item);
+        (to != slot).then_some(InventoryOp::Swap { from: slot, to })
+    } else if click.shift && click.right {
+        section(hotbar)
+            .find(|to| inventory.get_slot(*to).is_none())
+            .map(|to| InventoryOp::Split { from: slot, to })
+    } else if click.shift {
+        // Topping up a stack of the same item comes before an empty slot
+        let merge = section(!hotbar).find(|to| {
+            inventory.get_slot(*to).as_ref().map_or(false, |to_item| {
+                same_item(item, to_item) && to_item.stack_size < max_stack(to_item, item_table)
+            })
+        });
+        if let Some(to) = merge {
+            return Some(InventoryOp::Merge { from: slot, to });
         }
+        section(!hotbar)
+            .find(|to| inventory.get_slot(*to).is_none())
+            .map(|to| InventoryOp::Move { from: slot, to })
     } else {
-        if bar {
-            if inventory.hotbar[row_index][row_item].is_none() {
-                inventory.hotbar[row_index][row_item] =
-                    Some(held_items.0.get(0).unwrap().0.clone());
-                held_items.remove(0);
+        Some(InventoryOp::PickUp {
+            from: slot,
+            amount: if click.right {
+                (item.stack_size + 1) / 2
             } else {
-                let held_item = held_items.get(0).unwrap().clone();
-                held_items.remove(0);
-                let temp_item = inventory.hotbar[row_index][row_item].clone();
-                inventory.hotbar[row_index][row_item] = Some(held_item.0.clone());
-                match held_item.1 {
-                    "inventory" => {
-                        inventory.slots[held_item.2][held_item.3] = temp_item;
-                    }
-                    "bar" => {
-                        inventory.hotbar[held_item.2][held_item.3] = temp_item;
-                    }
-                    _ => {}
-                }
-            }
-        } else if inventory.slots[row_index][row_item].is_none() {
-            inventory.slots[row_index][row_item] = Some(held_items.0.get(0).unwrap().0.clone());
-            held_items.remove(0);
-        } else {
-            let held_item = held_items.get(0).unwrap().clone();
-            held_items.remove(0);
-            let temp_item = inventory.slots[row_index][row_item].clone();
-            inventory.slots[row_index][row_item] = Some(held_item.0.clone());
-            match held_item.1 {
-                "inventory" => {
-                    inventory.slots[held_item.2][held_item.3] = temp_item;
-                }
-                "bar" => {
-                    inventory.hotbar[held_item.2][held_item.3] = temp_item;
-                }
-                _ => {}
-            }
-        }
-        **holding = false;
+                item.stack_size
+            },
+        })
     }
 }
 
-// TODO: Actually make this work
-// pub fn grab_half_stack(
-//     held_items: &mut CurrentItemsHeld,
-//     inventory: &mut Inventory,
-//     holding: &mut Holding,
-//     row_index: usize,
-//     row_item: usize,
-//     bar: bool,
-// ) {
-// }
+fn slot_click(response: &egui::Response) -> Option<SlotClick> {
+    let right = if response.clicked() {
+        false
+    } else if response.secondary_clicked() {
+        true
+    } else {
+        return None;
+    };
+    let modifiers = response.ctx.input(|input| input.modifiers);
+    Some(SlotClick {
+        right,
+        shift: modifiers.shift,
+        ctrl: modifiers.command,
+    })
+}
+
+// Whatever is still held when the inventory closes goes back into it
+pub fn return_held(
+    mut player_query: Query<(&mut Inventory, &mut HeldItem), With<ControlledPlayer>>,
+    mut prediction: ResMut<InventoryPrediction>,
+    mut client: ResMut<Client>,
+    item_table: Res<ItemTable>,
+) {
+    if let Ok((mut inventory, mut held)) = player_query.get_single_mut() {
+        if !inventory.open && held.0.is_some() {
+            prediction.predict(
+                InventoryOp::Return,
+                &mut inventory,
+                &mut held,
+                &item_table,
+                &mut client,
+            );
+        }
+    }
+}
 
 pub fn inventory(
-    mut player_query: Query<&mut Inventory, With<ControlledPlayer>>,
-    mut held_items: ResMut<CurrentItemsHeld>,
-    mut holding: ResMut<Holding>,
+    mut player_query: Query<(&mut Inventory, &mut HeldItem), With<ControlledPlayer>>,
+    mut prediction: ResMut<InventoryPrediction>,
+    mut client: ResMut<Client>,
+    item_table: Res<ItemTable>,
     mut contexts: EguiContexts,
     _options: Res<GameOptions>,
     loadable_assets: Res<LoadableAssets>,
 ) {
     let ctx = contexts.ctx_mut().clone();
     let style = ctx.style();
-    if let Ok(mut inventory) = player_query.get_single_mut() {
+    if let Ok((mut inventory, mut held)) = player_query.get_single_mut() {
         if inventory.open {
             egui::Window::new("inventory")
                 .resizable(false)
@@ -306,7 +331,7 @@ pub fn inventory(
                                                     let color = if *inventory.current_inv_item
                                                         == item_num
                                                         && *inventory.current_inv_bar == row_num
-                                                        && **holding
+                                                        && held.is_some()
                                                     {
                                                         Color32::from_white_alpha(128)
                                                     } else if item.is_none() {
@@ -364,20 +389,27 @@ pub fn inventory(
                                                         });
                                                                 },
                                                             );
-                                                            if image.clicked() {
+                                                            if let Some(click) = slot_click(&image) {
                                                                 inventory.current_inv_item = CurrentInvItem(item_num);
                                                                 inventory.current_inv_bar = CurrentInvBar(row_num);
-                                                                grab_stack(
-                                                                    &mut held_items,
-                                                                    &mut inventory,
-                                                                    &mut holding,
-                                                                    row_num,
-                                                                    item_num,
-                                                                    false,
-                                                                );
+                                                                if let Some(op) = grab_stack(
+                                                                    &inventory,
+                                                                    &held,
+                                                                    InventorySlot::Inventory(row_num, item_num),
+                                                                    click,
+                                                                    &item_table,
+                                                                ) {
+                                                                    prediction.predict(
+                                                                        op,
+                                                                        &mut inventory,
+                                                                        &mut held,
+                                                                        &item_table,
+                                                                        &mut client,
+                                                                    );
+                                                                }
                                                             }
-                                                        } else if ui
-                                                            .add(
+                                                        } else if let Some(click) = slot_click(
+                                                            &ui.add(
                                                                 egui::widgets::Image::new(
                                                                     contexts
                                                                         .image_id(
@@ -395,19 +427,25 @@ pub fn inventory(
                                                                 )
                                                                 .tint(color)
                                                                 .sense(Sense::click()),
-                                                            )
-                                                            .clicked()
-                                                        {
+                                                            ),
+                                                        ) {
                                                             inventory.current_inv_item = CurrentInvItem(item_num);
                                                             inventory.current_inv_bar = CurrentInvBar(row_num);
-                                                            grab_stack(
-                                                                &mut held_items,
-                                                                &mut inventory,
-                                                                &mut holding,
-                                                                row_num,
-                                                                item_num,
-                                                                false,
-                                                            );
+                                                            if let Some(op) = grab_stack(
+                                                                &inventory,
+                                                                &held,
+                                                                InventorySlot::Inventory(row_num, item_num),
+                                                                click,
+                                                                &item_table,
+                                                            ) {
+                                                                prediction.predict(
+                                                                    op,
+                                                                    &mut inventory,
+                                                                    &mut held,
+                                                                    &item_table,
+                                                                    &mut client,
+                                                                );
+                                                            }
                                                         }
                                                         });
                                                 });
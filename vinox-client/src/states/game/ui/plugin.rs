@@ -4,7 +4,7 @@ use super::{
     crafting::crafting_ui,
     debug::{debug, targeted_block},
     dropdown::{create_ui, ConsoleOpen, Toast},
-    inventory::{inventory, status_bar, CurrentItemsHeld, Holding},
+    inventory::{inventory, return_held, status_bar},
 };
 use bevy::prelude::*;
 
@@ -16,8 +16,6 @@ pub struct InUi(pub bool);
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(ConsoleOpen(false))
-            .insert_resource(CurrentItemsHeld::default())
-            .insert_resource(Holding(false))
             .insert_resource(InUi(false))
             .insert_resource(Toast::default())
             .add_systems(
@@ -25,6 +23,7 @@ impl Plugin for UiPlugin {
                     create_ui,
                     status_bar,
                     inventory,
+                    return_held,
                     crafting_ui,
                     debug,
                     targeted_block,
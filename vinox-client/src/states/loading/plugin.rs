@@ -8,7 +8,10 @@ use crate::states::{
     game::networking::components::ClientData,
 };
 
-use super::ui::{load_blocks, new_client, setup_resources, switch, timeout, AssetsLoading};
+use super::ui::{
+    load_blocks, new_client, setup_resources, switch, sync_assets, timeout, AssetSync,
+    AssetsLoading, ServerAssets,
+};
 
 pub struct LoadingPlugin;
 
@@ -22,12 +25,15 @@ impl Plugin for LoadingPlugin {
             .insert_resource(ItemTable::default())
             .insert_resource(LoadableAssets::default())
             .insert_resource(AssetsLoading::default())
+            .insert_resource(AssetSync::default())
+            .insert_resource(ServerAssets::default())
+            .add_system(new_client.in_schedule(OnEnter(GameState::Loading)))
             .add_systems(
-                (setup_resources, new_client)
+                (sync_assets, setup_resources, load_blocks, switch)
                     .chain()
-                    .in_schedule(OnEnter(GameState::Loading)),
+                    .in_set(OnUpdate(GameState::Loading)),
             )
-            .add_systems((load_blocks, switch, timeout).in_set(OnUpdate(GameState::Loading)))
+            .add_system(timeout.in_set(OnUpdate(GameState::Loading)))
             .add_system(despawn_with::<Loading>.in_schedule(OnExit(GameState::Loading)));
     }
 }
@@ -5,10 +5,20 @@ use bevy_quinnet::client::{
     connection::{ConnectionConfiguration, ConnectionEvent},
     Client,
 };
-use std::time::Duration;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use vinox_common::{
     ecs::bundles::PlayerBundleBuilder,
-    networking::protocol::NetworkIP,
+    networking::{
+        assets::{
+            assets_path, content_hash, read_asset, remove_asset, server_cache, write_asset,
+            AssetManifest, PROTOCOL_VERSION,
+        },
+        protocol::{ClientMessage, NetworkIP, ServerMessage, DEFAULT_PORT},
+    },
     storage::{
         biomes::load::load_all_biomes,
         blocks::load::load_all_blocks,
@@ -23,29 +33,178 @@ use vinox_common::{
 };
 use vinox_mesher::mesh::GeometryTable;
 
-use crate::states::components::GameState;
+use crate::states::{components::GameState, game::networking::components::ClientData};
 
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct AssetsLoading(pub Vec<HandleUntyped>);
 
+// The server's cache folder relative to the assets folder, synced tables and their textures are
+// loaded from here instead of the client's own assets
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ServerAssets(pub String);
+
+impl ServerAssets {
+    pub fn path(&self) -> Option<PathBuf> {
+        assets_path().map(|assets| assets.join(&self.0))
+    }
+}
+
+// Where we are in the join handshake, tables are only loaded once the server accepts our assets
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetSync {
+    #[default]
+    Connecting,
+    Downloading,
+    Verifying,
+    Accepted,
+    Loaded,
+}
+
 //TODO: Right now we are building the client only as a multiplayer client. This is fine but eventually we need to have singleplayer.
 // To achieve this we will just have the client start up a server. But for now I am just going to use a dedicated one for testing
-pub fn new_client(ip_res: Res<NetworkIP>, mut client: ResMut<Client>) {
-    let ip = if ip_res.0 == "localhost" {
-        "127.0.0.1".to_string()
-    } else {
-        ip_res.0.clone()
-    }
-    .parse()
-    .unwrap();
+pub fn new_client(
+    ip_res: Res<NetworkIP>,
+    mut client: ResMut<Client>,
+    mut asset_sync: ResMut<AssetSync>,
+) {
+    *asset_sync = AssetSync::Connecting;
+    // Either just an address for the default port or address:port
+    let address = ip_res.0.replacen("localhost", "127.0.0.1", 1);
+    let (ip, port) = match address.parse::<SocketAddr>() {
+        Ok(socket) => (socket.ip(), socket.port()),
+        Err(_) => (address.parse().unwrap(), DEFAULT_PORT),
+    };
     client
         .open_connection(
-            ConnectionConfiguration::from_ips(ip, 25565, "0.0.0.0".to_string().parse().unwrap(), 0),
+            ConnectionConfiguration::from_ips(ip, port, "0.0.0.0".to_string().parse().unwrap(), 0),
             CertificateVerificationMode::SkipVerification,
         )
         .unwrap();
 }
 
+fn refuse(commands: &mut Commands, client: &mut Client, reason: &str) {
+    warn!("Couldn't join the server: {reason}");
+    client.close_all_connections().ok();
+    commands.insert_resource(NextState(Some(GameState::Menu)));
+}
+
+// Let the server know what we ended up with, it decides whether that matches
+fn send_assets_ready(client: &mut Client, cache: &Path) -> AssetSync {
+    let manifest = AssetManifest::load_from(cache);
+    client
+        .connection_mut()
+        .try_send_message(ClientMessage::AssetsReady {
+            protocol_version: PROTOCOL_VERSION,
+            content_hash: manifest.content_hash(),
+        });
+    AssetSync::Verifying
+}
+
+pub fn sync_assets(
+    mut commands: Commands,
+    mut client: ResMut<Client>,
+    mut connected_event: EventReader<ConnectionEvent>,
+    mut asset_sync: ResMut<AssetSync>,
+    mut client_data: ResMut<ClientData>,
+    mut server_assets: ResMut<ServerAssets>,
+    mut server_manifest: Local<AssetManifest>,
+) {
+    for _ in connected_event.iter() {
+        client
+            .connection_mut()
+            .set_default_channel(bevy_quinnet::shared::channel::ChannelId::UnorderedReliable);
+    }
+    if *asset_sync == AssetSync::Accepted || *asset_sync == AssetSync::Loaded {
+        return;
+    }
+    while let Some(message) = client
+        .connection_mut()
+        .try_receive_message::<ServerMessage>()
+    {
+        match message {
+            ServerMessage::Handshake {
+                protocol_version,
+                manifest,
+            } => {
+                if protocol_version != PROTOCOL_VERSION {
+                    refuse(
+                        &mut commands,
+                        &mut client,
+                        &format!(
+                            "Server uses protocol {protocol_version} but we use {PROTOCOL_VERSION}"
+                        ),
+                    );
+                    return;
+                }
+                **server_assets = server_cache(&manifest);
+                let Some(cache) = server_assets.path() else {
+                    refuse(
+                        &mut commands,
+                        &mut client,
+                        "Couldn't find the data directory",
+                    );
+                    return;
+                };
+                let cached = AssetManifest::load_from(&cache);
+                for path in cached.keys().filter(|path| !manifest.contains_key(*path)) {
+                    if let Err(e) = remove_asset(&cache, path) {
+                        warn!("Failed to remove stale {path}: {e}");
+                    }
+                }
+                // Our own copy of a file is used when it's the same version as the server's
+                let own = AssetManifest::load();
+                let missing: Vec<String> = cached
+                    .missing(&manifest)
+                    .into_iter()
+                    .filter(|path| {
+                        own.get(path) != manifest.get(path)
+                            || read_asset(path)
+                                .map_or(true, |bytes| write_asset(&cache, path, &bytes).is_err())
+                    })
+                    .collect();
+                *server_manifest = manifest;
+                if missing.is_empty() {
+                    *asset_sync = send_assets_ready(&mut client, &cache);
+                } else {
+                    info!("Downloading {} asset files from the server", missing.len());
+                    client
+                        .connection_mut()
+                        .try_send_message(ClientMessage::RequestAssets { files: missing });
+                    *asset_sync = AssetSync::Downloading;
+                }
+            }
+            ServerMessage::AssetFiles { files, remaining } => {
+                let Some(cache) = server_assets.path() else {
+                    continue;
+                };
+                for (path, bytes) in files {
+                    if server_manifest.get(&path) != Some(&content_hash(&bytes)) {
+                        warn!("Server sent {path} but it doesn't match its manifest");
+                        continue;
+                    }
+                    if let Err(e) = write_asset(&cache, &path, &bytes) {
+                        warn!("Failed to save {path}: {e}");
+                    }
+                }
+                if remaining == 0 {
+                    *asset_sync = send_assets_ready(&mut client, &cache);
+                }
+            }
+            ServerMessage::ClientId { id, block_registry } => {
+                **client_data = id;
+                commands.insert_resource(block_registry);
+                *asset_sync = AssetSync::Accepted;
+                return;
+            }
+            ServerMessage::Refused { reason } => {
+                refuse(&mut commands, &mut client, &reason);
+                return;
+            }
+            _ => {}
+        }
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn switch(
     mut commands: Commands,
@@ -54,18 +213,20 @@ pub fn switch(
     mut loadable_assets: ResMut<LoadableAssets>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut textures: ResMut<Assets<Image>>,
-    mut client: ResMut<Client>,
-    mut connected_event: EventReader<ConnectionEvent>,
+    asset_sync: Res<AssetSync>,
+    mut has_switched: Local<bool>,
 ) {
+    if *asset_sync != AssetSync::Loaded {
+        *has_switched = false;
+        return;
+    }
     match asset_server.get_group_load_state(loading.iter().map(|h| h.id())) {
         LoadState::Failed => {
             commands.insert_resource(NextState(Some(GameState::Menu)));
         }
         LoadState::Loaded => {
-            for _ in connected_event.iter() {
-                client.connection_mut().set_default_channel(
-                    bevy_quinnet::shared::channel::ChannelId::UnorderedReliable,
-                );
+            if !*has_switched {
+                *has_switched = true;
 
                 let mut texture_atlas_builder = TextureAtlasBuilder::default();
                 for handle in loadable_assets.block_textures.values() {
@@ -94,10 +255,16 @@ pub fn timeout(
     mut timer: Local<Timer>,
     time: Res<Time>,
     mut client: ResMut<Client>,
+    asset_sync: Res<AssetSync>,
 ) {
     timer.set_mode(TimerMode::Repeating);
     timer.set_duration(Duration::from_secs_f32(10.));
 
+    // Downloading assets can take a while, only time out while the server hasn't answered
+    if *asset_sync != AssetSync::Connecting {
+        timer.reset();
+        return;
+    }
     timer.tick(time.delta());
     if timer.just_finished() {
         client.close_all_connections().ok();
@@ -116,7 +283,18 @@ pub fn setup_resources(
     mut geo_table: ResMut<GeometryTable>,
     mut loadable_assets: ResMut<LoadableAssets>,
     mut egui_textures: ResMut<EguiUserTextures>,
+    mut asset_sync: ResMut<AssetSync>,
+    server_assets: Res<ServerAssets>,
 ) {
+    // Only load the tables once the files on disk are the ones the server accepted
+    if *asset_sync != AssetSync::Accepted {
+        return;
+    }
+    *asset_sync = AssetSync::Loaded;
+    let Some(cache) = server_assets.path() else {
+        return;
+    };
+
     let player_handle = asset_server.load("base_player.gltf#Scene0");
     loading.push(player_handle.clone_untyped());
     commands.insert_resource(PlayerBundleBuilder {
@@ -127,7 +305,7 @@ pub fn setup_resources(
         },
     });
 
-    for block in load_all_blocks() {
+    for block in load_all_blocks(&cache) {
         let mut name = block.clone().namespace;
         name.push(':');
         name.push_str(&block.name);
@@ -139,19 +317,19 @@ pub fn setup_resources(
 
         block_table.insert(name, block);
     }
-    for recipe in load_all_recipes() {
+    for recipe in load_all_recipes(&cache) {
         let mut name = recipe.clone().namespace;
         name.push(':');
         name.push_str(&recipe.name);
         recipe_table.insert(name, recipe);
     }
-    for geo in load_all_geo() {
+    for geo in load_all_geo(&cache) {
         let mut name = geo.clone().namespace;
         name.push(':');
         name.push_str(&geo.name);
         geo_table.insert(name, geo);
     }
-    for item in load_all_items() {
+    for item in load_all_items(&cache) {
         let mut name = item.clone().namespace;
         name.push(':');
         name.push_str(&item.name);
@@ -167,7 +345,7 @@ pub fn setup_resources(
             // let mut suffix = item.name.clone();
             // suffix.push('/');
             // suffix.push_str(&path);
-            let texture_handle = asset_server.load(path);
+            let texture_handle = asset_server.load(format!("{}/{path}", **server_assets));
             loading.push(texture_handle.clone_untyped());
             loadable_assets
                 .item_textures
@@ -187,7 +365,7 @@ pub fn setup_resources(
         egui_textures.add_image(item_texture.clone_weak());
     }
 
-    for biome in load_all_biomes() {
+    for biome in load_all_biomes(&cache) {
         let mut name = biome.clone().namespace;
         name.push(':');
         name.push_str(&biome.name);
@@ -201,6 +379,7 @@ pub fn load_blocks(
     mut loading: ResMut<AssetsLoading>,
     block_table: Res<BlockTable>,
     mut loadable_assets: ResMut<LoadableAssets>,
+    server_assets: Res<ServerAssets>,
     mut has_ran: Local<bool>,
 ) {
     if !(*has_ran) && block_table.is_changed() {
@@ -214,7 +393,7 @@ pub fn load_blocks(
             // If there is a front texture preset all faces to use it so someone can use the same texture for all just by providing the front
             if let Some(texture_path) = &block.textures {
                 if let Some(front) = texture_path.get(&Some("front".to_string())) {
-                    let mut path = "blocks/".to_string();
+                    let mut path = format!("{}/blocks/", **server_assets);
                     let name = trim_geo_identifier(block.clone().name);
                     path.push_str(name.as_str());
                     path.push('/');
@@ -232,7 +411,7 @@ pub fn load_blocks(
             for texture_path_and_type in block.textures.iter() {
                 for texture_path_and_type in texture_path_and_type.iter() {
                     if let (Some(texture_path), Some(texture_type)) = &texture_path_and_type {
-                        let mut path = "blocks/".to_string();
+                        let mut path = format!("{}/blocks/", **server_assets);
                         let name = trim_geo_identifier(block.clone().name);
                         path.push_str(name.as_str());
                         path.push('/');
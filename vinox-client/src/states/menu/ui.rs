@@ -17,7 +17,7 @@ use bevy_egui::{
     },
     EguiContexts, EguiSettings,
 };
-use vinox_common::networking::protocol::NetworkIP;
+use vinox_common::{networking::protocol::NetworkIP, world::chunks::storage::HORIZONTAL_DISTANCE};
 
 use crate::states::{
     components::{save_game_options, GameActions, GameOptions, GameState, Menu, ProjectPath},
@@ -143,6 +143,22 @@ pub fn options(
                                 ui.add(egui::Slider::new(&mut options.meshes_frame, 64..=2048));
                             });
                             ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("View distance: ");
+                                ui.add(egui::Slider::new(
+                                    &mut options.view_distance,
+                                    2..=HORIZONTAL_DISTANCE as u32,
+                                ));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Interpolation delay (ms): ");
+                                ui.add(egui::Slider::new(
+                                    &mut options.interpolation_delay,
+                                    0..=500,
+                                ));
+                            });
+                            ui.separator();
                             ui.horizontal(|ui| {
                                 ui.label("Vsync: ");
                                 if ui.small_button(format!("{}", options.vsync)).clicked() {
@@ -4,7 +4,11 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     networking::protocol::Player,
-    storage::items::descriptor::{ItemData, ItemDescriptor, MAX_STACK_SIZE},
+    storage::{
+        crafting::descriptor::RecipeDescriptor,
+        items::descriptor::{ItemData, ItemDescriptor, MAX_STACK_SIZE},
+    },
+    world::chunks::storage::{name_to_identifier, ItemTable},
 };
 
 #[derive(Default, Deref, DerefMut, Serialize, Deserialize, Debug, Clone)]
@@ -21,6 +25,82 @@ pub struct CurrentInvBar(pub usize);
 #[derive(Default, Deref, DerefMut, Serialize, Deserialize, Debug, Clone)]
 pub struct CurrentInvItem(pub usize);
 
+// Row then index in that row
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InventorySlot {
+    Hotbar(usize, usize),
+    Inventory(usize, usize),
+}
+
+impl InventorySlot {
+    // Anything coming from the network has to be checked before indexing with it
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            InventorySlot::Hotbar(row, idx) => row < 3 && idx < 3,
+            InventorySlot::Inventory(row, idx) => row < 5 && idx < 9,
+        }
+    }
+}
+
+// Every change a player can make to their own inventory. Held is the stack on the cursor
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum InventoryOp {
+    // Whole stack, merges into the same item and swaps with a different one
+    Move {
+        from: InventorySlot,
+        to: InventorySlot,
+    },
+    // Half of the stack into an empty slot
+    Split {
+        from: InventorySlot,
+        to: InventorySlot,
+    },
+    // As much as fits into a stack of the same item
+    Merge {
+        from: InventorySlot,
+        to: InventorySlot,
+    },
+    Swap {
+        from: InventorySlot,
+        to: InventorySlot,
+    },
+    // Put amount of the held stack into a slot
+    Drop {
+        to: InventorySlot,
+        amount: u32,
+    },
+    // Take amount from a slot into the held stack
+    PickUp {
+        from: InventorySlot,
+        amount: u32,
+    },
+    // Put the held stack back wherever it fits when the inventory closes, the server drops
+    // whatever doesn't
+    Return,
+}
+
+// Kept out of the inventory itself, it only exists while the inventory is open and is put back when
+// it closes or the player leaves
+#[derive(Component, Default, Deref, DerefMut, Serialize, Deserialize, Debug, Clone)]
+pub struct HeldItem(pub Option<ItemData>);
+
+pub fn same_item(first: &ItemData, second: &ItemData) -> bool {
+    first.namespace == second.namespace
+        && first.name == second.name
+        && first.durability == second.durability
+        && first.arbitary_data == second.arbitary_data
+}
+
+pub fn max_stack(item: &ItemData, item_table: &ItemTable) -> u32 {
+    item_table
+        .get(&name_to_identifier(
+            item.namespace.clone(),
+            item.name.clone(),
+        ))
+        .and_then(|descriptor| descriptor.max_stack_size)
+        .unwrap_or(MAX_STACK_SIZE)
+}
+
 #[derive(Component, Default, Serialize, Deserialize, Clone, Debug)]
 pub struct Inventory {
     pub username: String,
@@ -105,7 +185,7 @@ impl Inventory {
             if amount < item_comp.max_stack_size.unwrap_or(MAX_STACK_SIZE) {
                 match section {
                     "inventory" => {
-                        self.slots[row][row] = Some(ItemData {
+                        self.slots[row][idx] = Some(ItemData {
                             name: item_comp.name.clone(),
                             namespace: item_comp.namespace.clone(),
                             stack_size: amount + 1,
@@ -128,7 +208,7 @@ impl Inventory {
         if let Some((section, row, idx)) = self.get_first_slot() {
             match section {
                 "inventory" => {
-                    self.slots[row][row] = Some(ItemData {
+                    self.slots[row][idx] = Some(ItemData {
                         name: item_comp.name.clone(),
                         namespace: item_comp.namespace.clone(),
                         stack_size: 1,
@@ -175,6 +255,344 @@ impl Inventory {
             _ => {}
         }
     }
+
+    pub fn get_slot(&self, slot: InventorySlot) -> &Option<ItemData> {
+        match slot {
+            InventorySlot::Hotbar(row, idx) => &self.hotbar[row][idx],
+            InventorySlot::Inventory(row, idx) => &self.slots[row][idx],
+        }
+    }
+
+    pub fn get_slot_mut(&mut self, slot: InventorySlot) -> &mut Option<ItemData> {
+        match slot {
+            InventorySlot::Hotbar(row, idx) => &mut self.hotbar[row][idx],
+            InventorySlot::Inventory(row, idx) => &mut self.slots[row][idx],
+        }
+    }
+
+    // Hotbar first then the inventory, same order everything else fills in
+    pub fn all_slots() -> impl Iterator<Item = InventorySlot> {
+        let hotbar = (0..3).flat_map(|row| (0..3).map(move |idx| InventorySlot::Hotbar(row, idx)));
+        let inventory =
+            (0..5).flat_map(|row| (0..9).map(move |idx| InventorySlot::Inventory(row, idx)));
+        hotbar.chain(inventory)
+    }
+
+    pub fn item_count(&self, identifier: &str) -> u32 {
+        Self::all_slots()
+            .filter_map(|slot| self.get_slot(slot).as_ref())
+            .filter(|item| {
+                name_to_identifier(item.namespace.clone(), item.name.clone()) == identifier
+            })
+            .map(|item| item.stack_size)
+            .sum()
+    }
+
+    // Either all of them fit or nothing changes
+    pub fn add_items(&mut self, item_comp: &ItemDescriptor, amount: u32) -> bool {
+        let max_stack = item_comp.max_stack_size.unwrap_or(MAX_STACK_SIZE);
+        let identifier = name_to_identifier(item_comp.namespace.clone(), item_comp.name.clone());
+        let mut new_inventory = self.clone();
+        let mut left = amount;
+        for slot in Self::all_slots() {
+            if let Some(item) = new_inventory.get_slot_mut(slot) {
+                if name_to_identifier(item.namespace.clone(), item.name.clone()) == identifier
+                    && item.stack_size < max_stack
+                {
+                    let added = left.min(max_stack - item.stack_size);
+                    item.stack_size += added;
+                    left -= added;
+                }
+            }
+            if left == 0 {
+                break;
+            }
+        }
+        for slot in Self::all_slots() {
+            if left == 0 {
+                break;
+            }
+            let item = new_inventory.get_slot_mut(slot);
+            if item.is_none() {
+                let added = left.min(max_stack);
+                *item = Some(ItemData {
+                    name: item_comp.name.clone(),
+                    namespace: item_comp.namespace.clone(),
+                    stack_size: added,
+                    durability: item_comp.max_durability.unwrap_or_default(),
+                    ..Default::default()
+                });
+                left -= added;
+            }
+        }
+        if left == 0 {
+            *self = new_inventory;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Fits as much of the stack as it can, returns whatever is left over
+    pub fn add_stack(&mut self, mut stack: ItemData, item_table: &ItemTable) -> Option<ItemData> {
+        let max_stack = max_stack(&stack, item_table);
+        for slot in Self::all_slots() {
+            if let Some(item) = self.get_slot_mut(slot) {
+                if same_item(item, &stack) && item.stack_size < max_stack {
+                    let added = stack.stack_size.min(max_stack - item.stack_size);
+                    item.stack_size += added;
+                    stack.stack_size -= added;
+                }
+            }
+            if stack.stack_size == 0 {
+                return None;
+            }
+        }
+        for slot in Self::all_slots() {
+            let item = self.get_slot_mut(slot);
+            if item.is_none() {
+                let added = stack.stack_size.min(max_stack);
+                let mut new_item = stack.clone();
+                new_item.stack_size = added;
+                *item = Some(new_item);
+                stack.stack_size -= added;
+            }
+            if stack.stack_size == 0 {
+                return None;
+            }
+        }
+        Some(stack)
+    }
+
+    // Either all of them are there or nothing changes
+    pub fn remove_items(&mut self, identifier: &str, amount: u32) -> bool {
+        if self.item_count(identifier) < amount {
+            return false;
+        }
+        let mut left = amount;
+        for slot in Self::all_slots() {
+            if left == 0 {
+                break;
+            }
+            let item = self.get_slot_mut(slot);
+            if let Some(cur_item) = item {
+                if name_to_identifier(cur_item.namespace.clone(), cur_item.name.clone())
+                    == identifier
+                {
+                    if cur_item.stack_size > left {
+                        cur_item.stack_size -= left;
+                        left = 0;
+                    } else {
+                        left -= cur_item.stack_size;
+                        *item = None;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    pub fn craft(&mut self, recipe: &RecipeDescriptor, item_table: &ItemTable) -> bool {
+        let Some(output) = item_table.get(&recipe.output_item.0) else {
+            return false;
+        };
+        let mut new_inventory = self.clone();
+        for (identifier, amount) in recipe.required_items.clone().unwrap_or_default() {
+            if !new_inventory.remove_items(&identifier, amount) {
+                return false;
+            }
+        }
+        if new_inventory.add_items(output, recipe.output_item.1) {
+            *self = new_inventory;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Every slot that is different in other, applying this to self gets you other
+    pub fn diff(&self, other: &Inventory) -> Vec<(InventorySlot, Option<ItemData>)> {
+        Self::all_slots()
+            .filter(|slot| self.get_slot(*slot) != other.get_slot(*slot))
+            .map(|slot| (slot, other.get_slot(slot).clone()))
+            .collect()
+    }
+
+    pub fn apply_delta(&mut self, delta: Vec<(InventorySlot, Option<ItemData>)>) {
+        for (slot, item) in delta {
+            if slot.is_valid() {
+                *self.get_slot_mut(slot) = item;
+            }
+        }
+    }
+
+    // Both the client predicting and the server checking run this, returns false and changes nothing if the op isn't allowed
+    pub fn apply_op(
+        &mut self,
+        held: &mut HeldItem,
+        op: &InventoryOp,
+        item_table: &ItemTable,
+    ) -> bool {
+        match *op {
+            InventoryOp::Move { from, to } => {
+                if from == to || !from.is_valid() || !to.is_valid() {
+                    return false;
+                }
+                let Some(from_item) = self.get_slot(from).clone() else {
+                    return false;
+                };
+                match self.get_slot(to).clone() {
+                    None => {
+                        *self.get_slot_mut(to) = Some(from_item);
+                        *self.get_slot_mut(from) = None;
+                        true
+                    }
+                    Some(to_item) if same_item(&from_item, &to_item) => {
+                        self.apply_op(held, &InventoryOp::Merge { from, to }, item_table)
+                    }
+                    Some(to_item) => {
+                        *self.get_slot_mut(to) = Some(from_item);
+                        *self.get_slot_mut(from) = Some(to_item);
+                        true
+                    }
+                }
+            }
+            InventoryOp::Split { from, to } => {
+                if from == to || !from.is_valid() || !to.is_valid() || self.get_slot(to).is_some() {
+                    return false;
+                }
+                let Some(from_item) = self.get_slot_mut(from) else {
+                    return false;
+                };
+                if from_item.stack_size < 2 {
+                    return false;
+                }
+                let amount = from_item.stack_size / 2;
+                from_item.stack_size -= amount;
+                let mut new_item = from_item.clone();
+                new_item.stack_size = amount;
+                *self.get_slot_mut(to) = Some(new_item);
+                true
+            }
+            InventoryOp::Merge { from, to } => {
+                if from == to || !from.is_valid() || !to.is_valid() {
+                    return false;
+                }
+                let (Some(from_item), Some(to_item)) =
+                    (self.get_slot(from).clone(), self.get_slot(to).clone())
+                else {
+                    return false;
+                };
+                let room = max_stack(&to_item, item_table).saturating_sub(to_item.stack_size);
+                if !same_item(&from_item, &to_item) || room == 0 {
+                    return false;
+                }
+                let amount = room.min(from_item.stack_size);
+                if let Some(to_item) = self.get_slot_mut(to) {
+                    to_item.stack_size += amount;
+                }
+                if amount == from_item.stack_size {
+                    *self.get_slot_mut(from) = None;
+                } else if let Some(from_item) = self.get_slot_mut(from) {
+                    from_item.stack_size -= amount;
+                }
+                true
+            }
+            InventoryOp::Swap { from, to } => {
+                if from == to
+                    || !from.is_valid()
+                    || !to.is_valid()
+                    || (self.get_slot(from).is_none() && self.get_slot(to).is_none())
+                {
+                    return false;
+                }
+                let from_item = self.get_slot(from).clone();
+                *self.get_slot_mut(from) = self.get_slot(to).clone();
+                *self.get_slot_mut(to) = from_item;
+                true
+            }
+            InventoryOp::PickUp { from, amount } => {
+                if !from.is_valid() || amount == 0 {
+                    return false;
+                }
+                let Some(from_item) = self.get_slot(from).clone() else {
+                    return false;
+                };
+                if amount > from_item.stack_size {
+                    return false;
+                }
+                match held.0.as_mut() {
+                    None => {
+                        let mut new_item = from_item.clone();
+                        new_item.stack_size = amount;
+                        held.0 = Some(new_item);
+                    }
+                    Some(held_item)
+                        if same_item(held_item, &from_item)
+                            && held_item.stack_size + amount
+                                <= max_stack(held_item, item_table) =>
+                    {
+                        held_item.stack_size += amount;
+                    }
+                    _ => return false,
+                }
+                if amount == from_item.stack_size {
+                    *self.get_slot_mut(from) = None;
+                } else if let Some(from_item) = self.get_slot_mut(from) {
+                    from_item.stack_size -= amount;
+                }
+                true
+            }
+            InventoryOp::Drop { to, amount } => {
+                if !to.is_valid() || amount == 0 {
+                    return false;
+                }
+                let Some(held_item) = held.0.clone() else {
+                    return false;
+                };
+                if amount > held_item.stack_size {
+                    return false;
+                }
+                match self.get_slot(to).clone() {
+                    None => {
+                        let mut new_item = held_item.clone();
+                        new_item.stack_size = amount;
+                        *self.get_slot_mut(to) = Some(new_item);
+                    }
+                    Some(to_item) if same_item(&held_item, &to_item) => {
+                        if to_item.stack_size + amount > max_stack(&to_item, item_table) {
+                            return false;
+                        }
+                        if let Some(to_item) = self.get_slot_mut(to) {
+                            to_item.stack_size += amount;
+                        }
+                    }
+                    // Putting the whole stack down on something else picks that up instead
+                    Some(to_item) => {
+                        if amount != held_item.stack_size {
+                            return false;
+                        }
+                        *self.get_slot_mut(to) = Some(held_item);
+                        held.0 = Some(to_item);
+                        return true;
+                    }
+                }
+                if amount == held_item.stack_size {
+                    held.0 = None;
+                } else if let Some(held_item) = held.0.as_mut() {
+                    held_item.stack_size -= amount;
+                }
+                true
+            }
+            InventoryOp::Return => {
+                let Some(stack) = held.0.take() else {
+                    return false;
+                };
+                self.add_stack(stack, item_table);
+                true
+            }
+        }
+    }
 }
 
 #[derive(Component, Default, Deref, DerefMut)]
@@ -223,3 +641,419 @@ impl PlayerBundleBuilder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{HeldItem, Inventory, InventoryOp, InventorySlot};
+    use crate::{
+        storage::{
+            crafting::descriptor::RecipeDescriptor,
+            items::descriptor::{ItemData, ItemDescriptor},
+        },
+        world::chunks::storage::ItemTable,
+    };
+
+    const HOTBAR: InventorySlot = InventorySlot::Hotbar(0, 0);
+    const FIRST: InventorySlot = InventorySlot::Inventory(0, 0);
+    const SECOND: InventorySlot = InventorySlot::Inventory(0, 1);
+
+    // Stone stacks up to 10, dirt to the default
+    fn test_table() -> ItemTable {
+        let mut item_table = ItemTable::default();
+        for (name, max_stack_size) in [("stone", Some(10)), ("dirt", None)] {
+            item_table.insert(
+                format!("vinox:{name}"),
+                ItemDescriptor {
+                    namespace: "vinox".to_string(),
+                    name: name.to_string(),
+                    max_stack_size,
+                    ..Default::default()
+                },
+            );
+        }
+        item_table
+    }
+
+    fn stack(name: &str, stack_size: u32) -> Option<ItemData> {
+        Some(ItemData {
+            namespace: "vinox".to_string(),
+            name: name.to_string(),
+            stack_size,
+            ..Default::default()
+        })
+    }
+
+    fn inventory(slots: &[(InventorySlot, Option<ItemData>)]) -> Inventory {
+        let mut inventory = Inventory::default();
+        inventory.apply_delta(slots.to_vec());
+        inventory
+    }
+
+    fn assert_same(first: &Inventory, second: &Inventory) {
+        assert_eq!(first.hotbar.0, second.hotbar.0);
+        assert_eq!(first.slots, second.slots);
+    }
+
+    #[test]
+    fn moves_merge_and_swap() {
+        let item_table = test_table();
+        let mut held = HeldItem::default();
+        let mut inventory = inventory(&[(FIRST, stack("stone", 4))]);
+        assert!(inventory.apply_op(
+            &mut held,
+            &InventoryOp::Move {
+                from: FIRST,
+                to: HOTBAR
+            },
+            &item_table
+        ));
+        assert_eq!(*inventory.get_slot(FIRST), None);
+        assert_eq!(*inventory.get_slot(HOTBAR), stack("stone", 4));
+
+        // Same item merges, a different one swaps
+        *inventory.get_slot_mut(FIRST) = stack("stone", 3);
+        assert!(inventory.apply_op(
+            &mut held,
+            &InventoryOp::Move {
+                from: FIRST,
+                to: HOTBAR
+            },
+            &item_table
+        ));
+        assert_eq!(*inventory.get_slot(FIRST), None);
+        assert_eq!(*inventory.get_slot(HOTBAR), stack("stone", 7));
+        *inventory.get_slot_mut(FIRST) = stack("dirt", 2);
+        assert!(inventory.apply_op(
+            &mut held,
+            &InventoryOp::Move {
+                from: FIRST,
+                to: HOTBAR
+            },
+            &item_table
+        ));
+        assert_eq!(*inventory.get_slot(FIRST), stack("stone", 7));
+        assert_eq!(*inventory.get_slot(HOTBAR), stack("dirt", 2));
+
+        let before = inventory.clone();
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::Move {
+                from: SECOND,
+                to: FIRST
+            },
+            &item_table
+        ));
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::Move {
+                from: FIRST,
+                to: FIRST
+            },
+            &item_table
+        ));
+        assert_same(&inventory, &before);
+    }
+
+    #[test]
+    fn splits_into_empty_slots() {
+        let item_table = test_table();
+        let mut held = HeldItem::default();
+        let mut inventory = inventory(&[(FIRST, stack("stone", 5)), (HOTBAR, stack("dirt", 1))]);
+        assert!(inventory.apply_op(
+            &mut held,
+            &InventoryOp::Split {
+                from: FIRST,
+                to: SECOND
+            },
+            &item_table
+        ));
+        assert_eq!(*inventory.get_slot(FIRST), stack("stone", 3));
+        assert_eq!(*inventory.get_slot(SECOND), stack("stone", 2));
+
+        let before = inventory.clone();
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::Split {
+                from: FIRST,
+                to: SECOND
+            },
+            &item_table
+        ));
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::Split {
+                from: HOTBAR,
+                to: InventorySlot::Inventory(1, 0)
+            },
+            &item_table
+        ));
+        assert_same(&inventory, &before);
+    }
+
+    #[test]
+    fn merges_stop_at_max_stack_size() {
+        let item_table = test_table();
+        let mut held = HeldItem::default();
+        let mut inventory = inventory(&[
+            (FIRST, stack("stone", 6)),
+            (SECOND, stack("stone", 8)),
+            (HOTBAR, stack("dirt", 3)),
+        ]);
+        assert!(inventory.apply_op(
+            &mut held,
+            &InventoryOp::Merge {
+                from: FIRST,
+                to: SECOND
+            },
+            &item_table
+        ));
+        assert_eq!(*inventory.get_slot(FIRST), stack("stone", 4));
+        assert_eq!(*inventory.get_slot(SECOND), stack("stone", 10));
+
+        // Full or a different item
+        let before = inventory.clone();
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::Merge {
+                from: FIRST,
+                to: SECOND
+            },
+            &item_table
+        ));
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::Merge {
+                from: HOTBAR,
+                to: FIRST
+            },
+            &item_table
+        ));
+        assert_same(&inventory, &before);
+    }
+
+    #[test]
+    fn swaps_need_an_item() {
+        let item_table = test_table();
+        let mut held = HeldItem::default();
+        let mut inventory = inventory(&[(FIRST, stack("stone", 6))]);
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::Swap {
+                from: SECOND,
+                to: HOTBAR
+            },
+            &item_table
+        ));
+        assert!(inventory.apply_op(
+            &mut held,
+            &InventoryOp::Swap {
+                from: FIRST,
+                to: SECOND
+            },
+            &item_table
+        ));
+        assert_eq!(*inventory.get_slot(FIRST), None);
+        assert_eq!(*inventory.get_slot(SECOND), stack("stone", 6));
+    }
+
+    #[test]
+    fn picks_up_and_drops_the_held_stack() {
+        let item_table = test_table();
+        let mut held = HeldItem::default();
+        let mut inventory = inventory(&[(FIRST, stack("stone", 6)), (SECOND, stack("stone", 9))]);
+        assert!(inventory.apply_op(
+            &mut held,
+            &InventoryOp::PickUp {
+                from: FIRST,
+                amount: 4
+            },
+            &item_table
+        ));
+        assert_eq!(*inventory.get_slot(FIRST), stack("stone", 2));
+        assert_eq!(held.0, stack("stone", 4));
+        // Can't take more than is there or more than the held stack holds
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::PickUp {
+                from: FIRST,
+                amount: 3
+            },
+            &item_table
+        ));
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::PickUp {
+                from: SECOND,
+                amount: 7
+            },
+            &item_table
+        ));
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::PickUp {
+                from: FIRST,
+                amount: 0
+            },
+            &item_table
+        ));
+
+        assert!(inventory.apply_op(
+            &mut held,
+            &InventoryOp::Drop {
+                to: HOTBAR,
+                amount: 1
+            },
+            &item_table
+        ));
+        assert_eq!(*inventory.get_slot(HOTBAR), stack("stone", 1));
+        assert_eq!(held.0, stack("stone", 3));
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::Drop {
+                to: SECOND,
+                amount: 2
+            },
+            &item_table
+        ));
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::Drop {
+                to: HOTBAR,
+                amount: 4
+            },
+            &item_table
+        ));
+        assert!(inventory.apply_op(
+            &mut held,
+            &InventoryOp::Drop {
+                to: SECOND,
+                amount: 1
+            },
+            &item_table
+        ));
+        assert_eq!(*inventory.get_slot(SECOND), stack("stone", 10));
+        assert_eq!(held.0, stack("stone", 2));
+
+        // The whole stack on a different item picks that up instead
+        *inventory.get_slot_mut(HOTBAR) = stack("dirt", 5);
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::Drop {
+                to: HOTBAR,
+                amount: 1
+            },
+            &item_table
+        ));
+        assert!(inventory.apply_op(
+            &mut held,
+            &InventoryOp::Drop {
+                to: HOTBAR,
+                amount: 2
+            },
+            &item_table
+        ));
+        assert_eq!(*inventory.get_slot(HOTBAR), stack("stone", 2));
+        assert_eq!(held.0, stack("dirt", 5));
+
+        held.0 = None;
+        assert!(!inventory.apply_op(
+            &mut held,
+            &InventoryOp::Drop {
+                to: FIRST,
+                amount: 1
+            },
+            &item_table
+        ));
+    }
+
+    #[test]
+    fn invalid_slots_are_refused() {
+        let item_table = test_table();
+        let mut held = HeldItem(stack("stone", 2));
+        let mut inventory = inventory(&[(FIRST, stack("stone", 6)), (SECOND, stack("stone", 2))]);
+        let before = inventory.clone();
+        for invalid in [
+            InventorySlot::Hotbar(3, 0),
+            InventorySlot::Hotbar(0, 3),
+            InventorySlot::Inventory(5, 0),
+            InventorySlot::Inventory(0, 9),
+        ] {
+            for op in [
+                InventoryOp::Move {
+                    from: FIRST,
+                    to: invalid,
+                },
+                InventoryOp::Move {
+                    from: invalid,
+                    to: FIRST,
+                },
+                InventoryOp::Split {
+                    from: FIRST,
+                    to: invalid,
+                },
+                InventoryOp::Merge {
+                    from: invalid,
+                    to: SECOND,
+                },
+                InventoryOp::Swap {
+                    from: FIRST,
+                    to: invalid,
+                },
+                InventoryOp::Drop {
+                    to: invalid,
+                    amount: 1,
+                },
+                InventoryOp::PickUp {
+                    from: invalid,
+                    amount: 1,
+                },
+            ] {
+                assert!(!inventory.apply_op(&mut held, &op, &item_table), "{op:?}");
+            }
+            inventory.apply_delta(vec![(invalid, stack("dirt", 1))]);
+        }
+        assert_same(&inventory, &before);
+        assert_eq!(held.0, stack("stone", 2));
+    }
+
+    #[test]
+    fn diffs_apply_back() {
+        let first = inventory(&[(FIRST, stack("stone", 6)), (HOTBAR, stack("dirt", 2))]);
+        let second = inventory(&[
+            (FIRST, stack("stone", 5)),
+            (SECOND, stack("dirt", 1)),
+            (InventorySlot::Hotbar(2, 2), stack("stone", 1)),
+        ]);
+        let delta = first.diff(&second);
+        assert_eq!(delta.len(), 4);
+        let mut applied = first.clone();
+        applied.apply_delta(delta);
+        assert_same(&applied, &second);
+        assert!(applied.diff(&second).is_empty());
+    }
+
+    #[test]
+    fn crafting_takes_everything_or_nothing() {
+        let item_table = test_table();
+        let recipe = RecipeDescriptor {
+            required_items: Some(HashMap::from([("vinox:stone".to_string(), 8)])),
+            output_item: ("vinox:dirt".to_string(), 3),
+            ..Default::default()
+        };
+        let mut inventory = inventory(&[(FIRST, stack("stone", 6)), (HOTBAR, stack("stone", 5))]);
+        assert!(inventory.craft(&recipe, &item_table));
+        assert_eq!(inventory.item_count("vinox:stone"), 3);
+        assert_eq!(inventory.item_count("vinox:dirt"), 3);
+
+        let before = inventory.clone();
+        assert!(!inventory.craft(&recipe, &item_table));
+        let unknown = RecipeDescriptor {
+            output_item: ("vinox:missing".to_string(), 1),
+            ..Default::default()
+        };
+        assert!(!inventory.craft(&unknown, &item_table));
+        assert_same(&inventory, &before);
+    }
+}
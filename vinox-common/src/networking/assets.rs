@@ -0,0 +1,124 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+// Bump whenever a message or descriptor changes shape so old clients are refused
+pub const PROTOCOL_VERSION: u32 = 8;
+
+// Everything the client needs to load the same tables as the server
+pub const SYNCED_FOLDERS: [&str; 5] = ["blocks", "items", "recipes", "biomes", "geometry"];
+
+// FNV-1a, it only has to be stable between builds not secure
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Asset files the server sends a client per tick, at least one file goes out each time
+pub const ASSET_BANDWIDTH: usize = 256 * 1024;
+
+pub fn assets_path() -> Option<PathBuf> {
+    ProjectDirs::from("com", "vinox", "vinox").map(|proj_dirs| proj_dirs.data_dir().join("assets"))
+}
+
+// Where the client keeps a server's synced folders, relative to the assets folder so textures can
+// be loaded from it. Keyed by the server's manifest so joining never touches the client's own
+// assets or what another server sent
+pub fn server_cache(manifest: &AssetManifest) -> String {
+    format!("servers/{:016x}", manifest.content_hash())
+}
+
+// Only relative paths inside a synced folder, so a server can't write anywhere else
+pub fn is_synced_path(path: &str) -> bool {
+    let path = PathBuf::from(path);
+    let mut components = path.components();
+    matches!(components.next(), Some(Component::Normal(folder))
+        if SYNCED_FOLDERS.iter().any(|synced| folder == *synced))
+        && components.all(|component| matches!(component, Component::Normal(_)))
+}
+
+pub fn read_asset(path: &str) -> Option<Vec<u8>> {
+    if !is_synced_path(path) {
+        return None;
+    }
+    fs::read(assets_path()?.join(path)).ok()
+}
+
+pub fn write_asset(root: &Path, path: &str, bytes: &[u8]) -> Result<(), String> {
+    if !is_synced_path(path) {
+        return Err(format!("Refusing to write {path} outside of synced folders"));
+    }
+    let full_path = root.join(path);
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    fs::write(full_path, bytes).map_err(|e| e.to_string())
+}
+
+pub fn remove_asset(root: &Path, path: &str) -> Result<(), String> {
+    if !is_synced_path(path) {
+        return Err(format!("Refusing to remove {path} outside of synced folders"));
+    }
+    fs::remove_file(root.join(path)).map_err(|e| e.to_string())
+}
+
+// Relative path of every synced file and the hash of its contents
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Deref)]
+pub struct AssetManifest(pub BTreeMap<String, u64>);
+
+impl AssetManifest {
+    pub fn load() -> Self {
+        assets_path().map_or_else(Self::default, |assets_path| Self::load_from(&assets_path))
+    }
+
+    // Synced folders under root, the client's own assets or a server's cache
+    pub fn load_from(assets_path: &Path) -> Self {
+        let mut files = BTreeMap::new();
+        for folder in SYNCED_FOLDERS {
+            for entry in WalkDir::new(assets_path.join(folder))
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+            {
+                let Ok(relative) = entry.path().strip_prefix(assets_path) else { continue; };
+                let relative = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if let Ok(bytes) = fs::read(entry.path()) {
+                    files.insert(relative, content_hash(&bytes));
+                }
+            }
+        }
+        Self(files)
+    }
+
+    // Sorted so both sides get the same hash for the same files
+    pub fn content_hash(&self) -> u64 {
+        let mut bytes = Vec::new();
+        for (path, hash) in self.iter() {
+            bytes.extend_from_slice(path.as_bytes());
+            bytes.push(0);
+            bytes.extend_from_slice(&hash.to_le_bytes());
+        }
+        content_hash(&bytes)
+    }
+
+    // Files in other that we don't have or have a different version of
+    pub fn missing(&self, other: &AssetManifest) -> Vec<String> {
+        other
+            .iter()
+            .filter(|(path, hash)| self.get(*path) != Some(*hash))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+}
@@ -4,38 +4,51 @@ use bevy_quinnet::shared::ClientId;
 #[derive(Resource, Deref, DerefMut)]
 pub struct NetworkIP(pub String);
 
+pub const DEFAULT_PORT: u16 = 25565;
+
 use serde::{Deserialize, Serialize};
 
-use crate::{ecs::bundles::Inventory, world::chunks::storage::BlockData};
+use crate::{
+    ecs::bundles::{HeldItem, Inventory, InventoryOp, InventorySlot},
+    networking::assets::AssetManifest,
+    physics::movement::{InputFrame, MoveState},
+    storage::items::descriptor::ItemData,
+    world::chunks::storage::{BlockRegistry, PackedBlock},
+};
 
+// Only entities with this are replicated to clients
 #[derive(Component)]
 pub struct NetworkedEntity;
 
+// The transform only holds yaw so players don't tilt, the head is tracked separately
+#[derive(Component, Default, Clone, Copy, Deref, DerefMut)]
+pub struct HeadPitch(pub f32);
+
 #[derive(Debug, Component, Default)]
 pub struct Player {
     pub id: ClientId,
 }
 
 // Networking related
+// The server stamps entity updates with the FixedUpdate tick they were taken on
+pub const TICK_SECONDS: f64 = 1.0 / 60.0;
+
+// Fields are None when they haven't changed since they were last sent, entities that aren't
+// listed at all are known to the client and didn't change this tick
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct NetworkedEntities {
+    pub tick: u32,
     pub entities: Vec<Entity>,
-    pub translations: Vec<Vec3>,
-    pub yaws: Vec<f32>,
-    pub head_pitchs: Vec<f32>,
-}
-
-#[derive(Default, Resource)]
-pub struct EntityBuffer {
-    pub entities: [NetworkedEntities; 30],
+    pub translations: Vec<Option<Vec3>>,
+    pub yaws: Vec<Option<f32>>,
+    pub head_pitchs: Vec<Option<f32>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ClientMessage {
-    Position {
-        player_pos: Vec3,
-        yaw: f32,
-        head_pitch: f32,
+    // The newest input frames that haven't been acknowledged yet, resent until they are
+    Inputs {
+        frames: Vec<InputFrame>,
     },
     Interact {
         entity: Entity,
@@ -45,8 +58,17 @@ pub enum ClientMessage {
     SentBlock {
         chunk_pos: IVec3,
         voxel_pos: [u8; 3],
-        block_type: BlockData,
+        block_type: PackedBlock,
     },
+    StartBreak {
+        chunk_pos: IVec3,
+        voxel_pos: [u8; 3],
+    },
+    InteractBlock {
+        chunk_pos: IVec3,
+        voxel_pos: [u8; 3],
+    },
+    UseItem,
     Join {
         user_name: String,
         password: String,
@@ -58,20 +80,63 @@ pub enum ClientMessage {
     ChatMessage {
         message: String,
     },
-    Inventory {
-        inventory: Box<Inventory>,
+    SelectItem {
+        bar: usize,
+        item: usize,
+    },
+    // Sequence lets the client know which of its predictions were wrong
+    InventoryOp {
+        sequence: u32,
+        op: InventoryOp,
+    },
+    // Recipe identifier, the server does the actual crafting
+    Craft {
+        recipe: String,
+    },
+    // Files from the server's manifest that we don't have or that differ
+    RequestAssets {
+        files: Vec<String>,
+    },
+    // Sent once our assets match the server's, answered with ClientId or Refused
+    AssetsReady {
+        protocol_version: u32,
+        content_hash: u64,
+    },
+    // How many chunks around us we want, the server caps it to its own limit
+    ViewDistance {
+        horizontal: u32,
+        vertical: u32,
+    },
+    // A partly typed command, answered with CommandCompletions
+    CompleteCommand {
+        input: String,
     },
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ServerMessage {
+    // First thing sent on connect so the client can sync assets before joining
+    Handshake {
+        protocol_version: u32,
+        manifest: AssetManifest,
+    },
+    // Sent a few files at a time, the last one has nothing remaining
+    AssetFiles {
+        files: Vec<(String, Vec<u8>)>,
+        remaining: usize,
+    },
+    Refused {
+        reason: String,
+    },
     ChatMessage {
         user_name: String,
         message: String,
         id: u64,
     },
+    // Block ids are per world so they come with the go ahead to join
     ClientId {
         id: ClientId,
+        block_registry: BlockRegistry,
     },
     PlayerCreate {
         entity: Entity,
@@ -89,13 +154,60 @@ pub enum ServerMessage {
     SentBlock {
         chunk_pos: IVec3,
         voxel_pos: [u8; 3],
-        block_type: BlockData,
+        block_type: PackedBlock,
+    },
+    // The server didn't accept a block change so put back what it actually has
+    RejectBlock {
+        chunk_pos: IVec3,
+        voxel_pos: [u8; 3],
+        block_type: PackedBlock,
+        inventory: Box<Inventory>,
+    },
+    // Only the slots that changed
+    InventoryDelta {
+        delta: Vec<(InventorySlot, Option<ItemData>)>,
+    },
+    InventoryAck {
+        sequence: u32,
+    },
+    // Everything the server has after rejecting sequence, the client replays anything newer on top
+    InventoryRollback {
+        sequence: u32,
+        inventory: Box<Inventory>,
+        held: HeldItem,
     },
     NetworkedEntities {
         networked_entities: NetworkedEntities,
     },
+    // An entity came into or left our load radius
+    EntitySpawn {
+        entity: Entity,
+        tick: u32,
+        translation: Vec3,
+        yaw: f32,
+        head_pitch: f32,
+        // Set for items lying in the world, players are created with PlayerCreate
+        item: Option<ItemData>,
+    },
+    EntityDespawn {
+        entity: Entity,
+    },
     LevelData {
         chunk_data: Vec<u8>,
         pos: IVec3,
     },
+    // Where our player ended up after the server ran every input up to sequence
+    MoveAck {
+        sequence: u32,
+        state: MoveState,
+    },
+    // Sent every so often so clients know which tick the server is on
+    ServerTime {
+        tick: u32,
+    },
+    // Whole lines the input could be completed to, input is echoed so stale answers are ignored
+    CommandCompletions {
+        input: String,
+        suggestions: Vec<String>,
+    },
 }
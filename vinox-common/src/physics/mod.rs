@@ -1,3 +1,4 @@
 pub mod collision;
+pub mod movement;
 pub mod plugin;
 pub mod simulate;
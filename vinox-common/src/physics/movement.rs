@@ -0,0 +1,114 @@
+use bevy::{prelude::*, render::primitives::Aabb};
+use big_space::GridCell;
+use serde::{Deserialize, Serialize};
+
+use super::simulate::CollisionWorld;
+
+// Both sides step players at this rate so a replayed input lands in the same spot
+pub const MOVEMENT_TICK: f32 = 1.0 / 60.0;
+const GRAVITY: f32 = 35.0;
+const WALK_SPEED: f32 = 5.0;
+const RUN_SPEED: f32 = 10.0;
+const JUMP_SPEED: f32 = 10.0;
+
+// What the player was pressing for one movement tick, the server never trusts positions
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct InputFrame {
+    pub sequence: u32,
+    pub forward: bool,
+    pub backward: bool,
+    pub left: bool,
+    pub right: bool,
+    pub jump: bool,
+    pub run: bool,
+    pub yaw: f32,
+    pub head_pitch: f32,
+}
+
+#[derive(Component, Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct MoveState {
+    pub translation: Vec3,
+    pub velocity: Vec3,
+    // Goes up while we aren't moving vertically, high enough means we can jump
+    pub grounded: i32,
+    // Flies through blocks without gravity, only the server turns it on with /spectator
+    pub noclip: bool,
+}
+
+impl MoveState {
+    pub fn new(translation: Vec3) -> Self {
+        Self {
+            translation,
+            ..default()
+        }
+    }
+}
+
+// Unit length or zero
+fn wish_direction(input: &InputFrame, forward: Vec3, right: Vec3) -> Vec3 {
+    let mut movement = Vec3::ZERO;
+    if input.forward {
+        movement += forward;
+    }
+    if input.backward {
+        movement -= forward;
+    }
+    if input.left {
+        movement -= right;
+    }
+    if input.right {
+        movement += right;
+    }
+    movement.normalize_or_zero()
+}
+
+// Translation is absolute so client and server agree whatever cell the client is in
+pub fn step_movement(
+    state: &mut MoveState,
+    input: &InputFrame,
+    aabb: &Aabb,
+    world: &CollisionWorld,
+) {
+    let speed = if input.run { RUN_SPEED } else { WALK_SPEED };
+    // Straight where the camera looks and jump goes up, the world isn't touched so it doesn't have
+    // to be loaded
+    if state.noclip {
+        let look = Quat::from_rotation_y(input.yaw) * Quat::from_rotation_x(input.head_pitch);
+        let mut movement = wish_direction(input, look * Vec3::NEG_Z, look * Vec3::X) * speed;
+        if input.jump {
+            movement.y = JUMP_SPEED;
+        }
+        state.velocity = movement;
+        state.translation += movement * MOVEMENT_TICK;
+        state.grounded = 0;
+        return;
+    }
+
+    if !world.is_loaded(GridCell::default(), state.translation) {
+        return;
+    }
+
+    if state.velocity.y.abs() < 0.001 && state.grounded < 10 {
+        state.grounded += 4;
+    } else if state.grounded >= 0 {
+        state.grounded -= 1;
+    }
+
+    let rotation = Quat::from_rotation_y(input.yaw);
+    let movement = wish_direction(input, rotation * Vec3::NEG_Z, rotation * Vec3::X) * speed;
+
+    let mut velocity_y = state.velocity.y - GRAVITY * MOVEMENT_TICK;
+    if input.jump && state.grounded > 8 {
+        state.grounded = 0;
+        velocity_y = JUMP_SPEED;
+    }
+    state.velocity = Vec3::new(movement.x, velocity_y, movement.z);
+
+    world.collide_and_move(
+        &mut state.translation,
+        &mut state.velocity,
+        aabb,
+        GridCell::default(),
+        MOVEMENT_TICK,
+    );
+}
@@ -1,4 +1,5 @@
 use bevy::{
+    ecs::system::SystemParam,
     math::Vec3A,
     prelude::{Component, Entity, EventWriter, IVec3, Query, Res, Transform, Vec3, With, Without},
     render::primitives::Aabb,
@@ -49,99 +50,81 @@ pub fn move_and_collide(
     >,
     time: Res<Time>,
     chunks_without_data: Query<With<NeedsChunkData>>,
-    chunks: Query<&ChunkData>,
-    current_chunks: Res<CurrentChunks>,
-    block_table: Res<BlockTable>,
+    world: CollisionWorld,
     mut _collision_event_writer: EventWriter<VoxelCollisionEvent>,
 ) {
     for (_entity, aabb, mut velocity, mut transform, grid_cell) in moving_entities.iter_mut() {
-        let mut aabb = *aabb;
-        aabb.center = Vec3A::new(
-            transform.translation.x,
-            transform.translation.y + aabb.half_extents.y,
-            transform.translation.z,
-        );
         let chunk_pos: ChunkPos = ChunkPos::from_chunk_cell(*grid_cell, transform.translation);
-        if let Some(chunk_entity) = current_chunks.get_entity(chunk_pos) {
+        if let Some(chunk_entity) = world.current_chunks.get_entity(chunk_pos) {
             if chunks_without_data.get(chunk_entity).is_ok() {
                 continue;
             }
-            let movement = velocity.0 * time.delta().as_secs_f32();
-            let x_col = test_move_axis(
+            world.collide_and_move(
+                &mut transform.translation,
+                &mut velocity.0,
                 &aabb,
                 *grid_cell,
-                &Vec3 {
-                    x: movement.x,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                &chunks,
-                &current_chunks,
-                &block_table,
+                time.delta().as_secs_f32(),
             );
-            if let Some(c) = x_col {
-                aabb.center.x += c.dist.copysign(movement.x);
-                transform.translation.x += c.dist.copysign(movement.x);
-                velocity.0.x = 0.0;
-                // collision_event_writer.send(VoxelCollisionEvent {
-                //     entity,
-                //     voxel_pos: c.collision_aabb.center.floor().as_ivec3(),
-                //     normal: c.normal,
-                // });
-            } else {
-                aabb.center.x += movement.x;
-                transform.translation.x += movement.x;
-            }
-            let y_col = test_move_axis(
-                &aabb,
-                *grid_cell,
-                &Vec3 {
-                    x: 0.0,
-                    y: movement.y,
-                    z: 0.0,
-                },
-                &chunks,
-                &current_chunks,
-                &block_table,
-            );
-            if let Some(c) = y_col {
-                aabb.center.y += c.dist.copysign(movement.y);
-                transform.translation.y += c.dist.copysign(movement.y);
-                velocity.0.y = 0.0;
-                // collision_event_writer.send(VoxelCollisionEvent {
-                //     entity,
-                //     voxel_pos: c.collision_aabb.center.floor().as_ivec3(),
-                //     normal: c.normal,
-                // });
-            } else {
-                aabb.center.y += movement.y;
-                transform.translation.y += movement.y;
-            }
-            let z_col = test_move_axis(
+        }
+    }
+}
+
+// Everything needed to test movement against the blocks around an entity
+#[derive(SystemParam)]
+pub struct CollisionWorld<'w, 's> {
+    pub chunks: Query<'w, 's, &'static ChunkData>,
+    pub current_chunks: Res<'w, CurrentChunks>,
+    pub block_table: Res<'w, BlockTable>,
+}
+
+impl<'w, 's> CollisionWorld<'w, 's> {
+    // Only move through chunks we actually have blocks for, otherwise we fall into the void
+    pub fn is_loaded(&self, grid_cell: GridCell<i32>, translation: Vec3) -> bool {
+        self.current_chunks
+            .get_entity(ChunkPos::from_chunk_cell(grid_cell, translation))
+            .map_or(false, |entity| self.chunks.get(entity).is_ok())
+    }
+
+    // One axis at a time so we slide along walls, a blocked axis loses its velocity
+    pub fn collide_and_move(
+        &self,
+        translation: &mut Vec3,
+        velocity: &mut Vec3,
+        aabb: &Aabb,
+        grid_cell: GridCell<i32>,
+        delta: f32,
+    ) {
+        let mut aabb = *aabb;
+        aabb.center = Vec3A::new(
+            translation.x,
+            translation.y + aabb.half_extents.y,
+            translation.z,
+        );
+        let movement = *velocity * delta;
+        for axis in 0..3 {
+            let mut axis_movement = Vec3::ZERO;
+            axis_movement[axis] = movement[axis];
+            let distance = if let Some(c) = test_move_axis(
                 &aabb,
-                *grid_cell,
-                &Vec3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: movement.z,
-                },
-                &chunks,
-                &current_chunks,
-                &block_table,
-            );
-            if let Some(c) = z_col {
-                aabb.center.z += c.dist.copysign(movement.z);
-                transform.translation.z += c.dist.copysign(movement.z);
-                velocity.0.z = 0.0;
+                grid_cell,
+                &axis_movement,
+                &self.chunks,
+                &self.current_chunks,
+                &self.block_table,
+            ) {
+                velocity[axis] = 0.0;
                 // collision_event_writer.send(VoxelCollisionEvent {
                 //     entity,
                 //     voxel_pos: c.collision_aabb.center.floor().as_ivec3(),
                 //     normal: c.normal,
                 // });
+                c.dist.copysign(movement[axis])
             } else {
-                aabb.center.z += movement.z;
-                transform.translation.z += movement.z;
-            }
+                movement[axis]
+            };
+            aabb.center[axis] += distance;
+            translation[axis] += distance;
         }
     }
 }
@@ -1,4 +1,5 @@
 pub mod blocks;
 pub mod entities;
 pub mod guis;
+pub mod runtime;
 pub mod world;
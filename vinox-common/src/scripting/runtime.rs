@@ -0,0 +1,343 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    rc::Rc,
+};
+
+use bevy::prelude::*;
+use directories::ProjectDirs;
+use mlua::{Function, HookTriggers, Lua, LuaOptions, RegistryKey, StdLib, Table, Value};
+
+use crate::world::chunks::{
+    ecs::ChunkManager,
+    positions::VoxelPos,
+    storage::{trim_geo_identifier, BlockData, BlockTable, ItemTable, RecipeTable},
+};
+
+use super::world::{world_table, ScriptWorld};
+
+// Scripts shouldn't be able to take the whole server down with them
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+// Instructions every call into a script gets, counted every HOOK_INTERVAL so an endless loop
+// errors instead of hanging the server
+const INSTRUCTION_LIMIT: u32 = 10_000_000;
+const HOOK_INTERVAL: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptHook {
+    Place,
+    Break,
+    Interact,
+    Use,
+    Craft,
+}
+
+impl ScriptHook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScriptHook::Place => "on_place",
+            ScriptHook::Break => "on_break",
+            ScriptHook::Interact => "on_interact",
+            ScriptHook::Use => "on_use",
+            ScriptHook::Craft => "on_craft",
+        }
+    }
+}
+
+// Identifier is whatever owns the script ie a block, item or recipe in namespace:name form
+#[derive(Debug, Clone)]
+pub struct ScriptEvent {
+    pub hook: ScriptHook,
+    pub identifier: String,
+    pub user_name: String,
+    pub pos: Option<VoxelPos>,
+}
+
+// Registered by a script under `commands` ie `commands = { heal = { syntax = "<target:player>",
+// permission = "operator", run = function(world, ctx) end } }`
+#[derive(Debug, Clone)]
+pub struct ScriptCommand {
+    pub identifier: String,
+    pub name: String,
+    pub syntax: String,
+    pub permission: String,
+    pub description: String,
+}
+
+// Command arguments as scripts see them, positions become tables with x, y and z
+#[derive(Debug, Clone)]
+pub enum ScriptValue {
+    Boolean(bool),
+    Integer(i32),
+    Number(f32),
+    Text(String),
+    Position(IVec3),
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ScriptOutput {
+    pub chat: Vec<String>,
+    pub changed: Vec<(VoxelPos, BlockData)>,
+}
+
+// Lua isn't Send so this has to live as a non send resource
+pub struct ScriptRuntime {
+    lua: Lua,
+    scripts: HashMap<String, RegistryKey>,
+    // Instructions run since the budget was last reset
+    instructions: Rc<Cell<u32>>,
+}
+
+impl ScriptRuntime {
+    pub fn new() -> mlua::Result<Self> {
+        let lua = Lua::new_with(
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8 | StdLib::COROUTINE,
+            LuaOptions::default(),
+        )?;
+        lua.set_memory_limit(MEMORY_LIMIT)?;
+        let instructions = Rc::new(Cell::new(0));
+        let counted = instructions.clone();
+        // Coroutines started by scripts inherit this hook
+        lua.set_hook(
+            HookTriggers {
+                every_nth_instruction: Some(HOOK_INTERVAL),
+                ..Default::default()
+            },
+            move |_, _| {
+                counted.set(counted.get() + HOOK_INTERVAL);
+                if counted.get() > INSTRUCTION_LIMIT {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "Ran more than {INSTRUCTION_LIMIT} instructions"
+                    )));
+                }
+                Ok(())
+            },
+        )?;
+        // The base library is always loaded, strip anything that can touch the filesystem
+        {
+            let globals = lua.globals();
+            for name in ["dofile", "loadfile", "load", "require", "collectgarbage"] {
+                globals.set(name, Value::Nil)?;
+            }
+        }
+        Ok(Self {
+            lua,
+            scripts: HashMap::new(),
+            instructions,
+        })
+    }
+
+    // Called before anything that runs script code
+    fn reset_budget(&self) {
+        self.instructions.set(0);
+    }
+
+    pub fn has_script(&self, identifier: &str) -> bool {
+        self.scripts.contains_key(identifier)
+    }
+
+    /// Scripts are expected to return a table of hooks ie `return { on_break = function(world, ctx) end }`
+    pub fn load_script(&mut self, identifier: String, path: PathBuf) {
+        let Ok(source) = fs::read_to_string(&path) else {
+            warn!("Failed to read script {path:?} for {identifier}");
+            return;
+        };
+        self.reset_budget();
+        let key = match self.lua.load(source.as_str()).eval::<Option<Table>>() {
+            Ok(Some(hooks)) => self.lua.create_registry_value(hooks),
+            Ok(None) => {
+                warn!("Script {path:?} for {identifier} didn't return a table of hooks");
+                return;
+            }
+            Err(e) => {
+                warn!("Failed to load script {path:?} for {identifier}: {e}");
+                return;
+            }
+        };
+        match key {
+            Ok(key) => {
+                self.scripts.insert(identifier, key);
+            }
+            Err(e) => warn!("Failed to register script {path:?} for {identifier}: {e}"),
+        }
+    }
+
+    // Script paths are relative to the folder the descriptor lives in same as textures
+    pub fn load_all(
+        &mut self,
+        block_table: &BlockTable,
+        item_table: &ItemTable,
+        recipe_table: &RecipeTable,
+    ) {
+        for (identifier, block) in block_table.iter() {
+            if let Some(script) = &block.script {
+                let name = trim_geo_identifier(block.name.clone());
+                if let Some(path) = script_path("blocks", &name, script) {
+                    self.load_script(identifier.clone(), path);
+                }
+            }
+        }
+        for (identifier, item) in item_table.iter() {
+            if let Some(script) = &item.script {
+                if let Some(path) = script_path("items", &item.name, script) {
+                    self.load_script(identifier.clone(), path);
+                }
+            }
+        }
+        for (identifier, recipe) in recipe_table.iter() {
+            if let Some(script) = &recipe.script {
+                if let Some(path) = script_path("recipes", &recipe.name, script) {
+                    self.load_script(identifier.clone(), path);
+                }
+            }
+        }
+    }
+
+    pub fn call_hook(&self, event: &ScriptEvent, chunk_manager: &mut ChunkManager) -> ScriptOutput {
+        let Some(key) = self.scripts.get(&event.identifier) else {
+            return ScriptOutput::default();
+        };
+        self.reset_budget();
+        let world = RefCell::new(ScriptWorld::new(chunk_manager));
+        let result = self.lua.scope(|scope| {
+            let hooks: Table = self.lua.registry_value(key)?;
+            let Some(hook) = hooks.get::<_, Option<Function>>(event.hook.name())? else {
+                return Ok(());
+            };
+            let context = self.lua.create_table()?;
+            context.set("identifier", event.identifier.clone())?;
+            context.set("player", event.user_name.clone())?;
+            if let Some(pos) = event.pos {
+                context.set("x", pos.x)?;
+                context.set("y", pos.y)?;
+                context.set("z", pos.z)?;
+            }
+            hook.call::<_, ()>((world_table(&self.lua, scope, &world)?, context))
+        });
+        if let Err(e) = result {
+            warn!(
+                "Script hook {} for {} failed: {e}",
+                event.hook.name(),
+                event.identifier
+            );
+        }
+        world.into_inner().output
+    }
+
+    pub fn commands(&self) -> Vec<ScriptCommand> {
+        self.reset_budget();
+        let mut commands = Vec::new();
+        for (identifier, key) in self.scripts.iter() {
+            let Ok(hooks) = self.lua.registry_value::<Table>(key) else {
+                continue;
+            };
+            let Ok(Some(table)) = hooks.get::<_, Option<Table>>("commands") else {
+                continue;
+            };
+            for pair in table.pairs::<String, Table>() {
+                let Ok((name, command)) = pair else {
+                    warn!("Script for {identifier} has a command that isn't a table");
+                    continue;
+                };
+                let field = |field: &str| command.get::<_, Option<String>>(field).ok().flatten();
+                commands.push(ScriptCommand {
+                    identifier: identifier.clone(),
+                    syntax: field("syntax").unwrap_or_default(),
+                    permission: field("permission").unwrap_or_else(|| "player".to_string()),
+                    description: field("description").unwrap_or_default(),
+                    name,
+                });
+            }
+        }
+        commands
+    }
+
+    // Whatever run returns is sent back to whoever ran the command
+    pub fn run_command(
+        &self,
+        identifier: &str,
+        name: &str,
+        user_name: &str,
+        arguments: &[(String, ScriptValue)],
+        chunk_manager: &mut ChunkManager,
+    ) -> (ScriptOutput, Option<String>) {
+        let Some(key) = self.scripts.get(identifier) else {
+            return (ScriptOutput::default(), None);
+        };
+        self.reset_budget();
+        let world = RefCell::new(ScriptWorld::new(chunk_manager));
+        let result = self.lua.scope(|scope| {
+            let hooks: Table = self.lua.registry_value(key)?;
+            let commands: Table = hooks.get("commands")?;
+            let command: Table = commands.get(name)?;
+            let run: Function = command.get("run")?;
+            let args = self.lua.create_table()?;
+            for (arg_name, value) in arguments {
+                match value {
+                    ScriptValue::Boolean(value) => args.set(arg_name.as_str(), *value)?,
+                    ScriptValue::Integer(value) => args.set(arg_name.as_str(), *value)?,
+                    ScriptValue::Number(value) => args.set(arg_name.as_str(), *value)?,
+                    ScriptValue::Text(value) => args.set(arg_name.as_str(), value.as_str())?,
+                    ScriptValue::Position(pos) => {
+                        let table = self.lua.create_table()?;
+                        table.set("x", pos.x)?;
+                        table.set("y", pos.y)?;
+                        table.set("z", pos.z)?;
+                        args.set(arg_name.as_str(), table)?;
+                    }
+                }
+            }
+            let context = self.lua.create_table()?;
+            context.set("identifier", identifier)?;
+            context.set("player", user_name)?;
+            context.set("args", args)?;
+            run.call::<_, Option<String>>((world_table(&self.lua, scope, &world)?, context))
+        });
+        let feedback = match result {
+            Ok(feedback) => feedback,
+            Err(e) => {
+                warn!("Command /{name} from {identifier} failed: {e}");
+                Some(format!("/{name} failed"))
+            }
+        };
+        (world.into_inner().output, feedback)
+    }
+}
+
+pub fn script_path(folder: &str, name: &str, script: &str) -> Option<PathBuf> {
+    ProjectDirs::from("com", "vinox", "vinox").map(|proj_dirs| {
+        proj_dirs
+            .data_dir()
+            .join("assets")
+            .join(folder)
+            .join(name)
+            .join(script)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::ScriptRuntime;
+
+    #[test]
+    fn endless_scripts_stop() {
+        let path = std::env::temp_dir().join(format!("vinox-endless-{}.lua", std::process::id()));
+        fs::write(&path, "while true do end return {}").unwrap();
+        let mut runtime = ScriptRuntime::new().unwrap();
+        runtime.load_script("vinox:endless".to_string(), path.clone());
+        assert!(!runtime.has_script("vinox:endless"));
+        // The budget is per call so the next script still runs
+        fs::write(
+            &path,
+            "local n = 0 for i = 1, 100 do n = n + i end return {}",
+        )
+        .unwrap();
+        runtime.load_script("vinox:short".to_string(), path.clone());
+        assert!(runtime.has_script("vinox:short"));
+        fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,80 @@
+use std::cell::RefCell;
+
+use mlua::{Lua, Scope, Table};
+
+use crate::world::chunks::{
+    ecs::ChunkManager,
+    positions::VoxelPos,
+    storage::{identifier_to_name, BlockData},
+};
+
+use super::runtime::ScriptOutput;
+
+// Everything a script is allowed to do to the world goes through here so we can validate it and let the server sync it
+pub struct ScriptWorld<'a, 'w, 's> {
+    chunk_manager: &'a mut ChunkManager<'w, 's>,
+    pub output: ScriptOutput,
+}
+
+impl<'a, 'w, 's> ScriptWorld<'a, 'w, 's> {
+    pub fn new(chunk_manager: &'a mut ChunkManager<'w, 's>) -> Self {
+        Self {
+            chunk_manager,
+            output: ScriptOutput::default(),
+        }
+    }
+
+    pub fn get_block(&self, voxel_pos: VoxelPos) -> Option<String> {
+        self.chunk_manager.get_identifier(voxel_pos)
+    }
+
+    // Only blocks that exist and only chunks that are loaded, returns whether the block was set
+    pub fn set_block(&mut self, voxel_pos: VoxelPos, identifier: &str) -> bool {
+        if !self.chunk_manager.block_table.contains_key(identifier)
+            || self.chunk_manager.get_block(voxel_pos).is_none()
+        {
+            return false;
+        }
+        let Some((namespace, name)) = identifier_to_name(identifier.to_string()) else {
+            return false;
+        };
+        let block = BlockData::new(namespace, name);
+        self.chunk_manager.set_block(voxel_pos, block.clone());
+        self.output.changed.push((voxel_pos, block));
+        true
+    }
+
+    pub fn send_chat(&mut self, message: String) {
+        self.output.chat.push(message);
+    }
+}
+
+pub fn world_table<'lua, 'scope>(
+    lua: &'lua Lua,
+    scope: &Scope<'lua, 'scope>,
+    world: &'scope RefCell<ScriptWorld<'_, '_, '_>>,
+) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set(
+        "get_block",
+        scope.create_function(move |_, (x, y, z): (i32, i32, i32)| {
+            Ok(world.borrow().get_block(VoxelPos::new(x, y, z)))
+        })?,
+    )?;
+    table.set(
+        "set_block",
+        scope.create_function(move |_, (x, y, z, identifier): (i32, i32, i32, String)| {
+            Ok(world
+                .borrow_mut()
+                .set_block(VoxelPos::new(x, y, z), &identifier))
+        })?,
+    )?;
+    table.set(
+        "send_chat",
+        scope.create_function(move |_, message: String| {
+            world.borrow_mut().send_chat(message);
+            Ok(())
+        })?,
+    )?;
+    Ok(table)
+}
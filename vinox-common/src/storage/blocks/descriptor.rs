@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use crate::{
-    storage::items::descriptor::ToolType,
+    storage::items::descriptor::{ItemDescriptor, ToolType},
     world::chunks::storage::{identifier_to_just_name, VoxelVisibility},
 };
 use serde::{Deserialize, Serialize};
@@ -81,3 +81,20 @@ pub struct BlockDescriptor {
     pub gui: Option<String>,
     pub has_item: Option<bool>, // Basically whether or not we should auto generate an item for this block
 }
+
+impl BlockDescriptor {
+    // Seconds it takes to break this block. Durability is in ticks by hand, the right tool at a high enough tier is 4x faster
+    pub fn break_time(&self, tool: Option<&ItemDescriptor>) -> f32 {
+        let base = self.durability.unwrap_or(0) as f32 / 20.0;
+        let required = self.tool_type.clone().unwrap_or_default();
+        if let Some(tool) = tool {
+            if required != ToolType::Hand
+                && tool.tool_type.as_ref() == Some(&required)
+                && tool.tool_tier.unwrap_or(0) >= self.tool_tier.unwrap_or(0)
+            {
+                return base / 4.0;
+            }
+        }
+        base
+    }
+}
@@ -1,36 +1,33 @@
-use directories::ProjectDirs;
-use std::fs;
+use std::{fs, path::Path};
 
 use walkdir::WalkDir;
 
 use super::descriptor::BlockDescriptor;
 
-pub fn load_all_blocks() -> Vec<BlockDescriptor> {
+pub fn load_all_blocks(assets: &Path) -> Vec<BlockDescriptor> {
     let mut result = Vec::new();
-    if let Some(proj_dirs) = ProjectDirs::from("com", "vinox", "vinox") {
-        for entry in WalkDir::new(proj_dirs.data_dir().join("assets/blocks"))
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            if entry.path().extension().unwrap_or_default() == "ron" {
-                if let Ok(ron_string) = fs::read_to_string(entry.path()) {
-                    let ron_result = ron::from_str::<BlockDescriptor>(ron_string.as_str());
-                    if let Ok(block) = ron_result {
-                        if let Some(auto_geo) = block.clone().auto_geo {
-                            for geo in auto_geo.iter() {
-                                let mut new_block = block.clone();
-                                new_block.auto_geo = None;
-                                new_block.geometry = Some(geo.clone());
-                                new_block.has_item = Some(false);
-                                new_block.name = block.name.clone() + "." + &geo.get_geo_name();
-                                result.push(new_block);
-                            }
+    for entry in WalkDir::new(assets.join("blocks"))
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if entry.path().extension().unwrap_or_default() == "ron" {
+            if let Ok(ron_string) = fs::read_to_string(entry.path()) {
+                let ron_result = ron::from_str::<BlockDescriptor>(ron_string.as_str());
+                if let Ok(block) = ron_result {
+                    if let Some(auto_geo) = block.clone().auto_geo {
+                        for geo in auto_geo.iter() {
+                            let mut new_block = block.clone();
+                            new_block.auto_geo = None;
+                            new_block.geometry = Some(geo.clone());
+                            new_block.has_item = Some(false);
+                            new_block.name = block.name.clone() + "." + &geo.get_geo_name();
+                            result.push(new_block);
                         }
-
-                        result.push(block);
-                    } else {
-                        println!("{ron_result:?}, {:?}", entry.path());
                     }
+
+                    result.push(block);
+                } else {
+                    println!("{ron_result:?}, {:?}", entry.path());
                 }
             }
         }
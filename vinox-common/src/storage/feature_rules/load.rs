@@ -0,0 +1,28 @@
+use directories::ProjectDirs;
+use std::fs;
+
+use walkdir::WalkDir;
+
+use super::descriptor::FeatureRuleDescriptor;
+
+pub fn load_all_feature_rules() -> Vec<FeatureRuleDescriptor> {
+    let mut result = Vec::new();
+    if let Some(proj_dirs) = ProjectDirs::from("com", "vinox", "vinox") {
+        for entry in WalkDir::new(proj_dirs.data_dir().join("assets/feature_rules"))
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if entry.path().extension().unwrap_or_default() == "ron" {
+                if let Ok(ron_string) = fs::read_to_string(entry.path()) {
+                    let ron_result = ron::from_str(ron_string.as_str());
+                    if let Ok(feature_rule) = ron_result {
+                        result.push(feature_rule);
+                    } else {
+                        println!("{ron_result:?}");
+                    }
+                }
+            }
+        }
+    }
+    result
+}
@@ -45,6 +45,9 @@ pub enum FeatureType {
         body_blocks: Vec<(BlockData, u16)>,
         cap_radius: u8,
     },
+    Structure {
+        structure: String, // Identifier of a structure, placed at its anchor
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
@@ -23,6 +23,7 @@ pub struct ItemDescriptor {
     pub max_durability: Option<u32>,
     pub max_stack_size: Option<u32>, // Default will be the max
     pub tool_type: Option<ToolType>, // Basically for blocks we just do associated_block with no tool and vice versa for tools. But this allows people to make a tool that places a block for example. Scripts will also allow for people to add different functionality to items
+    pub tool_tier: Option<u8>,
     pub script: Option<String>,
     pub associated_block: Option<String>, // String should be an identifier in form of namespace:name, Potentially may change this to be block data instead so people could choose a certain state of a block to put down but we will see
 }
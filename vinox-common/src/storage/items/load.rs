@@ -1,5 +1,4 @@
-use directories::ProjectDirs;
-use std::fs;
+use std::{fs, path::Path};
 
 use walkdir::WalkDir;
 
@@ -7,33 +6,28 @@ use crate::storage::blocks::descriptor::BlockDescriptor;
 
 use super::descriptor::{ItemDescriptor, MAX_STACK_SIZE};
 
-pub fn load_all_items() -> Vec<ItemDescriptor> {
+pub fn load_all_items(assets: &Path) -> Vec<ItemDescriptor> {
     let mut result = Vec::new();
-    if let Some(proj_dirs) = ProjectDirs::from("com", "vinox", "vinox") {
-        for entry in WalkDir::new(proj_dirs.data_dir().join("assets/items"))
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            if entry.path().extension().unwrap_or_default() == "ron" {
-                if let Ok(ron_string) = fs::read_to_string(entry.path()) {
-                    let ron_result = ron::from_str::<ItemDescriptor>(ron_string.as_str());
-                    if let Ok(block) = ron_result {
-                        let mut new_block = block.clone();
-                        let block = if let Some(texture_path) = block.texture {
-                            new_block.texture = Some(
-                                "items/".to_string()
-                                    + &new_block.name.clone()
-                                    + "/"
-                                    + &texture_path,
-                            );
-                            new_block
-                        } else {
-                            block
-                        };
-                        result.push(block);
+    for entry in WalkDir::new(assets.join("items"))
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if entry.path().extension().unwrap_or_default() == "ron" {
+            if let Ok(ron_string) = fs::read_to_string(entry.path()) {
+                let ron_result = ron::from_str::<ItemDescriptor>(ron_string.as_str());
+                if let Ok(block) = ron_result {
+                    let mut new_block = block.clone();
+                    let block = if let Some(texture_path) = block.texture {
+                        new_block.texture = Some(
+                            "items/".to_string() + &new_block.name.clone() + "/" + &texture_path,
+                        );
+                        new_block
                     } else {
-                        println!("{ron_result:?}");
-                    }
+                        block
+                    };
+                    result.push(block);
+                } else {
+                    println!("{ron_result:?}");
                 }
             }
         }
@@ -68,6 +62,7 @@ pub fn item_from_block(block: BlockDescriptor) -> ItemDescriptor {
         max_durability: None,
         max_stack_size: Some(MAX_STACK_SIZE),
         tool_type: None,
+        tool_tier: None,
         script: None,
         associated_block: Some(name),
     }
@@ -1,4 +1,69 @@
+use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::world::chunks::storage::{BlockData, Direction};
+
+// Blocks are sparse so anything not listed is left alone, air has to be listed to carve
 #[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
-pub struct StructureBlocks {}
+pub struct StructureBlocks {
+    pub namespace: String,
+    pub name: String,
+    pub size: (u32, u32, u32),
+    pub anchor: (i32, i32, i32), // Point in the bounding box placed at the target position
+    pub palette: Vec<BlockData>,
+    pub blocks: Vec<((u32, u32, u32), u16)>, // Position inside the bounding box and palette index
+    pub rotate: Option<bool>, // Whether generation is allowed to pick a random rotation
+    pub mirror: Option<bool>,
+}
+
+impl StructureBlocks {
+    // Rotation is in clockwise quarter turns around Y, mirroring flips X before rotating
+    pub fn transformed_blocks(&self, rotation: u8, mirror: bool) -> Vec<(IVec3, BlockData)> {
+        let anchor = IVec3::new(self.anchor.0, self.anchor.1, self.anchor.2);
+        self.blocks
+            .iter()
+            .filter_map(|((x, y, z), index)| {
+                let mut block = self.palette.get(*index as usize)?.clone();
+                let pos = transform_pos(
+                    IVec3::new(*x as i32, *y as i32, *z as i32) - anchor,
+                    rotation,
+                    mirror,
+                );
+                block.direction = block
+                    .direction
+                    .map(|direction| transform_direction(direction, rotation, mirror));
+                Some((pos, block))
+            })
+            .collect()
+    }
+}
+
+pub fn transform_pos(pos: IVec3, rotation: u8, mirror: bool) -> IVec3 {
+    let mut pos = if mirror {
+        IVec3::new(-pos.x, pos.y, pos.z)
+    } else {
+        pos
+    };
+    for _ in 0..rotation % 4 {
+        pos = IVec3::new(-pos.z, pos.y, pos.x);
+    }
+    pos
+}
+
+// North is -Z and East is +X
+pub fn transform_direction(direction: Direction, rotation: u8, mirror: bool) -> Direction {
+    let mut direction = match (mirror, direction) {
+        (true, Direction::East) => Direction::West,
+        (true, Direction::West) => Direction::East,
+        (_, direction) => direction,
+    };
+    for _ in 0..rotation % 4 {
+        direction = match direction {
+            Direction::North => Direction::East,
+            Direction::East => Direction::South,
+            Direction::South => Direction::West,
+            Direction::West => Direction::North,
+        };
+    }
+    direction
+}
@@ -43,8 +43,12 @@ impl Default for LoadPoint {
 }
 
 impl LoadPoint {
-    pub fn new(_horizontal: u32, _vertical: u32) -> Self {
-        Self::default()
+    pub fn new(horizontal: u32, vertical: u32) -> Self {
+        Self {
+            horizontal: horizontal as i32,
+            vertical: vertical as i32,
+            ..Default::default()
+        }
     }
     pub fn is_in_radius(&self, chunk_pos: &ChunkPos) -> bool {
         ((-self.horizontal + self.chunk_pos.x) <= chunk_pos.x
@@ -271,7 +275,7 @@ impl<'w, 's> ChunkManager<'w, 's> {
             if let Ok(chunk) = self.chunk_query.get(chunk_entity) {
                 return self
                     .block_table
-                    .get(&chunk.get_identifier(local_pos))
+                    .descriptor(&chunk.get_identifier(local_pos))
                     .cloned();
             }
         }
@@ -1,31 +1,78 @@
 use std::collections::{HashSet, VecDeque};
 
 use bevy::prelude::*;
-use serde::{Deserialize, Serialize};
-
-use serde_with::{serde_as, Bytes};
 
 use super::{
-    ecs::{CurrentChunks, PriorityMesh},
+    ecs::{CurrentChunks, NeedsMesh, PriorityMesh},
     positions::{ChunkPos, RelativeVoxelPos, VoxelPos},
-    storage::{BlockData, BlockTable, ChunkData},
+    storage::{BlockData, BlockTable, ChunkData, CHUNK_SIZE},
 };
 
+// Chunks at or above this height are taken to be under open sky while the chunk above them isn't
+// loaded, anything lower is dark until it is
+pub const SKY_HEIGHT: i32 = 4;
+pub const MAX_LIGHT: u8 = 15;
+
+// Every channel gets 4 bits of a voxel's light, block lights spread per colour
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightChannel {
+    Red,
+    Green,
+    Blue,
+    Sun,
+}
+
+impl LightChannel {
+    pub const ALL: [LightChannel; 4] = [
+        LightChannel::Red,
+        LightChannel::Green,
+        LightChannel::Blue,
+        LightChannel::Sun,
+    ];
+
+    #[inline]
+    const fn shift(self) -> u16 {
+        match self {
+            LightChannel::Blue => 0,
+            LightChannel::Green => 4,
+            LightChannel::Red => 8,
+            LightChannel::Sun => 12,
+        }
+    }
+
+    fn get(self, chunk_data: &ChunkData, x: u32, y: u32, z: u32) -> u8 {
+        chunk_data.get_light_channel(x, y, z, self)
+    }
+
+    fn set(self, chunk_data: &mut ChunkData, x: u32, y: u32, z: u32, value: u8) {
+        chunk_data.set_light_channel(x, y, z, self, value);
+    }
+}
+
 #[inline]
-pub fn to_torchlight(value: u8) -> u8 {
-    value & 0xFu8
+pub fn to_channel(value: u16, channel: LightChannel) -> u8 {
+    ((value >> channel.shift()) & 0xFu16) as u8
 }
 
 #[inline]
-pub fn to_sunlight(value: u8) -> u8 {
-    (value >> 4) & 0xFu8
+pub fn to_sunlight(value: u16) -> u8 {
+    to_channel(value, LightChannel::Sun)
 }
 
-#[serde_as]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+// Colours are relative to the strongest one so (0, 0, 16, 10) is blue at 10 and no colour is white
+pub fn block_light_levels(light: (u8, u8, u8, u8)) -> [u8; 3] {
+    let (red, green, blue, intensity) = light;
+    let intensity = intensity.min(MAX_LIGHT) as u16;
+    let brightest = red.max(green).max(blue) as u16;
+    if brightest == 0 {
+        return [intensity as u8; 3];
+    }
+    [red, green, blue].map(|colour| (intensity * colour as u16 / brightest) as u8)
+}
+
+#[derive(Clone, Debug)]
 pub struct LightStorage {
-    #[serde_as(as = "Bytes")]
-    lights: Box<[u8; ChunkData::usize()]>,
+    lights: Box<[u16; ChunkData::usize()]>,
 }
 
 impl Default for LightStorage {
@@ -43,33 +90,32 @@ impl LightStorage {
         }
     }
 
-    /// Output contains both torch and sun light
-    pub fn get_light(&self, idx: usize) -> u8 {
+    /// Output contains every channel
+    pub fn get_light(&self, idx: usize) -> u16 {
         self.lights[idx]
     }
 
     /// Output is bounded between 0 and 15
-    pub fn get_torchlight(&self, idx: usize) -> u8 {
-        to_torchlight(self.lights[idx])
+    pub fn get_channel(&self, idx: usize, channel: LightChannel) -> u8 {
+        to_channel(self.lights[idx], channel)
     }
 
     /// Input is bounded between 0 and 15
-    pub fn set_torchlight(&mut self, idx: usize, value: u8) {
+    pub fn set_channel(&mut self, idx: usize, channel: LightChannel, value: u8) {
         debug_assert!(value < 16);
 
-        self.lights[idx] = (self.lights[idx] & 0xF0u8) | value;
+        self.lights[idx] =
+            (self.lights[idx] & !(0xFu16 << channel.shift())) | ((value as u16) << channel.shift());
     }
 
     /// Output is bounded between 0 and 15
     pub fn get_sunlight(&self, idx: usize) -> u8 {
-        to_sunlight(self.lights[idx])
+        self.get_channel(idx, LightChannel::Sun)
     }
 
     /// Input is bounded between 0 and 15
     pub fn set_sunlight(&mut self, idx: usize, value: u8) {
-        debug_assert!(value < 16);
-
-        self.lights[idx] = (self.lights[idx] & 0xFu8) | (value << 4);
+        self.set_channel(idx, LightChannel::Sun, value);
     }
 }
 
@@ -105,6 +151,122 @@ impl VoxelRemovedEvent {
     }
 }
 
+// The voxels on the side of a neighbour that touches the chunk at offset
+fn facing_voxels(offset: IVec3) -> impl Iterator<Item = (u32, u32, u32)> {
+    const MAX: u32 = ChunkData::edge() as u32 - 1;
+    (0..=MAX).flat_map(move |a| {
+        (0..=MAX).map(move |b| match (offset.x, offset.y, offset.z) {
+            (1, _, _) => (0, a, b),
+            (-1, _, _) => (MAX, a, b),
+            (_, 1, _) => (a, 0, b),
+            (_, -1, _) => (a, MAX, b),
+            (_, _, 1) => (a, b, 0),
+            _ => (a, b, MAX),
+        })
+    })
+}
+
+const NEIGHBOR_OFFSETS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+// Which columns of a chunk get full sunlight from above. The chunk above knows exactly, without it
+// only chunks at SKY_HEIGHT and up are open
+fn open_sky(
+    pos: ChunkPos,
+    chunks: &Query<(&ChunkPos, &mut ChunkData)>,
+    loaded_chunks: &CurrentChunks,
+) -> [bool; CHUNK_SIZE * CHUNK_SIZE] {
+    let mut open_sky = [pos.y >= SKY_HEIGHT; CHUNK_SIZE * CHUNK_SIZE];
+    let Some(above) = loaded_chunks
+        .get_entity(ChunkPos(*pos + IVec3::Y))
+        .and_then(|entity| chunks.get(entity).ok())
+    else {
+        return open_sky;
+    };
+    for (column, open) in open_sky.iter_mut().enumerate() {
+        let (x, z) = ((column / CHUNK_SIZE) as u32, (column % CHUNK_SIZE) as u32);
+        *open = above.1.get_light_channel(x, 0, z, LightChannel::Sun) == MAX_LIGHT;
+    }
+    open_sky
+}
+
+// Lights up freshly loaded chunks from the sky and their own light blocks,
+// then pulls in light from the chunks around them. Top down so the chunk above is always lit first
+fn light_new_chunks(
+    added_queues: &mut [VecDeque<LightAddNode>; 4],
+    rem_queues: &mut [VecDeque<LightRemNode>; 4],
+    chunks: &mut Query<(&ChunkPos, &mut ChunkData)>,
+    loaded_chunks: &CurrentChunks,
+    block_table: &BlockTable,
+) {
+    let mut new_chunks: Vec<(Entity, ChunkPos)> = chunks
+        .iter_mut()
+        .filter(|(_, chunk_data)| chunk_data.is_added())
+        .filter_map(|(pos, _)| Some((loaded_chunks.get_entity(*pos)?, *pos)))
+        .collect();
+    new_chunks.sort_by_key(|(_, pos)| -pos.y);
+
+    for (chunk_entity, pos) in new_chunks {
+        let sky = open_sky(pos, chunks, loaded_chunks);
+        if let Ok((_pos, mut chunk_data)) = chunks.get_mut(chunk_entity) {
+            for (channel, idx) in chunk_data.complete_relight(block_table, &sky) {
+                added_queues[channel as usize].push_back(LightAddNode {
+                    idx,
+                    chunk: chunk_entity,
+                });
+            }
+        }
+        // The chunk below took itself to be open when it loaded without this one, any column
+        // this chunk covers up has to go dark again
+        if let Some(below_entity) = loaded_chunks.get_entity(ChunkPos(*pos - IVec3::Y)) {
+            let covered: Vec<(u32, u32)> =
+                match (chunks.get(chunk_entity), chunks.get(below_entity)) {
+                    (Ok((_, chunk_data)), Ok((_, below_data))) => facing_voxels(IVec3::NEG_Y)
+                        .filter(|(x, y, z)| {
+                            chunk_data.get_light_channel(*x, 0, *z, LightChannel::Sun) < MAX_LIGHT
+                                && below_data.get_light_channel(*x, *y, *z, LightChannel::Sun)
+                                    == MAX_LIGHT
+                        })
+                        .map(|(x, _, z)| (x, z))
+                        .collect(),
+                    _ => Vec::new(),
+                };
+            if let Ok((_pos, mut below_data)) = chunks.get_mut(below_entity) {
+                const MAX: u32 = ChunkData::edge() as u32 - 1;
+                for (x, z) in covered {
+                    below_data.set_light_channel(x, MAX, z, LightChannel::Sun, 0);
+                    rem_queues[LightChannel::Sun as usize].push_back(LightRemNode {
+                        idx: ChunkData::linearize(x, MAX, z),
+                        val: MAX_LIGHT,
+                        chunk: below_entity,
+                    });
+                }
+            }
+        }
+        for offset in NEIGHBOR_OFFSETS {
+            let neighbor_pos = ChunkPos(*pos + offset);
+            let Some(neighbor_entity) = loaded_chunks.get_entity(neighbor_pos) else { continue; };
+            let Ok((_pos, neighbor_data)) = chunks.get(neighbor_entity) else { continue; };
+            for (x, y, z) in facing_voxels(offset) {
+                for channel in LightChannel::ALL {
+                    if neighbor_data.get_light_channel(x, y, z, channel) > 1 {
+                        added_queues[channel as usize].push_back(LightAddNode {
+                            idx: ChunkData::linearize(x, y, z),
+                            chunk: neighbor_entity,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
 pub fn propagate_lighting(
     mut commands: Commands,
     mut chunks: Query<(&ChunkPos, &mut ChunkData)>,
@@ -113,71 +275,130 @@ pub fn propagate_lighting(
     mut voxel_rem_event: EventReader<VoxelRemovedEvent>,
     block_table: Res<BlockTable>,
 ) {
-    let mut added_queue = VecDeque::new();
-    let mut rem_queue = VecDeque::new();
+    let mut added_queues: [VecDeque<LightAddNode>; 4] = Default::default();
+    let mut rem_queues: [VecDeque<LightRemNode>; 4] = Default::default();
     let mut changed = HashSet::new();
+    let mut loaded_changed = HashSet::new();
+
+    light_new_chunks(
+        &mut added_queues,
+        &mut rem_queues,
+        &mut chunks,
+        &loaded_chunks,
+        &block_table,
+    );
+    for channel in LightChannel::ALL {
+        handle_removed(
+            &mut added_queues[channel as usize],
+            &mut rem_queues[channel as usize],
+            &mut loaded_changed,
+            &mut chunks,
+            &loaded_chunks,
+            channel,
+        );
+        handle_added(
+            &mut added_queues[channel as usize],
+            &mut loaded_changed,
+            &mut chunks,
+            &loaded_chunks,
+            &block_table,
+            channel,
+        );
+    }
 
     for event in voxel_rem_event.iter() {
         let (local_pos, chunk_pos) = event.pos.to_offsets();
         let Some(chunk_entity) = loaded_chunks.get_entity(chunk_pos) else { continue; };
         let Ok((_pos, mut chunk_data)) = chunks.get_mut(chunk_entity) else { continue; };
 
-        let source_level = chunk_data.get_torchlight(local_pos.x, local_pos.y, local_pos.z);
-        chunk_data.set_torchlight(local_pos.x, local_pos.y, local_pos.z, 0);
+        // Anything the old block gave off is removed, the neighbours then spread back into the gap
+        for channel in LightChannel::ALL {
+            let source_level = channel.get(&chunk_data, local_pos.x, local_pos.y, local_pos.z);
+            channel.set(&mut chunk_data, local_pos.x, local_pos.y, local_pos.z, 0);
 
-        rem_queue.push_back(LightRemNode {
-            idx: ChunkData::linearize(local_pos.x, local_pos.y, local_pos.z),
-            val: source_level,
-            chunk: chunk_entity,
-        });
+            rem_queues[channel as usize].push_back(LightRemNode {
+                idx: ChunkData::linearize(local_pos.x, local_pos.y, local_pos.z),
+                val: source_level,
+                chunk: chunk_entity,
+            });
+        }
     }
 
     for event in voxel_add_event.iter() {
         let (local_pos, chunk_pos) = event.pos.to_offsets();
         let Some(chunk_entity) = loaded_chunks.get_entity(chunk_pos) else { continue; };
         let Ok((_pos, mut chunk_data)) = chunks.get_mut(chunk_entity) else { continue; };
-        let light_val = block_table
-            .get(&chunk_data.get_identifier(local_pos))
-            .unwrap()
-            .light
+        let levels = block_table
+            .descriptor(&chunk_data.get_identifier(local_pos))
+            .and_then(|descriptor| descriptor.light)
+            .map(block_light_levels)
             .unwrap_or_default();
-        if event.value.is_opaque(&block_table) && light_val.3 == 0 {
-            let source_level = chunk_data.get_torchlight(local_pos.x, local_pos.y, local_pos.z);
-            chunk_data.set_torchlight(local_pos.x, local_pos.y, local_pos.z, 0);
-
-            rem_queue.push_back(LightRemNode {
-                idx: ChunkData::linearize(local_pos.x, local_pos.y, local_pos.z),
-                val: source_level,
-                chunk: chunk_entity,
-            });
-        } else if light_val.3 > 0 {
-            chunk_data.set_torchlight(local_pos.x, local_pos.y, local_pos.z, light_val.3);
-            added_queue.push_back(LightAddNode {
-                idx: ChunkData::linearize(local_pos.x, local_pos.y, local_pos.z),
-                chunk: chunk_entity,
-            });
+        let blocks_light = !event.value.is_true_empty(&block_table);
+
+        for channel in LightChannel::ALL {
+            let level = match channel {
+                LightChannel::Red => levels[0],
+                LightChannel::Green => levels[1],
+                LightChannel::Blue => levels[2],
+                LightChannel::Sun => 0,
+            };
+            let source_level = channel.get(&chunk_data, local_pos.x, local_pos.y, local_pos.z);
+            if level > source_level {
+                channel.set(&mut chunk_data, local_pos.x, local_pos.y, local_pos.z, level);
+                added_queues[channel as usize].push_back(LightAddNode {
+                    idx: ChunkData::linearize(local_pos.x, local_pos.y, local_pos.z),
+                    chunk: chunk_entity,
+                });
+            } else if blocks_light {
+                // Whatever passed through is cleared and only the block's own light is left
+                channel.set(&mut chunk_data, local_pos.x, local_pos.y, local_pos.z, level);
+                rem_queues[channel as usize].push_back(LightRemNode {
+                    idx: ChunkData::linearize(local_pos.x, local_pos.y, local_pos.z),
+                    val: source_level,
+                    chunk: chunk_entity,
+                });
+                if level > 0 {
+                    added_queues[channel as usize].push_back(LightAddNode {
+                        idx: ChunkData::linearize(local_pos.x, local_pos.y, local_pos.z),
+                        chunk: chunk_entity,
+                    });
+                }
+            }
         }
     }
 
-    handle_removed(
-        &mut added_queue,
-        &mut rem_queue,
-        &mut changed,
-        &mut chunks,
-        &loaded_chunks,
-    );
-    handle_added(
-        &mut added_queue,
-        &mut changed,
-        &mut chunks,
-        &loaded_chunks,
-        block_table,
-    );
+    for channel in LightChannel::ALL {
+        let (added_queue, rem_queue) = (
+            &mut added_queues[channel as usize],
+            &mut rem_queues[channel as usize],
+        );
+        handle_removed(
+            added_queue,
+            rem_queue,
+            &mut changed,
+            &mut chunks,
+            &loaded_chunks,
+            channel,
+        );
+        handle_added(
+            added_queue,
+            &mut changed,
+            &mut chunks,
+            &loaded_chunks,
+            &block_table,
+            channel,
+        );
+    }
 
     let changed: Vec<ChunkPos> = changed.into_iter().collect();
     for chunk_entity in loaded_chunks.get_unique_loaded_chunks_and_neighbors(&changed) {
         commands.entity(chunk_entity).insert(PriorityMesh);
     }
+    // Chunks that were only lit by loading can wait for the regular mesh queue
+    let loaded_changed: Vec<ChunkPos> = loaded_changed.into_iter().collect();
+    for chunk_entity in loaded_chunks.get_unique_loaded_chunks_and_neighbors(&loaded_changed) {
+        commands.entity(chunk_entity).insert(NeedsMesh);
+    }
 }
 
 fn handle_added(
@@ -185,7 +406,8 @@ fn handle_added(
     changed: &mut HashSet<ChunkPos>,
     chunks: &mut Query<(&ChunkPos, &mut ChunkData)>,
     loaded_chunks: &CurrentChunks,
-    block_table: Res<BlockTable>,
+    block_table: &BlockTable,
+    channel: LightChannel,
 ) {
     while !added_queue.is_empty() {
         let node = added_queue.pop_front().unwrap();
@@ -193,9 +415,15 @@ fn handle_added(
         let (x, y, z) = ChunkData::delinearize(node.idx);
         let (pos, source_level) = {
             let Ok((pos, chunk_data)) = chunks.get(node.chunk) else { continue; };
-            (*pos, chunk_data.get_torchlight(x, y, z))
+            (*pos, channel.get(chunk_data, x, y, z))
         };
         let new_level = source_level.saturating_sub(1);
+        // Full sunlight carries straight down without falling off
+        let below_level = if channel == LightChannel::Sun && source_level == MAX_LIGHT {
+            MAX_LIGHT
+        } else {
+            new_level
+        };
 
         changed.insert(pos);
 
@@ -213,7 +441,8 @@ fn handle_added(
                 z,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
 
             check_neighbor_simple_add(
@@ -225,7 +454,8 @@ fn handle_added(
                 z,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
         } else if x == 0 {
             check_neighbor_complex_add(
@@ -238,7 +468,8 @@ fn handle_added(
                 z,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -251,7 +482,8 @@ fn handle_added(
                 z,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
         } else if x == MAX {
             check_neighbor_complex_add(
@@ -264,7 +496,8 @@ fn handle_added(
                 z,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -277,7 +510,8 @@ fn handle_added(
                 z,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
         }
 
@@ -292,8 +526,9 @@ fn handle_added(
                 y - 1,
                 z,
                 source_level,
-                new_level,
-                &block_table,
+                below_level,
+                block_table,
+                channel,
             );
 
             check_neighbor_simple_add(
@@ -305,7 +540,8 @@ fn handle_added(
                 z,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
         } else if y == 0 {
             check_neighbor_complex_add(
@@ -317,8 +553,9 @@ fn handle_added(
                 MAX,
                 z,
                 source_level,
-                new_level,
-                &block_table,
+                below_level,
+                block_table,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -331,7 +568,8 @@ fn handle_added(
                 z,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
         } else if y == MAX {
             check_neighbor_complex_add(
@@ -344,7 +582,8 @@ fn handle_added(
                 z,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -356,8 +595,9 @@ fn handle_added(
                 y - 1,
                 z,
                 source_level,
-                new_level,
-                &block_table,
+                below_level,
+                block_table,
+                channel,
             );
         }
 
@@ -373,7 +613,8 @@ fn handle_added(
                 z - 1,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
 
             check_neighbor_simple_add(
@@ -385,7 +626,8 @@ fn handle_added(
                 z + 1,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
         } else if z == 0 {
             check_neighbor_complex_add(
@@ -398,7 +640,8 @@ fn handle_added(
                 MAX,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -411,7 +654,8 @@ fn handle_added(
                 z + 1,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
         } else if z == MAX {
             check_neighbor_complex_add(
@@ -424,7 +668,8 @@ fn handle_added(
                 0,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -437,7 +682,8 @@ fn handle_added(
                 z - 1,
                 source_level,
                 new_level,
-                &block_table,
+                block_table,
+                channel,
             );
         }
     }
@@ -454,13 +700,19 @@ fn check_neighbor_simple_add(
     source_level: u8,
     new_level: u8,
     block_table: &BlockTable,
+    channel: LightChannel,
 ) {
-    if chunk_data
-        .get(RelativeVoxelPos(UVec3::new(x, y, z)))
-        .is_true_empty(block_table)
-        && chunk_data.get_torchlight(x, y, z) + 2 < source_level
+    let current_level = channel.get(chunk_data, x, y, z);
+    let is_darker = match channel {
+        LightChannel::Sun => current_level < new_level,
+        _ => current_level + 2 < source_level,
+    };
+    if is_darker
+        && chunk_data
+            .get(RelativeVoxelPos(UVec3::new(x, y, z)))
+            .is_true_empty(block_table)
     {
-        chunk_data.set_torchlight(x, y, z, new_level);
+        channel.set(chunk_data, x, y, z, new_level);
         add_queue.push_back(LightAddNode {
             idx: ChunkData::linearize(x, y, z),
             chunk: chunk_entity,
@@ -480,6 +732,7 @@ fn check_neighbor_complex_add(
     source_level: u8,
     new_level: u8,
     block_table: &BlockTable,
+    channel: LightChannel,
 ) {
     let (chunk_entity, mut chunk_data) = {
         let Some(chunk_entity) = loaded_chunks.get_entity(pos) else { return; };
@@ -497,6 +750,7 @@ fn check_neighbor_complex_add(
         source_level,
         new_level,
         block_table,
+        channel,
     );
 }
 
@@ -506,6 +760,7 @@ fn handle_removed(
     changed: &mut HashSet<ChunkPos>,
     chunks: &mut Query<(&ChunkPos, &mut ChunkData)>,
     loaded_chunks: &CurrentChunks,
+    channel: LightChannel,
 ) {
     while !rem_queue.is_empty() {
         let node = rem_queue.pop_front().unwrap();
@@ -516,6 +771,12 @@ fn handle_removed(
             *pos
         };
         let source_level = node.val;
+        // Full sunlight below a removed source came from it as well
+        let below_level = if channel == LightChannel::Sun && source_level == MAX_LIGHT {
+            source_level + 1
+        } else {
+            source_level
+        };
 
         changed.insert(pos);
 
@@ -533,6 +794,7 @@ fn handle_removed(
                 y,
                 z,
                 source_level,
+                channel,
             );
 
             check_neighbor_simple_rem(
@@ -544,6 +806,7 @@ fn handle_removed(
                 y,
                 z,
                 source_level,
+                channel,
             );
         } else if x == 0 {
             check_neighbor_complex_rem(
@@ -556,6 +819,7 @@ fn handle_removed(
                 y,
                 z,
                 source_level,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -568,6 +832,7 @@ fn handle_removed(
                 y,
                 z,
                 source_level,
+                channel,
             );
         } else if x == MAX {
             check_neighbor_complex_rem(
@@ -580,6 +845,7 @@ fn handle_removed(
                 y,
                 z,
                 source_level,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -592,6 +858,7 @@ fn handle_removed(
                 y,
                 z,
                 source_level,
+                channel,
             );
         }
 
@@ -606,7 +873,8 @@ fn handle_removed(
                 x,
                 y - 1,
                 z,
-                source_level,
+                below_level,
+                channel,
             );
 
             check_neighbor_simple_rem(
@@ -618,6 +886,7 @@ fn handle_removed(
                 y + 1,
                 z,
                 source_level,
+                channel,
             );
         } else if y == 0 {
             check_neighbor_complex_rem(
@@ -629,7 +898,8 @@ fn handle_removed(
                 x,
                 MAX,
                 z,
-                source_level,
+                below_level,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -642,6 +912,7 @@ fn handle_removed(
                 y + 1,
                 z,
                 source_level,
+                channel,
             );
         } else if y == MAX {
             check_neighbor_complex_rem(
@@ -654,6 +925,7 @@ fn handle_removed(
                 0,
                 z,
                 source_level,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -665,7 +937,8 @@ fn handle_removed(
                 x,
                 y - 1,
                 z,
-                source_level,
+                below_level,
+                channel,
             );
         }
 
@@ -681,6 +954,7 @@ fn handle_removed(
                 y,
                 z - 1,
                 source_level,
+                channel,
             );
 
             check_neighbor_simple_rem(
@@ -692,6 +966,7 @@ fn handle_removed(
                 y,
                 z + 1,
                 source_level,
+                channel,
             );
         } else if z == 0 {
             check_neighbor_complex_rem(
@@ -704,6 +979,7 @@ fn handle_removed(
                 y,
                 MAX,
                 source_level,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -716,6 +992,7 @@ fn handle_removed(
                 y,
                 z + 1,
                 source_level,
+                channel,
             );
         } else if z == MAX {
             check_neighbor_complex_rem(
@@ -728,6 +1005,7 @@ fn handle_removed(
                 y,
                 0,
                 source_level,
+                channel,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -740,6 +1018,7 @@ fn handle_removed(
                 y,
                 z - 1,
                 source_level,
+                channel,
             );
         }
     }
@@ -755,10 +1034,11 @@ fn check_neighbor_simple_rem(
     y: u32,
     z: u32,
     source_level: u8,
+    channel: LightChannel,
 ) {
-    let curr_level = chunk_data.get_torchlight(x, y, z);
+    let curr_level = channel.get(chunk_data, x, y, z);
     if curr_level != 0 && curr_level < source_level {
-        chunk_data.set_torchlight(x, y, z, 0);
+        channel.set(chunk_data, x, y, z, 0);
         rem_queue.push_back(LightRemNode {
             idx: ChunkData::linearize(x, y, z),
             chunk: chunk_entity,
@@ -783,6 +1063,7 @@ fn check_neighbor_complex_rem(
     y: u32,
     z: u32,
     source_level: u8,
+    channel: LightChannel,
 ) {
     let (chunk_entity, mut chunk_data) = {
         let Some(chunk_entity) = loaded_chunks.get_entity(pos) else { return; };
@@ -799,6 +1080,7 @@ fn check_neighbor_complex_rem(
         y,
         z,
         source_level,
+        channel,
     );
 }
 
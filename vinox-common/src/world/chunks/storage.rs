@@ -1,3 +1,6 @@
+use std::sync::{OnceLock, RwLock};
+
+use bimap::BiMap;
 use bitvec::prelude::*;
 use rustc_hash::FxHashMap;
 
@@ -9,10 +12,15 @@ use strum::EnumString;
 
 use crate::storage::{
     biomes::descriptor::BiomeDescriptor, blocks::descriptor::BlockDescriptor,
-    crafting::descriptor::RecipeDescriptor, items::descriptor::ItemDescriptor,
+    crafting::descriptor::RecipeDescriptor, feature_rules::descriptor::FeatureRuleDescriptor,
+    features::descriptor::FeatureDescriptor, items::descriptor::ItemDescriptor,
+    structures::descriptor::StructureBlocks,
 };
 
-use super::{light::LightStorage, positions::RelativeVoxelPos};
+use super::{
+    light::{block_light_levels, LightChannel, LightStorage, MAX_LIGHT},
+    positions::RelativeVoxelPos,
+};
 
 pub const HORIZONTAL_DISTANCE: usize = 12;
 pub const VERTICAL_DISTANCE: usize = 8;
@@ -28,12 +36,31 @@ pub struct RecipeTable(pub FxHashMap<String, RecipeDescriptor>);
 #[derive(Resource, Clone, Default, Deref, DerefMut)]
 pub struct BlockTable(pub FxHashMap<String, BlockDescriptor>);
 
+// Blocks without a file, ie from a mod that was removed, look and act like this one but keep their
+// identifier so they come back if it's added again
+pub const UNKNOWN_BLOCK: &str = "vinox:unknown";
+
+impl BlockTable {
+    pub fn descriptor(&self, identifier: &str) -> Option<&BlockDescriptor> {
+        self.get(identifier).or_else(|| self.get(UNKNOWN_BLOCK))
+    }
+}
+
 #[derive(Resource, Clone, Default, Deref, DerefMut)]
 pub struct ItemTable(pub FxHashMap<String, ItemDescriptor>);
 
 #[derive(Resource, Clone, Default, Deref, DerefMut)]
 pub struct BiomeTable(pub FxHashMap<String, BiomeDescriptor>);
 
+#[derive(Resource, Clone, Default, Deref, DerefMut)]
+pub struct FeatureTable(pub FxHashMap<String, FeatureDescriptor>);
+
+#[derive(Resource, Clone, Default, Deref, DerefMut)]
+pub struct FeatureRuleTable(pub FxHashMap<String, FeatureRuleDescriptor>);
+
+#[derive(Resource, Clone, Default, Deref, DerefMut)]
+pub struct StructureTable(pub FxHashMap<String, StructureBlocks>);
+
 #[derive(EnumString, Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone, Copy, Hash)]
 pub enum VoxelVisibility {
     #[default]
@@ -114,7 +141,7 @@ pub struct RenderedBlockData {
     pub textures: [usize; 6],
     pub tex_variance: [bool; 6],
     pub blocks: [bool; 6],
-    pub light: u8,
+    pub light: u16,
 }
 
 pub fn name_to_identifier(namespace: String, name: String) -> String {
@@ -181,7 +208,7 @@ pub struct BlockData {
 impl BlockData {
     pub fn is_empty(&self, block_table: &BlockTable) -> bool {
         block_table
-            .get(&name_to_identifier(
+            .descriptor(&name_to_identifier(
                 self.namespace.clone(),
                 self.name.clone(),
             ))
@@ -192,7 +219,7 @@ impl BlockData {
     }
     pub fn is_opaque(&self, block_table: &BlockTable) -> bool {
         block_table
-            .get(&name_to_identifier(
+            .descriptor(&name_to_identifier(
                 self.namespace.clone(),
                 self.name.clone(),
             ))
@@ -203,7 +230,7 @@ impl BlockData {
     }
     pub fn is_true_empty(&self, block_table: &BlockTable) -> bool {
         let descriptor = block_table
-            .get(&name_to_identifier(
+            .descriptor(&name_to_identifier(
                 self.namespace.clone(),
                 self.name.clone(),
             ))
@@ -241,29 +268,270 @@ impl BlockData {
             ..Default::default()
         }
     }
+
+    pub fn pack(&self, block_registry: &BlockRegistry) -> PackedBlock {
+        let identifier = name_to_identifier(self.namespace.clone(), self.name.clone());
+        PackedBlock {
+            id: block_registry.pack_id(&identifier),
+            direction: self.direction,
+            container: self.container.clone(),
+            growth_state: self.growth_state.clone(),
+            last_tick: self.last_tick,
+            arbitary_data: self.arbitary_data.clone(),
+            top: self.top,
+        }
+    }
+}
+
+pub type BlockId = u16;
+
+// BlockData with the identifier swapped for its id in the world's BlockRegistry
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Default)]
+pub struct PackedBlock {
+    pub id: BlockId,
+    pub direction: Option<Direction>,
+    pub container: Option<Container>,
+    pub growth_state: Option<GrowthState>,
+    pub last_tick: Option<u64>,
+    pub arbitary_data: Option<String>,
+    pub top: Option<bool>,
+}
+
+impl PackedBlock {
+    pub fn unpack(&self, block_registry: &BlockRegistry) -> BlockData {
+        let (namespace, name) = identifier_to_name(block_registry.unpack_identifier(self.id))
+            .unwrap_or_else(|| ("vinox".to_string(), "air".to_string()));
+        BlockData {
+            namespace,
+            name,
+            direction: self.direction,
+            container: self.container.clone(),
+            growth_state: self.growth_state.clone(),
+            last_tick: self.last_tick,
+            arbitary_data: self.arbitary_data.clone(),
+            top: self.top,
+        }
+    }
+}
+
+// Ids are per world and never reused, air is always 0 so a default PackedBlock is air
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Deref)]
+pub struct BlockRegistry(pub BiMap<String, BlockId>);
+
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        let mut ids = BiMap::new();
+        ids.insert("vinox:air".to_string(), 0);
+        Self(ids)
+    }
+}
+
+// Ids a world's registry doesn't have load as vinox:unknown#<id> so they are saved back the same
+pub fn unknown_identifier(id: BlockId) -> String {
+    format!("{UNKNOWN_BLOCK}#{id}")
+}
+
+pub fn unknown_id(identifier: &str) -> Option<BlockId> {
+    identifier
+        .strip_prefix(UNKNOWN_BLOCK)?
+        .strip_prefix('#')?
+        .parse()
+        .ok()
+}
+
+impl BlockRegistry {
+    pub fn get_id(&self, identifier: &str) -> Option<BlockId> {
+        self.get_by_left(identifier).copied()
+    }
+
+    pub fn get_identifier(&self, id: BlockId) -> Option<&String> {
+        self.get_by_right(&id)
+    }
+
+    // The server gives new blocks an id before saving them, anything else without one is sent as
+    // the unknown block
+    pub fn pack_id(&self, identifier: &str) -> BlockId {
+        self.get_id(identifier)
+            .or_else(|| unknown_id(identifier))
+            .unwrap_or_else(|| {
+                warn!("{identifier} has no block id yet, sending it as {UNKNOWN_BLOCK}");
+                self.get_id(UNKNOWN_BLOCK).unwrap_or(0)
+            })
+    }
+
+    pub fn unpack_identifier(&self, id: BlockId) -> String {
+        self.get_identifier(id)
+            .cloned()
+            .unwrap_or_else(|| unknown_identifier(id))
+    }
+
+    pub fn register(&mut self, identifier: String) -> BlockId {
+        if let Some(id) = self.get_id(&identifier) {
+            return id;
+        }
+        let id = self.right_values().max().map_or(0, |id| id + 1);
+        self.0.insert(identifier, id);
+        id
+    }
+
+    // Sorted so a new world gets the same ids for the same blocks, returns what was added
+    pub fn register_all(&mut self, block_table: &BlockTable) -> Vec<(String, BlockId)> {
+        self.register_missing(block_table.keys().cloned())
+    }
+
+    // Also gives an id to blocks placed by scripts or structures that no file has
+    pub fn register_missing(
+        &mut self,
+        identifiers: impl IntoIterator<Item = String>,
+    ) -> Vec<(String, BlockId)> {
+        let mut identifiers = identifiers
+            .into_iter()
+            .filter(|identifier| {
+                !self.contains_left(identifier) && unknown_id(identifier).is_none()
+            })
+            .collect::<Vec<_>>();
+        identifiers.sort();
+        identifiers.dedup();
+        identifiers
+            .into_iter()
+            .map(|identifier| {
+                let id = self.register(identifier.clone());
+                (identifier, id)
+            })
+            .collect()
+    }
+}
+
+// Palettes keep a number for each identifier so comparing blocks doesn't compare strings. Numbers
+// are handed out the first time an identifier is seen and only last while the game runs, saves
+// and messages use the world's BlockRegistry ids instead
+#[derive(Default)]
+struct BlockNames {
+    ids: FxHashMap<String, FxHashMap<String, u32>>,
+    names: Vec<(String, String)>,
+}
+
+impl BlockNames {
+    fn get() -> &'static RwLock<BlockNames> {
+        static BLOCK_NAMES: OnceLock<RwLock<BlockNames>> = OnceLock::new();
+        BLOCK_NAMES.get_or_init(|| {
+            let mut block_names = BlockNames::default();
+            // Air is 0 so a default PaletteBlock is air
+            block_names.insert("vinox", "air");
+            RwLock::new(block_names)
+        })
+    }
+
+    fn insert(&mut self, namespace: &str, name: &str) -> u32 {
+        if let Some(id) = self.ids.get(namespace).and_then(|names| names.get(name)) {
+            return *id;
+        }
+        let id = self.names.len() as u32;
+        self.names.push((namespace.to_string(), name.to_string()));
+        self.ids
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(name.to_string(), id);
+        id
+    }
+
+    fn id(namespace: &str, name: &str) -> u32 {
+        let block_names = Self::get();
+        let id = block_names
+            .read()
+            .unwrap()
+            .ids
+            .get(namespace)
+            .and_then(|names| names.get(name))
+            .copied();
+        id.unwrap_or_else(|| block_names.write().unwrap().insert(namespace, name))
+    }
+
+    fn name(id: u32) -> (String, String) {
+        Self::get().read().unwrap().names[id as usize].clone()
+    }
+}
+
+// BlockData as palettes keep it
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
+struct PaletteBlock {
+    name: u32,
+    direction: Option<Direction>,
+    container: Option<Container>,
+    growth_state: Option<GrowthState>,
+    last_tick: Option<u64>,
+    arbitary_data: Option<String>,
+    top: Option<bool>,
+}
+
+impl From<BlockData> for PaletteBlock {
+    fn from(block: BlockData) -> Self {
+        PaletteBlock {
+            name: BlockNames::id(&block.namespace, &block.name),
+            direction: block.direction,
+            container: block.container,
+            growth_state: block.growth_state,
+            last_tick: block.last_tick,
+            arbitary_data: block.arbitary_data,
+            top: block.top,
+        }
+    }
+}
+
+impl PaletteBlock {
+    fn identifier(&self) -> String {
+        let (namespace, name) = BlockNames::name(self.name);
+        name_to_identifier(namespace, name)
+    }
+
+    fn to_block(&self) -> BlockData {
+        let (namespace, name) = BlockNames::name(self.name);
+        BlockData {
+            namespace,
+            name,
+            direction: self.direction,
+            container: self.container.clone(),
+            growth_state: self.growth_state.clone(),
+            last_tick: self.last_tick,
+            arbitary_data: self.arbitary_data.clone(),
+            top: self.top,
+        }
+    }
+
+    fn pack(&self, block_registry: &BlockRegistry) -> PackedBlock {
+        PackedBlock {
+            id: block_registry.pack_id(&self.identifier()),
+            direction: self.direction,
+            container: self.container.clone(),
+            growth_state: self.growth_state.clone(),
+            last_tick: self.last_tick,
+            arbitary_data: self.arbitary_data.clone(),
+            top: self.top,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub enum Storage {
-    Single(SingleStorage),
-    Multi(MultiStorage),
+pub enum Storage<T = BlockData> {
+    Single(SingleStorage<T>),
+    Multi(MultiStorage<T>),
 }
 
 /// Compressed storage for volumes with a single voxel type
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct SingleStorage {
+pub struct SingleStorage<T = BlockData> {
     size: usize,
-    voxel: BlockData,
+    voxel: T,
 }
 
 /// Palette compressed storage for volumes with multiple voxel types
 /// Based on https://voxel.wiki/wiki/palette-compression/
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct MultiStorage {
+pub struct MultiStorage<T = BlockData> {
     /// Size of chunk storage, in voxels
     size: usize,
     data: BitBuffer,
-    palette: Vec<PaletteEntry>,
+    palette: Vec<PaletteEntry<T>>,
     /// Palette capacity given size of indices
     /// Not necessarily equal to palette vector capacity
     palette_capacity: usize,
@@ -271,8 +539,8 @@ pub struct MultiStorage {
     indices_length: usize,
 }
 
-impl MultiStorage {
-    fn new(size: usize, initial_voxel: BlockData) -> Self {
+impl<T> MultiStorage<T> {
+    fn new(size: usize, initial_voxel: T) -> Self {
         // Indices_length of 2 since this is only used for multiple voxel types
         let indices_length = 2;
         let initial_capacity = 2_usize.pow(indices_length as u32);
@@ -311,11 +579,67 @@ impl MultiStorage {
     }
 }
 
-impl Storage {
+impl<T> Storage<T> {
+    /// Every voxel type that is in use
+    pub fn voxel_types(&self) -> Vec<&T> {
+        match self {
+            Storage::Single(storage) => vec![&storage.voxel],
+            Storage::Multi(storage) => storage
+                .palette
+                .iter()
+                .filter(|entry| entry.ref_count > 0)
+                .map(|entry| &entry.voxel_type)
+                .collect(),
+        }
+    }
+
+    pub fn get_ref(&self, idx: usize) -> &T {
+        match self {
+            Storage::Single(storage) => &storage.voxel,
+            Storage::Multi(storage) => {
+                let palette_idx: usize = storage
+                    .data
+                    .get(idx * storage.indices_length, storage.indices_length);
+
+                &storage
+                    .palette
+                    .get(palette_idx)
+                    .expect("Failed to get palette entry in voxel get")
+                    .voxel_type
+            }
+        }
+    }
+
+    /// Converts every voxel type while keeping the indices, only touches the palette
+    pub fn map_palette<U>(&self, mut f: impl FnMut(&T) -> U) -> Storage<U> {
+        match self {
+            Storage::Single(storage) => Storage::Single(SingleStorage {
+                size: storage.size,
+                voxel: f(&storage.voxel),
+            }),
+            Storage::Multi(storage) => Storage::Multi(MultiStorage {
+                size: storage.size,
+                data: storage.data.clone(),
+                palette: storage
+                    .palette
+                    .iter()
+                    .map(|entry| PaletteEntry {
+                        voxel_type: f(&entry.voxel_type),
+                        ref_count: entry.ref_count,
+                    })
+                    .collect(),
+                palette_capacity: storage.palette_capacity,
+                indices_length: storage.indices_length,
+            }),
+        }
+    }
+}
+
+impl<T: Clone + PartialEq + Default> Storage<T> {
     pub fn new(size: usize) -> Self {
         Self::Single(SingleStorage {
             size,
-            voxel: BlockData::default(),
+            voxel: T::default(),
         })
     }
 
@@ -334,7 +658,7 @@ impl Storage {
         };
     }
 
-    pub fn set(&mut self, target_idx: usize, voxel: BlockData) {
+    pub fn set(&mut self, target_idx: usize, voxel: T) {
         match self {
             Storage::Single(storage) => {
                 if storage.voxel != voxel {
@@ -421,22 +745,8 @@ impl Storage {
         }
     }
 
-    pub fn get(&self, idx: usize) -> BlockData {
-        match self {
-            Storage::Single(storage) => storage.voxel.clone(),
-            Storage::Multi(storage) => {
-                let palette_idx: usize = storage
-                    .data
-                    .get(idx * storage.indices_length, storage.indices_length);
-
-                storage
-                    .palette
-                    .get(palette_idx)
-                    .expect("Failed to get palette entry in voxel get")
-                    .voxel_type
-                    .clone()
-            }
-        }
+    pub fn get(&self, idx: usize) -> T {
+        self.get_ref(idx).clone()
     }
 
     pub fn trim(&mut self) {
@@ -452,8 +762,8 @@ impl Storage {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct PaletteEntry {
-    voxel_type: BlockData,
+struct PaletteEntry<T = BlockData> {
+    voxel_type: T,
     ref_count: usize,
 }
 
@@ -486,12 +796,68 @@ impl BitBuffer {
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct RawChunk {
-    voxels: Storage,
+    #[serde(with = "block_data_palette")]
+    voxels: Storage<PaletteBlock>,
+}
+
+// Serialized with the whole BlockData in every palette entry, the way the first release saved it
+mod block_data_palette {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{BlockData, PaletteBlock, Storage};
+
+    pub fn serialize<S: Serializer>(
+        voxels: &Storage<PaletteBlock>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        voxels
+            .map_palette(PaletteBlock::to_block)
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Storage<PaletteBlock>, D::Error> {
+        let voxels = Storage::<BlockData>::deserialize(deserializer)?;
+        Ok(voxels.map_palette(|voxel| PaletteBlock::from(voxel.clone())))
+    }
+}
+
+impl RawChunk {
+    pub fn pack(&self, block_registry: &BlockRegistry) -> PackedChunk {
+        PackedChunk {
+            voxels: self.voxels.map_palette(|voxel| voxel.pack(block_registry)),
+        }
+    }
+
+    pub fn identifiers(&self) -> Vec<String> {
+        self.voxels
+            .voxel_types()
+            .into_iter()
+            .map(PaletteBlock::identifier)
+            .collect()
+    }
+}
+
+// What actually gets saved and sent, the palette only holds ids
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PackedChunk {
+    voxels: Storage<PackedBlock>,
+}
+
+impl PackedChunk {
+    pub fn unpack(&self, block_registry: &BlockRegistry) -> RawChunk {
+        RawChunk {
+            voxels: self
+                .voxels
+                .map_palette(|voxel| PaletteBlock::from(voxel.unpack(block_registry))),
+        }
+    }
 }
 
 #[derive(Component, Clone, Debug)]
 pub struct ChunkData {
-    voxels: Storage,
+    voxels: Storage<PaletteBlock>,
     lights: LightStorage,
     change_count: u16,
     dirty: bool,
@@ -512,21 +878,20 @@ impl Default for ChunkData {
 impl ChunkData {
     pub fn get(&self, voxel_pos: RelativeVoxelPos) -> BlockData {
         self.voxels
-            .get(Self::linearize(voxel_pos.x, voxel_pos.y, voxel_pos.z))
+            .get_ref(Self::linearize(voxel_pos.x, voxel_pos.y, voxel_pos.z))
+            .to_block()
     }
 
     pub fn get_identifier(&self, voxel_pos: RelativeVoxelPos) -> String {
-        let voxel = self
-            .voxels
-            .get(Self::linearize(voxel_pos.x, voxel_pos.y, voxel_pos.z));
-
-        name_to_identifier(voxel.namespace, voxel.name)
+        self.voxels
+            .get_ref(Self::linearize(voxel_pos.x, voxel_pos.y, voxel_pos.z))
+            .identifier()
     }
 
     pub fn set(&mut self, voxel_pos: RelativeVoxelPos, voxel: BlockData) {
         self.voxels.set(
             Self::linearize(voxel_pos.x, voxel_pos.y, voxel_pos.z),
-            voxel,
+            PaletteBlock::from(voxel),
         );
         self.change_count += 1;
         self.set_dirty(true);
@@ -543,29 +908,39 @@ impl ChunkData {
             Storage::Multi(_) => false,
         }
     }
-    pub fn complete_relight(&mut self, _block_table: &BlockTable) -> ChunkData {
-        // for x in 0..CHUNK_SIZE {
-        //     for y in 0..CHUNK_SIZE {
-        //         for z in 0..CHUNK_SIZE {
-        //             let index = Self::linearize(x, y, z);
-        //             if let Some(block) = block_table.get(&self.get_identifier(x, y, z)) {
-        //                 if let Some(light) = block.light {
-        //                     self.set_light(
-        //                         index,
-        //                         LightData {
-        //                             r: light.0,
-        //                             g: light.1,
-        //                             b: light.2,
-        //                             a: light.3,
-        //                         },
-        //                     );
-        //                 }
-        //             }
-        //         }
-        //     }
-        // }
-        // self.calculate_all_light(block_table);
-        self.clone()
+    // Fills the open columns, x * CHUNK_SIZE + z, straight down from the sky and lights up light
+    // blocks, the returned voxels still have to spread
+    pub fn complete_relight(
+        &mut self,
+        block_table: &BlockTable,
+        open_sky: &[bool; CHUNK_SIZE * CHUNK_SIZE],
+    ) -> Vec<(LightChannel, usize)> {
+        let mut lit = Vec::new();
+        for x in 0..CHUNK_SIZE as u32 {
+            for z in 0..CHUNK_SIZE as u32 {
+                let mut sky = open_sky[x as usize * CHUNK_SIZE + z as usize];
+                for y in (0..CHUNK_SIZE as u32).rev() {
+                    let block = self.get(RelativeVoxelPos(UVec3::new(x, y, z)));
+                    let identifier =
+                        name_to_identifier(block.namespace.clone(), block.name.clone());
+                    let Some(descriptor) = block_table.descriptor(&identifier) else { continue; };
+                    sky = sky && block.is_true_empty(block_table);
+                    let levels = descriptor.light.map(block_light_levels).unwrap_or_default();
+                    for (channel, level) in [
+                        (LightChannel::Red, levels[0]),
+                        (LightChannel::Green, levels[1]),
+                        (LightChannel::Blue, levels[2]),
+                        (LightChannel::Sun, if sky { MAX_LIGHT } else { 0 }),
+                    ] {
+                        if level > 0 {
+                            self.set_light_channel(x, y, z, channel, level);
+                            lit.push((channel, Self::linearize(x, y, z)));
+                        }
+                    }
+                }
+            }
+        }
+        lit
     }
     pub fn is_empty(&self, block_table: &BlockTable) -> bool {
         self.is_uniform()
@@ -624,17 +999,28 @@ impl ChunkData {
         }
     }
 
-    pub fn get_light(&self, x: u32, y: u32, z: u32) -> u8 {
+    pub fn get_light(&self, x: u32, y: u32, z: u32) -> u16 {
         self.lights.get_light(Self::linearize(x, y, z))
     }
 
     /// Output is bounded between 0 and 15
-    pub fn get_torchlight(&self, x: u32, y: u32, z: u32) -> u8 {
-        self.lights.get_torchlight(Self::linearize(x, y, z))
+    pub fn get_light_channel(&self, x: u32, y: u32, z: u32, channel: LightChannel) -> u8 {
+        self.lights.get_channel(Self::linearize(x, y, z), channel)
+    }
+
+    /// Input is bounded between 0 and 15
+    pub fn set_light_channel(&mut self, x: u32, y: u32, z: u32, channel: LightChannel, value: u8) {
+        self.lights
+            .set_channel(Self::linearize(x, y, z), channel, value);
+    }
+
+    /// Output is bounded between 0 and 15
+    pub fn get_sunlight(&self, x: u32, y: u32, z: u32) -> u8 {
+        self.lights.get_sunlight(Self::linearize(x, y, z))
     }
 
     /// Input is bounded between 0 and 15
-    pub fn set_torchlight(&mut self, x: u32, y: u32, z: u32, value: u8) {
-        self.lights.set_torchlight(Self::linearize(x, y, z), value);
+    pub fn set_sunlight(&mut self, x: u32, y: u32, z: u32, value: u8) {
+        self.lights.set_sunlight(Self::linearize(x, y, z), value);
     }
 }
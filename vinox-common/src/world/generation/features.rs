@@ -0,0 +1,526 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    storage::{
+        biomes::descriptor::BiomeDescriptor,
+        feature_rules::descriptor::{FeatureRuleDescriptor, FeatureRuleType, ListType},
+        features::descriptor::FeatureType,
+    },
+    world::chunks::{
+        positions::{RelativeVoxelPos, VoxelPos},
+        storage::{
+            name_to_identifier, BiomeTable, BlockData, BlockTable, ChunkData, FeatureRuleTable,
+            FeatureTable, RelativeVoxelAxis, StructureTable, VoxelAxis, CHUNK_SIZE,
+        },
+    },
+};
+
+use super::terrain::{chunk_biome, generate_chunk, BiomeHashmap, BiomeTree, ToBePlaced};
+
+const NEIGHBOURS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+const DEFAULT_VEIN_SIZE: u8 = 8;
+const BLOB_RADIUS: i32 = 2;
+
+fn in_chunk(local: IVec3) -> bool {
+    local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::splat(CHUNK_SIZE as i32)).all()
+}
+
+fn in_list(identifier: &str, list: &[BlockData]) -> bool {
+    list.iter()
+        .any(|block| name_to_identifier(block.namespace.clone(), block.name.clone()) == identifier)
+}
+
+fn axis_direction(axis: &VoxelAxis) -> IVec3 {
+    match axis {
+        VoxelAxis::PosX => IVec3::X,
+        VoxelAxis::NegX => IVec3::NEG_X,
+        VoxelAxis::PosY => IVec3::Y,
+        VoxelAxis::NegY => IVec3::NEG_Y,
+        VoxelAxis::PosZ => IVec3::Z,
+        VoxelAxis::NegZ => IVec3::NEG_Z,
+    }
+}
+
+fn relative_axis_direction(axis: &RelativeVoxelAxis) -> IVec3 {
+    match axis {
+        RelativeVoxelAxis::Front => IVec3::NEG_Z,
+        RelativeVoxelAxis::Back => IVec3::Z,
+        RelativeVoxelAxis::Left => IVec3::NEG_X,
+        RelativeVoxelAxis::Right => IVec3::X,
+        RelativeVoxelAxis::Up => IVec3::Y,
+        RelativeVoxelAxis::Down => IVec3::NEG_Y,
+    }
+}
+
+// Positions are relative to the chunk being decorated but are allowed to go outside of it.
+// We can only look at blocks inside the chunk since the neighbours might not exist yet, so anything
+// that has to check what it replaces stays inside and anything else spills into ToBePlaced
+struct Decorator<'a> {
+    chunk: &'a mut ChunkData,
+    pos: IVec3,
+    rng: StdRng,
+    spill: ToBePlaced,
+    feature_table: &'a FeatureTable,
+    structure_table: &'a StructureTable,
+}
+
+impl<'a> Decorator<'a> {
+    fn get(&self, local: IVec3) -> Option<String> {
+        in_chunk(local).then(|| {
+            self.chunk
+                .get_identifier(RelativeVoxelPos(local.as_uvec3()))
+        })
+    }
+
+    fn is_air(&self, local: IVec3) -> bool {
+        self.get(local)
+            .map_or(false, |identifier| identifier == "vinox:air")
+    }
+
+    fn is_solid(&self, local: IVec3) -> bool {
+        self.get(local)
+            .map_or(false, |identifier| identifier != "vinox:air")
+    }
+
+    fn supported_by(&self, local: IVec3, valid_placements: &Option<Vec<BlockData>>) -> bool {
+        match self.get(local) {
+            Some(identifier) if identifier != "vinox:air" => valid_placements
+                .as_ref()
+                .map_or(true, |list| in_list(&identifier, list)),
+            _ => false,
+        }
+    }
+
+    fn set(&mut self, local: IVec3, block: BlockData) {
+        if in_chunk(local) {
+            self.chunk.set(RelativeVoxelPos(local.as_uvec3()), block);
+        } else {
+            let (relative_pos, chunk_pos) =
+                VoxelPos(local + self.pos * CHUNK_SIZE as i32).to_offsets();
+            self.spill
+                .entry(*chunk_pos)
+                .or_default()
+                .push((*relative_pos, block));
+        }
+    }
+
+    fn random_pos(&mut self) -> IVec3 {
+        IVec3::new(
+            self.rng.gen_range(0..CHUNK_SIZE as i32),
+            self.rng.gen_range(0..CHUNK_SIZE as i32),
+            self.rng.gen_range(0..CHUNK_SIZE as i32),
+        )
+    }
+
+    fn choose(&mut self, blocks: &[(BlockData, u16)]) -> Option<BlockData> {
+        blocks
+            .choose_weighted(&mut self.rng, |item| item.1)
+            .ok()
+            .map(|item| item.0.clone())
+    }
+
+    fn column_height(&mut self, heights: &[((u8, u8), u16)]) -> u8 {
+        let Ok(((min, max), _)) = heights.choose_weighted(&mut self.rng, |item| item.1) else {
+            return 0;
+        };
+        self.rng.gen_range(*min..=*max.max(min))
+    }
+
+    // Grows through air until it hits something solid, returns where the column ended
+    fn column(
+        &mut self,
+        origin: IVec3,
+        direction: u8,
+        height: u8,
+        body_blocks: &[(BlockData, u16)],
+        head_block: Option<&[(BlockData, u16)]>,
+    ) -> Option<IVec3> {
+        let step = if direction == 0 {
+            IVec3::NEG_Y
+        } else {
+            IVec3::Y
+        };
+        let mut positions = Vec::new();
+        for offset in 0..height as i32 {
+            let current = origin + step * offset;
+            if in_chunk(current) && !self.is_air(current) {
+                break;
+            }
+            positions.push(current);
+        }
+        let last = positions.pop()?;
+        for current in positions {
+            if let Some(block) = self.choose(body_blocks) {
+                self.set(current, block);
+            }
+        }
+        if let Some(block) = self.choose(head_block.unwrap_or(body_blocks)) {
+            self.set(last, block);
+        }
+        Some(last)
+    }
+
+    fn place_feature(&mut self, feature_type: &FeatureType, origin: IVec3) -> bool {
+        match feature_type {
+            FeatureType::SingleBlock {
+                places,
+                valid_placements,
+                can_replace,
+            } => {
+                let Some(target) = self.get(origin) else {
+                    return false;
+                };
+                let replaceable = match can_replace {
+                    Some(list) => in_list(&target, list),
+                    None => target == "vinox:air",
+                };
+                let supported = valid_placements.is_none()
+                    || self.supported_by(origin - IVec3::Y, valid_placements);
+                if replaceable && supported {
+                    self.set(origin, places.clone());
+                }
+                replaceable && supported
+            }
+            FeatureType::Vein {
+                amount,
+                can_replace,
+                places,
+            } => {
+                let mut placed = false;
+                let mut current = origin;
+                for _ in 0..amount.unwrap_or(DEFAULT_VEIN_SIZE) {
+                    if let Some(target) = self.get(current) {
+                        let replaceable = match can_replace {
+                            Some(list) => in_list(&target, list),
+                            None => target != "vinox:air",
+                        };
+                        if replaceable {
+                            self.set(current, places.clone());
+                            placed = true;
+                        }
+                    }
+                    current += *NEIGHBOURS.choose(&mut self.rng).unwrap();
+                }
+                placed
+            }
+            FeatureType::Column {
+                direction,
+                heights,
+                body_blocks,
+            } => {
+                let height = self.column_height(heights);
+                self.column(origin, *direction, height, body_blocks, None)
+                    .is_some()
+            }
+            FeatureType::ColumnHead {
+                direction,
+                heights,
+                head_block,
+                body_blocks,
+            } => {
+                let height = self.column_height(heights);
+                self.column(origin, *direction, height, body_blocks, Some(head_block))
+                    .is_some()
+            }
+            FeatureType::CappedColumn {
+                direction,
+                heights,
+                cap_block,
+                body_blocks,
+                cap_radius,
+            } => {
+                let height = self.column_height(heights);
+                let Some(end) =
+                    self.column(origin, *direction, height, body_blocks, Some(cap_block))
+                else {
+                    return false;
+                };
+                let radius = *cap_radius as i32;
+                for x in -radius..=radius {
+                    for z in -radius..=radius {
+                        let current = end + IVec3::new(x, 0, z);
+                        if (x * x + z * z) > radius * radius
+                            || (in_chunk(current) && !self.is_air(current))
+                        {
+                            continue;
+                        }
+                        if let Some(block) = self.choose(cap_block) {
+                            self.set(current, block);
+                        }
+                    }
+                }
+                true
+            }
+            FeatureType::FaceFeature {
+                places,
+                range,
+                ceiling,
+                floor,
+                wall,
+                valid_placements,
+            } => {
+                let mut placed = false;
+                let range = *range as i32;
+                for x in -range..=range {
+                    for y in -range..=range {
+                        for z in -range..=range {
+                            let current = origin + IVec3::new(x, y, z);
+                            if !self.is_air(current) {
+                                continue;
+                            }
+                            let on_floor =
+                                *floor && self.supported_by(current - IVec3::Y, valid_placements);
+                            let on_ceiling =
+                                *ceiling && self.supported_by(current + IVec3::Y, valid_placements);
+                            let on_wall = *wall
+                                && [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z].iter().any(
+                                    |side| self.supported_by(current + *side, valid_placements),
+                                );
+                            if on_floor || on_ceiling || on_wall {
+                                self.set(current, places.clone());
+                                placed = true;
+                            }
+                        }
+                    }
+                }
+                placed
+            }
+            FeatureType::ExposedBlob { places, exposed } => {
+                let mut placed = false;
+                let direction = relative_axis_direction(exposed);
+                for x in -BLOB_RADIUS..=BLOB_RADIUS {
+                    for y in -BLOB_RADIUS..=BLOB_RADIUS {
+                        for z in -BLOB_RADIUS..=BLOB_RADIUS {
+                            let offset = IVec3::new(x, y, z);
+                            let current = origin + offset;
+                            if offset.length_squared() <= BLOB_RADIUS * BLOB_RADIUS
+                                && self.is_solid(current)
+                                && self.is_air(current + direction)
+                            {
+                                self.set(current, places.clone());
+                                placed = true;
+                            }
+                        }
+                    }
+                }
+                placed
+            }
+            // Structures go wherever the rule puts them and spill over like everything else
+            FeatureType::Structure { structure } => {
+                let structure_table = self.structure_table;
+                let Some(structure) = structure_table.get(structure) else {
+                    warn!("Feature uses missing structure {structure}");
+                    return false;
+                };
+                let rotation = if structure.rotate.unwrap_or(false) {
+                    self.rng.gen_range(0..4)
+                } else {
+                    0
+                };
+                let mirror = structure.mirror.unwrap_or(false) && self.rng.gen_bool(0.5);
+                for (pos, block) in structure.transformed_blocks(rotation, mirror) {
+                    self.set(origin + pos, block);
+                }
+                true
+            }
+        }
+    }
+
+    // Ordered tries each feature in turn until one fits, Unordered does the same in a random order
+    // and Weighted (the default) only tries one
+    fn place_list(&mut self, rule: &FeatureRuleDescriptor, origin: IVec3) -> bool {
+        let mut features = match rule.list_type.as_ref().unwrap_or(&ListType::Weighted) {
+            ListType::Weighted => rule
+                .feature
+                .choose_weighted(&mut self.rng, |item| item.1)
+                .map(|item| vec![item.0.clone()])
+                .unwrap_or_default(),
+            ListType::Ordered | ListType::Unordered => {
+                rule.feature.iter().map(|item| item.0.clone()).collect()
+            }
+        };
+        if rule.list_type == Some(ListType::Unordered) {
+            features.shuffle(&mut self.rng);
+        }
+        for identifier in features {
+            let feature_table = self.feature_table;
+            let Some(feature) = feature_table.get(&identifier) else {
+                warn!(
+                    "Feature rule {} uses missing feature {identifier}",
+                    rule.name
+                );
+                continue;
+            };
+            if self.place_feature(&feature.feature_type, origin) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn run_rule(&mut self, rule: &FeatureRuleDescriptor) {
+        match &rule.feature_type {
+            // Chance is out of 100 for each iteration
+            FeatureRuleType::Scatter { iterations, chance } => {
+                for _ in 0..*iterations {
+                    let origin = self.random_pos();
+                    if self.rng.gen_range(0..100) < *chance {
+                        self.place_list(rule, origin);
+                    }
+                }
+            }
+            // Walks every line through the box along the axis and uses the first air block after a
+            // solid one, ie PosY finds floors. Nothing is placed unless there are enough spots
+            FeatureRuleType::Search {
+                start,
+                end,
+                axis,
+                min_placements,
+            } => {
+                let start = IVec3::new(start.0 as i32, start.1 as i32, start.2 as i32);
+                let end = IVec3::new(end.0 as i32, end.1 as i32, end.2 as i32);
+                let (min, max) = (
+                    start.min(end).max(IVec3::ZERO),
+                    start.max(end).min(IVec3::splat(CHUNK_SIZE as i32 - 1)),
+                );
+                let step = axis_direction(axis);
+                let mut spots = Vec::new();
+                for x in min.x..=max.x {
+                    for y in min.y..=max.y {
+                        for z in min.z..=max.z {
+                            let current = IVec3::new(x, y, z);
+                            let previous = current - step;
+                            // Only start lines on the face of the box
+                            if previous.cmpge(min).all() && previous.cmple(max).all() {
+                                continue;
+                            }
+                            let mut solid = false;
+                            let mut current = current;
+                            while current.cmpge(min).all() && current.cmple(max).all() {
+                                if solid && self.is_air(current) {
+                                    spots.push(current);
+                                    break;
+                                }
+                                solid = self.is_solid(current);
+                                current += step;
+                            }
+                        }
+                    }
+                }
+                if spots.len() < *min_placements as usize {
+                    return;
+                }
+                for origin in spots {
+                    self.place_list(rule, origin);
+                }
+            }
+            // Drops (or rises) from a random air block onto the first surface within range
+            FeatureRuleType::Snap { range, ceiling } => {
+                let step = if *ceiling { IVec3::Y } else { IVec3::NEG_Y };
+                let mut current = self.random_pos();
+                for _ in 0..*range {
+                    if !self.is_air(current) {
+                        return;
+                    }
+                    if self.is_solid(current + step) {
+                        self.place_list(rule, current);
+                        return;
+                    }
+                    current += step;
+                }
+            }
+        }
+    }
+}
+
+// Runs after generate_chunk, anything that ended up outside of the chunk is returned so it can be
+// placed once the chunk it belongs to exists
+pub fn decorate_chunk(
+    chunk: &mut ChunkData,
+    pos: IVec3,
+    seed: u32,
+    biome: &BiomeDescriptor,
+    feature_rule_table: &FeatureRuleTable,
+    feature_table: &FeatureTable,
+    structure_table: &StructureTable,
+) -> ToBePlaced {
+    let mut decorator = Decorator {
+        chunk,
+        pos,
+        rng: SeedableRng::seed_from_u64(pos.reflect_hash().unwrap() ^ seed as u64),
+        spill: ToBePlaced::default(),
+        feature_table,
+        structure_table,
+    };
+    for identifier in biome.feature_rules.iter().flatten() {
+        let Some(rule) = feature_rule_table.get(identifier) else {
+            warn!(
+                "Biome {} uses missing feature rule {identifier}",
+                biome.name
+            );
+            continue;
+        };
+        decorator.run_rule(rule);
+    }
+    decorator.spill
+}
+
+// Spill only goes into empty blocks so it never replaces terrain or another feature's blocks and
+// ends up the same whichever order chunks generate in. Returns whether anything was placed
+pub fn place_spill(
+    chunk: &mut ChunkData,
+    blocks: impl IntoIterator<Item = (UVec3, BlockData)>,
+    block_table: &BlockTable,
+) -> bool {
+    let mut placed = false;
+    for (relative_pos, block) in blocks {
+        let relative_pos = RelativeVoxelPos(relative_pos);
+        if chunk.get(relative_pos).is_empty(block_table) {
+            chunk.set(relative_pos, block);
+            placed = true;
+        }
+    }
+    placed
+}
+
+// A chunk the way the world generates it on its own, blocks spilling out of it are returned
+#[allow(clippy::too_many_arguments)]
+pub fn generate_decorated_chunk(
+    pos: IVec3,
+    seed: u32,
+    biome_table: &BiomeTable,
+    biome_hashmap: &BiomeHashmap,
+    biome_tree: &BiomeTree,
+    feature_rule_table: &FeatureRuleTable,
+    feature_table: &FeatureTable,
+    structure_table: &StructureTable,
+) -> (ChunkData, ToBePlaced) {
+    let mut chunk = ChunkData::from_raw(generate_chunk(
+        pos,
+        seed,
+        biome_table,
+        biome_hashmap,
+        biome_tree,
+    ));
+    let biome = biome_table
+        .get(&chunk_biome(pos, seed, biome_hashmap, biome_tree))
+        .unwrap();
+    let spill = decorate_chunk(
+        &mut chunk,
+        pos,
+        seed,
+        biome,
+        feature_rule_table,
+        feature_table,
+        structure_table,
+    );
+    (chunk, spill)
+}
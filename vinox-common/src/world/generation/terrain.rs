@@ -10,103 +10,79 @@ use bracket_noise::prelude::*;
 //     NoiseFn, OpenSimplex, RidgedMulti, RotatePoint, Worley,
 // };
 use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
-use std::{
-    collections::HashMap,
-};
+use std::collections::HashMap;
 // use rand::{rngs::StdRng, Rng, SeedableRng};
-use serde::{Deserialize, Serialize};
-use vinox_common::{
-    world::chunks::{
-        positions::RelativeVoxelPos,
-        storage::{
-            identifier_to_name, BiomeTable, BlockData, ChunkData, RawChunk, CHUNK_SIZE,
-        },
-    },
+use crate::world::chunks::{
+    positions::RelativeVoxelPos,
+    storage::{identifier_to_name, BiomeTable, BlockData, ChunkData, RawChunk, CHUNK_SIZE},
 };
+use serde::{Deserialize, Serialize};
 
 #[derive(Resource, Default, Serialize, Deserialize, Deref, DerefMut, Clone)]
 pub struct ToBePlaced(pub HashMap<IVec3, Vec<(UVec3, BlockData)>>);
 
+// Heat, humidity and depth bias as biome_depth has it
 #[derive(Resource, Default, Deref, DerefMut, Clone)]
-pub struct BiomeHashmap(pub HashMap<IVec2, String>);
+pub struct BiomeHashmap(pub HashMap<IVec3, String>);
 
 #[derive(Resource, Default, Deref, DerefMut, Clone)]
-pub struct BiomeTree(pub ExhaustiveSearch<Euclidean<[i32; 2]>>);
+pub struct BiomeTree(pub ExhaustiveSearch<Euclidean<[i32; 3]>>);
 
-// Just some interesting stuff to look at while testing
-#[allow(clippy::type_complexity)]
-pub fn add_surface(
-    raw_chunk: &mut ChunkData,
-    pos: IVec3,
-    block_types: Vec<(BlockData, i32)>,
-    rng: &mut StdRng,
-) {
-    for z in 0..=CHUNK_SIZE - 1 {
-        for y in 0..=CHUNK_SIZE - 1 {
-            for x in 0..=CHUNK_SIZE - 1 {
-                let (x, y, z) = (x as u32, y as u32, z as u32);
-                let relative_pos = RelativeVoxelPos(UVec3::new(x, y, z));
-                if y == CHUNK_SIZE as u32 - 1 {
-                    let _full_x = x as i32 + ((CHUNK_SIZE as i32) * pos.x);
-                    let _full_z = z as i32 + ((CHUNK_SIZE as i32) * pos.z);
-                    let _full_y = y as i32 + ((CHUNK_SIZE as i32) * pos.y) + 1;
-                    if raw_chunk.get_identifier(relative_pos) != "vinox:air" {
-                        // We need to add a vec for adding blocks in a new chunk when out of range
-                        // raw_chunk.set(x, y, z, grass, block_table);
-                    }
-                } else if raw_chunk.get_identifier(RelativeVoxelPos::new(x, y + 1, z))
-                    == "vinox:air"
-                    && raw_chunk.get_identifier(relative_pos) != "vinox:air"
-                {
-                    raw_chunk.set(
-                        relative_pos,
-                        block_types
-                            .choose_weighted(rng, |item| item.1)
-                            .unwrap()
-                            .clone()
-                            .0,
-                    );
-                }
-            }
-        }
-    }
+// Everything solid that isn't a cave. Kept separate so we can sample past the edges of a chunk
+struct TerrainNoise {
+    ridged_noise: FastNoise,
+    d_noise: FastNoise,
+    a_noise: FastNoise,
 }
-#[allow(clippy::type_complexity)]
-pub fn add_ceiling(
-    raw_chunk: &mut ChunkData,
-    pos: IVec3,
-    block_types: Vec<(BlockData, i32)>,
-    rng: &mut StdRng,
-) {
-    for z in 0..=CHUNK_SIZE - 1 {
-        for y in 0..=CHUNK_SIZE - 1 {
-            for x in 0..=CHUNK_SIZE - 1 {
-                let (x, y, z) = (x as u32, y as u32, z as u32);
-                let relative_pos = RelativeVoxelPos(UVec3::new(x, y, z));
-                if y == 0 {
-                    let _full_x = x as i32 + ((CHUNK_SIZE as i32) * pos.x);
-                    let _full_z = z as i32 + ((CHUNK_SIZE as i32) * pos.z);
-                    let _full_y = y as i32 + ((CHUNK_SIZE as i32) * pos.y) + 1;
-                    if raw_chunk.get_identifier(relative_pos) != "vinox:air" {
-                        // We need to add a vec for adding blocks in a new chunk when out of range
-                        // raw_chunk.set(x, y, z, grass, block_table);
-                    }
-                } else if raw_chunk.get_identifier(RelativeVoxelPos::new(x, y - 1, z))
-                    == "vinox:air"
-                    && raw_chunk.get_identifier(relative_pos) != "vinox:air"
-                {
-                    raw_chunk.set(
-                        relative_pos,
-                        block_types
-                            .choose_weighted(rng, |item| item.1)
-                            .unwrap()
-                            .clone()
-                            .0,
-                    );
-                }
-            }
+
+impl TerrainNoise {
+    fn new(seed: u32) -> Self {
+        let mut ridged_noise = FastNoise::seeded(seed as u64);
+        let mut a_noise = FastNoise::seeded(seed as u64);
+        let mut d_noise = FastNoise::seeded(seed.wrapping_add(1) as u64);
+        ridged_noise.set_noise_type(NoiseType::SimplexFractal);
+        ridged_noise.set_fractal_octaves(8);
+        ridged_noise.set_frequency(0.0025122);
+        d_noise.set_noise_type(NoiseType::Perlin);
+        d_noise.set_fractal_octaves(4);
+        d_noise.set_frequency(0.01881);
+        a_noise.set_noise_type(NoiseType::PerlinFractal);
+        a_noise.set_fractal_octaves(3);
+        ridged_noise.set_frequency(0.02);
+        Self {
+            ridged_noise,
+            d_noise,
+            a_noise,
         }
     }
+
+    fn is_solid(&self, full_x: i32, full_y: i32, full_z: i32) -> bool {
+        let (x, y, z) = (full_x as f32, full_y as f32, full_z as f32);
+        let is_cave = self.ridged_noise.get_noise3d(x, y, z).abs() < 0.1
+            && self.d_noise.get_noise3d(x, y, z).abs() < 0.1
+            && (self.a_noise.get_noise3d(x, y, z) < 0.45);
+        !is_cave
+    }
+}
+
+fn choose_block(blocks: &[(String, u16)], rng: &mut StdRng) -> BlockData {
+    let identifier = blocks
+        .choose_weighted(rng, |item| item.1)
+        .unwrap()
+        .clone()
+        .0;
+    let (namespace, name) = identifier_to_name(identifier).unwrap();
+    BlockData::new(namespace, name)
+}
+
+// Solid with air within depth above it. Column starts ceil_depth below the chunk
+fn is_surface(column: &[bool], idx: usize, depth: usize) -> bool {
+    (1..=depth).any(|offset| !column[idx + offset])
+}
+
+// Solid with air within depth below it
+fn is_ceiling(column: &[bool], idx: usize, depth: usize) -> bool {
+    (1..=depth).any(|offset| !column[idx - offset])
 }
 
 // fn world_noise(seed: u32) -> impl NoiseFn<f64, 3> {
@@ -137,13 +113,25 @@ pub fn add_ceiling(
 //     )
 // }
 
+// Heat and humidity come out of the noise between -100 and 100, heights are squashed into the same
+// range so being a few blocks up or down can't outweigh them. Anything this many blocks away from 0
+// is as high or as deep as it gets
+pub const BIOME_DEPTH_RANGE: i32 = 256;
+
+// Biomes are keyed by this of their depth_bias and looked up by this of the height
+pub fn biome_depth(blocks: i32) -> i32 {
+    blocks.clamp(-BIOME_DEPTH_RANGE, BIOME_DEPTH_RANGE) * 100 / BIOME_DEPTH_RANGE
+}
+
+// Depth is the height in blocks so biomes with a higher depth_bias show up higher in the world
 fn values_to_biome(
     heat: i32,
     moisture: i32,
+    depth: i32,
     biome_hashmap: &BiomeHashmap,
     biome_tree: &BiomeTree,
 ) -> String {
-    if let Some(nearest) = biome_tree.0.nearest(&[heat, moisture]) {
+    if let Some(nearest) = biome_tree.0.nearest(&[heat, moisture, biome_depth(depth)]) {
         return biome_hashmap
             .get(&(*nearest.item.inner()).into())
             .unwrap()
@@ -152,7 +140,29 @@ fn values_to_biome(
     "vinox:stone".to_string()
 }
 
-fn biome_noise(x: f32, y: f32, z: f32, seed: u32) -> (i32, i32) {
+// The whole chunk shares a biome so decoration can look it up again without regenerating
+pub fn chunk_biome(
+    pos: IVec3,
+    seed: u32,
+    biome_hashmap: &BiomeHashmap,
+    biome_tree: &BiomeTree,
+) -> String {
+    let (heat, humidity) = biome_noise(
+        pos.x as f32 * CHUNK_SIZE as f32,
+        pos.y as f32 * CHUNK_SIZE as f32,
+        pos.z as f32 * CHUNK_SIZE as f32,
+        seed,
+    );
+    values_to_biome(
+        heat,
+        humidity,
+        pos.y * CHUNK_SIZE as i32 + CHUNK_SIZE as i32 / 2,
+        biome_hashmap,
+        biome_tree,
+    )
+}
+
+pub fn biome_noise(x: f32, y: f32, z: f32, seed: u32) -> (i32, i32) {
     let mut heat_noise = FastNoise::seeded(seed as u64);
     let mut moisture_noise = FastNoise::seeded(seed as u64);
     moisture_noise.set_noise_type(NoiseType::Cellular);
@@ -223,30 +233,20 @@ pub fn generate_chunk(
     biome_tree: &BiomeTree,
     // to_be_placed: &ToBePlaced,
 ) -> RawChunk {
-    let (heat, humidity) = biome_noise(
-        pos.x as f32 * CHUNK_SIZE as f32,
-        pos.y as f32 * CHUNK_SIZE as f32,
-        pos.z as f32 * CHUNK_SIZE as f32,
-        seed,
-    );
-    let main_blocks = biome_table
-        .get(&values_to_biome(heat, humidity, biome_hashmap, biome_tree))
-        .unwrap()
-        .main_block
-        .clone();
-    let mut ridged_noise = FastNoise::seeded(seed as u64);
-    let mut a_noise = FastNoise::seeded(seed as u64);
-    let mut d_noise = FastNoise::seeded(seed.wrapping_add(1) as u64);
-    ridged_noise.set_noise_type(NoiseType::SimplexFractal);
-    ridged_noise.set_fractal_octaves(8);
-    ridged_noise.set_frequency(0.0025122);
-    d_noise.set_noise_type(NoiseType::Perlin);
-    d_noise.set_fractal_octaves(4);
-    d_noise.set_frequency(0.01881);
-    a_noise.set_noise_type(NoiseType::PerlinFractal);
-    a_noise.set_fractal_octaves(3);
-    ridged_noise.set_frequency(0.02);
-    //TODO: Switch to using ron files to determine biomes and what blocks they should use. For now hardcoding a simplex noise
+    let biome = biome_table
+        .get(&chunk_biome(pos, seed, biome_hashmap, biome_tree))
+        .unwrap();
+    let surface_depth = biome
+        .surface_block
+        .as_ref()
+        .map(|_| biome.surface_depth.unwrap_or(1) as usize)
+        .unwrap_or(0);
+    let ceil_depth = biome
+        .ceil_block
+        .as_ref()
+        .map(|_| biome.ceil_depth.unwrap_or(1) as usize)
+        .unwrap_or(0);
+    let terrain_noise = TerrainNoise::new(seed);
     // let ridged_noise: RidgedMulti<OpenSimplex> = RidgedMulti::new(seed)
     //     .set_octaves(8)
     //     .set_frequency(0.0025122);
@@ -293,41 +293,32 @@ pub fn generate_chunk(
     // worley_noise.set_distance_function(move |x, y, z| DISTANCE_FN_EUCLIDEAN_SQ(x, y, z));
     // worley_noise.set_value_function(move |distances| VALUE_FN_BLOBS_2(distances));
     let mut raw_chunk = ChunkData::default();
+    let base_y = (CHUNK_SIZE as i32) * pos.y;
     for x in 0..=CHUNK_SIZE - 1 {
         let full_x = x as i32 + ((CHUNK_SIZE as i32) * pos.x);
         for z in 0..=CHUNK_SIZE - 1 {
             let full_z = z as i32 + ((CHUNK_SIZE as i32) * pos.z);
+            // Sample past the top and bottom of the chunk so layers carry on into the neighbours
+            let column: Vec<bool> = (base_y - ceil_depth as i32
+                ..base_y + CHUNK_SIZE as i32 + surface_depth as i32)
+                .map(|full_y| terrain_noise.is_solid(full_x, full_y, full_z))
+                .collect();
             for y in 0..=CHUNK_SIZE - 1 {
-                let full_y = y as i32 + ((CHUNK_SIZE as i32) * pos.y);
-                let (x, y, z) = (x as u32, y as u32, z as u32);
-                let relative_pos = RelativeVoxelPos(UVec3::new(x, y, z));
-                let is_cave = ridged_noise
-                    .get_noise3d(full_x as f32, full_y as f32, full_z as f32)
-                    .abs()
-                    < 0.1
-                    && d_noise
-                        .get_noise3d(full_x as f32, full_y as f32, full_z as f32)
-                        .abs()
-                        < 0.1
-                    && (a_noise.get_noise3d(full_x as f32, full_y as f32, full_z as f32) < 0.45);
-                // let noise_val =
-                //     final_noise.get([full_x as f64, full_y as f64, full_z as f64]) * 45.152;
-                // let noise_val =
-                // world_noise(seed).get([full_x as f64, full_y as f64, full_z as f64]) * 45.152;
-                if !is_cave {
+                let full_y = y as i32 + base_y;
+                let idx = y + ceil_depth;
+                let relative_pos = RelativeVoxelPos(UVec3::new(x as u32, y as u32, z as u32));
+                if column[idx] {
                     let mut rng: StdRng = SeedableRng::seed_from_u64(
                         IVec3::new(full_x, full_y, full_z).reflect_hash().unwrap(),
                     );
-                    let main_block = main_blocks
-                        .choose_weighted(&mut rng, |item| item.1)
-                        .unwrap()
-                        .clone()
-                        .0;
-                    let (namespace, name) = identifier_to_name(main_block).unwrap();
-                    raw_chunk.set(
-                        relative_pos,
-                        BlockData::new(namespace.clone(), name.clone()),
-                    );
+                    let blocks = match (&biome.surface_block, &biome.ceil_block) {
+                        (Some(surface_block), _) if is_surface(&column, idx, surface_depth) => {
+                            surface_block
+                        }
+                        (_, Some(ceil_block)) if is_ceiling(&column, idx, ceil_depth) => ceil_block,
+                        _ => &biome.main_block,
+                    };
+                    raw_chunk.set(relative_pos, choose_block(blocks, &mut rng));
                 } else {
                     raw_chunk.set(
                         relative_pos,
@@ -337,30 +328,6 @@ pub fn generate_chunk(
             }
         }
     }
-    // add_surface(
-    //     &mut raw_chunk,
-    //     pos,
-    //     block_table,
-    //     vec![
-    //         (BlockData::new("vinox".to_string(), "ignis".to_string()), 3),
-    //         (BlockData::new("vinox".to_string(), "slate".to_string()), 1),
-    //         (BlockData::new("vinox".to_string(), "gravel".to_string()), 1),
-    //     ],
-    //     &mut rng,
-    // );
-    // add_ceiling(
-    //     &mut raw_chunk,
-    //     pos,
-    //     block_table,
-    //     vec![
-    //         (BlockData::new("vinox".to_string(), "worley".to_string()), 4),
-    //         (
-    //             BlockData::new("vinox".to_string(), "granite".to_string()),
-    //             1,
-    //         ),
-    //     ],
-    //     &mut rng,
-    // );
     // add_blobs(
     //     &mut raw_chunk,
     //     pos,
@@ -1 +1,2 @@
 pub mod chunks;
+pub mod generation;
@@ -1,3 +1,346 @@
+mod maps;
+
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fs::create_dir_all,
+    path::PathBuf,
+    process,
+};
+
+use bevy::prelude::*;
+use directories::ProjectDirs;
+use rand::Rng;
+use vinox_common::world::{
+    chunks::storage::{
+        BiomeTable, BlockData, BlockTable, ChunkData, FeatureRuleTable, FeatureTable, ItemTable,
+        RecipeTable, StructureTable,
+    },
+    generation::{
+        features::{generate_decorated_chunk, place_spill},
+        terrain::{BiomeHashmap, BiomeTree, ToBePlaced},
+    },
+};
+use vinox_server::game::{
+    networking::start::setup_loadables,
+    world::storage::{
+        diff_blocks, load_world_info, open_world_storage, SavedChunk, SpilledFrom, StorageBackend,
+        WorldInfo, WorldStorage,
+    },
+};
+
+const USAGE: &str = "Usage: vinox-generation <world name> [--seed <seed>] [--from <x,y,z>] [--to <x,y,z>] [--maps <folder>] [--storage <backend>] [--no-db] [--prune]
+  --from and --to are inclusive chunk positions, they default to -4,-2,-4 and 4,2,4
+  --maps writes height.png, biome.png and climate.png into the folder
+  --storage is sqlite or region, the same as the server's storage option
+  --no-db skips creating the world, only chunks players change are ever saved to it
+  --prune deletes saved chunks that are still what generation made instead of generating";
+
+struct Options {
+    world_name: String,
+    seed: Option<u32>,
+    from: IVec3,
+    to: IVec3,
+    maps: Option<PathBuf>,
+    storage: StorageBackend,
+    write_db: bool,
+    prune: bool,
+}
+
+fn parse_pos(arg: Option<&String>) -> Result<IVec3, String> {
+    let arg = arg.ok_or("Missing position")?;
+    let parts = arg
+        .split(',')
+        .map(|part| part.trim().parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid position {arg}: {e}"))?;
+    match parts.as_slice() {
+        [x, y, z] => Ok(IVec3::new(*x, *y, *z)),
+        _ => Err(format!("Position {arg} should look like x,y,z")),
+    }
+}
+
+// TODO: Same as the server, swap to something like clap eventually
+fn parse_args() -> Result<Options, String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut options = Options {
+        world_name: String::new(),
+        seed: None,
+        from: IVec3::new(-4, -2, -4),
+        to: IVec3::new(4, 2, 4),
+        maps: None,
+        storage: StorageBackend::Sqlite,
+        write_db: true,
+        prune: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                let seed = args.next().ok_or("Missing seed")?;
+                options.seed = Some(
+                    seed.parse()
+                        .map_err(|e| format!("Invalid seed {seed}: {e}"))?,
+                );
+            }
+            "--from" => options.from = parse_pos(args.next())?,
+            "--to" => options.to = parse_pos(args.next())?,
+            "--maps" => options.maps = Some(args.next().ok_or("Missing maps folder")?.into()),
+            "--storage" => {
+                let storage = args.next().ok_or("Missing storage backend")?;
+                options.storage = storage
+                    .parse()
+                    .map_err(|_| format!("Unknown storage backend {storage}"))?;
+            }
+            "--no-db" => options.write_db = false,
+            "--prune" => options.prune = true,
+            "--help" | "-h" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {arg}")),
+            _ if options.world_name.is_empty() => options.world_name = arg.clone(),
+            _ => return Err(format!("Unexpected argument {arg}")),
+        }
+    }
+    if options.world_name.is_empty() {
+        return Err("Missing world name".to_string());
+    }
+    let (from, to) = (options.from.min(options.to), options.from.max(options.to));
+    options.from = from;
+    options.to = to;
+    Ok(options)
+}
+
+// Runs the server's own loader so the tables match what the server would generate with
+fn load_tables() -> App {
+    let mut app = App::new();
+    app.init_resource::<BlockTable>()
+        .init_resource::<ItemTable>()
+        .init_resource::<RecipeTable>()
+        .init_resource::<BiomeTable>()
+        .init_resource::<BiomeHashmap>()
+        .init_resource::<BiomeTree>()
+        .init_resource::<FeatureTable>()
+        .init_resource::<FeatureRuleTable>()
+        .init_resource::<StructureTable>()
+        .add_startup_system(setup_loadables);
+    app.update();
+    app
+}
+
+pub struct Region {
+    pub seed: u32,
+    pub from: IVec3,
+    pub to: IVec3,
+    pub chunks: HashMap<IVec3, ChunkData>,
+}
+
+// The same as the server generates a chunk, blocks spilling into other chunks are returned
+fn generate_at(app: &App, seed: u32, pos: IVec3) -> (ChunkData, ToBePlaced) {
+    let world = &app.world;
+    generate_decorated_chunk(
+        pos,
+        seed,
+        world.resource::<BiomeTable>(),
+        world.resource::<BiomeHashmap>(),
+        world.resource::<BiomeTree>(),
+        world.resource::<FeatureRuleTable>(),
+        world.resource::<FeatureTable>(),
+        world.resource::<StructureTable>(),
+    )
+}
+
+fn generate_region(app: &App, seed: u32, from: IVec3, to: IVec3) -> Region {
+    let mut chunks = HashMap::new();
+    let mut to_be_placed = ToBePlaced::default();
+    for x in from.x..=to.x {
+        println!(
+            "Generating chunks at x {x} ({} of {})",
+            x - from.x + 1,
+            to.x - from.x + 1
+        );
+        for y in from.y..=to.y {
+            for z in from.z..=to.z {
+                let pos = IVec3::new(x, y, z);
+                let (chunk, spill) = generate_at(app, seed, pos);
+                for (pos, blocks) in spill.0 {
+                    to_be_placed.entry(pos).or_default().extend(blocks);
+                }
+                chunks.insert(pos, chunk);
+            }
+        }
+    }
+    // Anything spilling out of the region is dropped, the server regenerates those chunks anyway
+    let block_table = app.world.resource::<BlockTable>();
+    for (pos, blocks) in to_be_placed.0 {
+        if let Some(chunk) = chunks.get_mut(&pos) {
+            place_spill(chunk, blocks, block_table);
+        }
+    }
+    Region {
+        seed,
+        from,
+        to,
+        chunks,
+    }
+}
+
+// A saved chunk is untouched if every block that differs from its own generation is something a
+// neighbour it says had spilled into it put there. Chunks saved before that was kept count every
+// neighbour next to them
+fn prune(app: &App, seed: u32, database: &dyn WorldStorage) -> (usize, usize) {
+    let block_registry = database.load_block_registry();
+    let mut saved = database.saved_chunks();
+    saved.sort_by_key(|pos| (pos.x, pos.y, pos.z));
+    // Generation is slow and every chunk needs its neighbours, keep the last few slices of x
+    let mut generated: HashMap<IVec3, (ChunkData, ToBePlaced)> = HashMap::new();
+    let mut untouched = Vec::new();
+    for (i, chunk_pos) in saved.iter().enumerate() {
+        if i % 1000 == 0 {
+            println!("Checking chunk {} of {}", i + 1, saved.len());
+        }
+        generated.retain(|pos, _| pos.x >= chunk_pos.x - 1);
+        let Some(saved_chunk) = database.load_chunk(*chunk_pos, &block_registry) else {
+            continue;
+        };
+        let own = &generated
+            .entry(**chunk_pos)
+            .or_insert_with(|| generate_at(app, seed, **chunk_pos))
+            .0;
+        let (changed, spilled_from) = match saved_chunk {
+            SavedChunk::Full(raw_chunk, spilled_from) => (
+                diff_blocks(&ChunkData::from_raw(raw_chunk), own),
+                spilled_from,
+            ),
+            SavedChunk::Diff(blocks, spilled_from) => (blocks, spilled_from),
+        };
+        let neighbours: Vec<IVec3> = match spilled_from {
+            SpilledFrom::Nothing => Vec::new(),
+            SpilledFrom::Neighbours(offsets) => offsets.into_iter().collect(),
+            SpilledFrom::Everything => (-1..=1)
+                .flat_map(|x| {
+                    (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z)))
+                })
+                .filter(|offset| *offset != IVec3::ZERO)
+                .collect(),
+        };
+        if changed.is_empty() {
+            untouched.push(*chunk_pos);
+            continue;
+        }
+        let mut spilled: HashMap<u16, HashSet<BlockData>> = HashMap::new();
+        for offset in neighbours {
+            let pos = **chunk_pos + offset;
+            let (_, spill) = generated
+                .entry(pos)
+                .or_insert_with(|| generate_at(app, seed, pos));
+            for (relative_pos, block) in spill.get(&**chunk_pos).into_iter().flatten() {
+                let (x, y, z) = (relative_pos.x, relative_pos.y, relative_pos.z);
+                spilled
+                    .entry(ChunkData::linearize(x, y, z) as u16)
+                    .or_default()
+                    .insert(block.clone());
+            }
+        }
+        if changed.iter().all(|(idx, block)| {
+            spilled
+                .get(idx)
+                .map_or(false, |blocks| blocks.contains(block))
+        }) {
+            untouched.push(*chunk_pos);
+        }
+    }
+    database.delete_chunks(&untouched);
+    (untouched.len(), saved.len())
+}
+
 fn main() {
-    println!("Hello, world!");
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            if !e.is_empty() {
+                println!("{e}");
+            }
+            println!("{USAGE}");
+            process::exit(1);
+        }
+    };
+    let Some(proj_dirs) = ProjectDirs::from("com", "vinox", "vinox") else {
+        println!("Couldn't find the data directory");
+        process::exit(1);
+    };
+    let world_path = proj_dirs
+        .data_dir()
+        .join("assets/worlds")
+        .join(&options.world_name);
+    create_dir_all(world_path.parent().unwrap()).ok();
+
+    // Reuse the seed of an existing world, otherwise the new chunks wouldn't match the old ones
+    let info_path: PathBuf = format!("{}.ron", world_path.display()).into();
+    let world_info = match (load_world_info(info_path.clone()), options.seed) {
+        (Some(world_info), Some(seed)) if world_info.seed != seed => {
+            println!(
+                "World {} already exists with seed {}",
+                options.world_name, world_info.seed
+            );
+            process::exit(1);
+        }
+        (Some(world_info), _) => world_info,
+        (None, seed) => WorldInfo {
+            name: options.world_name.clone(),
+            seed: seed.unwrap_or_else(|| rand::thread_rng().gen_range(0..=u32::MAX)),
+            damage: false,
+        },
+    };
+
+    let app = load_tables();
+    if app.world.resource::<BiomeTable>().is_empty() {
+        println!("No biomes were found, make sure the assets folder has been copied over");
+        process::exit(1);
+    }
+    if options.prune {
+        if !info_path.exists() {
+            println!("World {} doesn't exist", options.world_name);
+            process::exit(1);
+        }
+        let database = match open_world_storage(options.storage, &world_path) {
+            Ok(database) => database,
+            Err(e) => {
+                println!("{e}");
+                process::exit(1);
+            }
+        };
+        let (pruned, total) = prune(&app, world_info.seed, database.as_ref());
+        println!("Deleted {pruned} of {total} saved chunks that were never changed");
+        return;
+    }
+    let region = generate_region(&app, world_info.seed, options.from, options.to);
+
+    if options.write_db {
+        let database = match open_world_storage(options.storage, &world_path) {
+            Ok(database) => database,
+            Err(e) => {
+                println!("{e}");
+                process::exit(1);
+            }
+        };
+        // Only chunks players changed are stored, the server generates the rest from the seed
+        let mut block_registry = database.load_block_registry();
+        database.save_block_ids(&block_registry.register_all(app.world.resource::<BlockTable>()));
+        database.save_world_info(&world_info);
+        println!(
+            "Saved world {} with seed {} to {}",
+            world_info.name,
+            world_info.seed,
+            world_path.display()
+        );
+    }
+
+    if let Some(maps_path) = options.maps {
+        let world = &app.world;
+        maps::write_maps(
+            &region,
+            &maps_path,
+            world.resource::<BiomeHashmap>(),
+            world.resource::<BiomeTree>(),
+        );
+    }
 }
@@ -0,0 +1,104 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::create_dir_all,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use bevy::prelude::*;
+use image::{Rgb, RgbImage};
+use vinox_common::world::{
+    chunks::{positions::RelativeVoxelPos, storage::CHUNK_SIZE},
+    generation::terrain::{biome_noise, chunk_biome, BiomeHashmap, BiomeTree},
+};
+
+use crate::Region;
+
+// Same colour for the same biome every run so maps can be compared
+fn biome_colour(identifier: &str) -> Rgb<u8> {
+    let mut hasher = DefaultHasher::new();
+    identifier.hash(&mut hasher);
+    let [r, g, b, ..] = hasher.finish().to_le_bytes();
+    Rgb([r, g, b])
+}
+
+// Biome noise is roughly -100 to 100
+fn climate_channel(value: i32) -> u8 {
+    ((value.clamp(-100, 100) + 100) * 255 / 200) as u8
+}
+
+fn is_air(region: &Region, pos: IVec3) -> bool {
+    let chunk_pos = pos.div_euclid(IVec3::splat(CHUNK_SIZE as i32));
+    let relative_pos = pos.rem_euclid(IVec3::splat(CHUNK_SIZE as i32)).as_uvec3();
+    region.chunks.get(&chunk_pos).map_or(true, |chunk| {
+        chunk.get_identifier(RelativeVoxelPos(relative_pos)) == "vinox:air"
+    })
+}
+
+// The whole world is underground so the "surface" is the first floor below open air from the top
+fn surface_height(region: &Region, x: i32, z: i32) -> Option<i32> {
+    let top = (region.to.y + 1) * CHUNK_SIZE as i32 - 1;
+    let bottom = region.from.y * CHUNK_SIZE as i32;
+    (bottom..top)
+        .rev()
+        .find(|y| !is_air(region, IVec3::new(x, *y, z)) && is_air(region, IVec3::new(x, y + 1, z)))
+}
+
+pub fn write_maps(
+    region: &Region,
+    folder: &Path,
+    biome_hashmap: &BiomeHashmap,
+    biome_tree: &BiomeTree,
+) {
+    if create_dir_all(folder).is_err() {
+        println!("Failed to create {folder:?} directory!");
+        return;
+    }
+    let size = (region.to - region.from + IVec3::ONE) * CHUNK_SIZE as i32;
+    let origin = region.from * CHUNK_SIZE as i32;
+    let (bottom, top) = (origin.y, origin.y + size.y - 1);
+    let mut height_map = RgbImage::new(size.x as u32, size.z as u32);
+    let mut biome_map = RgbImage::new(size.x as u32, size.z as u32);
+    let mut climate_map = RgbImage::new(size.x as u32, size.z as u32);
+    let mut biomes = Vec::new();
+    for image_x in 0..size.x {
+        for image_z in 0..size.z {
+            let (x, z) = (origin.x + image_x, origin.z + image_z);
+            let height = surface_height(region, x, z);
+            let shade = height.map_or(0, |y| ((y - bottom) * 255 / (top - bottom).max(1)) as u8);
+            height_map.put_pixel(image_x as u32, image_z as u32, Rgb([shade, shade, shade]));
+
+            // Biomes and climate are picked per chunk so sample the chunk the surface is in
+            let chunk_pos =
+                IVec3::new(x, height.unwrap_or(top), z).div_euclid(IVec3::splat(CHUNK_SIZE as i32));
+            let biome = chunk_biome(chunk_pos, region.seed, biome_hashmap, biome_tree);
+            biome_map.put_pixel(image_x as u32, image_z as u32, biome_colour(&biome));
+            if !biomes.contains(&biome) {
+                biomes.push(biome);
+            }
+            let chunk_origin = (chunk_pos * CHUNK_SIZE as i32).as_vec3();
+            let (heat, humidity) =
+                biome_noise(chunk_origin.x, chunk_origin.y, chunk_origin.z, region.seed);
+            climate_map.put_pixel(
+                image_x as u32,
+                image_z as u32,
+                Rgb([climate_channel(heat), 0, climate_channel(humidity)]),
+            );
+        }
+    }
+    for (name, image) in [
+        ("height.png", height_map),
+        ("biome.png", biome_map),
+        ("climate.png", climate_map),
+    ] {
+        if let Err(e) = image.save(folder.join(name)) {
+            println!("Failed to save {name}: {e}");
+        }
+    }
+    println!("Heights go from {bottom} (black) to {top} (white)");
+    println!("Climate maps heat to red and humidity to blue");
+    for biome in biomes {
+        let Rgb([r, g, b]) = biome_colour(&biome);
+        println!("{biome}: #{r:02x}{g:02x}{b:02x}");
+    }
+}
@@ -6,7 +6,7 @@ use vinox_common::{
     world::chunks::{
         ecs::LoadableAssets,
         positions::RelativeVoxelPos,
-        storage::{trim_geo_identifier, BlockTable, ChunkData, RenderedBlockData},
+        storage::{trim_geo_identifier, BlockTable, ChunkData, RenderedBlockData, UNKNOWN_BLOCK},
     },
 };
 
@@ -426,7 +426,7 @@ pub fn get_rend(
     let (x, y, z) = (x as u32, y as u32, z as u32);
     let voxel = chunk.get(RelativeVoxelPos::new(x, y, z));
     let identifier = chunk.get_identifier(RelativeVoxelPos::new(x, y, z));
-    let block_data = block_table.get(&identifier).unwrap();
+    let block_data = block_table.descriptor(&identifier).unwrap();
     let geo_data = geo_table.get(
         &block_data
             .clone()
@@ -463,10 +463,14 @@ pub fn get_rend(
         tex_variance[4].unwrap_or(false),
         tex_variance[5].unwrap_or(false),
     ];
+    let block_textures = loadable_assets
+        .block_textures
+        .get(&identifier)
+        .or_else(|| loadable_assets.block_textures.get(UNKNOWN_BLOCK));
     let mut textures = [0, 0, 0, 0, 0, 0];
     for (i, texture) in textures.iter_mut().enumerate() {
-        *texture = texture_atlas
-            .get_texture_index(&loadable_assets.block_textures.get(&identifier).unwrap()[i])
+        *texture = block_textures
+            .and_then(|block_textures| texture_atlas.get_texture_index(&block_textures[i]))
             .unwrap_or_default();
     }
 
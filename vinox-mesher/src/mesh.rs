@@ -10,6 +10,7 @@ use bevy::{
 use vinox_common::{
     storage::geometry::descriptor::{BlockGeo, GeometryDescriptor},
     world::chunks::{
+        light::{to_channel, to_sunlight, LightChannel},
         positions::{ChunkPos, VoxelPos},
         storage::{self, RawChunk, RenderedBlockData, VoxelVisibility},
     },
@@ -523,7 +524,7 @@ pub fn face_aos(face: &Face, chunk: &ChunkBoundary) -> [u32; 4] {
     }
 }
 
-pub fn face_lights(face: &Face, chunk: &ChunkBoundary) -> [f32; 4] {
+pub fn face_lights(face: &Face, chunk: &ChunkBoundary) -> [[f32; 3]; 4] {
     let [x, y, z] = face.voxel();
     // let (x, y, z) = (x as u32, y as u32, z as u32);
     match (face.side.axis, face.side.positive) {
@@ -593,7 +594,7 @@ pub fn face_lights(face: &Face, chunk: &ChunkBoundary) -> [f32; 4] {
 pub struct FaceWithAO<'a> {
     face: Face<'a>,
     aos: [u32; 4],
-    light: [f32; 4],
+    light: [[f32; 3]; 4],
 }
 
 impl<'a> FaceWithAO<'a> {
@@ -607,7 +608,7 @@ impl<'a> FaceWithAO<'a> {
         self.aos
     }
 
-    pub fn light(&self) -> [f32; 4] {
+    pub fn light(&self) -> [[f32; 3]; 4] {
         self.light
     }
 
@@ -651,24 +652,16 @@ pub(crate) fn side_aos(neighbors: [(RenderedBlockData, &BlockGeo); 8]) -> [u32;
     ]
 }
 
-pub(crate) fn side_light(neighbors: [u8; 8]) -> [f32; 4] {
+pub(crate) fn side_light(neighbors: [u16; 8]) -> [[f32; 3]; 4] {
+    let neighbors = neighbors.map(light_to_colour);
+    let average = |a: [f32; 3], b: [f32; 3], c: [f32; 3]| {
+        [0, 1, 2].map(|channel| (a[channel] + b[channel] + c[channel]) / 3.0)
+    };
     [
-        (light_to_intern(neighbors[0])
-            + light_to_intern(neighbors[1])
-            + light_to_intern(neighbors[2]))
-            / 3.0,
-        (light_to_intern(neighbors[2])
-            + light_to_intern(neighbors[3])
-            + light_to_intern(neighbors[4]))
-            / 3.0,
-        (light_to_intern(neighbors[6])
-            + light_to_intern(neighbors[7])
-            + light_to_intern(neighbors[0]))
-            / 3.0,
-        (light_to_intern(neighbors[4])
-            + light_to_intern(neighbors[5])
-            + light_to_intern(neighbors[6]))
-            / 3.0,
+        average(neighbors[0], neighbors[1], neighbors[2]),
+        average(neighbors[2], neighbors[3], neighbors[4]),
+        average(neighbors[6], neighbors[7], neighbors[0]),
+        average(neighbors[4], neighbors[5], neighbors[6]),
     ]
 }
 
@@ -1144,9 +1137,9 @@ pub fn full_mesh(
     let mut final_color = Vec::new();
     for (idx, color) in final_ao.iter().enumerate() {
         final_color.extend_from_slice(&[[
-            color[0] * light[idx],
-            color[1] * light[idx],
-            color[2] * light[idx],
+            color[0] * light[idx][0],
+            color[1] * light[idx][1],
+            color[2] * light[idx][2],
             color[3],
         ]]);
     }
@@ -1213,10 +1206,16 @@ pub struct MeshedChunk {
     pub pos: ChunkPos,
 }
 
-fn light_to_intern(color: u8) -> f32 {
-    match color {
-        0 => 0.75,
-        // 0 => 0.0,
+// Sunlight is white so each colour is lit by whichever is brighter of it and the sun
+fn light_to_colour(light: u16) -> [f32; 3] {
+    let sun = to_sunlight(light);
+    [LightChannel::Red, LightChannel::Green, LightChannel::Blue]
+        .map(|channel| light_to_intern(to_channel(light, channel).max(sun)))
+}
+
+fn light_to_intern(level: u8) -> f32 {
+    match level {
+        0 => 0.0,
         1 => 1.0,
         2 => 1.5,
         3 => 1.75,
@@ -1231,8 +1230,7 @@ fn light_to_intern(color: u8) -> f32 {
         12 => 4.0,
         13 => 4.5,
         14 => 5.0,
-        15 => 7.5,
-        _ => 10.0,
+        _ => 7.5,
     }
 }
 
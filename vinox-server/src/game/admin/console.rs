@@ -0,0 +1,59 @@
+use std::{
+    io,
+    sync::{
+        mpsc::{channel, Receiver},
+        Mutex,
+    },
+    thread,
+};
+
+use bevy::prelude::*;
+
+use crate::game::networking::components::{ChatCommand, LocalGame};
+
+// Lines typed into the terminal the server was started from
+#[derive(Resource, Deref)]
+pub struct ConsoleInput(pub Mutex<Receiver<String>>);
+
+// Reading stdin blocks so it gets its own thread, single player has no terminal to read
+pub fn spawn_console(mut commands: Commands, local_game: Res<LocalGame>) {
+    if **local_game {
+        return;
+    }
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines().flatten() {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    commands.insert_resource(ConsoleInput(Mutex::new(receiver)));
+}
+
+// Console lines are run like chat commands from an operator, the / is optional
+pub fn read_console(
+    console: Option<Res<ConsoleInput>>,
+    mut chat_commands: EventWriter<ChatCommand>,
+) {
+    let Some(console) = console else {
+        return;
+    };
+    let receiver = console.lock().unwrap();
+    for line in receiver.try_iter() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let command = if line.starts_with('/') {
+            line.to_string()
+        } else {
+            format!("/{line}")
+        };
+        chat_commands.send(ChatCommand {
+            client_id: None,
+            user_name: "Console".to_string(),
+            command,
+        });
+    }
+}
@@ -0,0 +1,4 @@
+pub mod console;
+pub mod moderation;
+pub mod plugin;
+pub mod server_commands;
@@ -0,0 +1,296 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use bevy_quinnet::{
+    server::{Endpoint, Server},
+    shared::channel::ChannelId,
+};
+use vinox_common::{
+    ecs::bundles::{ClientName, HeldItem, Inventory},
+    networking::protocol::{Player, ServerMessage},
+    world::chunks::storage::ItemTable,
+};
+
+use crate::game::{
+    commands::dispatcher::{
+        argument, literal, ArgumentType, CommandRegistry, PermissionLevel, RunCommand,
+    },
+    networking::{
+        components::{LocalGame, ServerLobby},
+        syncing::return_held,
+    },
+    world::storage::{NameList, PlayersToSave, SavedPlayer, WorldDatabase},
+};
+
+// Long enough for the reason to arrive before the connection goes away
+const KICK_GRACE: f32 = 1.0;
+
+// Kept in memory so joins don't touch the database, every change is written straight through
+#[derive(Resource, Default)]
+pub struct Moderation {
+    pub ops: HashSet<String>,
+    pub whitelist: HashSet<String>,
+    // User name and the reason they were given.
+    // TODO: Ban by address as well once bevy_quinnet gives us the client's address, 0.4 only hands
+    // out client ids
+    pub bans: HashMap<String, String>,
+    // Whoever is playing a single player world can use every command
+    pub local_game: bool,
+}
+
+impl Moderation {
+    pub fn load(database: &WorldDatabase, local_game: bool) -> Self {
+        Self {
+            ops: database.load_name_list(NameList::Ops),
+            whitelist: database.load_name_list(NameList::Whitelist),
+            bans: database.load_bans(),
+            local_game,
+        }
+    }
+
+    pub fn is_op(&self, name: &str) -> bool {
+        self.local_game || self.ops.contains(name)
+    }
+
+    // Operators skip the whitelist so one can't lock themselves out
+    pub fn can_join(&self, name: &str, whitelist: bool) -> Result<(), String> {
+        if let Some(reason) = self.bans.get(name) {
+            return Err(format!("Banned: {reason}"));
+        }
+        if whitelist && !self.is_op(name) && !self.whitelist.contains(name) {
+            return Err("You aren't on this server's whitelist".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Tells the client why and closes the connection once it has had a moment to get the message
+pub fn refuse(
+    endpoint: &mut Endpoint,
+    lobby: &mut ServerLobby,
+    client_id: u64,
+    reason: String,
+    now: f32,
+) {
+    println!("Disconnecting {client_id}: {reason}");
+    endpoint.try_send_message(client_id, ServerMessage::Refused { reason });
+    lobby.verified.remove(&client_id);
+    lobby.breaking.remove(&client_id);
+    lobby.downloads.remove(&client_id);
+    lobby.disconnecting.insert(client_id, now + KICK_GRACE);
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn kick(
+    commands: &mut Commands,
+    endpoint: &mut Endpoint,
+    lobby: &mut ServerLobby,
+    players: &mut Query<(&ClientName, &Transform, &mut Inventory, &mut HeldItem)>,
+    item_table: &ItemTable,
+    players_to_save: &mut PlayersToSave,
+    client_id: u64,
+    reason: String,
+    now: f32,
+) {
+    if let Some(player_entity) = lobby.players.remove(&client_id) {
+        if let Ok((name, transform, mut inventory, mut held)) = players.get_mut(player_entity) {
+            return_held(commands, transform, &mut inventory, &mut held, item_table);
+            players_to_save.push(((*name).clone(), SavedPlayer::new(transform, &inventory)));
+        }
+        commands.entity(player_entity).despawn();
+    }
+    endpoint.try_broadcast_message_on(
+        ChannelId::OrderedReliable(1),
+        ServerMessage::PlayerRemove { id: client_id },
+    );
+    refuse(endpoint, lobby, client_id, reason, now);
+}
+
+pub fn setup_moderation(
+    mut commands: Commands,
+    database: Res<WorldDatabase>,
+    local_game: Res<LocalGame>,
+) {
+    commands.insert_resource(Moderation::load(&database, **local_game));
+}
+
+pub fn disconnect_kicked(
+    mut server: ResMut<Server>,
+    mut lobby: ResMut<ServerLobby>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_seconds();
+    lobby.disconnecting.retain(|client_id, deadline| {
+        if *deadline > now {
+            return true;
+        }
+        server.endpoint_mut().disconnect_client(*client_id).ok();
+        false
+    });
+}
+
+pub fn register_moderation_commands(mut registry: ResMut<CommandRegistry>) {
+    let name = || argument("name", ArgumentType::Word);
+    let reason = || argument("reason", ArgumentType::Text).executes();
+    let operator = PermissionLevel::Operator;
+    registry.register(
+        literal("op").then(name().executes()),
+        operator,
+        "Let someone use operator commands",
+    );
+    registry.register(
+        literal("deop").then(name().executes()),
+        operator,
+        "Take operator commands away from someone",
+    );
+    registry.register(
+        literal("kick").then(
+            argument("player", ArgumentType::Player)
+                .executes()
+                .then(reason()),
+        ),
+        operator,
+        "Disconnect someone with a reason",
+    );
+    registry.register(
+        literal("ban").then(name().executes().then(reason())),
+        operator,
+        "Stop someone from joining",
+    );
+    registry.register(
+        literal("unban").then(name().executes()),
+        operator,
+        "Let a banned player join again",
+    );
+    registry.register(
+        literal("banlist").executes(),
+        operator,
+        "List banned players",
+    );
+    registry.register(
+        literal("whitelist")
+            .then(literal("add").then(name().executes()))
+            .then(literal("remove").then(name().executes()))
+            .then(literal("list").executes()),
+        operator,
+        "Manage who can join while the whitelist is on",
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn moderation_commands(
+    mut commands: Commands,
+    mut run_commands: EventReader<RunCommand>,
+    mut server: ResMut<Server>,
+    mut lobby: ResMut<ServerLobby>,
+    mut moderation: ResMut<Moderation>,
+    players: Query<(&Player, &ClientName)>,
+    mut kicked: Query<(&ClientName, &Transform, &mut Inventory, &mut HeldItem)>,
+    item_table: Res<ItemTable>,
+    mut players_to_save: ResMut<PlayersToSave>,
+    database: Res<WorldDatabase>,
+    time: Res<Time>,
+) {
+    for command in run_commands.iter() {
+        let online = |name: &str| {
+            players
+                .iter()
+                .find(|(_, client_name)| client_name.as_str() == name)
+                .map(|(player, _)| player.id)
+        };
+        let name = command.text("name").unwrap_or_default();
+        let reason = command.text("reason");
+        let now = time.elapsed_seconds();
+        let message = match (command.name.as_str(), command.literals().as_slice()) {
+            ("op", _) => {
+                moderation.ops.insert(name.to_string());
+                database.set_in_name_list(NameList::Ops, name, true);
+                format!("{name} is now an operator")
+            }
+            ("deop", _) => {
+                moderation.ops.remove(name);
+                database.set_in_name_list(NameList::Ops, name, false);
+                format!("{name} is no longer an operator")
+            }
+            ("kick", _) => {
+                let Some((client_id, name)) = command.player("player") else {
+                    continue;
+                };
+                kick(
+                    &mut commands,
+                    server.endpoint_mut(),
+                    &mut lobby,
+                    &mut kicked,
+                    &item_table,
+                    &mut players_to_save,
+                    client_id,
+                    reason.unwrap_or("Kicked by an operator").to_string(),
+                    now,
+                );
+                format!("Kicked {name}")
+            }
+            ("ban", _) => {
+                let reason = reason.unwrap_or("Banned by an operator").to_string();
+                database.save_ban(name, Some(&reason));
+                moderation.bans.insert(name.to_string(), reason.clone());
+                if let Some(client_id) = online(name) {
+                    kick(
+                        &mut commands,
+                        server.endpoint_mut(),
+                        &mut lobby,
+                        &mut kicked,
+                        &item_table,
+                        &mut players_to_save,
+                        client_id,
+                        format!("Banned: {reason}"),
+                        now,
+                    );
+                }
+                format!("Banned {name}")
+            }
+            ("unban", _) => {
+                if moderation.bans.remove(name).is_some() {
+                    database.save_ban(name, None);
+                    format!("Unbanned {name}")
+                } else {
+                    format!("{name} isn't banned")
+                }
+            }
+            ("banlist", _) => {
+                let mut bans: Vec<String> = moderation
+                    .bans
+                    .iter()
+                    .map(|(name, reason)| format!("{name} ({reason})"))
+                    .collect();
+                bans.sort();
+                if bans.is_empty() {
+                    "Nobody is banned".to_string()
+                } else {
+                    format!("Banned: {}", bans.join(", "))
+                }
+            }
+            ("whitelist", ["add"]) => {
+                moderation.whitelist.insert(name.to_string());
+                database.set_in_name_list(NameList::Whitelist, name, true);
+                format!("Added {name} to the whitelist")
+            }
+            ("whitelist", ["remove"]) => {
+                moderation.whitelist.remove(name);
+                database.set_in_name_list(NameList::Whitelist, name, false);
+                format!("Removed {name} from the whitelist")
+            }
+            ("whitelist", _) => {
+                let mut names: Vec<&str> =
+                    moderation.whitelist.iter().map(String::as_str).collect();
+                names.sort();
+                if names.is_empty() {
+                    "Nobody is whitelisted".to_string()
+                } else {
+                    format!("Whitelisted: {}", names.join(", "))
+                }
+            }
+            _ => continue,
+        };
+        command.reply(&mut server, message);
+    }
+}
@@ -0,0 +1,24 @@
+use bevy::prelude::*;
+
+use crate::game::commands::dispatcher::dispatch_commands;
+
+use super::{
+    console::{read_console, spawn_console},
+    moderation::{
+        disconnect_kicked, moderation_commands, register_moderation_commands, setup_moderation,
+    },
+    server_commands::{register_server_commands, server_commands},
+};
+
+pub struct AdminPlugin;
+
+impl Plugin for AdminPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup_moderation)
+            .add_startup_system(spawn_console)
+            .add_startup_system(register_moderation_commands)
+            .add_startup_system(register_server_commands)
+            .add_system(read_console.before(dispatch_commands))
+            .add_systems((moderation_commands, server_commands, disconnect_kicked));
+    }
+}
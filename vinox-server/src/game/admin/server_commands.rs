@@ -0,0 +1,116 @@
+use bevy::{app::AppExit, prelude::*};
+use bevy_quinnet::{server::Server, shared::channel::ChannelId};
+use vinox_common::{ecs::bundles::ClientName, networking::protocol::ServerMessage};
+
+use crate::game::{
+    commands::dispatcher::{
+        argument, literal, ArgumentType, CommandRegistry, PermissionLevel, RunCommand,
+    },
+    config::ServerConfig,
+    networking::components::{ServerLobby, ServerTick},
+    world::storage::SaveRequest,
+};
+
+pub fn register_server_commands(mut registry: ResMut<CommandRegistry>) {
+    registry.register(
+        literal("list").executes(),
+        PermissionLevel::Player,
+        "List who is online",
+    );
+    registry.register(
+        literal("time").executes(),
+        PermissionLevel::Player,
+        "Show how long the server has been up",
+    );
+    registry.register(
+        literal("say").then(argument("message", ArgumentType::Text).executes()),
+        PermissionLevel::Operator,
+        "Send a message to everyone",
+    );
+    registry.register(
+        literal("save").executes(),
+        PermissionLevel::Operator,
+        "Write the world to disk now",
+    );
+    registry.register(
+        literal("stop").executes(),
+        PermissionLevel::Console,
+        "Save the world and shut the server down",
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn server_commands(
+    mut run_commands: EventReader<RunCommand>,
+    mut server: ResMut<Server>,
+    lobby: Res<ServerLobby>,
+    players: Query<&ClientName>,
+    mut request: ResMut<SaveRequest>,
+    mut exit: EventWriter<AppExit>,
+    (config, tick, time): (Res<ServerConfig>, Res<ServerTick>, Res<Time>),
+) {
+    for command in run_commands.iter() {
+        let online = lobby
+            .players
+            .values()
+            .filter_map(|entity| players.get(*entity).ok());
+        let message = match command.name.as_str() {
+            "list" => {
+                let mut names: Vec<String> = online.map(|name| (*name).clone()).collect();
+                names.sort();
+                format!(
+                    "{}/{} online: {}",
+                    names.len(),
+                    config.max_players,
+                    names.join(", ")
+                )
+            }
+            "time" => {
+                let uptime = time.elapsed_seconds() as u64;
+                format!(
+                    "Up for {}h {}m {}s, on tick {}",
+                    uptime / 3600,
+                    uptime / 60 % 60,
+                    uptime % 60,
+                    **tick
+                )
+            }
+            "say" => {
+                let Some(message) = command.text("message") else {
+                    continue;
+                };
+                let user_name = if command.source.client_id.is_none() {
+                    "Server".to_string()
+                } else {
+                    command.source.user_name.clone()
+                };
+                println!("{user_name}: {message}");
+                server.endpoint_mut().try_broadcast_message_on(
+                    ChannelId::OrderedReliable(1),
+                    ServerMessage::ChatMessage {
+                        user_name,
+                        message: message.to_string(),
+                        id: 0,
+                    },
+                );
+                continue;
+            }
+            "save" => {
+                request.now = true;
+                "Saving the world".to_string()
+            }
+            // Everything is saved on the way out
+            "stop" => {
+                exit.send(AppExit);
+                server
+                    .endpoint_mut()
+                    .try_broadcast_message(ServerMessage::Refused {
+                        reason: "The server is stopping".to_string(),
+                    });
+                "Stopping the server".to_string()
+            }
+            _ => continue,
+        };
+        command.reply(&mut server, message);
+    }
+}
@@ -0,0 +1,145 @@
+use bevy::prelude::*;
+use bevy_quinnet::server::Server;
+use vinox_common::{networking::protocol::Player, physics::movement::MoveState};
+
+use crate::game::admin::moderation::Moderation;
+
+use super::dispatcher::{
+    argument, literal, permission_level, ArgumentType, CommandRegistry, PermissionLevel, RunCommand,
+};
+
+pub fn register_builtin_commands(mut registry: ResMut<CommandRegistry>) {
+    registry.register(
+        literal("help")
+            .executes()
+            .then(argument("command", ArgumentType::Word).executes()),
+        PermissionLevel::Player,
+        "List commands or show how to use one",
+    );
+    registry.register(
+        literal("tp")
+            .then(argument("pos", ArgumentType::Position).executes())
+            .then(
+                argument("player", ArgumentType::Player)
+                    .then(argument("pos", ArgumentType::Position).executes()),
+            ),
+        PermissionLevel::Operator,
+        "Teleport yourself or someone else",
+    );
+    registry.register(
+        literal("spectator")
+            .executes()
+            .then(argument("enabled", ArgumentType::Boolean).executes())
+            .then(
+                argument("player", ArgumentType::Player)
+                    .executes()
+                    .then(argument("enabled", ArgumentType::Boolean).executes()),
+            ),
+        PermissionLevel::Operator,
+        "Fly through blocks, toggles without true or false",
+    );
+}
+
+// Only lists what whoever asked is allowed to run
+pub fn help_command(
+    mut run_commands: EventReader<RunCommand>,
+    mut server: ResMut<Server>,
+    registry: Res<CommandRegistry>,
+    moderation: Res<Moderation>,
+) {
+    for command in run_commands.iter().filter(|command| command.name == "help") {
+        let level = permission_level(
+            &moderation,
+            command.source.client_id,
+            &command.source.user_name,
+        );
+        let message = if let Some(name) = command.text("command") {
+            match registry.get(name) {
+                Some(registered) if registered.permission <= level => {
+                    let mut lines = vec![registered.description.clone()];
+                    lines.extend(
+                        registered
+                            .node
+                            .usages()
+                            .into_iter()
+                            .map(|usage| format!("/{usage}")),
+                    );
+                    lines.join("\n")
+                }
+                _ => format!("Unknown command {name}"),
+            }
+        } else {
+            registry
+                .available(level)
+                .map(|(name, registered)| format!("/{name} - {}", registered.description))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        command.reply(&mut server, message);
+    }
+}
+
+// Movement is simulated here so moving the state is enough, the client snaps to it on the next ack
+pub fn tp_command(
+    mut run_commands: EventReader<RunCommand>,
+    mut server: ResMut<Server>,
+    mut players: Query<(&Player, &mut MoveState, &mut Transform)>,
+) {
+    for command in run_commands.iter().filter(|command| command.name == "tp") {
+        let Some(pos) = command.position("pos") else {
+            continue;
+        };
+        let Some(target) = command
+            .player("player")
+            .map(|(id, _)| id)
+            .or(command.source.client_id)
+        else {
+            command.reply(&mut server, "Say who to teleport".to_string());
+            continue;
+        };
+        let Some((_, mut state, mut transform)) = players
+            .iter_mut()
+            .find(|(player, _, _)| player.id == target)
+        else {
+            continue;
+        };
+        // Centered on the block so we don't end up inside a wall
+        state.translation = pos.as_vec3() + Vec3::new(0.5, 0.0, 0.5);
+        state.velocity = Vec3::ZERO;
+        transform.translation = state.translation;
+        command.reply(&mut server, format!("Teleported to {pos}"));
+    }
+}
+
+// Same as /tp, the client picks the flag up from the next ack and predicts with it
+pub fn spectator_command(
+    mut run_commands: EventReader<RunCommand>,
+    mut server: ResMut<Server>,
+    mut players: Query<(&Player, &mut MoveState)>,
+) {
+    for command in run_commands
+        .iter()
+        .filter(|command| command.name == "spectator")
+    {
+        let Some(target) = command
+            .player("player")
+            .map(|(id, _)| id)
+            .or(command.source.client_id)
+        else {
+            command.reply(&mut server, "Say who to make a spectator".to_string());
+            continue;
+        };
+        let Some((_, mut state)) = players.iter_mut().find(|(player, _)| player.id == target)
+        else {
+            continue;
+        };
+        state.noclip = command.boolean("enabled").unwrap_or(!state.noclip);
+        state.velocity = Vec3::ZERO;
+        let message = if state.noclip {
+            "Spectating, blocks don't stop you"
+        } else {
+            "Stopped spectating"
+        };
+        command.reply(&mut server, message.to_string());
+    }
+}
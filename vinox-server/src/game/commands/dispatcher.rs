@@ -0,0 +1,1006 @@
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+};
+
+use bevy::prelude::*;
+use bevy_quinnet::server::Server;
+use vinox_common::{
+    ecs::bundles::ClientName,
+    networking::protocol::{Player, ServerMessage},
+    world::chunks::{positions::VoxelPos, storage::BlockTable},
+};
+
+use crate::game::{admin::moderation::Moderation, networking::components::ChatCommand};
+
+// Completions are only sent while typing so a handful is enough
+const MAX_SUGGESTIONS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PermissionLevel {
+    Player,
+    Operator,
+    Console,
+}
+
+impl PermissionLevel {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "player" => Some(PermissionLevel::Player),
+            "operator" | "op" => Some(PermissionLevel::Operator),
+            "console" => Some(PermissionLevel::Console),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgumentType {
+    Boolean,
+    Integer,
+    Number,
+    Word,
+    // Everything left on the line, has to come last
+    Text,
+    // Three coordinates, ~ is relative to whoever ran the command
+    Position,
+    // Name of someone online
+    Player,
+    // Block identifier, the vinox namespace can be left off
+    Block,
+}
+
+impl ArgumentType {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "boolean" => Some(ArgumentType::Boolean),
+            "integer" => Some(ArgumentType::Integer),
+            "number" => Some(ArgumentType::Number),
+            "word" => Some(ArgumentType::Word),
+            "text" => Some(ArgumentType::Text),
+            "position" => Some(ArgumentType::Position),
+            "player" => Some(ArgumentType::Player),
+            "block" => Some(ArgumentType::Block),
+            _ => None,
+        }
+    }
+
+    fn width(&self, remaining: usize) -> usize {
+        match self {
+            ArgumentType::Text => remaining.max(1),
+            ArgumentType::Position => 3,
+            _ => 1,
+        }
+    }
+
+    fn read(&self, words: &[&str], context: &ParseContext) -> Result<Argument, String> {
+        let word = words[0];
+        match self {
+            ArgumentType::Boolean => read_boolean(word)
+                .map(Argument::Boolean)
+                .ok_or_else(|| format!("{word} isn't true or false")),
+            ArgumentType::Integer => read_integer(word)
+                .and_then(|value| i32::try_from(value).ok())
+                .map(Argument::Integer)
+                .ok_or_else(|| format!("{word} isn't a whole number")),
+            ArgumentType::Number => word
+                .parse()
+                .map(Argument::Number)
+                .map_err(|_| format!("{word} isn't a number")),
+            ArgumentType::Word => Ok(Argument::Word(word.to_string())),
+            ArgumentType::Text => Ok(Argument::Text(words.join(" "))),
+            ArgumentType::Position => {
+                let mut pos = IVec3::ZERO;
+                for axis in 0..3 {
+                    pos[axis] =
+                        parse_coordinate(words[axis], context.origin.map(|origin| origin[axis]))?;
+                }
+                Ok(Argument::Position(pos))
+            }
+            ArgumentType::Player => context
+                .players
+                .iter()
+                .find(|(_, name)| name == word)
+                .map(|(id, name)| Argument::Player {
+                    id: *id,
+                    name: name.clone(),
+                })
+                .ok_or_else(|| format!("{word} isn't online")),
+            ArgumentType::Block => {
+                let identifier = if word.contains(':') {
+                    word.to_string()
+                } else {
+                    format!("vinox:{word}")
+                };
+                if context.block_table.contains_key(&identifier) {
+                    Ok(Argument::Block(identifier))
+                } else {
+                    Err(format!("There is no block called {word}"))
+                }
+            }
+        }
+    }
+
+    fn suggestions(&self, context: &ParseContext) -> Vec<String> {
+        match self {
+            ArgumentType::Boolean => vec!["false".to_string(), "true".to_string()],
+            ArgumentType::Player => context
+                .players
+                .iter()
+                .map(|(_, name)| name.clone())
+                .collect(),
+            ArgumentType::Block => context.block_table.keys().cloned().collect(),
+            ArgumentType::Position if context.origin.is_some() => vec!["~".to_string()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Booleans and integers are read by brigadier_rs. Its trees are fixed when they're compiled and
+// script commands are only known at runtime, so the tree itself is walked here. The literal is
+// only there to give the argument a parent
+fn read_boolean(word: &str) -> Option<bool> {
+    use brigadier_rs::*;
+    let value = Cell::new(None);
+    let parser = literal("value")
+        .then(boolean("value").build_exec(|_ctx: (), read| {
+            value.set(Some(read));
+            Ok::<(), Infallible>(())
+        }))
+        .build_exec(|_ctx: ()| Ok::<(), Infallible>(()));
+    match parser.parse((), &format!("value {word}")) {
+        Ok((rest, _)) if rest.trim().is_empty() => value.get(),
+        _ => None,
+    }
+}
+
+fn read_integer(word: &str) -> Option<i64> {
+    use brigadier_rs::*;
+    let value = Cell::new(None);
+    let parser = literal("value")
+        .then(integer_i64("value").build_exec(|_ctx: (), read| {
+            value.set(Some(read));
+            Ok::<(), Infallible>(())
+        }))
+        .build_exec(|_ctx: ()| Ok::<(), Infallible>(()));
+    match parser.parse((), &format!("value {word}")) {
+        Ok((rest, _)) if rest.trim().is_empty() => value.get(),
+        _ => None,
+    }
+}
+
+fn parse_coordinate(word: &str, origin: Option<i32>) -> Result<i32, String> {
+    let coordinate = |word: &str| read_integer(word).and_then(|value| i32::try_from(value).ok());
+    let Some(offset) = word.strip_prefix('~') else {
+        return coordinate(word).ok_or_else(|| format!("{word} isn't a coordinate"));
+    };
+    let origin = origin.ok_or("Only players can use ~ positions")?;
+    if offset.is_empty() {
+        return Ok(origin);
+    }
+    coordinate(offset)
+        .map(|offset| origin + offset)
+        .ok_or_else(|| format!("{word} isn't a coordinate"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Argument {
+    Boolean(bool),
+    Integer(i32),
+    Number(f32),
+    Word(String),
+    Text(String),
+    Position(IVec3),
+    Player { id: u64, name: String },
+    Block(String),
+}
+
+// What arguments are checked against
+pub struct ParseContext<'a> {
+    pub players: &'a [(u64, String)],
+    pub block_table: &'a BlockTable,
+    pub origin: Option<IVec3>,
+}
+
+#[derive(Debug, Clone)]
+enum NodeKind {
+    Literal(String),
+    Argument(String, ArgumentType),
+}
+
+// Commands are trees of words and arguments same as brigadier, any node marked as executes is a
+// valid place for the command to end. Kept as data so completions and usages can walk it
+#[derive(Debug, Clone)]
+pub struct CommandNode {
+    kind: NodeKind,
+    children: Vec<CommandNode>,
+    executes: bool,
+}
+
+pub fn literal(name: &str) -> CommandNode {
+    CommandNode {
+        kind: NodeKind::Literal(name.to_string()),
+        children: Vec::new(),
+        executes: false,
+    }
+}
+
+pub fn argument(name: &str, argument_type: ArgumentType) -> CommandNode {
+    CommandNode {
+        kind: NodeKind::Argument(name.to_string(), argument_type),
+        children: Vec::new(),
+        executes: false,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Parsed {
+    literals: Vec<String>,
+    arguments: HashMap<String, Argument>,
+}
+
+impl CommandNode {
+    // Literals are tried first so "/whitelist list" isn't read as a name
+    pub fn then(mut self, child: CommandNode) -> Self {
+        self.children.push(child);
+        self.children
+            .sort_by_key(|child| matches!(child.kind, NodeKind::Argument(..)));
+        self
+    }
+
+    pub fn executes(mut self) -> Self {
+        self.executes = true;
+        self
+    }
+
+    // Scripts describe their commands as a line ie "add <name:word> [count:integer]"
+    pub fn from_syntax(name: &str, syntax: &str) -> Result<Self, String> {
+        let mut nodes = vec![literal(name)];
+        let mut optional = false;
+        for word in syntax.split_whitespace() {
+            if matches!(
+                nodes.last().map(|node| &node.kind),
+                Some(NodeKind::Argument(_, ArgumentType::Text))
+            ) {
+                return Err("Text arguments have to come last".to_string());
+            }
+            let (inner, is_optional) = if let Some(inner) =
+                word.strip_prefix('[').and_then(|w| w.strip_suffix(']'))
+            {
+                (inner, true)
+            } else if let Some(inner) = word.strip_prefix('<').and_then(|w| w.strip_suffix('>')) {
+                (inner, false)
+            } else {
+                (word, false)
+            };
+            if optional && !is_optional {
+                return Err(format!("{word} can't come after an optional argument"));
+            }
+            if is_optional && !optional {
+                optional = true;
+                nodes.last_mut().unwrap().executes = true;
+            }
+            if inner.len() == word.len() {
+                nodes.push(literal(word));
+                continue;
+            }
+            let Some((arg_name, type_name)) = inner.split_once(':') else {
+                return Err(format!("{word} needs a type ie <{inner}:word>"));
+            };
+            let argument_type = ArgumentType::parse(type_name)
+                .ok_or_else(|| format!("{type_name} isn't an argument type"))?;
+            nodes.push(argument(arg_name, argument_type));
+        }
+        let mut node = nodes.pop().unwrap().executes();
+        while let Some(parent) = nodes.pop() {
+            node = parent.then(node);
+        }
+        Ok(node)
+    }
+
+    fn name(&self) -> &str {
+        match &self.kind {
+            NodeKind::Literal(name) | NodeKind::Argument(name, _) => name,
+        }
+    }
+
+    fn label(&self) -> String {
+        match &self.kind {
+            NodeKind::Literal(name) => name.clone(),
+            NodeKind::Argument(name, _) => format!("<{name}>"),
+        }
+    }
+
+    // Every way this can be run, ie "whitelist add <name>"
+    pub fn usages(&self) -> Vec<String> {
+        let label = self.label();
+        let mut usages = Vec::new();
+        if self.executes {
+            usages.push(label.clone());
+        }
+        for child in self.children.iter() {
+            for usage in child.usages() {
+                usages.push(format!("{label} {usage}"));
+            }
+        }
+        usages
+    }
+
+    // Backtracks so arguments of different types can share a spot
+    fn parse(
+        &self,
+        words: &[&str],
+        context: &ParseContext,
+        parsed: &mut Parsed,
+    ) -> Result<(), String> {
+        let Some(first) = words.first() else {
+            return if self.executes {
+                Ok(())
+            } else {
+                Err("Missing arguments".to_string())
+            };
+        };
+        let mut error = None;
+        for child in self.children.iter() {
+            match &child.kind {
+                NodeKind::Literal(name) => {
+                    if first == name {
+                        parsed.literals.push(name.clone());
+                        return child.parse(&words[1..], context, parsed);
+                    }
+                }
+                NodeKind::Argument(name, argument_type) => {
+                    let width = argument_type.width(words.len());
+                    if words.len() < width {
+                        error.get_or_insert_with(|| "Missing arguments".to_string());
+                        continue;
+                    }
+                    match argument_type.read(&words[..width], context) {
+                        Ok(argument) => {
+                            let mut attempt = parsed.clone();
+                            attempt.arguments.insert(name.clone(), argument);
+                            match child.parse(&words[width..], context, &mut attempt) {
+                                Ok(()) => {
+                                    *parsed = attempt;
+                                    return Ok(());
+                                }
+                                Err(e) => {
+                                    error.get_or_insert(e);
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            error.get_or_insert(e);
+                        }
+                    }
+                }
+            }
+        }
+        Err(error.unwrap_or_else(|| format!("Didn't expect {first}")))
+    }
+
+    // The last word is the one being typed, everything before it has to parse
+    fn complete(&self, words: &[&str], context: &ParseContext, suggestions: &mut Vec<String>) {
+        for child in self.children.iter() {
+            match &child.kind {
+                NodeKind::Literal(name) => {
+                    if words.len() == 1 {
+                        if name.starts_with(words[0]) {
+                            suggestions.push(name.clone());
+                        }
+                    } else if words[0] == name {
+                        child.complete(&words[1..], context, suggestions);
+                    }
+                }
+                NodeKind::Argument(_, argument_type) => {
+                    let width = argument_type.width(words.len());
+                    if words.len() <= width {
+                        let typed = words[words.len() - 1];
+                        suggestions.extend(
+                            argument_type
+                                .suggestions(context)
+                                .into_iter()
+                                .filter(|suggestion| suggestion.starts_with(typed)),
+                        );
+                    } else if argument_type.read(&words[..width], context).is_ok() {
+                        child.complete(&words[width..], context, suggestions);
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct RegisteredCommand {
+    pub node: CommandNode,
+    pub permission: PermissionLevel,
+    pub description: String,
+    // Identifier of the script that runs it, everything else is run by the game's systems
+    pub script: Option<String>,
+}
+
+// Every command the server knows about, names are stored without the /
+#[derive(Resource, Default)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, RegisteredCommand>,
+}
+
+impl CommandRegistry {
+    pub fn register(&mut self, node: CommandNode, permission: PermissionLevel, description: &str) {
+        self.insert(node, permission, description, None);
+    }
+
+    pub fn register_script(
+        &mut self,
+        identifier: String,
+        node: CommandNode,
+        permission: PermissionLevel,
+        description: &str,
+    ) {
+        self.insert(node, permission, description, Some(identifier));
+    }
+
+    // The first one registered under a name wins so scripts can't replace game commands
+    fn insert(
+        &mut self,
+        node: CommandNode,
+        permission: PermissionLevel,
+        description: &str,
+        script: Option<String>,
+    ) {
+        let name = node.name().to_string();
+        if self.commands.contains_key(&name) {
+            warn!("Command /{name} is already registered");
+            return;
+        }
+        self.commands.insert(
+            name,
+            RegisteredCommand {
+                node,
+                permission,
+                description: description.to_string(),
+                script,
+            },
+        );
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RegisteredCommand> {
+        self.commands.get(name.trim_start_matches('/'))
+    }
+
+    // Only what level is allowed to run
+    pub fn available(
+        &self,
+        level: PermissionLevel,
+    ) -> impl Iterator<Item = (&String, &RegisteredCommand)> {
+        self.commands
+            .iter()
+            .filter(move |(_, command)| command.permission <= level)
+    }
+
+    pub fn parse(
+        &self,
+        source: &ChatCommand,
+        level: PermissionLevel,
+        context: &ParseContext,
+    ) -> Result<RunCommand, String> {
+        let input = source.command.trim_start_matches('/');
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let Some(name) = words.first() else {
+            return Err("Type /help for a list of commands".to_string());
+        };
+        let Some(command) = self.commands.get(*name) else {
+            return Err(format!("Unknown command /{name}, type /help for a list"));
+        };
+        if command.permission > level {
+            return Err(format!("You don't have permission to use /{name}"));
+        }
+        let mut parsed = Parsed::default();
+        command
+            .node
+            .parse(&words[1..], context, &mut parsed)
+            .map_err(|e| {
+                let usages: Vec<String> = command
+                    .node
+                    .usages()
+                    .into_iter()
+                    .map(|usage| format!("/{usage}"))
+                    .collect();
+                format!("{e}, usage: {}", usages.join(" | "))
+            })?;
+        Ok(RunCommand {
+            source: source.clone(),
+            name: name.to_string(),
+            literals: parsed.literals,
+            arguments: parsed.arguments,
+            script: command.script.clone(),
+        })
+    }
+
+    // Whole lines so the client can swap its input out without knowing anything about commands
+    pub fn complete(
+        &self,
+        input: &str,
+        level: PermissionLevel,
+        context: &ParseContext,
+    ) -> Vec<String> {
+        let Some(line) = input.strip_prefix('/') else {
+            return Vec::new();
+        };
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() || line.ends_with(char::is_whitespace) {
+            words.push("");
+        }
+        let typed = words[words.len() - 1];
+        let prefix = &input[..input.len() - typed.len()];
+
+        let mut suggestions = Vec::new();
+        if words.len() == 1 {
+            suggestions.extend(
+                self.available(level)
+                    .map(|(name, _)| name.clone())
+                    .filter(|name| name.starts_with(typed)),
+            );
+        } else if let Some(command) = self.commands.get(words[0]) {
+            if command.permission <= level {
+                command
+                    .node
+                    .complete(&words[1..], context, &mut suggestions);
+            }
+        }
+        suggestions.sort();
+        suggestions.dedup();
+        suggestions
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|suggestion| format!("{prefix}{suggestion}"))
+            .collect()
+    }
+}
+
+// A command that parsed and that its sender is allowed to run
+#[derive(Debug, Clone)]
+pub struct RunCommand {
+    pub source: ChatCommand,
+    pub name: String,
+    // The fixed words that were matched ie ["add"] for /whitelist add <name>
+    pub literals: Vec<String>,
+    pub arguments: HashMap<String, Argument>,
+    pub script: Option<String>,
+}
+
+impl RunCommand {
+    pub fn reply(&self, server: &mut Server, message: String) {
+        self.source.reply(server, message);
+    }
+
+    pub fn literals(&self) -> Vec<&str> {
+        self.literals.iter().map(String::as_str).collect()
+    }
+
+    pub fn boolean(&self, name: &str) -> Option<bool> {
+        match self.arguments.get(name)? {
+            Argument::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn integer(&self, name: &str) -> Option<i32> {
+        match self.arguments.get(name)? {
+            Argument::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn number(&self, name: &str) -> Option<f32> {
+        match self.arguments.get(name)? {
+            Argument::Number(value) => Some(*value),
+            Argument::Integer(value) => Some(*value as f32),
+            _ => None,
+        }
+    }
+
+    // Words, text and block identifiers
+    pub fn text(&self, name: &str) -> Option<&str> {
+        match self.arguments.get(name)? {
+            Argument::Word(text) | Argument::Text(text) | Argument::Block(text) => Some(text),
+            Argument::Player { name, .. } => Some(name.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn position(&self, name: &str) -> Option<IVec3> {
+        match self.arguments.get(name)? {
+            Argument::Position(pos) => Some(*pos),
+            _ => None,
+        }
+    }
+
+    pub fn player(&self, name: &str) -> Option<(u64, &str)> {
+        match self.arguments.get(name)? {
+            Argument::Player { id, name } => Some((*id, name.as_str())),
+            _ => None,
+        }
+    }
+}
+
+// Someone typing a command wants to know what could come next
+#[derive(Debug, Clone)]
+pub struct CompletionRequest {
+    pub client_id: u64,
+    pub user_name: String,
+    pub input: String,
+}
+
+// The console can run anything
+pub fn permission_level(
+    moderation: &Moderation,
+    client_id: Option<u64>,
+    user_name: &str,
+) -> PermissionLevel {
+    if client_id.is_none() {
+        PermissionLevel::Console
+    } else if moderation.is_op(user_name) {
+        PermissionLevel::Operator
+    } else {
+        PermissionLevel::Player
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn dispatch_commands(
+    mut server: ResMut<Server>,
+    mut chat_commands: EventReader<ChatCommand>,
+    mut completion_requests: EventReader<CompletionRequest>,
+    mut run_commands: EventWriter<RunCommand>,
+    registry: Res<CommandRegistry>,
+    moderation: Res<Moderation>,
+    players: Query<(&Player, &ClientName, &VoxelPos)>,
+    block_table: Res<BlockTable>,
+) {
+    let online: Vec<(u64, String)> = players
+        .iter()
+        .map(|(player, name, _)| (player.id, (*name).clone()))
+        .collect();
+    let origin = |client_id: Option<u64>| {
+        players
+            .iter()
+            .find(|(player, _, _)| Some(player.id) == client_id)
+            .map(|(_, _, voxel_pos)| **voxel_pos)
+    };
+
+    for command in chat_commands.iter() {
+        let context = ParseContext {
+            players: &online,
+            block_table: &block_table,
+            origin: origin(command.client_id),
+        };
+        let level = permission_level(&moderation, command.client_id, &command.user_name);
+        match registry.parse(command, level, &context) {
+            Ok(run) => run_commands.send(run),
+            Err(e) => command.reply(&mut server, e),
+        }
+    }
+
+    for request in completion_requests.iter() {
+        let context = ParseContext {
+            players: &online,
+            block_table: &block_table,
+            origin: origin(Some(request.client_id)),
+        };
+        let level = permission_level(&moderation, Some(request.client_id), &request.user_name);
+        let suggestions = registry.complete(&request.input, level, &context);
+        server.endpoint_mut().try_send_message(
+            request.client_id,
+            ServerMessage::CommandCompletions {
+                input: request.input.clone(),
+                suggestions,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::IVec3;
+    use vinox_common::{
+        storage::blocks::descriptor::BlockDescriptor, world::chunks::storage::BlockTable,
+    };
+
+    use super::{
+        argument, literal, Argument, ArgumentType, CommandNode, CommandRegistry, ParseContext,
+        Parsed, PermissionLevel,
+    };
+    use crate::game::networking::components::ChatCommand;
+
+    fn block_table() -> BlockTable {
+        let mut block_table = BlockTable::default();
+        block_table.insert("vinox:stone".to_string(), BlockDescriptor::default());
+        block_table
+    }
+
+    fn players() -> Vec<(u64, String)> {
+        vec![(2, "bob".to_string()), (3, "carol".to_string())]
+    }
+
+    fn parse(node: &CommandNode, line: &str, context: &ParseContext) -> Result<Parsed, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let mut parsed = Parsed::default();
+        node.parse(&words, context, &mut parsed).map(|_| parsed)
+    }
+
+    fn chat(command: &str) -> ChatCommand {
+        ChatCommand {
+            client_id: Some(1),
+            user_name: "alice".to_string(),
+            command: command.to_string(),
+        }
+    }
+
+    fn registry() -> CommandRegistry {
+        let mut registry = CommandRegistry::default();
+        registry.register(literal("help").executes(), PermissionLevel::Player, "");
+        registry.register(
+            literal("kick").then(argument("player", ArgumentType::Player).executes()),
+            PermissionLevel::Operator,
+            "",
+        );
+        registry.register(
+            literal("whitelist")
+                .then(literal("add").then(argument("name", ArgumentType::Word).executes()))
+                .then(literal("list").executes()),
+            PermissionLevel::Operator,
+            "",
+        );
+        registry.register(
+            literal("tp").then(argument("pos", ArgumentType::Position).executes()),
+            PermissionLevel::Operator,
+            "",
+        );
+        registry.register(
+            literal("fill").then(argument("block", ArgumentType::Block).executes()),
+            PermissionLevel::Operator,
+            "",
+        );
+        registry
+    }
+
+    // An integer that doesn't lead anywhere is tried again as a word
+    #[test]
+    fn arguments_backtrack() {
+        let block_table = block_table();
+        let players = players();
+        let context = ParseContext {
+            players: &players,
+            block_table: &block_table,
+            origin: None,
+        };
+        let node = literal("set")
+            .then(argument("count", ArgumentType::Integer).then(literal("times").executes()))
+            .then(
+                argument("name", ArgumentType::Word)
+                    .then(argument("value", ArgumentType::Word).executes()),
+            );
+
+        let parsed = parse(&node, "5 times", &context).unwrap();
+        assert_eq!(parsed.arguments["count"], Argument::Integer(5));
+        assert_eq!(parsed.literals, vec!["times".to_string()]);
+        assert!(!parsed.arguments.contains_key("name"));
+
+        let parsed = parse(&node, "5 apples", &context).unwrap();
+        assert!(!parsed.arguments.contains_key("count"));
+        assert_eq!(parsed.arguments["name"], Argument::Word("5".to_string()));
+        assert_eq!(
+            parsed.arguments["value"],
+            Argument::Word("apples".to_string())
+        );
+        assert!(parsed.literals.is_empty());
+
+        let parsed = parse(&node, "five times", &context).unwrap();
+        assert_eq!(parsed.arguments["name"], Argument::Word("five".to_string()));
+        assert!(parse(&node, "5", &context).is_err());
+        assert!(parse(&node, "5 times more", &context).is_err());
+
+        // Only whole words are numbers
+        let node = literal("count").then(argument("count", ArgumentType::Integer).executes());
+        assert!(parse(&node, "5x", &context).is_err());
+        assert!(parse(&node, "99999999999", &context).is_err());
+        let node = literal("flag").then(argument("flag", ArgumentType::Boolean).executes());
+        assert_eq!(
+            parse(&node, "true", &context).unwrap().arguments["flag"],
+            Argument::Boolean(true)
+        );
+        assert!(parse(&node, "yes", &context).is_err());
+    }
+
+    #[test]
+    fn relative_positions() {
+        let block_table = block_table();
+        let players = players();
+        let node = CommandNode::from_syntax("tp", "<pos:position>").unwrap();
+        let context = ParseContext {
+            players: &players,
+            block_table: &block_table,
+            origin: Some(IVec3::new(10, 20, 30)),
+        };
+        assert_eq!(
+            parse(&node, "~ ~5 ~-3", &context).unwrap().arguments["pos"],
+            Argument::Position(IVec3::new(10, 25, 27))
+        );
+        assert_eq!(
+            parse(&node, "1 -2 3", &context).unwrap().arguments["pos"],
+            Argument::Position(IVec3::new(1, -2, 3))
+        );
+        assert!(parse(&node, "~x 0 0", &context).is_err());
+        assert!(parse(&node, "1 2", &context).is_err());
+
+        // The console isn't anywhere
+        let context = ParseContext {
+            origin: None,
+            ..context
+        };
+        assert!(parse(&node, "~ 0 0", &context)
+            .unwrap_err()
+            .contains("Only players"));
+        assert!(parse(&node, "0 0 0", &context).is_ok());
+    }
+
+    #[test]
+    fn optional_script_arguments() {
+        let block_table = block_table();
+        let players = players();
+        let context = ParseContext {
+            players: &players,
+            block_table: &block_table,
+            origin: None,
+        };
+        let node = CommandNode::from_syntax("add", "<name:word> [count:integer]").unwrap();
+        assert_eq!(node.usages(), vec!["add <name>", "add <name> <count>"]);
+
+        let parsed = parse(&node, "apple", &context).unwrap();
+        assert_eq!(
+            parsed.arguments["name"],
+            Argument::Word("apple".to_string())
+        );
+        assert!(!parsed.arguments.contains_key("count"));
+        let parsed = parse(&node, "apple 3", &context).unwrap();
+        assert_eq!(parsed.arguments["count"], Argument::Integer(3));
+        assert!(parse(&node, "", &context).is_err());
+        assert!(parse(&node, "apple three", &context).is_err());
+
+        let node = CommandNode::from_syntax("say", "to <who:player> [message:text]").unwrap();
+        let parsed = parse(&node, "to bob hello there", &context).unwrap();
+        assert_eq!(parsed.literals, vec!["to".to_string()]);
+        assert_eq!(
+            parsed.arguments["who"],
+            Argument::Player {
+                id: 2,
+                name: "bob".to_string()
+            }
+        );
+        assert_eq!(
+            parsed.arguments["message"],
+            Argument::Text("hello there".to_string())
+        );
+        assert!(parse(&node, "to dave", &context).is_err());
+
+        assert!(CommandNode::from_syntax("add", "[count:integer] <name:word>").is_err());
+        assert!(CommandNode::from_syntax("add", "<message:text> <name:word>").is_err());
+        assert!(CommandNode::from_syntax("add", "<name>").is_err());
+        assert!(CommandNode::from_syntax("add", "<name:thing>").is_err());
+    }
+
+    #[test]
+    fn permissions_are_checked() {
+        let block_table = block_table();
+        let players = players();
+        let context = ParseContext {
+            players: &players,
+            block_table: &block_table,
+            origin: None,
+        };
+        let registry = registry();
+        assert!(registry
+            .parse(&chat("/kick bob"), PermissionLevel::Player, &context)
+            .unwrap_err()
+            .contains("permission"));
+        let run = registry
+            .parse(&chat("/kick bob"), PermissionLevel::Operator, &context)
+            .unwrap();
+        assert_eq!(run.name, "kick");
+        assert_eq!(run.player("player"), Some((2, "bob")));
+        assert!(registry
+            .parse(&chat("/kick dave"), PermissionLevel::Operator, &context)
+            .is_err());
+        assert!(registry
+            .parse(&chat("/help"), PermissionLevel::Player, &context)
+            .is_ok());
+        assert!(registry
+            .parse(&chat("/nothing"), PermissionLevel::Console, &context)
+            .is_err());
+
+        let run = registry
+            .parse(
+                &chat("/whitelist add dave"),
+                PermissionLevel::Console,
+                &context,
+            )
+            .unwrap();
+        assert_eq!(run.literals(), vec!["add"]);
+        assert_eq!(run.text("name"), Some("dave"));
+        let run = registry
+            .parse(&chat("/fill stone"), PermissionLevel::Console, &context)
+            .unwrap();
+        assert_eq!(run.text("block"), Some("vinox:stone"));
+
+        // Scripts can't take over a name that is already used
+        let mut registry = registry;
+        registry.register_script(
+            "vinox:script".to_string(),
+            literal("help").executes(),
+            PermissionLevel::Player,
+            "",
+        );
+        assert!(registry.get("/help").unwrap().script.is_none());
+    }
+
+    #[test]
+    fn completions() {
+        let block_table = block_table();
+        let players = players();
+        let context = ParseContext {
+            players: &players,
+            block_table: &block_table,
+            origin: Some(IVec3::ZERO),
+        };
+        let registry = registry();
+        assert_eq!(
+            registry.complete("/", PermissionLevel::Player, &context),
+            vec!["/help"]
+        );
+        assert_eq!(
+            registry.complete("/", PermissionLevel::Operator, &context),
+            vec!["/fill", "/help", "/kick", "/tp", "/whitelist"]
+        );
+        assert_eq!(
+            registry.complete("/he", PermissionLevel::Player, &context),
+            vec!["/help"]
+        );
+        assert!(registry
+            .complete("/kick ", PermissionLevel::Player, &context)
+            .is_empty());
+        assert!(registry
+            .complete("help", PermissionLevel::Player, &context)
+            .is_empty());
+
+        // Whatever was typed before the last word is kept as it is
+        assert_eq!(
+            registry.complete("/kick  b", PermissionLevel::Operator, &context),
+            vec!["/kick  bob"]
+        );
+        assert_eq!(
+            registry.complete("/kick ", PermissionLevel::Operator, &context),
+            vec!["/kick bob", "/kick carol"]
+        );
+        assert_eq!(
+            registry.complete("/whitelist ", PermissionLevel::Operator, &context),
+            vec!["/whitelist add", "/whitelist list"]
+        );
+        assert_eq!(
+            registry.complete("/whitelist l", PermissionLevel::Operator, &context),
+            vec!["/whitelist list"]
+        );
+        assert_eq!(
+            registry.complete("/tp ~ ~ ", PermissionLevel::Operator, &context),
+            vec!["/tp ~ ~ ~"]
+        );
+        assert_eq!(
+            registry.complete("/fill vinox:s", PermissionLevel::Operator, &context),
+            vec!["/fill vinox:stone"]
+        );
+        assert!(registry
+            .complete("/kick bob ", PermissionLevel::Operator, &context)
+            .is_empty());
+    }
+}
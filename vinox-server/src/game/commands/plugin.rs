@@ -0,0 +1,19 @@
+use bevy::prelude::*;
+
+use super::{
+    builtin::{help_command, register_builtin_commands, spectator_command, tp_command},
+    dispatcher::{dispatch_commands, CommandRegistry, CompletionRequest, RunCommand},
+};
+
+pub struct CommandsPlugin;
+
+impl Plugin for CommandsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(CommandRegistry::default())
+            .add_event::<RunCommand>()
+            .add_event::<CompletionRequest>()
+            .add_startup_system(register_builtin_commands)
+            .add_system(dispatch_commands)
+            .add_systems((help_command, tp_command, spectator_command).after(dispatch_commands));
+    }
+}
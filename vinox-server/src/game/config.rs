@@ -0,0 +1,195 @@
+use std::{fs, net::IpAddr, path::Path};
+
+use bevy::prelude::*;
+use ron::ser::{to_string_pretty, PrettyConfig};
+use serde::{Deserialize, Serialize};
+use vinox_common::{
+    networking::protocol::DEFAULT_PORT, world::chunks::storage::HORIZONTAL_DISTANCE,
+};
+
+use super::world::storage::StorageBackend;
+
+pub const DEFAULT_CONFIG_PATH: &str = "server.ron";
+
+pub const USAGE: &str = "Usage: vinox-server [options]
+
+Options are read from server.ron in the working directory, flags override them.
+  --config <path>          Config file to use instead of server.ron
+  --bind <address>         Address to listen on
+  --port <port>            Port to listen on
+  --max-players <count>    Players allowed at once
+  --world <name>           World to load or create
+  --seed <seed>            Seed for a new world
+  --view-distance <chunks> Furthest a client may ask to load
+  --chunk-limit <count>    Chunks sent to each client per tick
+  --tick-rate <hz>         How often the server loop runs
+  --motd <message>         Sent to players when they join
+  --save-interval <secs>   How often changes are written to disk, 0 turns autosave off
+  --whitelist <true|false> Only let whitelisted players join
+  --storage <backend>      Where the world is kept: sqlite, region or memory
+  --chunk-diffs <true|false> Save chunks as the blocks changed since generation
+  --backup-interval <secs> How often the world is backed up, 0 turns backups off
+  --backups-kept <count>   Older backups are deleted past this, 0 keeps them all
+  --restore <backup>       Roll the world back to a backup before starting
+  --help                   Print this message";
+
+// Missing fields use the defaults so old config files keep working
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub port: u16,
+    pub max_players: usize,
+    pub world_name: String,
+    // Only used when the world is created, random if there isn't one
+    pub seed: Option<u32>,
+    pub view_distance: u32,
+    pub chunk_limit: usize,
+    // The simulation always steps at 60 ticks per second, this is how often the loop runs
+    pub tick_rate: f64,
+    pub motd: String,
+    // Seconds between autosaves, 0 only saves when chunks unload, players leave and on shutdown
+    pub save_interval: f32,
+    // Names are added with /whitelist, operators can always join
+    pub whitelist: bool,
+    // Only picks how a world is opened, existing worlds aren't converted between backends
+    pub storage: StorageBackend,
+    // Smaller saves for generating the chunk again when it loads and saves
+    pub chunk_diffs: bool,
+    // Seconds between backups, 0 only backs up when asked to
+    pub backup_interval: f32,
+    pub backups_kept: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: "0.0.0.0".to_string(),
+            port: DEFAULT_PORT,
+            max_players: 8,
+            world_name: "world".to_string(),
+            seed: None,
+            view_distance: HORIZONTAL_DISTANCE as u32,
+            chunk_limit: 16,
+            tick_rate: 60.0,
+            motd: "Welcome to Vinox!".to_string(),
+            save_interval: 10.0,
+            whitelist: false,
+            storage: StorageBackend::Sqlite,
+            chunk_diffs: false,
+            backup_interval: 6.0 * 60.0 * 60.0,
+            backups_kept: 10,
+        }
+    }
+}
+
+impl ServerConfig {
+    // A missing default config is written out so there is something to edit, a missing one that
+    // was asked for by name is a mistake
+    pub fn load(path: &Path, explicit: bool) -> Result<Self, String> {
+        if !path.exists() {
+            if explicit {
+                return Err(format!("Config file {} doesn't exist", path.display()));
+            }
+            let config = Self::default();
+            let pretty = PrettyConfig::new().depth_limit(2);
+            if let Ok(s) = to_string_pretty(&config, pretty) {
+                if fs::write(path, s).is_ok() {
+                    println!("Wrote default config to {}", path.display());
+                }
+            }
+            return Ok(config);
+        }
+        let s = fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read {}: {e}", path.display()))?;
+        ron::from_str(&s).map_err(|e| format!("Couldn't parse {}: {e}", path.display()))
+    }
+
+    // Flags take priority over the file, --config, --restore and --help are handled outside this
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), String> {
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| format!("{flag} needs a value"))?;
+            match flag.as_str() {
+                "--config" | "--restore" => {}
+                "--bind" => self.bind_address = value.clone(),
+                "--port" => self.port = parse_arg(flag, value)?,
+                "--max-players" => self.max_players = parse_arg(flag, value)?,
+                "--world" => self.world_name = value.clone(),
+                "--seed" => self.seed = Some(parse_arg(flag, value)?),
+                "--view-distance" => self.view_distance = parse_arg(flag, value)?,
+                "--chunk-limit" => self.chunk_limit = parse_arg(flag, value)?,
+                "--tick-rate" => self.tick_rate = parse_arg(flag, value)?,
+                "--motd" => self.motd = value.clone(),
+                "--save-interval" => self.save_interval = parse_arg(flag, value)?,
+                "--whitelist" => self.whitelist = parse_arg(flag, value)?,
+                "--storage" => self.storage = parse_arg(flag, value)?,
+                "--chunk-diffs" => self.chunk_diffs = parse_arg(flag, value)?,
+                "--backup-interval" => self.backup_interval = parse_arg(flag, value)?,
+                "--backups-kept" => self.backups_kept = parse_arg(flag, value)?,
+                _ => return Err(format!("Unknown option {flag}")),
+            }
+        }
+        Ok(())
+    }
+
+    // Every problem at once so a config doesn't have to be fixed one run at a time
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if self.bind_address.parse::<IpAddr>().is_err() {
+            errors.push(format!(
+                "bind_address {:?} isn't an IP address",
+                self.bind_address
+            ));
+        }
+        if self.port == 0 {
+            errors.push("port can't be 0".to_string());
+        }
+        if self.max_players == 0 {
+            errors.push("max_players has to be at least 1".to_string());
+        }
+        // The world name ends up in file names
+        if self.world_name.is_empty()
+            || !self
+                .world_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            errors.push(format!(
+                "world_name {:?} can only use letters, numbers, _ and -",
+                self.world_name
+            ));
+        }
+        if !(1..=HORIZONTAL_DISTANCE as u32).contains(&self.view_distance) {
+            errors.push(format!(
+                "view_distance has to be between 1 and {HORIZONTAL_DISTANCE}"
+            ));
+        }
+        if self.chunk_limit == 0 {
+            errors.push("chunk_limit has to be at least 1".to_string());
+        }
+        if !(1.0..=1000.0).contains(&self.tick_rate) {
+            errors.push("tick_rate has to be between 1 and 1000".to_string());
+        }
+        if self.motd.len() > 256 {
+            errors.push("motd can't be longer than 256 bytes".to_string());
+        }
+        if !(self.save_interval.is_finite() && self.save_interval >= 0.0) {
+            errors.push("save_interval can't be negative".to_string());
+        }
+        if !(self.backup_interval.is_finite() && self.backup_interval >= 0.0) {
+            errors.push("backup_interval can't be negative".to_string());
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{flag} got {value:?} which isn't a valid value"))
+}
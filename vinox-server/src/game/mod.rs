@@ -1,3 +1,7 @@
+pub mod admin;
+pub mod commands;
+pub mod config;
 pub mod networking;
 pub mod plugin;
+pub mod scripting;
 pub mod world;
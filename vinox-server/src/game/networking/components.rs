@@ -1,6 +1,16 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use bevy::prelude::*;
+use bevy_quinnet::{server::Server, shared::channel::ChannelId};
+use vinox_common::{
+    networking::protocol::ServerMessage,
+    physics::movement::InputFrame,
+    storage::items::descriptor::ItemData,
+    world::chunks::{
+        ecs::{LoadPoint, SentChunks},
+        positions::{ChunkPos, VoxelPos},
+    },
+};
 
 // TODO: Not networking move to different file
 #[derive(Debug, Resource, Deref, DerefMut)]
@@ -18,7 +28,203 @@ pub struct LocalGame(pub bool);
 #[derive(Debug, Default, Resource)]
 pub struct ServerLobby {
     pub players: HashMap<u64, Entity>,
+    // Clients whose protocol and assets match ours, only these are allowed to join
+    pub verified: HashSet<u64>,
+    // Kicked clients and when to close their connection, they get a moment to read why
+    pub disconnecting: HashMap<u64, f32>,
+    // Kept here rather than on the player so a break sent in the same update still sees it
+    pub breaking: HashMap<u64, BreakingBlock>,
+    // Asset files each client is still waiting on, a client only gets to ask once per connection
+    pub downloads: HashMap<u64, VecDeque<String>>,
 }
 
 #[derive(Debug, Default, Resource, Deref, DerefMut)]
 pub struct ChunkLimit(pub usize);
+
+// Compressed chunk bytes each client can be sent per tick
+#[derive(Debug, Default, Resource, Deref, DerefMut)]
+pub struct ChunkBandwidth(pub usize);
+
+// Chunks a player still needs, sorted so the most important one is at the end
+#[derive(Debug, Default, Component)]
+pub struct ChunkQueue {
+    pub chunks: Vec<ChunkPos>,
+    center: Option<ChunkPos>,
+    radius: (i32, i32),
+    forward: Vec3,
+    sent: usize,
+}
+
+impl ChunkQueue {
+    // Anything removed from SentChunks elsewhere has to be sent again so that counts too
+    pub fn is_stale(
+        &self,
+        load_point: &LoadPoint,
+        forward: Vec3,
+        sent_chunks: &SentChunks,
+    ) -> bool {
+        self.center != Some(load_point.chunk_pos)
+            || self.radius != (load_point.horizontal, load_point.vertical)
+            || self.sent != sent_chunks.chunks.len()
+            || self.forward.dot(forward) < 0.7
+    }
+
+    pub fn rebuild(&mut self, load_point: &LoadPoint, forward: Vec3, sent_chunks: &SentChunks) {
+        let mut chunks = Vec::new();
+        for x in -load_point.horizontal..=load_point.horizontal {
+            for y in -load_point.vertical..=load_point.vertical {
+                for z in -load_point.horizontal..=load_point.horizontal {
+                    let offset = IVec3::new(x, y, z);
+                    let chunk_pos = ChunkPos(*load_point.chunk_pos + offset);
+                    if !sent_chunks.chunks.contains(&chunk_pos) {
+                        chunks.push((chunk_pos, chunk_priority(offset, forward)));
+                    }
+                }
+            }
+        }
+        chunks.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        self.chunks = chunks.into_iter().map(|(chunk_pos, _)| chunk_pos).collect();
+        self.center = Some(load_point.chunk_pos);
+        self.radius = (load_point.horizontal, load_point.vertical);
+        self.forward = forward;
+        self.sent = sent_chunks.chunks.len();
+    }
+
+    pub fn mark_sent(&mut self, sent_chunks: &SentChunks) {
+        self.sent = sent_chunks.chunks.len();
+    }
+}
+
+// Lower goes first, chunks behind the player count as up to twice as far away
+fn chunk_priority(offset: IVec3, forward: Vec3) -> f32 {
+    let distance = offset.as_vec3().length();
+    if distance < 2.0 {
+        return distance;
+    }
+    distance * (1.5 - 0.5 * offset.as_vec3().normalize().dot(forward))
+}
+
+// Counts FixedUpdate ticks since the server started, entity updates are stamped with it
+#[derive(Debug, Default, Resource, Deref, DerefMut)]
+pub struct ServerTick(pub u32);
+
+// How often clients are told the current tick to keep their clocks in line
+pub const TIME_SYNC_TICKS: u32 = 30;
+
+// Every this many ticks everything is sent again in case an unreliable update was lost
+pub const FULL_UPDATE_TICKS: u32 = 60;
+
+// Translation, yaw and head pitch
+pub type EntityDelta = (Option<Vec3>, Option<f32>, Option<f32>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntityState {
+    pub translation: Vec3,
+    pub yaw: f32,
+    pub head_pitch: f32,
+}
+
+impl EntityState {
+    // Only what moved far enough to notice, None if there is nothing to send
+    pub fn delta(&self, new: &EntityState, full: bool) -> Option<EntityDelta> {
+        let translation = (full || self.translation.distance_squared(new.translation) > 0.0001)
+            .then_some(new.translation);
+        let yaw = (full || (self.yaw - new.yaw).abs() > 0.01).then_some(new.yaw);
+        let head_pitch =
+            (full || (self.head_pitch - new.head_pitch).abs() > 0.01).then_some(new.head_pitch);
+        if translation.is_none() && yaw.is_none() && head_pitch.is_none() {
+            None
+        } else {
+            Some((translation, yaw, head_pitch))
+        }
+    }
+}
+
+// What a player's client knows about the entities around it
+#[derive(Debug, Default, Component)]
+pub struct EntityInterest {
+    pub known: HashMap<Entity, EntityState>,
+    pub ticks: u32,
+}
+
+// How far ahead of the simulation a client may queue inputs, newer frames wait to be resent
+pub const MAX_QUEUED_INPUTS: usize = 60;
+// Frames we may run in one tick after a late packet, on average it is still one per tick so
+// sending inputs faster doesn't make anyone move faster
+const MAX_INPUT_BUDGET: u32 = 5;
+
+#[derive(Debug, Default, Component)]
+pub struct PlayerInputs {
+    pub queue: VecDeque<InputFrame>,
+    // Newest sequence we queued, anything at or below it is a resend
+    pub last_sequence: Option<u32>,
+    budget: u32,
+}
+
+impl PlayerInputs {
+    pub fn push(&mut self, mut frames: Vec<InputFrame>) {
+        frames.sort_by_key(|frame| frame.sequence);
+        for frame in frames {
+            if self.queue.len() >= MAX_QUEUED_INPUTS {
+                break;
+            }
+            let newer = self
+                .last_sequence
+                .map_or(true, |last| frame.sequence > last);
+            if newer {
+                self.last_sequence = Some(frame.sequence);
+                self.queue.push_back(frame);
+            }
+        }
+    }
+
+    // The frames to simulate this tick
+    pub fn take_tick(&mut self) -> Vec<InputFrame> {
+        self.budget = (self.budget + 1).min(MAX_INPUT_BUDGET);
+        let count = (self.budget as usize).min(self.queue.len());
+        self.budget -= count as u32;
+        self.queue.drain(..count).collect()
+    }
+}
+
+// Which block a player started breaking and when so we can check they actually took long enough
+#[derive(Debug)]
+pub struct BreakingBlock {
+    pub pos: VoxelPos,
+    pub started: f32,
+}
+
+// An item lying in the world, the first player with room walking over it picks it up. These
+// aren't saved so anything left lying around is gone after a restart
+#[derive(Debug, Component)]
+pub struct DroppedItem(pub ItemData);
+
+// From the middle of a player to the middle of the item
+pub const PICKUP_DISTANCE: f32 = 1.5;
+
+// Chat messages starting with / are handled on the server instead of being broadcast
+#[derive(Debug, Clone)]
+pub struct ChatCommand {
+    // None when it was typed into the server console
+    pub client_id: Option<u64>,
+    pub user_name: String,
+    pub command: String,
+}
+
+impl ChatCommand {
+    pub fn reply(&self, server: &mut Server, message: String) {
+        if let Some(client_id) = self.client_id {
+            server.endpoint_mut().try_send_message_on(
+                client_id,
+                ChannelId::OrderedReliable(1),
+                ServerMessage::ChatMessage {
+                    user_name: "Server".to_string(),
+                    message,
+                    id: 0,
+                },
+            );
+        } else {
+            println!("{message}");
+        }
+    }
+}
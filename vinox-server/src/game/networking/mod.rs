@@ -2,3 +2,4 @@ pub mod components;
 pub mod plugin;
 pub mod start;
 pub mod syncing;
+pub mod validation;
@@ -1,9 +1,12 @@
 use bevy::prelude::*;
 
 use super::{
-    components::ServerLobby,
-    start::{new_server, setup_loadables},
-    syncing::{connections, get_messages, send_chunks, send_entities, sync_voxel_pos},
+    components::{ChatCommand, ServerLobby, ServerTick},
+    start::{new_server, setup_block_registry, setup_loadables, setup_manifest},
+    syncing::{
+        advance_tick, connections, get_messages, pickup_items, send_assets, send_chunks,
+        send_entities, simulate_movement, sync_voxel_pos,
+    },
 };
 
 pub struct NetworkingPlugin;
@@ -11,13 +14,24 @@ pub struct NetworkingPlugin;
 impl Plugin for NetworkingPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(ServerLobby::default())
+            .insert_resource(ServerTick::default())
             .add_startup_system(setup_loadables)
+            .add_startup_system(setup_block_registry.after(setup_loadables))
+            .add_startup_system(setup_manifest)
             .add_startup_system(new_server)
             .add_systems(
-                (send_chunks, send_entities)
+                (
+                    advance_tick,
+                    simulate_movement,
+                    pickup_items,
+                    send_assets,
+                    send_chunks,
+                    send_entities,
+                )
                     .chain()
                     .in_schedule(CoreSchedule::FixedUpdate),
             )
-            .add_systems((get_messages, connections, sync_voxel_pos));
+            .add_systems((get_messages, connections, sync_voxel_pos))
+            .add_event::<ChatCommand>();
     }
 }
@@ -1,19 +1,29 @@
 use acap::euclid::Euclidean;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::IpAddr;
 
 use bevy::prelude::*;
 use bevy_quinnet::server::*;
 use vinox_common::{
+    networking::assets::{assets_path, AssetManifest},
     storage::{
         biomes::load::load_all_biomes,
         blocks::load::load_all_blocks,
         crafting::load::load_all_recipes,
+        feature_rules::load::load_all_feature_rules,
+        features::load::load_all_features,
         items::load::{item_from_block, load_all_items},
+        structures::load::load_all_structures,
+    },
+    world::{
+        chunks::storage::{
+            BiomeTable, BlockTable, FeatureRuleTable, FeatureTable, ItemTable, RecipeTable,
+            StructureTable,
+        },
+        generation::terrain::{biome_depth, BiomeHashmap, BiomeTree},
     },
-    world::chunks::storage::{BiomeTable, BlockTable, ItemTable, RecipeTable},
 };
 
-use crate::game::world::generation::{BiomeHashmap, BiomeTree};
+use crate::game::{config::ServerConfig, world::storage::WorldDatabase};
 
 pub fn setup_loadables(
     mut block_table: ResMut<BlockTable>,
@@ -22,8 +32,15 @@ pub fn setup_loadables(
     mut biome_table: ResMut<BiomeTable>,
     mut biome_tree: ResMut<BiomeTree>,
     mut biome_hashmap: ResMut<BiomeHashmap>,
+    mut feature_table: ResMut<FeatureTable>,
+    mut feature_rule_table: ResMut<FeatureRuleTable>,
+    mut structure_table: ResMut<StructureTable>,
 ) {
-    for block in load_all_blocks() {
+    let Some(assets) = assets_path() else {
+        println!("Couldn't find the data directory");
+        return;
+    };
+    for block in load_all_blocks(&assets) {
         let mut name = block.clone().namespace;
         name.push(':');
         name.push_str(&block.name);
@@ -34,35 +51,79 @@ pub fn setup_loadables(
         }
         block_table.insert(name, block);
     }
-    for recipe in load_all_recipes() {
+    for recipe in load_all_recipes(&assets) {
         let mut name = recipe.clone().namespace;
         name.push(':');
         name.push_str(&recipe.name);
         recipe_table.insert(name, recipe);
     }
-    for item in load_all_items() {
+    for item in load_all_items(&assets) {
         let mut name = item.clone().namespace;
         name.push(':');
         name.push_str(&item.name);
         item_table.insert(name, item);
     }
-    for biome in load_all_biomes() {
+    for biome in load_all_biomes(&assets) {
         let mut name = biome.clone().namespace;
         name.push(':');
         name.push_str(&biome.name);
-        biome_hashmap.insert(
-            IVec2::new(biome.heat.clone(), biome.humidity.clone()),
-            name.clone(),
-        );
-        biome_tree.push(Euclidean([biome.heat.clone(), biome.humidity.clone()]));
+        let depth = biome_depth(biome.depth_bias);
+        biome_hashmap.insert(IVec3::new(biome.heat, biome.humidity, depth), name.clone());
+        biome_tree.push(Euclidean([biome.heat, biome.humidity, depth]));
         biome_table.insert(name, biome);
     }
+    for feature in load_all_features() {
+        let mut name = feature.clone().namespace;
+        name.push(':');
+        name.push_str(&feature.name);
+        feature_table.insert(name, feature);
+    }
+    for feature_rule in load_all_feature_rules() {
+        let mut name = feature_rule.clone().namespace;
+        name.push(':');
+        name.push_str(&feature_rule.name);
+        feature_rule_table.insert(name, feature_rule);
+    }
+    for structure in load_all_structures() {
+        let mut name = structure.clone().namespace;
+        name.push(':');
+        name.push_str(&structure.name);
+        structure_table.insert(name, structure);
+    }
+}
+
+// Hashed once on startup, clients sync against this before joining
+pub fn setup_manifest(mut commands: Commands) {
+    let manifest = AssetManifest::load();
+    println!(
+        "Serving {} asset files with content hash {:x}",
+        manifest.len(),
+        manifest.content_hash()
+    );
+    commands.insert_resource(manifest);
+}
+
+// Blocks keep the id they were first saved with, new ones are added on the end
+pub fn setup_block_registry(
+    mut commands: Commands,
+    block_table: Res<BlockTable>,
+    database: Res<WorldDatabase>,
+) {
+    let mut block_registry = database.load_block_registry();
+    let new_ids = block_registry.register_all(&block_table);
+    if !new_ids.is_empty() {
+        println!("Registered {} new block ids", new_ids.len());
+        database.save_block_ids(&new_ids);
+    }
+    commands.insert_resource(block_registry);
 }
 
-pub fn new_server(mut server: ResMut<Server>) {
+pub fn new_server(mut server: ResMut<Server>, config: Res<ServerConfig>) {
+    // Already checked when the config was loaded
+    let bind_address: IpAddr = config.bind_address.parse().unwrap();
     server
         .start_endpoint(
-            ServerConfiguration::from_ip(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 25565),
+            ServerConfiguration::from_ip(bind_address, config.port),
             certificate::CertificateRetrievalMode::GenerateSelfSigned {
                 server_hostname: "vinox".to_string(), //TODO: Change to computer hostname
             },
@@ -1,29 +1,56 @@
-use std::io::Cursor;
+use std::{collections::BTreeSet, io::Cursor};
 
 use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use rand::{rngs::OsRng, Rng};
+use rand::rngs::OsRng;
 use rustc_data_structures::stable_set::FxHashSet;
 
-use bevy::{app::AppExit, prelude::*};
+use bevy::{app::AppExit, prelude::*, render::primitives::Aabb};
 use bevy_quinnet::server::*;
 use vinox_common::{
-    ecs::bundles::{ClientName, Inventory, PlayerBundleBuilder},
-    networking::protocol::{ClientMessage, NetworkedEntities, Player, ServerMessage},
+    ecs::bundles::{ClientName, HeldItem, Inventory, InventoryOp, PlayerBundleBuilder},
+    networking::{
+        assets::{read_asset, AssetManifest, ASSET_BANDWIDTH, PROTOCOL_VERSION},
+        protocol::{
+            ClientMessage, HeadPitch, NetworkedEntities, NetworkedEntity, Player, ServerMessage,
+        },
+    },
+    physics::{
+        movement::{step_movement, MoveState},
+        simulate::CollisionWorld,
+    },
+    scripting::runtime::{ScriptEvent, ScriptHook},
+    storage::items::descriptor::ItemData,
     world::chunks::{
-        ecs::{ChunkManager, CurrentChunks, LoadPoint, NeedsChunkData, PrepassChunk, SentChunks},
+        ecs::{ChunkManager, LoadPoint, NeedsChunkData, PrepassChunk, SentChunks},
         positions::{ChunkPos, RelativeVoxelPos, VoxelPos},
-        storage::{BlockTable, ChunkData},
+        storage::{
+            identifier_to_name, name_to_identifier, trim_geo_identifier, BlockData, BlockRegistry,
+            ItemTable, RecipeTable, VERTICAL_DISTANCE,
+        },
     },
 };
 use zstd::stream::copy_encode;
 
-use crate::game::world::{
-    chunk::GeneratingChunk,
-    storage::{load_player, ChunksToSave, FirstSaves, PlayersToSave, SavedPlayer, WorldDatabase},
+use crate::game::{
+    admin::moderation::{refuse, Moderation},
+    commands::dispatcher::CompletionRequest,
+    config::ServerConfig,
+    world::{
+        chunk::GeneratingChunk,
+        storage::{FirstSaves, ModifiedChunks, PlayersToSave, SavedPlayer, WorldDatabase},
+    },
 };
 
-use super::components::{ChunkLimit, LocalGame, ServerLobby};
+use super::{
+    components::{
+        BreakingBlock, ChatCommand, ChunkBandwidth, ChunkLimit, ChunkQueue, DroppedItem,
+        EntityInterest, EntityState, LocalGame, PlayerInputs, ServerLobby, ServerTick,
+        FULL_UPDATE_TICKS, PICKUP_DISTANCE, TIME_SYNC_TICKS,
+    },
+    validation::{held_item, in_reach, inside_player, BREAK_LEEWAY},
+};
 
+#[allow(clippy::too_many_arguments)]
 pub fn connections(
     mut commands: Commands,
     mut server: ResMut<Server>,
@@ -32,30 +59,156 @@ pub fn connections(
     mut connection_lost_events: EventReader<ConnectionLostEvent>,
     local_game: Res<LocalGame>,
     mut exit: EventWriter<AppExit>,
+    manifest: Res<AssetManifest>,
+    config: Res<ServerConfig>,
+    mut players: Query<(&ClientName, &Transform, &mut Inventory, &mut HeldItem)>,
+    mut players_to_save: ResMut<PlayersToSave>,
+    item_table: Res<ItemTable>,
 ) {
     for client in connection_lost_events.iter() {
         let id = client.id;
+        lobby.verified.remove(&id);
+        lobby.disconnecting.remove(&id);
+        lobby.breaking.remove(&id);
+        lobby.downloads.remove(&id);
         if **local_game {
             exit.send(AppExit);
         } else {
             println!("Player {id} disconnected.");
             if let Some(player_entity) = lobby.players.remove(&id) {
+                if let Ok((name, transform, mut inventory, mut held)) =
+                    players.get_mut(player_entity)
+                {
+                    return_held(
+                        &mut commands,
+                        transform,
+                        &mut inventory,
+                        &mut held,
+                        &item_table,
+                    );
+                    players_to_save
+                        .push(((*name).clone(), SavedPlayer::new(transform, &inventory)));
+                }
                 commands.entity(player_entity).despawn();
             }
 
-            server
-                .endpoint_mut()
-                .try_broadcast_message(&ServerMessage::PlayerRemove { id });
+            server.endpoint_mut().try_broadcast_message_on(
+                bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                ServerMessage::PlayerRemove { id },
+            );
         }
     }
     for client in connection_events.iter() {
-        // Refuse connection once we already have two players
-        if lobby.players.len() >= 8 {
+        // Refuse connection once the server is full
+        if lobby.players.len() >= config.max_players {
             server.endpoint_mut().disconnect_client(client.id).unwrap();
         } else {
-            server
-                .endpoint_mut()
-                .try_send_message(client.id, ServerMessage::ClientId { id: client.id });
+            server.endpoint_mut().try_send_message(
+                client.id,
+                ServerMessage::Handshake {
+                    protocol_version: PROTOCOL_VERSION,
+                    manifest: manifest.clone(),
+                },
+            );
+        }
+    }
+}
+
+pub fn drop_item(commands: &mut Commands, translation: Vec3, item: ItemData) {
+    commands.spawn((
+        DroppedItem(item),
+        TransformBundle::from_transform(Transform::from_translation(translation)),
+        NetworkedEntity,
+    ));
+}
+
+// Whatever doesn't fit back into the inventory is dropped where the player is
+pub fn return_held(
+    commands: &mut Commands,
+    transform: &Transform,
+    inventory: &mut Inventory,
+    held: &mut HeldItem,
+    item_table: &ItemTable,
+) {
+    let Some(stack) = held.0.take() else {
+        return;
+    };
+    if let Some(left) = inventory.add_stack(stack, item_table) {
+        drop_item(
+            commands,
+            transform.translation + Vec3::new(0.0, 0.9, 0.0),
+            left,
+        );
+    }
+}
+
+// Asset files go out a bandwidth's worth per client each tick so a download can't hold up
+// everyone else
+pub fn send_assets(mut server: ResMut<Server>, mut lobby: ResMut<ServerLobby>) {
+    for (client_id, queue) in lobby.downloads.iter_mut() {
+        if queue.is_empty() {
+            continue;
+        }
+        let mut files = Vec::new();
+        let mut bytes = 0;
+        while bytes < ASSET_BANDWIDTH {
+            let Some(path) = queue.pop_front() else {
+                break;
+            };
+            if let Some(file) = read_asset(&path) {
+                bytes += file.len();
+                files.push((path, file));
+            }
+        }
+        // Ordered so the one with nothing remaining is the last to arrive
+        server.endpoint_mut().try_send_message_on(
+            *client_id,
+            bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+            ServerMessage::AssetFiles {
+                files,
+                remaining: queue.len(),
+            },
+        );
+    }
+}
+
+// Anyone standing on an item takes as much of it as fits
+pub fn pickup_items(
+    mut commands: Commands,
+    mut server: ResMut<Server>,
+    lobby: Res<ServerLobby>,
+    mut players: Query<(&Transform, &mut Inventory)>,
+    mut items: Query<(Entity, &Transform, &mut DroppedItem)>,
+    item_table: Res<ItemTable>,
+) {
+    for (client_id, player_entity) in lobby.players.iter() {
+        let Ok((player_transform, mut inventory)) = players.get_mut(*player_entity) else {
+            continue;
+        };
+        // The transform is at the feet and items sit in the middle of a block
+        let center = player_transform.translation + Vec3::new(0.0, 0.9, 0.0);
+        for (item_entity, transform, mut dropped) in items.iter_mut() {
+            if dropped.0.stack_size == 0 || center.distance(transform.translation) > PICKUP_DISTANCE
+            {
+                continue;
+            }
+            let old_inventory = inventory.clone();
+            match inventory.add_stack(dropped.0.clone(), &item_table) {
+                Some(left) if left.stack_size == dropped.0.stack_size => continue,
+                Some(left) => dropped.0 = left,
+                None => {
+                    // Zeroed so nobody else grabs it before the despawn goes through
+                    dropped.0.stack_size = 0;
+                    commands.entity(item_entity).despawn();
+                }
+            }
+            server.endpoint_mut().try_send_message_on(
+                *client_id,
+                bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                ServerMessage::InventoryDelta {
+                    delta: old_inventory.diff(&inventory),
+                },
+            );
         }
     }
 }
@@ -68,34 +221,138 @@ pub fn get_messages(
     mut lobby: ResMut<ServerLobby>,
     mut players: Query<(Entity, &Player, &Transform, &ClientName, &mut Inventory)>,
     player_builder: Res<PlayerBundleBuilder>,
-    mut chunks: Query<&mut ChunkData>,
-    current_chunks: Res<CurrentChunks>,
-    mut chunks_to_save: ResMut<ChunksToSave>,
+    mut chunk_manager: ChunkManager,
+    (item_table, recipe_table, manifest, block_registry, config, moderation): (
+        Res<ItemTable>,
+        Res<RecipeTable>,
+        Res<AssetManifest>,
+        Res<BlockRegistry>,
+        Res<ServerConfig>,
+        Res<Moderation>,
+    ),
+    time: Res<Time>,
+    mut modified_chunks: ResMut<ModifiedChunks>,
     mut players_to_save: ResMut<PlayersToSave>,
     mut first_saves: ResMut<FirstSaves>,
     database: Res<WorldDatabase>,
-    _block_table: Res<BlockTable>,
+    (mut script_events, mut chat_commands, mut completion_requests): (
+        EventWriter<ScriptEvent>,
+        EventWriter<ChatCommand>,
+        EventWriter<CompletionRequest>,
+    ),
+    (mut held_items, mut load_points, mut player_inputs): (
+        Query<&mut HeldItem>,
+        Query<&mut LoadPoint>,
+        Query<&mut PlayerInputs>,
+    ),
 ) {
     let endpoint = server.endpoint_mut();
     for client_id in endpoint.clients() {
         while let Some(message) = endpoint.try_receive_message_from::<ClientMessage>(client_id) {
+            // Kicked clients are only waiting to be disconnected
+            if lobby.disconnecting.contains_key(&client_id) {
+                continue;
+            }
             match message {
+                ClientMessage::RequestAssets { files } => {
+                    if lobby.verified.contains(&client_id)
+                        || lobby.downloads.contains_key(&client_id)
+                    {
+                        refuse(
+                            endpoint,
+                            &mut lobby,
+                            client_id,
+                            "Assets can only be asked for once".to_string(),
+                            time.elapsed_seconds(),
+                        );
+                        continue;
+                    }
+                    // Only ever hand out what is in the manifest and each file once, send_assets
+                    // spreads them over as many ticks as it takes
+                    let files: BTreeSet<String> = files
+                        .into_iter()
+                        .filter(|path| manifest.contains_key(path))
+                        .collect();
+                    println!("Sending {} asset files to {client_id}", files.len());
+                    if files.is_empty() {
+                        endpoint.try_send_message_on(
+                            client_id,
+                            bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                            ServerMessage::AssetFiles {
+                                files: Vec::new(),
+                                remaining: 0,
+                            },
+                        );
+                    }
+                    lobby
+                        .downloads
+                        .insert(client_id, files.into_iter().collect());
+                }
+                ClientMessage::AssetsReady {
+                    protocol_version,
+                    content_hash,
+                } => {
+                    let refused = if protocol_version != PROTOCOL_VERSION {
+                        Some(format!(
+                            "Protocol {protocol_version} but the server uses {PROTOCOL_VERSION}"
+                        ))
+                    } else if content_hash != manifest.content_hash() {
+                        Some("Assets don't match the server's".to_string())
+                    } else {
+                        None
+                    };
+                    if let Some(reason) = refused {
+                        refuse(
+                            endpoint,
+                            &mut lobby,
+                            client_id,
+                            reason,
+                            time.elapsed_seconds(),
+                        );
+                    } else {
+                        lobby.downloads.remove(&client_id);
+                        lobby.verified.insert(client_id);
+                        endpoint.try_send_message(
+                            client_id,
+                            ServerMessage::ClientId {
+                                id: client_id,
+                                block_registry: block_registry.clone(),
+                            },
+                        );
+                    }
+                }
                 ClientMessage::Join {
                     id,
                     user_name,
                     password,
                 } => {
+                    if !lobby.verified.contains(&client_id) || id != client_id {
+                        println!("{user_name} tried to join without syncing assets");
+                        continue;
+                    }
+                    if let Err(reason) = moderation.can_join(&user_name, config.whitelist) {
+                        refuse(
+                            endpoint,
+                            &mut lobby,
+                            client_id,
+                            reason,
+                            time.elapsed_seconds(),
+                        );
+                        continue;
+                    }
                     println!("Player {user_name} connected.");
 
-                    // Initialize other players for this new client
+                    // Initialize other players for this new client, on the same ordered channel
+                    // as entity spawns so those never arrive before the player they belong to
                     for (entity, player, transform, client_name, inventory) in players.iter_mut() {
-                        endpoint.try_send_message(
+                        endpoint.try_send_message_on(
                             id,
+                            bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
                             ServerMessage::PlayerCreate {
                                 id: player.id,
                                 entity,
                                 translation: transform.translation,
-                                yaw: transform.rotation.to_euler(EulerRot::XYZ).1,
+                                yaw: transform.rotation.to_euler(EulerRot::YXZ).0,
                                 head_pitch: transform.rotation.to_euler(EulerRot::XYZ).0,
                                 user_name: (*client_name).clone(),
                                 init: false,
@@ -104,8 +361,7 @@ pub fn get_messages(
                         );
                     }
 
-                    let data = database.connection.get().unwrap();
-                    let player = if let Some(player) = load_player(user_name.clone(), &data) {
+                    let player = if let Some(player) = database.load_player(&user_name) {
                         if let Ok(hashed) = PasswordHash::new(player.1.as_str()) {
                             if Argon2::default()
                                 .verify_password(password.as_bytes(), &hashed)
@@ -208,70 +464,318 @@ pub fn get_messages(
                     };
 
                     if let Some(player) = player {
-                        let player_entity = commands.spawn(player.0).id();
+                        let player_entity = commands
+                            .spawn((
+                                player.0,
+                                HeldItem::default(),
+                                ChunkQueue::default(),
+                                EntityInterest::default(),
+                                NetworkedEntity,
+                                HeadPitch::default(),
+                                MoveState::new(player.2.translation),
+                                PlayerInputs::default(),
+                            ))
+                            .id();
 
-                        endpoint.try_broadcast_message(&ServerMessage::PlayerCreate {
-                            id,
-                            entity: player_entity,
-                            translation: player.2.translation,
-                            yaw: player.2.rotation.to_euler(EulerRot::XYZ).1,
-                            head_pitch: player.2.rotation.to_euler(EulerRot::XYZ).0,
-                            user_name,
-                            init: true,
-                            inventory: Box::new(player.3),
-                        });
+                        endpoint.try_broadcast_message_on(
+                            bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                            ServerMessage::PlayerCreate {
+                                id,
+                                entity: player_entity,
+                                translation: player.2.translation,
+                                yaw: player.2.rotation.to_euler(EulerRot::YXZ).0,
+                                head_pitch: player.2.rotation.to_euler(EulerRot::XYZ).0,
+                                user_name,
+                                init: true,
+                                inventory: Box::new(player.3),
+                            },
+                        );
 
                         lobby.players.insert(id, player_entity);
+                        if !config.motd.is_empty() {
+                            endpoint.try_send_message(
+                                id,
+                                ServerMessage::ChatMessage {
+                                    user_name: "Server".to_string(),
+                                    message: config.motd.clone(),
+                                    id: 0,
+                                },
+                            );
+                        }
                     }
                 }
                 ClientMessage::Leave { id } => {
                     println!("Player {id} disconnected.");
+                    lobby.breaking.remove(&id);
                     if let Some(player_entity) = lobby.players.remove(&id) {
+                        if let (Ok((_, _, transform, name, mut inventory)), Ok(mut held)) = (
+                            players.get_mut(player_entity),
+                            held_items.get_mut(player_entity),
+                        ) {
+                            return_held(
+                                &mut commands,
+                                transform,
+                                &mut inventory,
+                                &mut held,
+                                &item_table,
+                            );
+                            players_to_save
+                                .push(((*name).clone(), SavedPlayer::new(transform, &inventory)));
+                        }
                         commands.entity(player_entity).despawn();
                     }
 
-                    endpoint.try_broadcast_message(&ServerMessage::PlayerRemove { id });
+                    endpoint.try_broadcast_message_on(
+                        bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                        ServerMessage::PlayerRemove { id },
+                    );
                 }
-                ClientMessage::Position {
-                    player_pos,
-                    yaw,
-                    head_pitch: _,
+                ClientMessage::Inputs { frames } => {
+                    if let Some(mut inputs) = lobby
+                        .players
+                        .get(&client_id)
+                        .and_then(|entity| player_inputs.get_mut(*entity).ok())
+                    {
+                        inputs.push(frames);
+                    }
+                }
+
+                ClientMessage::ViewDistance {
+                    horizontal,
+                    vertical,
                 } => {
-                    if let Some(player_entity) = lobby.players.get(&client_id) {
-                        commands.entity(*player_entity).insert(
-                            Transform::from_translation(player_pos)
-                                .with_rotation(Quat::from_euler(EulerRot::XYZ, 0.0, yaw, 0.0)),
+                    let Some(mut load_point) = lobby
+                        .players
+                        .get(&client_id)
+                        .and_then(|entity| load_points.get_mut(*entity).ok())
+                    else {
+                        continue;
+                    };
+                    let max_vertical = config.view_distance.min(VERTICAL_DISTANCE as u32);
+                    load_point.horizontal = horizontal.clamp(1, config.view_distance) as i32;
+                    load_point.vertical = vertical.clamp(1, max_vertical) as i32;
+                }
+                ClientMessage::StartBreak {
+                    chunk_pos,
+                    voxel_pos,
+                } => {
+                    if lobby.players.contains_key(&client_id) {
+                        lobby.breaking.insert(
+                            client_id,
+                            BreakingBlock {
+                                pos: VoxelPos::from((
+                                    RelativeVoxelPos::new(
+                                        voxel_pos[0] as u32,
+                                        voxel_pos[1] as u32,
+                                        voxel_pos[2] as u32,
+                                    ),
+                                    ChunkPos(chunk_pos),
+                                )),
+                                started: time.elapsed_seconds(),
+                            },
                         );
                     }
                 }
-
                 ClientMessage::SentBlock {
                     chunk_pos,
                     voxel_pos,
-                    block_type,
+                    block_type: packed_block,
                 } => {
-                    if let Some(chunk_entity) = current_chunks.get_entity(ChunkPos(chunk_pos)) {
-                        if let Ok(mut chunk) = chunks.get_mut(chunk_entity) {
-                            chunk.set(
-                                RelativeVoxelPos::new(
-                                    voxel_pos[0] as u32,
-                                    voxel_pos[1] as u32,
-                                    voxel_pos[2] as u32,
-                                ),
-                                block_type.clone(),
-                            );
-                            chunks_to_save.push((ChunkPos(chunk_pos), chunk.to_raw()));
-                            endpoint.try_broadcast_message(ServerMessage::SentBlock {
+                    let Some(player_entity) = lobby.players.get(&client_id) else {
+                        continue;
+                    };
+                    let Ok((_, _, transform, username, mut inventory)) =
+                        players.get_mut(*player_entity)
+                    else {
+                        continue;
+                    };
+                    let relative_pos = RelativeVoxelPos::new(
+                        voxel_pos[0] as u32,
+                        voxel_pos[1] as u32,
+                        voxel_pos[2] as u32,
+                    );
+                    let target = VoxelPos::from((relative_pos, ChunkPos(chunk_pos)));
+                    let Some(old_block) = chunk_manager.get_block(target) else {
+                        continue;
+                    };
+                    let Some(new_identifier) =
+                        block_registry.get_identifier(packed_block.id).cloned()
+                    else {
+                        continue;
+                    };
+                    let Some((namespace, name)) = identifier_to_name(new_identifier.clone()) else {
+                        continue;
+                    };
+                    // Only the block and which way it faces come from the client, anything else it
+                    // sent could fill a container or skip growing
+                    let faces = chunk_manager
+                        .block_table
+                        .get(&new_identifier)
+                        .and_then(|descriptor| descriptor.has_direction)
+                        .unwrap_or(false);
+                    let block_type = BlockData {
+                        direction: packed_block.direction.filter(|_| faces),
+                        top: packed_block.top.filter(|_| faces),
+                        ..BlockData::new(namespace, name)
+                    };
+                    let old_identifier =
+                        name_to_identifier(old_block.namespace.clone(), old_block.name.clone());
+                    let breaking = new_identifier == "vinox:air";
+
+                    let accepted = if !in_reach(&chunk_manager, transform.translation, target) {
+                        false
+                    } else if breaking {
+                        match (
+                            chunk_manager.block_table.descriptor(&old_identifier),
+                            lobby.breaking.get(&client_id),
+                        ) {
+                            (Some(descriptor), Some(breaking_block))
+                                if breaking_block.pos == target
+                                    && !old_block.is_empty(&chunk_manager.block_table) =>
+                            {
+                                let tool = held_item(&inventory)
+                                    .and_then(|identifier| item_table.get(&identifier));
+                                let elapsed = time.elapsed_seconds() - breaking_block.started;
+                                elapsed >= descriptor.break_time(tool) * BREAK_LEEWAY
+                            }
+                            _ => false,
+                        }
+                    } else {
+                        let places = held_item(&inventory)
+                            .and_then(|identifier| item_table.get(&identifier))
+                            .and_then(|item| item.associated_block.clone())
+                            .map(|block| block == trim_geo_identifier(new_identifier.clone()))
+                            .unwrap_or(false);
+                        if places
+                            && chunk_manager.block_table.contains_key(&new_identifier)
+                            && old_block.is_empty(&chunk_manager.block_table)
+                            && !inside_player(transform.translation, target)
+                        {
+                            let (bar, item) = (*inventory.current_bar, *inventory.current_item);
+                            inventory.item_decrement("hotbar", bar, item);
+                            true
+                        } else {
+                            false
+                        }
+                    };
+
+                    if !accepted {
+                        endpoint.try_send_message(
+                            client_id,
+                            ServerMessage::RejectBlock {
                                 chunk_pos,
                                 voxel_pos,
-                                block_type,
-                            });
+                                block_type: old_block.pack(&block_registry),
+                                inventory: Box::new(inventory.clone()),
+                            },
+                        );
+                        continue;
+                    }
+
+                    let packed_block = block_type.pack(&block_registry);
+                    chunk_manager.set_block(target, block_type);
+                    modified_chunks.insert(ChunkPos(chunk_pos));
+                    if breaking {
+                        lobby.breaking.remove(&client_id);
+                        // Blocks without an item just disappear
+                        if let Some(item) =
+                            item_table.get(&trim_geo_identifier(old_identifier.clone()))
+                        {
+                            if inventory.add_item(item).is_err() {
+                                let stack = ItemData {
+                                    namespace: item.namespace.clone(),
+                                    name: item.name.clone(),
+                                    stack_size: 1,
+                                    ..default()
+                                };
+                                drop_item(&mut commands, Vec3::from(target) + 0.5, stack);
+                            }
+                        }
+                    }
+                    let (hook, identifier) = if breaking {
+                        (ScriptHook::Break, old_identifier)
+                    } else {
+                        (ScriptHook::Place, new_identifier)
+                    };
+                    script_events.send(ScriptEvent {
+                        hook,
+                        identifier,
+                        user_name: (*username).clone(),
+                        pos: Some(target),
+                    });
+                    endpoint.try_broadcast_message(ServerMessage::SentBlock {
+                        chunk_pos,
+                        voxel_pos,
+                        block_type: packed_block,
+                    });
+                }
+                ClientMessage::InteractBlock {
+                    chunk_pos,
+                    voxel_pos,
+                } => {
+                    let target = VoxelPos::from((
+                        RelativeVoxelPos::new(
+                            voxel_pos[0] as u32,
+                            voxel_pos[1] as u32,
+                            voxel_pos[2] as u32,
+                        ),
+                        ChunkPos(chunk_pos),
+                    ));
+                    if let Some(identifier) = chunk_manager.get_identifier(target) {
+                        let interactable = chunk_manager
+                            .block_table
+                            .get(&identifier)
+                            .and_then(|descriptor| descriptor.interactable)
+                            .unwrap_or(false);
+                        if let Some(player_entity) = lobby.players.get(&client_id) {
+                            if let Ok((_, _, transform, username, _)) = players.get(*player_entity)
+                            {
+                                if interactable
+                                    && in_reach(&chunk_manager, transform.translation, target)
+                                {
+                                    script_events.send(ScriptEvent {
+                                        hook: ScriptHook::Interact,
+                                        identifier,
+                                        user_name: (*username).clone(),
+                                        pos: Some(target),
+                                    });
+                                }
+                            }
+                        }
+                    }
+                }
+                ClientMessage::UseItem => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        if let Ok((_, _, transform, username, inventory)) =
+                            players.get(*player_entity)
+                        {
+                            if let Some(item) =
+                                &inventory.hotbar[*inventory.current_bar][*inventory.current_item]
+                            {
+                                script_events.send(ScriptEvent {
+                                    hook: ScriptHook::Use,
+                                    identifier: name_to_identifier(
+                                        item.namespace.clone(),
+                                        item.name.clone(),
+                                    ),
+                                    user_name: (*username).clone(),
+                                    pos: Some(VoxelPos::from(transform.translation)),
+                                });
+                            }
                         }
                     }
                 }
                 ClientMessage::ChatMessage { message } => {
                     if let Some(player_entity) = lobby.players.get(&client_id) {
                         if let Ok((_, _, _, username, _)) = players.get(*player_entity) {
+                            if message.starts_with('/') {
+                                chat_commands.send(ChatCommand {
+                                    client_id: Some(client_id),
+                                    user_name: (*username).clone(),
+                                    command: message,
+                                });
+                                continue;
+                            }
                             endpoint.try_broadcast_message_on(
                                 bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
                                 ServerMessage::ChatMessage {
@@ -283,20 +787,102 @@ pub fn get_messages(
                         }
                     }
                 }
-                ClientMessage::Inventory { inventory } => {
+                ClientMessage::CompleteCommand { input } => {
                     if let Some(player_entity) = lobby.players.get(&client_id) {
-                        if let Ok((_, _, transform, username, mut cur_inventory)) =
-                            players.get_mut(*player_entity)
-                        {
-                            *cur_inventory = *inventory.clone();
-                            let voxel_pos = *VoxelPos::from(transform.translation);
-                            players_to_save.push((
-                                (*username).clone(),
-                                SavedPlayer {
-                                    position: voxel_pos.into(),
-                                    inventory: *inventory,
-                                },
-                            ))
+                        if let Ok((_, _, _, username, _)) = players.get(*player_entity) {
+                            completion_requests.send(CompletionRequest {
+                                client_id,
+                                user_name: (*username).clone(),
+                                input,
+                            });
+                        }
+                    }
+                }
+                ClientMessage::InventoryOp { sequence, op } => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        if let (Ok((_, _, transform, username, mut inventory)), Ok(mut held)) = (
+                            players.get_mut(*player_entity),
+                            held_items.get_mut(*player_entity),
+                        ) {
+                            let applied = if let InventoryOp::Return = op {
+                                // Leftovers go on the ground which the client can't predict
+                                let had_stack = held.is_some();
+                                return_held(
+                                    &mut commands,
+                                    transform,
+                                    &mut inventory,
+                                    &mut held,
+                                    &item_table,
+                                );
+                                had_stack
+                            } else {
+                                inventory.apply_op(&mut held, &op, &item_table)
+                            };
+                            // Everything has to be ordered or a late ack could skip over a rollback
+                            if applied {
+                                players_to_save.push((
+                                    (*username).clone(),
+                                    SavedPlayer::holding(transform, &inventory, &held, &item_table),
+                                ));
+                                endpoint.try_send_message_on(
+                                    client_id,
+                                    bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                                    ServerMessage::InventoryAck { sequence },
+                                );
+                            } else {
+                                endpoint.try_send_message_on(
+                                    client_id,
+                                    bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                                    ServerMessage::InventoryRollback {
+                                        sequence,
+                                        inventory: Box::new(inventory.clone()),
+                                        held: held.clone(),
+                                    },
+                                );
+                            }
+                        }
+                    }
+                }
+                ClientMessage::SelectItem { bar, item } => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        if let Ok((_, _, _, _, mut inventory)) = players.get_mut(*player_entity) {
+                            if bar < 3 && item < 3 {
+                                *inventory.current_bar = bar;
+                                *inventory.current_item = item;
+                            }
+                        }
+                    }
+                }
+                ClientMessage::Craft { recipe } => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        if let (Ok((_, _, transform, username, mut inventory)), Ok(held)) = (
+                            players.get_mut(*player_entity),
+                            held_items.get(*player_entity),
+                        ) {
+                            let Some(recipe_descriptor) = recipe_table.get(&recipe) else {
+                                continue;
+                            };
+                            let old_inventory = inventory.clone();
+                            if inventory.craft(recipe_descriptor, &item_table) {
+                                // In order with acks and rollbacks for the inventory ops
+                                endpoint.try_send_message_on(
+                                    client_id,
+                                    bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                                    ServerMessage::InventoryDelta {
+                                        delta: old_inventory.diff(&inventory),
+                                    },
+                                );
+                                players_to_save.push((
+                                    (*username).clone(),
+                                    SavedPlayer::holding(transform, &inventory, held, &item_table),
+                                ));
+                                script_events.send(ScriptEvent {
+                                    hook: ScriptHook::Craft,
+                                    identifier: recipe,
+                                    user_name: (*username).clone(),
+                                    pos: Some(VoxelPos::from(transform.translation)),
+                                });
+                            }
                         }
                     }
                 }
@@ -306,134 +892,205 @@ pub fn get_messages(
     }
 }
 
+// Runs the queued inputs with the same code the client predicts with and tells it where it ended up
+pub fn simulate_movement(
+    mut server: ResMut<Server>,
+    mut players: Query<(
+        &Player,
+        &mut PlayerInputs,
+        &mut MoveState,
+        &mut Transform,
+        &mut HeadPitch,
+        &Aabb,
+    )>,
+    world: CollisionWorld,
+) {
+    let endpoint = server.endpoint_mut();
+    for (player, mut inputs, mut state, mut transform, mut head_pitch, aabb) in players.iter_mut() {
+        let frames = inputs.take_tick();
+        let Some(last) = frames.last().copied() else {
+            continue;
+        };
+        for frame in frames.iter() {
+            step_movement(&mut state, frame, aabb, &world);
+        }
+        transform.translation = state.translation;
+        transform.rotation = Quat::from_rotation_y(last.yaw);
+        **head_pitch = last.head_pitch;
+        endpoint.try_send_message_on(
+            player.id,
+            bevy_quinnet::shared::channel::ChannelId::Unreliable,
+            ServerMessage::MoveAck {
+                sequence: last.sequence,
+                state: *state,
+            },
+        );
+    }
+}
+
 pub fn sync_voxel_pos(mut players: Query<(&mut VoxelPos, &Transform)>) {
     for (mut voxel_pos, transform) in players.iter_mut() {
         *voxel_pos = VoxelPos::from(transform.translation);
     }
 }
 
-#[allow(clippy::type_complexity)]
-//This would eventually take in any networkedentity for now just player
-pub fn send_entities(mut server: ResMut<Server>, query: Query<(Entity, &Transform)>) {
-    let mut networked_entities = NetworkedEntities::default();
-    for (entity, transform) in query.iter() {
-        networked_entities.entities.push(entity);
-        networked_entities.translations.push(transform.translation);
-        networked_entities
-            .yaws
-            .push(transform.rotation.to_euler(EulerRot::XYZ).1);
+pub fn advance_tick(mut tick: ResMut<ServerTick>) {
+    **tick += 1;
+}
+
+// Each client only hears about entities inside its load radius and only what changed
+pub fn send_entities(
+    mut server: ResMut<Server>,
+    lobby: Res<ServerLobby>,
+    mut viewers: Query<(&LoadPoint, &mut EntityInterest)>,
+    entities: Query<
+        (Entity, &Transform, Option<&HeadPitch>, Option<&DroppedItem>),
+        With<NetworkedEntity>,
+    >,
+    tick: Res<ServerTick>,
+) {
+    let endpoint = server.endpoint_mut();
+    if **tick % TIME_SYNC_TICKS == 0 {
+        endpoint.try_broadcast_message_on(
+            bevy_quinnet::shared::channel::ChannelId::Unreliable,
+            ServerMessage::ServerTime { tick: **tick },
+        );
+    }
+    for (client_id, player_entity) in lobby.players.iter() {
+        let Ok((load_point, mut interest)) = viewers.get_mut(*player_entity) else {
+            continue;
+        };
+        interest.ticks = interest.ticks.wrapping_add(1);
+        let full = interest.ticks % FULL_UPDATE_TICKS == 0;
+        let mut networked_entities = NetworkedEntities {
+            tick: **tick,
+            ..default()
+        };
+        let mut in_range = FxHashSet::default();
+        for (entity, transform, head_pitch, item) in entities.iter() {
+            // Our own player is simulated by the client
+            if entity == *player_entity
+                || !load_point.is_in_radius(&ChunkPos::from(VoxelPos::from(transform.translation)))
+            {
+                continue;
+            }
+            in_range.insert(entity);
+            let state = EntityState {
+                translation: transform.translation,
+                yaw: transform.rotation.to_euler(EulerRot::YXZ).0,
+                head_pitch: head_pitch.map(|pitch| **pitch).unwrap_or_default(),
+            };
+            let Some(old_state) = interest.known.get(&entity) else {
+                endpoint.try_send_message_on(
+                    *client_id,
+                    bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                    ServerMessage::EntitySpawn {
+                        entity,
+                        tick: **tick,
+                        translation: state.translation,
+                        yaw: state.yaw,
+                        head_pitch: state.head_pitch,
+                        item: item.map(|item| item.0.clone()),
+                    },
+                );
+                interest.known.insert(entity, state);
+                continue;
+            };
+            if let Some((translation, yaw, head_pitch)) = old_state.delta(&state, full) {
+                networked_entities.entities.push(entity);
+                networked_entities.translations.push(translation);
+                networked_entities.yaws.push(yaw);
+                networked_entities.head_pitchs.push(head_pitch);
+                interest.known.insert(entity, state);
+            }
+        }
+        interest.known.retain(|entity, _| {
+            let keep = in_range.contains(entity);
+            if !keep {
+                endpoint.try_send_message_on(
+                    *client_id,
+                    bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                    ServerMessage::EntityDespawn { entity: *entity },
+                );
+            }
+            keep
+        });
+        // Sent even when nothing moved so the client knows it can stop extrapolating
+        if !interest.known.is_empty() {
+            endpoint.try_send_message_on(
+                *client_id,
+                bevy_quinnet::shared::channel::ChannelId::Unreliable,
+                ServerMessage::NetworkedEntities { networked_entities },
+            );
+        }
     }
-    server.endpoint_mut().try_broadcast_message_on(
-        bevy_quinnet::shared::channel::ChannelId::Unreliable,
-        ServerMessage::NetworkedEntities { networked_entities },
-    );
 }
 
 pub fn send_chunks(
-    _commands: Commands,
     mut server: ResMut<Server>,
-    lobby: ResMut<ServerLobby>,
-    mut players: Query<(&LoadPoint, &mut SentChunks), With<Player>>,
+    lobby: Res<ServerLobby>,
+    mut players: Query<(&LoadPoint, &Transform, &mut SentChunks, &mut ChunkQueue), With<Player>>,
     chunk_manager: ChunkManager,
     is_ready: Query<(
         Without<NeedsChunkData>,
         Without<GeneratingChunk>,
         Without<PrepassChunk>,
     )>,
-    chunk_limit: Res<ChunkLimit>,
+    (chunk_limit, chunk_bandwidth): (Res<ChunkLimit>, Res<ChunkBandwidth>),
+    block_registry: Res<BlockRegistry>,
 ) {
-    let mut rng = rand::thread_rng();
     let endpoint = server.endpoint_mut();
     for client_id in endpoint.clients() {
-        if let Some(player_entity) = lobby.players.get(&client_id) {
-            if let Ok((load_point, mut sent_chunks)) = players.get_mut(*player_entity) {
-                let mut count = **chunk_limit;
-                while count != 0 {
-                    let x = rng.gen_range(-load_point.horizontal..=load_point.horizontal);
-                    let y = rng.gen_range(-load_point.vertical..=load_point.vertical);
-                    let z = rng.gen_range(-load_point.horizontal..=load_point.horizontal);
-                    let (x, y, z) = (
-                        x + load_point.chunk_pos.x,
-                        y + load_point.chunk_pos.y,
-                        z + load_point.chunk_pos.z,
-                    );
-                    let total_chunks = ((load_point.horizontal * 2) + 1) // Account for middle(0)
-                            * ((load_point.vertical * 2) + 1)
-                            * ((load_point.horizontal
-                            * 2) + 1);
-
-                    if sent_chunks.chunks.len() as i32 == total_chunks {
-                        // println!(
-                        //     "Total chunks: {total_chunks}, Sent chunks: {}",
-                        //     sent_chunks.chunks.len()
-                        // );
-                        break;
-                    }
+        let Some(player_entity) = lobby.players.get(&client_id) else {
+            continue;
+        };
+        let Ok((load_point, transform, mut sent_chunks, mut chunk_queue)) =
+            players.get_mut(*player_entity)
+        else {
+            continue;
+        };
+        let forward = transform.forward();
+        if chunk_queue.is_stale(load_point, forward, &sent_chunks) {
+            chunk_queue.rebuild(load_point, forward, &sent_chunks);
+        }
 
-                    if sent_chunks.chunks.contains(&ChunkPos::new(x, y, z)) {
-                        continue;
-                    }
-                    count -= 1;
-                    if let Some(chunk_entity) = chunk_manager
-                        .current_chunks
-                        .get_entity(ChunkPos::new(x, y, z))
-                    {
-                        if is_ready.get(chunk_entity).is_ok() {
-                            if let Some(chunk) = chunk_manager.get_chunk(chunk_entity) {
-                                let raw_chunk = chunk.to_raw();
-                                if let Ok(raw_chunk_bin) = bincode::serialize(&raw_chunk) {
-                                    let mut final_chunk = Cursor::new(raw_chunk_bin);
-                                    let mut output = Cursor::new(Vec::new());
-                                    copy_encode(&mut final_chunk, &mut output, 0).unwrap();
-                                    if server
-                                        .endpoint_mut()
-                                        .send_message(
-                                            client_id,
-                                            ServerMessage::LevelData {
-                                                chunk_data: output.get_ref().clone(),
-                                                pos: IVec3::new(x, y, z),
-                                            },
-                                        )
-                                        .is_ok()
-                                    {
-                                        sent_chunks.chunks.insert(ChunkPos::new(x, y, z));
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                // for (chunk_entity, chunk_pos) in chunk_manager
-                //     .current_chunks
-                //     .get_entities(&[*load_point])
-                //     .iter()
-                //     .choose_multiple(&mut rng, **chunk_limit)
-                // {
-                //     if has_data.get(*chunk_entity).is_ok() {
-                //         if let Some(chunk) = chunk_manager.get_chunk(*chunk_entity) {
-                //             let raw_chunk = chunk.to_raw();
-                //             if let Ok(raw_chunk_bin) = bincode::serialize(&raw_chunk) {
-                //                 let mut final_chunk = Cursor::new(raw_chunk_bin);
-                //                 let mut output = Cursor::new(Vec::new());
-                //                 copy_encode(&mut final_chunk, &mut output, 0).unwrap();
-                //                 if server
-                //                     .endpoint_mut()
-                //                     .send_message(
-                //                         client_id,
-                //                         ServerMessage::LevelData {
-                //                             chunk_data: output.get_ref().clone(),
-                //                             pos: **chunk_pos,
-                //                         },
-                //                     )
-                //                     .is_ok()
-                //                 {
-                //                     sent_chunks.chunks.insert(*chunk_pos);
-                //                 }
-                //             }
-                //         }
-                //     }
-                // }
+        let (mut count, mut bytes) = (0, 0);
+        let mut index = chunk_queue.chunks.len();
+        while index > 0 && count < **chunk_limit && bytes < **chunk_bandwidth {
+            index -= 1;
+            let chunk_pos = chunk_queue.chunks[index];
+            // Not generated yet, it keeps its place for next time
+            let Some(chunk) = chunk_manager
+                .current_chunks
+                .get_entity(chunk_pos)
+                .filter(|entity| is_ready.get(*entity).is_ok())
+                .and_then(|entity| chunk_manager.get_chunk(entity))
+            else {
+                continue;
+            };
+            let Ok(chunk_bin) = bincode::serialize(&chunk.to_raw().pack(&block_registry)) else {
+                continue;
+            };
+            let mut output = Cursor::new(Vec::new());
+            copy_encode(&mut Cursor::new(chunk_bin), &mut output, 0).unwrap();
+            let size = output.get_ref().len();
+            if endpoint
+                .send_message(
+                    client_id,
+                    ServerMessage::LevelData {
+                        chunk_data: output.into_inner(),
+                        pos: *chunk_pos,
+                    },
+                )
+                .is_err()
+            {
+                break;
             }
+            sent_chunks.chunks.insert(chunk_pos);
+            chunk_queue.chunks.remove(index);
+            count += 1;
+            bytes += size;
         }
+        chunk_queue.mark_sent(&sent_chunks);
     }
 }
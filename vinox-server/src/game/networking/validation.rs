@@ -0,0 +1,49 @@
+use bevy::prelude::*;
+use big_space::GridCell;
+use vinox_common::{
+    ecs::bundles::Inventory,
+    physics::collision::raycast::raycast_world,
+    world::chunks::{ecs::ChunkManager, positions::VoxelPos, storage::name_to_identifier},
+};
+
+pub const REACH: f32 = 6.0;
+// Positions only come in as whole voxels so give players a little extra
+const REACH_LEEWAY: f32 = 1.5;
+const EYE_OFFSET: Vec3 = Vec3::new(0.5, 1.6, 0.5);
+// Latency means the client can finish a bit earlier than we see it
+pub const BREAK_LEEWAY: f32 = 0.75;
+
+// Whether a player standing at player_pos could see and reach target. Anything solid more than a block away from the target in the way means they are going through a wall
+pub fn in_reach(chunk_manager: &ChunkManager, player_pos: Vec3, target: VoxelPos) -> bool {
+    let origin = player_pos + EYE_OFFSET;
+    let to_target = target.as_vec3() + Vec3::splat(0.5) - origin;
+    let distance = to_target.length();
+    if distance > REACH + REACH_LEEWAY {
+        return false;
+    }
+    match raycast_world(
+        origin,
+        to_target.normalize_or_zero(),
+        distance,
+        chunk_manager,
+        &GridCell::default(),
+    ) {
+        Some((chunk_pos, voxel_pos, _, _)) => {
+            let hit = VoxelPos::from((voxel_pos, chunk_pos));
+            (*hit - *target).abs().max_element() <= 1
+        }
+        None => true,
+    }
+}
+
+// Standing in the block or the one above it
+pub fn inside_player(player_pos: Vec3, target: VoxelPos) -> bool {
+    let feet = VoxelPos::from(player_pos);
+    *target == *feet || *target == *feet + IVec3::Y
+}
+
+pub fn held_item(inventory: &Inventory) -> Option<String> {
+    inventory.hotbar[*inventory.current_bar][*inventory.current_item]
+        .as_ref()
+        .map(|item| name_to_identifier(item.namespace.clone(), item.name.clone()))
+}
@@ -1,19 +1,25 @@
 use bevy::prelude::*;
 use vinox_common::{
     ecs::bundles::PlayerBundleBuilder,
-    world::chunks::{
-        ecs::CommonPlugin,
-        light::LightPlugin,
-        storage::{BiomeTable, BlockTable, ItemTable, RecipeTable},
+    world::{
+        chunks::{
+            ecs::CommonPlugin,
+            light::LightPlugin,
+            storage::{
+                BiomeTable, BlockTable, FeatureRuleTable, FeatureTable, ItemTable, RecipeTable,
+                StructureTable,
+            },
+        },
+        generation::terrain::{BiomeHashmap, BiomeTree},
     },
 };
 
 use super::{
+    admin::plugin::AdminPlugin,
+    commands::plugin::CommandsPlugin,
     networking::plugin::NetworkingPlugin,
-    world::{
-        chunk::ChunkPlugin,
-        generation::{BiomeHashmap, BiomeTree},
-    },
+    scripting::plugin::ScriptingPlugin,
+    world::{backup::BackupPlugin, chunk::ChunkPlugin},
 };
 
 pub struct GamePlugin;
@@ -26,10 +32,17 @@ impl Plugin for GamePlugin {
             .insert_resource(BiomeTable::default())
             .insert_resource(BiomeHashmap::default())
             .insert_resource(BiomeTree::default())
+            .insert_resource(FeatureTable::default())
+            .insert_resource(FeatureRuleTable::default())
+            .insert_resource(StructureTable::default())
             .insert_resource(PlayerBundleBuilder::default())
             .add_plugin(CommonPlugin)
             .add_plugin(ChunkPlugin)
+            .add_plugin(BackupPlugin)
             .add_plugin(NetworkingPlugin)
-            .add_plugin(LightPlugin);
+            .add_plugin(LightPlugin)
+            .add_plugin(ScriptingPlugin)
+            .add_plugin(AdminPlugin)
+            .add_plugin(CommandsPlugin);
     }
 }
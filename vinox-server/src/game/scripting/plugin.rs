@@ -0,0 +1,21 @@
+use bevy::prelude::*;
+use vinox_common::scripting::runtime::{ScriptEvent, ScriptRuntime};
+
+use crate::game::{commands::dispatcher::dispatch_commands, networking::start::setup_loadables};
+
+use super::systems::{load_scripts, register_script_commands, run_script_commands, run_scripts};
+
+pub struct ScriptingPlugin;
+
+impl Plugin for ScriptingPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_non_send_resource(
+            ScriptRuntime::new().expect("Failed to create the lua runtime"),
+        )
+        .add_event::<ScriptEvent>()
+        .add_startup_system(load_scripts.after(setup_loadables))
+        .add_startup_system(register_script_commands.in_base_set(StartupSet::PostStartup))
+        .add_system(run_scripts)
+        .add_system(run_script_commands.after(dispatch_commands));
+    }
+}
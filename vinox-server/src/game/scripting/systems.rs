@@ -0,0 +1,135 @@
+use bevy::prelude::*;
+use bevy_quinnet::server::*;
+use vinox_common::{
+    networking::protocol::ServerMessage,
+    scripting::runtime::{ScriptEvent, ScriptOutput, ScriptRuntime, ScriptValue},
+    world::chunks::{
+        ecs::ChunkManager,
+        storage::{BlockRegistry, BlockTable, ItemTable, RecipeTable},
+    },
+};
+
+use crate::game::{
+    commands::dispatcher::{Argument, CommandNode, CommandRegistry, PermissionLevel, RunCommand},
+    world::storage::ModifiedChunks,
+};
+
+pub fn load_scripts(
+    mut runtime: NonSendMut<ScriptRuntime>,
+    block_table: Res<BlockTable>,
+    item_table: Res<ItemTable>,
+    recipe_table: Res<RecipeTable>,
+) {
+    runtime.load_all(&block_table, &item_table, &recipe_table);
+}
+
+// Blocks scripts changed have to be marked for saving and sent out, chat goes to everyone
+fn apply_output(
+    output: ScriptOutput,
+    server: &mut Server,
+    modified_chunks: &mut ModifiedChunks,
+    block_registry: &BlockRegistry,
+) {
+    let endpoint = server.endpoint_mut();
+    for (voxel_pos, block_type) in output.changed {
+        let (local_pos, chunk_pos) = voxel_pos.to_offsets();
+        modified_chunks.insert(chunk_pos);
+        endpoint.try_broadcast_message(ServerMessage::SentBlock {
+            chunk_pos: *chunk_pos,
+            voxel_pos: [local_pos.x as u8, local_pos.y as u8, local_pos.z as u8],
+            block_type: block_type.pack(block_registry),
+        });
+    }
+    for message in output.chat {
+        endpoint.try_broadcast_message_on(
+            bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+            ServerMessage::ChatMessage {
+                user_name: "Server".to_string(),
+                message,
+                id: 0,
+            },
+        );
+    }
+}
+
+pub fn run_scripts(
+    runtime: NonSend<ScriptRuntime>,
+    mut script_events: EventReader<ScriptEvent>,
+    mut chunk_manager: ChunkManager,
+    mut server: ResMut<Server>,
+    mut modified_chunks: ResMut<ModifiedChunks>,
+    block_registry: Res<BlockRegistry>,
+) {
+    for event in script_events.iter() {
+        let output = runtime.call_hook(event, &mut chunk_manager);
+        apply_output(output, &mut server, &mut modified_chunks, &block_registry);
+    }
+}
+
+// Game commands are registered first so a script can't take over one of their names
+pub fn register_script_commands(
+    runtime: NonSend<ScriptRuntime>,
+    mut registry: ResMut<CommandRegistry>,
+) {
+    for command in runtime.commands() {
+        let Some(permission) = PermissionLevel::parse(&command.permission) else {
+            warn!(
+                "Command /{} from {} has an unknown permission {}",
+                command.name, command.identifier, command.permission
+            );
+            continue;
+        };
+        match CommandNode::from_syntax(&command.name, &command.syntax) {
+            Ok(node) => {
+                registry.register_script(command.identifier, node, permission, &command.description)
+            }
+            Err(e) => warn!(
+                "Command /{} from {} has a bad syntax: {e}",
+                command.name, command.identifier
+            ),
+        }
+    }
+}
+
+pub fn run_script_commands(
+    runtime: NonSend<ScriptRuntime>,
+    mut run_commands: EventReader<RunCommand>,
+    mut chunk_manager: ChunkManager,
+    mut server: ResMut<Server>,
+    mut modified_chunks: ResMut<ModifiedChunks>,
+    block_registry: Res<BlockRegistry>,
+) {
+    for command in run_commands.iter() {
+        let Some(identifier) = &command.script else {
+            continue;
+        };
+        let arguments: Vec<(String, ScriptValue)> = command
+            .arguments
+            .iter()
+            .map(|(name, argument)| {
+                let value = match argument {
+                    Argument::Boolean(value) => ScriptValue::Boolean(*value),
+                    Argument::Integer(value) => ScriptValue::Integer(*value),
+                    Argument::Number(value) => ScriptValue::Number(*value),
+                    Argument::Position(pos) => ScriptValue::Position(*pos),
+                    Argument::Word(text) | Argument::Text(text) | Argument::Block(text) => {
+                        ScriptValue::Text(text.clone())
+                    }
+                    Argument::Player { name, .. } => ScriptValue::Text(name.clone()),
+                };
+                (name.clone(), value)
+            })
+            .collect();
+        let (output, feedback) = runtime.run_command(
+            identifier,
+            &command.name,
+            &command.source.user_name,
+            &arguments,
+            &mut chunk_manager,
+        );
+        apply_output(output, &mut server, &mut modified_chunks, &block_registry);
+        if let Some(feedback) = feedback {
+            command.reply(&mut server, feedback);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use bevy_quinnet::server::Server;
+use directories::ProjectDirs;
+use futures_lite::future;
+use vinox_common::world::chunks::ecs::CurrentChunks;
+
+use crate::game::{
+    admin::moderation::refuse,
+    commands::dispatcher::{
+        argument, literal, ArgumentType, CommandRegistry, PermissionLevel, RunCommand,
+    },
+    config::ServerConfig,
+    networking::components::ServerLobby,
+};
+
+use super::{
+    chunk::{process_save, PendingDiffs, PendingSpill},
+    storage::{
+        load_world_info, save_world_info, ChunksToSave, FirstSaves, ModifiedChunks, NameList,
+        PlayersToSave, SaveRequest, WorldDatabase, WorldInfo, WorldStorage,
+    },
+    structures::ToBePasted,
+};
+
+#[derive(Resource, Default)]
+pub struct BackupRequest {
+    pub now: bool,
+    // Rolled back to once everyone has been kicked
+    pub restore: Option<String>,
+    // The backup being copied, gives its name
+    pub task: Option<Task<Result<String, String>>>,
+}
+
+// Each backup is a folder named after when it was made holding world.ron and whatever the
+// storage backend copied
+pub fn backups_path(world_name: &str) -> Result<PathBuf, String> {
+    let Some(proj_dirs) = ProjectDirs::from("com", "vinox", "vinox") else {
+        return Err("Couldn't find the data directory".to_string());
+    };
+    Ok(proj_dirs.data_dir().join("assets/backups").join(world_name))
+}
+
+// UTC so the names sort in the order the backups were made
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // Days since 1970 to a date, from Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}_{:02}-{:02}-{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// Oldest first
+pub fn list_backups(world_name: &str) -> Vec<String> {
+    let Ok(path) = backups_path(world_name) else {
+        return Vec::new();
+    };
+    let mut backups: Vec<String> = fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| !name.ends_with(".tmp"))
+        .collect();
+    backups.sort();
+    backups
+}
+
+// Returns the backup's name
+pub fn create_backup(
+    database: &dyn WorldStorage,
+    world_info: &WorldInfo,
+    world_name: &str,
+) -> Result<String, String> {
+    let path = backups_path(world_name)?;
+    let name = timestamp();
+    if path.join(&name).exists() {
+        return Err(format!("There is already a backup called {name}"));
+    }
+    // Only renamed once it's complete so a failed backup is never listed or restored
+    let temp_path = path.join(format!("{name}.tmp"));
+    fs::create_dir_all(&temp_path)
+        .map_err(|e| format!("Couldn't create {}: {e}", temp_path.display()))?;
+    let result = database.backup(&temp_path).and_then(|_| {
+        save_world_info(world_info.clone(), temp_path.join("world.ron"));
+        fs::rename(&temp_path, path.join(&name)).map_err(|e| e.to_string())
+    });
+    if result.is_err() {
+        fs::remove_dir_all(&temp_path).ok();
+    }
+    result.map(|_| name)
+}
+
+// 0 keeps every backup
+pub fn prune_backups(world_name: &str, keep: usize) {
+    if keep == 0 {
+        return;
+    }
+    let Ok(path) = backups_path(world_name) else {
+        return;
+    };
+    let backups = list_backups(world_name);
+    for name in backups.iter().take(backups.len().saturating_sub(keep)) {
+        if let Err(e) = fs::remove_dir_all(path.join(name)) {
+            println!("Failed to remove backup {name}: {e}");
+        }
+    }
+}
+
+// The world is backed up first so a restore can be undone. Operators, the whitelist, bans and
+// block ids are kept as they are now, rolling back shouldn't unban anyone
+pub fn restore_backup(
+    database: &dyn WorldStorage,
+    world_name: &str,
+    name: &str,
+) -> Result<WorldInfo, String> {
+    if !list_backups(world_name).iter().any(|backup| backup == name) {
+        return Err(format!("No backup called {name}"));
+    }
+    let folder = backups_path(world_name)?.join(name);
+    let world_info = load_world_info(folder.join("world.ron"))
+        .ok_or_else(|| format!("Backup {name} has no world info"))?;
+    if let Some(current) = database.load_world_info() {
+        let undo = create_backup(database, &current, world_name)?;
+        println!("Backed up the world as {undo} before restoring");
+    }
+    let block_registry = database.load_block_registry();
+    let lists =
+        [NameList::Ops, NameList::Whitelist].map(|list| (list, database.load_name_list(list)));
+    let bans = database.load_bans();
+
+    database.restore(&folder)?;
+
+    let block_ids: Vec<_> = block_registry
+        .0
+        .iter()
+        .map(|(identifier, id)| (identifier.clone(), *id))
+        .collect();
+    database.save_block_ids(&block_ids);
+    for (list, names) in lists {
+        for user_name in database.load_name_list(list).difference(&names) {
+            database.set_in_name_list(list, user_name, false);
+        }
+        for user_name in names.iter() {
+            database.set_in_name_list(list, user_name, true);
+        }
+    }
+    for user_name in database.load_bans().keys() {
+        if !bans.contains_key(user_name) {
+            database.save_ban(user_name, None);
+        }
+    }
+    for (user_name, reason) in bans.iter() {
+        database.save_ban(user_name, Some(reason));
+    }
+    database.save_world_info(&world_info);
+    Ok(world_info)
+}
+
+pub fn register_backup_commands(mut registry: ResMut<CommandRegistry>) {
+    registry.register(
+        literal("backup")
+            .executes()
+            .then(literal("list").executes()),
+        PermissionLevel::Operator,
+        "Back up the world now or list its backups",
+    );
+    registry.register(
+        literal("restore").then(argument("name", ArgumentType::Word).executes()),
+        PermissionLevel::Console,
+        "Kick everyone and roll the world back to a backup",
+    );
+}
+
+pub fn backup_commands(
+    mut run_commands: EventReader<RunCommand>,
+    mut server: ResMut<Server>,
+    mut request: ResMut<BackupRequest>,
+    config: Res<ServerConfig>,
+) {
+    for command in run_commands.iter() {
+        let message = match (command.name.as_str(), command.literals().as_slice()) {
+            ("backup", ["list"]) => {
+                let backups = list_backups(&config.world_name);
+                if backups.is_empty() {
+                    "There are no backups yet".to_string()
+                } else {
+                    format!("{} backups: {}", backups.len(), backups.join(", "))
+                }
+            }
+            ("backup", _) => {
+                request.now = true;
+                "Backing up the world".to_string()
+            }
+            ("restore", _) => {
+                let Some(name) = command.text("name") else {
+                    continue;
+                };
+                if list_backups(&config.world_name)
+                    .iter()
+                    .any(|backup| backup == name)
+                {
+                    request.restore = Some(name.to_string());
+                    format!("Restoring {name} once everyone has been kicked")
+                } else {
+                    format!("No backup called {name}")
+                }
+            }
+            _ => continue,
+        };
+        command.reply(&mut server, message);
+    }
+}
+
+// Asks for a save and copies the world in the background once everything has been written, diffs
+// can take a few updates
+pub fn backup_world(
+    database: Res<WorldDatabase>,
+    (world_info, config, time): (Res<WorldInfo>, Res<ServerConfig>, Res<Time>),
+    mut request: ResMut<BackupRequest>,
+    mut save_request: ResMut<SaveRequest>,
+    pending_diffs: Res<PendingDiffs>,
+    mut since_backup: Local<f32>,
+    mut saved: Local<bool>,
+) {
+    if let Some(task) = request.task.as_mut() {
+        let Some(result) = future::block_on(future::poll_once(task)) else {
+            return;
+        };
+        request.task = None;
+        match result {
+            Ok(name) => println!("Backed up the world as {name}"),
+            Err(e) => println!("Failed to back up the world: {e}"),
+        }
+        return;
+    }
+    if *saved {
+        if !pending_diffs.is_empty() {
+            return;
+        }
+        *saved = false;
+        let database = database.0.clone();
+        let world_info = (*world_info).clone();
+        let (world_name, backups_kept) = (config.world_name.clone(), config.backups_kept);
+        request.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+            let result = create_backup(database.as_ref(), &world_info, &world_name);
+            prune_backups(&world_name, backups_kept);
+            result
+        }));
+        return;
+    }
+    *since_backup += time.delta_seconds();
+    let due = config.backup_interval > 0.0 && *since_backup >= config.backup_interval;
+    if due || request.now {
+        *since_backup = 0.0;
+        request.now = false;
+        save_request.now = true;
+        *saved = true;
+    }
+}
+
+// Players are kicked first so where they are gets saved and then thrown away with everything
+// else. Loaded chunks are dropped without saving and load again from the backup
+#[allow(clippy::too_many_arguments)]
+pub fn restore_world(
+    mut commands: Commands,
+    mut request: ResMut<BackupRequest>,
+    mut server: ResMut<Server>,
+    mut lobby: ResMut<ServerLobby>,
+    mut current_chunks: ResMut<CurrentChunks>,
+    database: Res<WorldDatabase>,
+    (config, time): (Res<ServerConfig>, Res<Time>),
+    mut world_info: ResMut<WorldInfo>,
+    (mut chunks_to_save, mut players_to_save, mut first_saves): (
+        ResMut<ChunksToSave>,
+        ResMut<PlayersToSave>,
+        ResMut<FirstSaves>,
+    ),
+    (mut modified_chunks, mut pending_spill, mut to_be_pasted, mut pending_diffs): (
+        ResMut<ModifiedChunks>,
+        ResMut<PendingSpill>,
+        ResMut<ToBePasted>,
+        ResMut<PendingDiffs>,
+    ),
+) {
+    let Some(name) = request.restore.clone() else {
+        return;
+    };
+    // A backup that is still being copied finishes first
+    if request.task.is_some() {
+        return;
+    }
+    // Anyone who joined since is kicked too
+    if !lobby.players.is_empty() {
+        let client_ids: Vec<u64> = lobby
+            .players
+            .keys()
+            .filter(|client_id| !lobby.disconnecting.contains_key(client_id))
+            .copied()
+            .collect();
+        for client_id in client_ids {
+            refuse(
+                server.endpoint_mut(),
+                &mut lobby,
+                client_id,
+                "The world is being restored from a backup".to_string(),
+                time.elapsed_seconds(),
+            );
+        }
+        return;
+    }
+    request.restore = None;
+    for (_, chunk_entity) in current_chunks.chunks.drain() {
+        commands.entity(chunk_entity).despawn_recursive();
+    }
+    chunks_to_save.clear();
+    players_to_save.clear();
+    first_saves.clear();
+    modified_chunks.clear();
+    pending_spill.clear();
+    to_be_pasted.clear();
+    pending_diffs.clear();
+    match restore_backup(database.0.as_ref(), &config.world_name, &name) {
+        Ok(restored) => {
+            *world_info = restored;
+            println!("Restored the world from {name}");
+        }
+        Err(e) => println!("Failed to restore {name}: {e}"),
+    }
+}
+
+pub struct BackupPlugin;
+
+impl Plugin for BackupPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(BackupRequest::default())
+            .add_startup_system(register_backup_commands)
+            .add_system(backup_commands)
+            .add_systems(
+                (backup_world, restore_world)
+                    .in_base_set(CoreSet::Last)
+                    .after(process_save),
+            );
+    }
+}
@@ -1,25 +1,40 @@
+use std::collections::{HashMap, VecDeque};
+
 use bevy::{
+    app::AppExit,
     prelude::*,
     tasks::{AsyncComputeTaskPool, Task},
 };
 use futures_lite::future;
-use vinox_common::world::chunks::{
-    ecs::{
-        ChunkManager, CurrentChunks, LoadPoint, NeedsChunkData, PrepassChunk, RemoveChunk,
-        SentChunks, SimulationRadius,
+use vinox_common::{
+    ecs::bundles::{ClientName, HeldItem, Inventory},
+    world::{
+        chunks::{
+            ecs::{
+                ChunkManager, CurrentChunks, LoadPoint, NeedsChunkData, PrepassChunk, RemoveChunk,
+                SentChunks, SimulationRadius,
+            },
+            positions::{ChunkPos, RelativeVoxelPos},
+            storage::{
+                BiomeTable, BlockData, BlockRegistry, BlockTable, ChunkData, FeatureRuleTable,
+                FeatureTable, ItemTable, StructureTable,
+            },
+        },
+        generation::{
+            features::{generate_decorated_chunk, place_spill},
+            terrain::{BiomeHashmap, BiomeTree, ToBePlaced},
+        },
     },
-    positions::ChunkPos,
-    storage::{BiomeTable, BlockTable, ChunkData},
 };
 
-use crate::game::networking::components::SaveGame;
+use crate::game::{config::ServerConfig, networking::components::SaveGame};
 
 use super::{
-    generation::{generate_chunk, BiomeHashmap, BiomeTree},
     storage::{
-        load_chunk, save_chunks, save_passwords, save_players, ChunksToSave, FirstSaves,
-        PlayersToSave, WorldDatabase, WorldInfo,
+        apply_diff, diff_blocks, register_blocks, ChunksToSave, FirstSaves, ModifiedChunks,
+        PlayersToSave, SaveRequest, SavedChunk, SavedPlayer, SpilledFrom, WorldDatabase, WorldInfo,
     },
+    structures::{register_structure_commands, structure_commands, ToBePasted},
 };
 
 #[derive(Default, Resource, Debug, Deref, DerefMut)]
@@ -31,29 +46,42 @@ pub struct FullGenEvent(pub ChunkPos);
 #[derive(Default, Component, Debug)]
 pub struct GeneratingChunk;
 
+// Applied once the chunk it was saved for has been generated again. Whole chunks are generated
+// again too so what their features spill into neighbours that weren't saved comes back
+#[derive(Component, Debug)]
+pub struct SavedData(pub SavedChunk);
+
+// Diffs being worked out off the main thread, written in the order they were queued so an older
+// one never lands on top of a newer one
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct PendingDiffs(pub VecDeque<Task<Vec<(ChunkPos, SavedChunk)>>>);
+
+// Blocks from features in neighbouring chunks by the chunk they go into, with the chunk they
+// spilled out of so each neighbour's spill only goes into a chunk once
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct PendingSpill(pub HashMap<ChunkPos, Vec<(ChunkPos, Vec<(UVec3, BlockData)>)>>);
+
+#[allow(clippy::too_many_arguments)]
 pub fn generate_chunks_world(
     load_points: Query<&LoadPoint>,
     mut chunk_queue: EventWriter<PrepassEvent>,
     mut commands: Commands,
     chunk_manager: ChunkManager,
     database: Res<WorldDatabase>,
+    block_registry: Res<BlockRegistry>,
     save: Res<SaveGame>,
     no_data: Query<With<NeedsChunkData>>,
+    mut modified_chunks: ResMut<ModifiedChunks>,
 ) {
     for (entity, pos) in chunk_manager
         .current_chunks
         .get_entities(load_points.iter().copied().collect::<Vec<_>>().as_slice())
     {
         if no_data.get(entity).is_ok() {
-            let data = database.connection.get().unwrap();
-            if let Some(chunk) = load_chunk(pos, &data) {
-                if **save {
-                    commands
-                        .entity(entity)
-                        .insert(ChunkData::from_raw(chunk))
-                        .insert(pos);
-                    commands.entity(entity).remove::<NeedsChunkData>();
-                    continue;
+            if **save {
+                if let Some(saved_chunk) = database.load_chunk(pos, &block_registry) {
+                    modified_chunks.insert(pos);
+                    commands.entity(entity).insert(SavedData(saved_chunk));
                 }
             }
             chunk_queue.send(PrepassEvent(pos));
@@ -63,12 +91,24 @@ pub fn generate_chunks_world(
     }
 }
 
+// Changes to a chunk that wasn't saved since are queued up before it goes
 pub fn destroy_chunks(
     mut commands: Commands,
-    remove_chunks: Query<(&ChunkPos, Entity), With<RemoveChunk>>,
+    remove_chunks: Query<
+        (&ChunkPos, Entity, Option<&ChunkData>, Option<&SpilledFrom>),
+        With<RemoveChunk>,
+    >,
     mut load_points: Query<&mut SentChunks>,
+    mut chunks_to_save: ResMut<ChunksToSave>,
+    modified_chunks: Res<ModifiedChunks>,
 ) {
-    for (chunk, chunk_entity) in remove_chunks.iter() {
+    for (chunk, chunk_entity, chunk_data, spilled) in remove_chunks.iter() {
+        if let Some(chunk_data) = chunk_data {
+            if chunk_data.is_dirty() && modified_chunks.contains(chunk) {
+                let spilled = spilled.cloned().unwrap_or_default();
+                chunks_to_save.push((*chunk, chunk_data.to_raw(), spilled));
+            }
+        }
         for mut sent_chunks in load_points.iter_mut() {
             sent_chunks.chunks.remove(chunk);
         }
@@ -91,18 +131,177 @@ pub fn unsend_chunks(
     }
 }
 
+// Unloaded chunks and players that left are written on the next update. Dirty chunks that are
+// still loaded and everyone online are written every save interval, when a save is asked for and
+// right before the app exits. Diffs need the chunk generated again so they are worked out on the
+// task pool and written once they are done, or straight away when exiting
+#[allow(clippy::too_many_arguments)]
 pub fn process_save(
-    mut chunks_to_save: ResMut<ChunksToSave>,
+    (mut chunks_to_save, mut pending_diffs): (ResMut<ChunksToSave>, ResMut<PendingDiffs>),
     mut players_to_save: ResMut<PlayersToSave>,
     mut first_saves: ResMut<FirstSaves>,
     database: Res<WorldDatabase>,
+    mut block_registry: ResMut<BlockRegistry>,
+    (time, config, world_info): (Res<Time>, Res<ServerConfig>, Res<WorldInfo>),
+    mut since_save: Local<f32>,
+    mut request: ResMut<SaveRequest>,
+    mut exit: EventReader<AppExit>,
+    mut chunks: Query<(&ChunkPos, &mut ChunkData, &SpilledFrom)>,
+    players: Query<(&ClientName, &Transform, &Inventory, &HeldItem)>,
+    item_table: Res<ItemTable>,
+    modified_chunks: Res<ModifiedChunks>,
+    (biome_table, biome_hashmap, biome_tree): (Res<BiomeTable>, Res<BiomeHashmap>, Res<BiomeTree>),
+    (feature_rule_table, feature_table, structure_table): (
+        Res<FeatureRuleTable>,
+        Res<FeatureTable>,
+        Res<StructureTable>,
+    ),
 ) {
-    save_chunks(&chunks_to_save, &database.connection.get().unwrap());
-    save_passwords(&first_saves, &database.connection.get().unwrap());
-    save_players(&players_to_save, &database.connection.get().unwrap());
-    chunks_to_save.clear();
-    players_to_save.clear();
-    first_saves.clear();
+    *since_save += time.delta_seconds();
+    let exiting = exit.iter().last().is_some();
+    // An interval of 0 only saves when something is unloaded, asked for or on exit
+    let autosave = config.save_interval > 0.0 && *since_save >= config.save_interval;
+    if autosave || request.now || exiting {
+        *since_save = 0.0;
+        for (chunk_pos, mut chunk, spilled) in chunks.iter_mut() {
+            if chunk.is_dirty() && modified_chunks.contains(chunk_pos) {
+                chunks_to_save.push((*chunk_pos, chunk.to_raw(), spilled.clone()));
+                chunk.set_dirty(false);
+            }
+        }
+        for (name, transform, inventory, held) in players.iter() {
+            players_to_save.push((
+                (*name).clone(),
+                SavedPlayer::holding(transform, inventory, held, &item_table),
+            ));
+        }
+    }
+    if !chunks_to_save.is_empty() {
+        // A chunk can be unloaded and loaded again before it's written, only the last one matters
+        let chunks: HashMap<ChunkPos, _> = chunks_to_save
+            .drain(..)
+            .map(|(chunk_pos, raw_chunk, spilled)| (chunk_pos, (raw_chunk, spilled)))
+            .collect();
+        if config.chunk_diffs {
+            let seed = world_info.seed;
+            let cloned_table = biome_table.clone();
+            let cloned_hashmap = biome_hashmap.clone();
+            let cloned_tree = biome_tree.clone();
+            let cloned_rules = feature_rule_table.clone();
+            let cloned_features = feature_table.clone();
+            let cloned_structures = structure_table.clone();
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                chunks
+                    .into_iter()
+                    .map(|(chunk_pos, (raw_chunk, spilled))| {
+                        let (baseline, _) = generate_decorated_chunk(
+                            *chunk_pos,
+                            seed,
+                            &cloned_table,
+                            &cloned_hashmap,
+                            &cloned_tree,
+                            &cloned_rules,
+                            &cloned_features,
+                            &cloned_structures,
+                        );
+                        let chunk = ChunkData::from_raw(raw_chunk);
+                        let blocks = diff_blocks(&chunk, &baseline);
+                        (chunk_pos, SavedChunk::Diff(blocks, spilled))
+                    })
+                    .collect()
+            });
+            pending_diffs.push_back(task);
+        } else {
+            let saved_chunks: Vec<_> = chunks
+                .into_iter()
+                .map(|(chunk_pos, (raw_chunk, spilled))| {
+                    (chunk_pos, SavedChunk::Full(raw_chunk, spilled))
+                })
+                .collect();
+            register_blocks(&saved_chunks, &mut block_registry, database.0.as_ref());
+            database.save_chunks(&saved_chunks, &block_registry);
+        }
+    }
+    while let Some(task) = pending_diffs.front_mut() {
+        let saved_chunks = if exiting {
+            Some(future::block_on(task))
+        } else {
+            future::block_on(future::poll_once(task))
+        };
+        let Some(saved_chunks) = saved_chunks else {
+            break;
+        };
+        register_blocks(&saved_chunks, &mut block_registry, database.0.as_ref());
+        database.save_chunks(&saved_chunks, &block_registry);
+        pending_diffs.pop_front();
+    }
+    if !players_to_save.is_empty() || !first_saves.is_empty() {
+        database.save_passwords(&first_saves);
+        database.save_players(&players_to_save);
+        players_to_save.clear();
+        first_saves.clear();
+    }
+    if request.now || exiting {
+        println!("Saved the world");
+    }
+    *request = SaveRequest::default();
+}
+
+// Blocks from features in neighbouring chunks wait here until the chunk they belong to has data
+// and only go into empty blocks. A chunk takes each neighbour's spill once, saved chunks remember
+// whose spill they already have so blocks broken since don't grow back. Pasted structures replace
+// whatever is there. Chunks that were already sent get sent again
+pub fn place_pending_blocks(
+    (mut pending_spill, mut to_be_pasted): (ResMut<PendingSpill>, ResMut<ToBePasted>),
+    current_chunks: Res<CurrentChunks>,
+    mut chunks: Query<(&mut ChunkData, &mut SpilledFrom)>,
+    mut load_points: Query<&mut SentChunks>,
+    block_table: Res<BlockTable>,
+) {
+    if pending_spill.is_empty() && to_be_pasted.is_empty() {
+        return;
+    }
+    let mut changed = Vec::new();
+    pending_spill.retain(|chunk_pos, spills| {
+        let Some((mut chunk, mut spilled)) = current_chunks
+            .get_entity(*chunk_pos)
+            .and_then(|entity| chunks.get_mut(entity).ok())
+        else {
+            return true;
+        };
+        for (from, blocks) in spills.drain(..) {
+            let offset = *from - **chunk_pos;
+            if spilled.contains(offset) {
+                continue;
+            }
+            spilled.insert(offset);
+            // A modified chunk is saved again to remember it has this spill
+            chunk.set_dirty(true);
+            if place_spill(&mut chunk, blocks, &block_table) {
+                changed.push(*chunk_pos);
+            }
+        }
+        false
+    });
+    to_be_pasted.retain(|pos, blocks| {
+        let chunk_pos = ChunkPos(*pos);
+        let Some((mut chunk, _)) = current_chunks
+            .get_entity(chunk_pos)
+            .and_then(|entity| chunks.get_mut(entity).ok())
+        else {
+            return true;
+        };
+        for (relative_pos, block) in blocks.drain(..) {
+            chunk.set(RelativeVoxelPos(relative_pos), block);
+        }
+        changed.push(chunk_pos);
+        false
+    });
+    for mut sent_chunks in load_points.iter_mut() {
+        for chunk_pos in changed.iter() {
+            sent_chunks.chunks.remove(chunk_pos);
+        }
+    }
 }
 
 // #[derive(Component)]
@@ -111,8 +310,9 @@ pub fn process_save(
 pub struct GenTask(Task<ChunkPos>);
 
 #[derive(Component)]
-pub struct PreGenTask(Task<(ChunkData, ChunkPos)>);
+pub struct PreGenTask(Task<(ChunkData, ChunkPos, ToBePlaced)>);
 
+#[allow(clippy::too_many_arguments)]
 pub fn process_pre_queue(
     mut commands: Commands,
     mut chunk_queue: EventReader<PrepassEvent>,
@@ -123,7 +323,14 @@ pub fn process_pre_queue(
     _chunks_to_save: ResMut<ChunksToSave>,
     _block_table: Res<BlockTable>,
     (biome_table, biome_hashmap, biome_tree): (Res<BiomeTable>, Res<BiomeHashmap>, Res<BiomeTree>),
+    (feature_rule_table, feature_table, structure_table): (
+        Res<FeatureRuleTable>,
+        Res<FeatureTable>,
+        Res<StructureTable>,
+    ),
+    mut pending_spill: ResMut<PendingSpill>,
     _save: Res<SaveGame>,
+    saved: Query<&SavedData>,
 ) {
     let cloned_seed = world_info.seed;
     let task_pool = AsyncComputeTaskPool::get();
@@ -132,31 +339,56 @@ pub fn process_pre_queue(
         let cloned_table = biome_table.clone();
         let cloned_hashmap = biome_hashmap.clone();
         let cloned_tree = biome_tree.clone();
+        let cloned_rules = feature_rule_table.clone();
+        let cloned_features = feature_table.clone();
+        let cloned_structures = structure_table.clone();
         let task = task_pool.spawn(async move {
-            (
-                ChunkData::from_raw(generate_chunk(
-                    *chunk_pos,
-                    cloned_seed,
-                    &cloned_table,
-                    &cloned_hashmap,
-                    &cloned_tree,
-                )),
-                chunk_pos,
-            )
+            let (chunk, spill) = generate_decorated_chunk(
+                *chunk_pos,
+                cloned_seed,
+                &cloned_table,
+                &cloned_hashmap,
+                &cloned_tree,
+                &cloned_rules,
+                &cloned_features,
+                &cloned_structures,
+            );
+            (chunk, chunk_pos, spill)
         });
         commands.spawn(PreGenTask(task));
     }
     gen_task.for_each_mut(|(entity, mut task)| {
-        if let Some(chunk) = future::block_on(future::poll_once(&mut task.0)) {
-            let chunk_pos = chunk.1;
+        if let Some((mut chunk, chunk_pos, spill)) =
+            future::block_on(future::poll_once(&mut task.0))
+        {
             // if **save {
             //     chunks_to_save.push((chunk_pos, chunk.0.to_raw()));
             // }
+            for (pos, blocks) in spill.0 {
+                pending_spill
+                    .entry(ChunkPos(pos))
+                    .or_default()
+                    .push((chunk_pos, blocks));
+            }
             if let Some(chunk_entity) = current_chunks.get_entity(chunk_pos) {
+                let spilled = match saved.get(chunk_entity).map(|saved| &saved.0) {
+                    Ok(SavedChunk::Full(raw_chunk, spilled)) => {
+                        chunk = ChunkData::from_raw(raw_chunk.clone());
+                        spilled.clone()
+                    }
+                    Ok(SavedChunk::Diff(blocks, spilled)) => {
+                        apply_diff(&mut chunk, blocks);
+                        spilled.clone()
+                    }
+                    Err(_) => SpilledFrom::default(),
+                };
+                commands.entity(chunk_entity).remove::<SavedData>();
+                // The same as what is saved, if anything
+                chunk.set_dirty(false);
                 full_gen_queue.send(FullGenEvent(chunk_pos));
                 commands
                     .entity(chunk_entity)
-                    .insert(chunk)
+                    .insert((chunk, chunk_pos, spilled))
                     .insert(GeneratingChunk);
                 commands.entity(chunk_entity).remove::<PrepassChunk>();
             }
@@ -215,6 +447,11 @@ impl Plugin for ChunkPlugin {
         app.insert_resource(ChunksToSave::default())
             .insert_resource(PlayersToSave::default())
             .insert_resource(FirstSaves::default())
+            .insert_resource(SaveRequest::default())
+            .insert_resource(PendingDiffs::default())
+            .insert_resource(PendingSpill::default())
+            .insert_resource(ToBePasted::default())
+            .insert_resource(ModifiedChunks::default())
             .insert_resource(CurrentChunks::default())
             .insert_resource(SimulationRadius {
                 vertical: 4,
@@ -223,7 +460,11 @@ impl Plugin for ChunkPlugin {
             .add_systems((unsend_chunks, generate_chunks_world))
             .add_system(process_pre_queue.after(unsend_chunks))
             .add_system(process_queue.after(unsend_chunks))
-            .add_system(process_save.after(process_queue))
+            .add_startup_system(register_structure_commands)
+            .add_system(structure_commands.before(place_pending_blocks))
+            .add_system(place_pending_blocks.after(process_pre_queue))
+            // Last so it sees every AppExit sent this update and saves before the app stops
+            .add_system(process_save.in_base_set(CoreSet::Last))
             .add_system(destroy_chunks.after(process_queue))
             .add_event::<PrepassEvent>()
             .add_event::<FullGenEvent>();
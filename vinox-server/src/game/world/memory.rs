@@ -0,0 +1,139 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Mutex,
+};
+
+use vinox_common::world::chunks::{
+    positions::ChunkPos,
+    storage::{BlockId, BlockRegistry},
+};
+
+use super::storage::{NameList, SavedChunk, SavedPlayer, WorldInfo, WorldStorage};
+
+#[derive(Default)]
+struct MemoryWorld {
+    world_info: Option<WorldInfo>,
+    block_ids: HashMap<String, BlockId>,
+    chunks: HashMap<ChunkPos, SavedChunk>,
+    // Saved player and hashed password
+    players: HashMap<String, (SavedPlayer, String)>,
+    ops: HashSet<String>,
+    whitelist: HashSet<String>,
+    bans: HashMap<String, String>,
+}
+
+// Keeps everything until the server stops. Chunks are kept unpacked so there is no format to
+// upgrade
+#[derive(Default)]
+pub struct MemoryStorage {
+    world: Mutex<MemoryWorld>,
+}
+
+impl WorldStorage for MemoryStorage {
+    fn load_world_info(&self) -> Option<WorldInfo> {
+        self.world.lock().unwrap().world_info.clone()
+    }
+
+    fn save_world_info(&self, world_info: &WorldInfo) {
+        self.world.lock().unwrap().world_info = Some(world_info.clone());
+    }
+
+    fn load_block_registry(&self) -> BlockRegistry {
+        let world = self.world.lock().unwrap();
+        let mut block_registry = BlockRegistry::default();
+        for (identifier, id) in world.block_ids.iter() {
+            block_registry.0.insert(identifier.clone(), *id);
+        }
+        block_registry
+    }
+
+    fn save_block_ids(&self, block_ids: &[(String, BlockId)]) {
+        let mut world = self.world.lock().unwrap();
+        world.block_ids.extend(block_ids.iter().cloned());
+    }
+
+    fn load_chunk(&self, chunk_pos: ChunkPos, _: &BlockRegistry) -> Option<SavedChunk> {
+        self.world.lock().unwrap().chunks.get(&chunk_pos).cloned()
+    }
+
+    fn save_chunks(&self, chunks: &[(ChunkPos, SavedChunk)], _: &BlockRegistry) {
+        let mut world = self.world.lock().unwrap();
+        world.chunks.extend(chunks.iter().cloned());
+    }
+
+    fn saved_chunks(&self) -> Vec<ChunkPos> {
+        self.world.lock().unwrap().chunks.keys().copied().collect()
+    }
+
+    fn delete_chunks(&self, chunks: &[ChunkPos]) {
+        let mut world = self.world.lock().unwrap();
+        for chunk_pos in chunks.iter() {
+            world.chunks.remove(chunk_pos);
+        }
+    }
+
+    fn load_player(&self, name: &str) -> Option<(SavedPlayer, String)> {
+        self.world.lock().unwrap().players.get(name).cloned()
+    }
+
+    fn save_passwords(&self, players: &[(String, SavedPlayer, String)]) {
+        let mut world = self.world.lock().unwrap();
+        for (user_name, player, password) in players.iter() {
+            world
+                .players
+                .insert(user_name.clone(), (player.clone(), password.clone()));
+        }
+    }
+
+    fn save_players(&self, players: &[(String, SavedPlayer)]) {
+        let mut world = self.world.lock().unwrap();
+        for (user_name, player) in players.iter() {
+            if let Some(saved) = world.players.get_mut(user_name) {
+                saved.0 = player.clone();
+            }
+        }
+    }
+
+    fn load_name_list(&self, list: NameList) -> HashSet<String> {
+        let world = self.world.lock().unwrap();
+        match list {
+            NameList::Ops => world.ops.clone(),
+            NameList::Whitelist => world.whitelist.clone(),
+        }
+    }
+
+    fn set_in_name_list(&self, list: NameList, name: &str, present: bool) {
+        let mut world = self.world.lock().unwrap();
+        let names = match list {
+            NameList::Ops => &mut world.ops,
+            NameList::Whitelist => &mut world.whitelist,
+        };
+        if present {
+            names.insert(name.to_string());
+        } else {
+            names.remove(name);
+        }
+    }
+
+    fn load_bans(&self) -> HashMap<String, String> {
+        self.world.lock().unwrap().bans.clone()
+    }
+
+    fn save_ban(&self, name: &str, reason: Option<&str>) {
+        let mut world = self.world.lock().unwrap();
+        if let Some(reason) = reason {
+            world.bans.insert(name.to_string(), reason.to_string());
+        } else {
+            world.bans.remove(name);
+        }
+    }
+
+    fn backup(&self, _: &Path) -> Result<(), String> {
+        Err("Worlds kept in memory can't be backed up".to_string())
+    }
+
+    fn restore(&self, _: &Path) -> Result<(), String> {
+        Err("Worlds kept in memory can't be restored".to_string())
+    }
+}
@@ -0,0 +1,290 @@
+use std::io::Cursor;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use vinox_common::world::chunks::{
+    positions::ChunkPos,
+    storage::{BlockRegistry, PackedBlock, PackedChunk, RawChunk},
+};
+use zstd::stream::{copy_decode, copy_encode};
+
+use super::storage::{PackedSavedChunk, SavedPlayer, SpilledFrom};
+
+// Bump whenever a chunk, player or the database changes shape and add a step for each below
+pub const SAVE_FORMAT_VERSION: u32 = 3;
+
+// Blobs start with one of these and the version they were written with, anything saved before
+// that is version 0
+const CHUNK_MAGIC: &[u8; 4] = b"VXCH";
+const PLAYER_MAGIC: &[u8; 4] = b"VXPL";
+
+// Step i takes a payload from version i to i + 1. Chunks need the block ids of the world
+type ChunkStep = fn(Vec<u8>, &BlockRegistry) -> Result<Vec<u8>, String>;
+type PlayerStep = fn(Vec<u8>) -> Result<Vec<u8>, String>;
+type DatabaseStep = fn(&Connection) -> rusqlite::Result<()>;
+
+// Chunk payloads are zstd compressed bincode of a PackedSavedChunk, a SavedChunkV2 in 2, a
+// PackedChunk in 1 and a RawChunk with the whole BlockData in every palette entry in 0. Light was
+// never saved so it changing shape doesn't matter here
+const CHUNK_STEPS: [ChunkStep; SAVE_FORMAT_VERSION as usize] =
+    [pack_raw_chunk, wrap_full_chunk, add_spilled_from];
+// Player payloads are bincode of a SavedPlayer
+const PLAYER_STEPS: [PlayerStep; SAVE_FORMAT_VERSION as usize] =
+    [keep_payload, keep_payload, keep_payload];
+const DATABASE_STEPS: [DatabaseStep; SAVE_FORMAT_VERSION as usize] =
+    [add_meta_tables, keep_tables, keep_tables];
+
+// A PackedSavedChunk before it kept which neighbours had spilled into it
+#[derive(Serialize, Deserialize)]
+enum SavedChunkV2 {
+    Full(PackedChunk),
+    Diff(Vec<(u16, PackedBlock)>),
+}
+
+// Players haven't changed since the first release
+fn keep_payload(payload: Vec<u8>) -> Result<Vec<u8>, String> {
+    Ok(payload)
+}
+
+fn decompress<T: serde::de::DeserializeOwned>(payload: &[u8]) -> Result<T, String> {
+    let mut bytes = Cursor::new(Vec::new());
+    copy_decode(payload, &mut bytes).map_err(|e| format!("Couldn't decompress: {e}"))?;
+    bincode::deserialize(bytes.get_ref()).map_err(|e| e.to_string())
+}
+
+fn compress<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    let bytes = bincode::serialize(value).map_err(|e| e.to_string())?;
+    let mut output = Cursor::new(Vec::new());
+    copy_encode(&bytes[..], &mut output, 0).map_err(|e| e.to_string())?;
+    Ok(output.into_inner())
+}
+
+// Palettes moved from identifiers to the world's block ids
+fn pack_raw_chunk(payload: Vec<u8>, block_registry: &BlockRegistry) -> Result<Vec<u8>, String> {
+    let chunk: RawChunk = decompress(&payload)?;
+    compress(&chunk.pack(block_registry))
+}
+
+// Chunks were always saved whole before diffs
+fn wrap_full_chunk(payload: Vec<u8>, _: &BlockRegistry) -> Result<Vec<u8>, String> {
+    let chunk: PackedChunk = decompress(&payload)?;
+    compress(&SavedChunkV2::Full(chunk))
+}
+
+// Saved chunks kept everything spilled into them, nothing is placed into them again
+fn add_spilled_from(payload: Vec<u8>, _: &BlockRegistry) -> Result<Vec<u8>, String> {
+    let chunk = match decompress(&payload)? {
+        SavedChunkV2::Full(chunk) => PackedSavedChunk::Full(chunk, SpilledFrom::Everything),
+        SavedChunkV2::Diff(blocks) => PackedSavedChunk::Diff(blocks, SpilledFrom::Everything),
+    };
+    compress(&chunk)
+}
+
+// create_database already made them, there is nothing to move over
+fn add_meta_tables(_database: &Connection) -> rusqlite::Result<()> {
+    Ok(())
+}
+
+fn keep_tables(_database: &Connection) -> rusqlite::Result<()> {
+    Ok(())
+}
+
+fn encode_versioned(magic: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(payload.len() + 8);
+    blob.extend_from_slice(magic);
+    blob.extend_from_slice(&SAVE_FORMAT_VERSION.to_le_bytes());
+    blob.extend_from_slice(payload);
+    blob
+}
+
+// Returns the version a blob was saved with and its payload
+fn decode_versioned<'a>(magic: &[u8; 4], blob: &'a [u8]) -> Result<(u32, &'a [u8]), String> {
+    let (version, payload) = if blob.len() >= 8 && blob.starts_with(magic) {
+        let version = u32::from_le_bytes([blob[4], blob[5], blob[6], blob[7]]);
+        (version, &blob[8..])
+    } else {
+        (0, blob)
+    };
+    if version > SAVE_FORMAT_VERSION {
+        return Err(format!(
+            "Saved with format {version} but this version only knows up to {SAVE_FORMAT_VERSION}"
+        ));
+    }
+    Ok((version, payload))
+}
+
+pub fn encode_chunk(chunk: &PackedSavedChunk) -> Result<Vec<u8>, String> {
+    Ok(encode_versioned(CHUNK_MAGIC, &compress(chunk)?))
+}
+
+// Also returns whether it had to be upgraded
+pub fn decode_chunk(
+    blob: &[u8],
+    block_registry: &BlockRegistry,
+) -> Result<(PackedSavedChunk, bool), String> {
+    let (version, payload) = decode_versioned(CHUNK_MAGIC, blob)?;
+    let mut payload = payload.to_vec();
+    for step in CHUNK_STEPS[version as usize..].iter() {
+        payload = step(payload, block_registry)?;
+    }
+    Ok((decompress(&payload)?, version < SAVE_FORMAT_VERSION))
+}
+
+pub fn encode_player(player: &SavedPlayer) -> Result<Vec<u8>, String> {
+    let bytes = bincode::serialize(player).map_err(|e| e.to_string())?;
+    Ok(encode_versioned(PLAYER_MAGIC, &bytes))
+}
+
+pub fn decode_player(blob: &[u8]) -> Result<(SavedPlayer, bool), String> {
+    let (version, payload) = decode_versioned(PLAYER_MAGIC, blob)?;
+    let mut payload = payload.to_vec();
+    for step in PLAYER_STEPS[version as usize..].iter() {
+        payload = step(payload)?;
+    }
+    let player = bincode::deserialize(&payload).map_err(|e| e.to_string())?;
+    Ok((player, version < SAVE_FORMAT_VERSION))
+}
+
+fn load_format_version(database: &Connection) -> Option<u32> {
+    database
+        .query_row(
+            "SELECT value FROM meta WHERE key = 'format_version'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()
+        .ok()
+        .flatten()
+}
+
+fn has_saves(database: &Connection) -> bool {
+    ["blocks", "players"].iter().any(|table| {
+        database
+            .query_row(&format!("SELECT count(*) FROM {table}"), [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map_or(false, |count| count > 0)
+    })
+}
+
+// Runs before anything else touches the world. A world from a newer version is refused so we
+// don't write old data into it
+pub fn migrate_database(database: &Connection) -> Result<(), String> {
+    let version = match load_format_version(database) {
+        Some(version) => version,
+        None if has_saves(database) => 0,
+        None => SAVE_FORMAT_VERSION,
+    };
+    if version > SAVE_FORMAT_VERSION {
+        return Err(format!(
+            "This world was saved with format {version} but this version of the server only \
+             knows up to {SAVE_FORMAT_VERSION}"
+        ));
+    }
+    for (from, step) in DATABASE_STEPS.iter().enumerate().skip(version as usize) {
+        step(database).map_err(|e| format!("Upgrading the world from format {from}: {e}"))?;
+        println!("Upgraded the world from format {from} to {}", from + 1);
+    }
+    database
+        .execute(
+            "REPLACE INTO meta (key, value) values ('format_version', ?1)",
+            params![SAVE_FORMAT_VERSION],
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Kept out of the way so the chunk regenerates, the data is still there if it can be recovered
+pub fn quarantine_chunk(chunk_pos: ChunkPos, blob: &[u8], reason: &str, database: &Connection) {
+    println!("Quarantined chunk {chunk_pos:?}: {reason}");
+    let key = format!("{},{},{}", chunk_pos.x, chunk_pos.y, chunk_pos.z);
+    database
+        .execute(
+            "REPLACE INTO quarantine (kind, key, data, reason) values ('chunk', ?1, ?2, ?3)",
+            params![key, blob, reason],
+        )
+        .ok();
+    database
+        .execute(
+            "DELETE FROM blocks WHERE posx = ?1 AND posy = ?2 AND posz = ?3",
+            params![chunk_pos.x, chunk_pos.y, chunk_pos.z],
+        )
+        .ok();
+}
+
+pub fn quarantine_player(name: &str, blob: &[u8], reason: &str, database: &Connection) {
+    println!("Quarantined the saved data of {name}: {reason}");
+    database
+        .execute(
+            "REPLACE INTO quarantine (kind, key, data, reason) values ('player', ?1, ?2, ?3)",
+            params![name, blob, reason],
+        )
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use vinox_common::world::chunks::{
+        positions::RelativeVoxelPos,
+        storage::{BlockData, BlockRegistry, ChunkData},
+    };
+
+    use super::{compress, decode_chunk, encode_chunk};
+    use crate::game::world::storage::{SavedChunk, SpilledFrom};
+
+    fn test_chunk() -> ChunkData {
+        let mut chunk = ChunkData::default();
+        chunk.set(
+            RelativeVoxelPos::new(1, 2, 3),
+            BlockData::new("vinox".to_string(), "grass".to_string()),
+        );
+        chunk
+    }
+
+    fn test_registry() -> BlockRegistry {
+        let mut block_registry = BlockRegistry::default();
+        block_registry.register("vinox:grass".to_string());
+        block_registry
+    }
+
+    #[test]
+    fn baseline_chunks_migrate() {
+        let block_registry = test_registry();
+        // What the first release wrote, no header and the RawChunk itself
+        let blob = compress(&test_chunk().to_raw()).unwrap();
+        let (chunk, upgraded) = decode_chunk(&blob, &block_registry).unwrap();
+        assert!(upgraded);
+        let SavedChunk::Full(raw_chunk, spilled) = chunk.unpack(&block_registry) else {
+            panic!("A baseline chunk should load as a full chunk");
+        };
+        assert_eq!(spilled, SpilledFrom::Everything);
+        let chunk = ChunkData::from_raw(raw_chunk);
+        assert_eq!(
+            chunk.get_identifier(RelativeVoxelPos::new(1, 2, 3)),
+            "vinox:grass"
+        );
+        assert_eq!(
+            chunk.get_identifier(RelativeVoxelPos::new(0, 0, 0)),
+            "vinox:air"
+        );
+    }
+
+    #[test]
+    fn current_chunks_load_as_is() {
+        let block_registry = test_registry();
+        let saved =
+            SavedChunk::Full(test_chunk().to_raw(), SpilledFrom::Nothing).pack(&block_registry);
+        let blob = encode_chunk(&saved).unwrap();
+        let (chunk, upgraded) = decode_chunk(&blob, &block_registry).unwrap();
+        assert!(!upgraded);
+        let SavedChunk::Full(raw_chunk, spilled) = chunk.unpack(&block_registry) else {
+            panic!("A full chunk should load as a full chunk");
+        };
+        assert_eq!(spilled, SpilledFrom::Nothing);
+        let chunk = ChunkData::from_raw(raw_chunk);
+        assert_eq!(
+            chunk.get_identifier(RelativeVoxelPos::new(1, 2, 3)),
+            "vinox:grass"
+        );
+    }
+}
@@ -1,3 +1,8 @@
+pub mod backup;
 pub mod chunk;
-pub mod generation;
+pub mod memory;
+pub mod migration;
+pub mod region;
+pub mod sqlite;
 pub mod storage;
+pub mod structures;
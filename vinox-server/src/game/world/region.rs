@@ -0,0 +1,564 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use bevy::prelude::*;
+use fs_extra::dir::{copy, CopyOptions};
+use serde::{Deserialize, Serialize};
+use vinox_common::world::chunks::{
+    positions::ChunkPos,
+    storage::{BlockId, BlockRegistry},
+};
+
+use super::{
+    migration::{decode_chunk, decode_player, encode_chunk, encode_player, SAVE_FORMAT_VERSION},
+    storage::{
+        load_world_info, save_world_info, NameList, SavedChunk, SavedPlayer, WorldInfo,
+        WorldStorage,
+    },
+};
+
+// Chunks along each side of a region file
+pub const REGION_SIZE: i32 = 32;
+const REGION_CHUNKS: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+// A region file starts with this and where every chunk is, the first sector and the length in
+// bytes or 0 if it was never saved. Chunk blobs are the same as the ones SQLite keeps and are
+// written in sectors after that so one can change without rewriting the rest
+const REGION_MAGIC: &[u8; 4] = b"VXRG";
+const SECTOR_SIZE: u64 = 512;
+const HEADER_SIZE: u64 = REGION_MAGIC.len() as u64 + REGION_CHUNKS as u64 * 8;
+
+// Region files kept open at once, the one used longest ago is closed first
+const OPEN_REGIONS: usize = 16;
+
+struct RegionFile {
+    file: File,
+    entries: Vec<(u32, u32)>,
+    // Which sectors are taken, the header's included
+    used: Vec<bool>,
+}
+
+fn sectors(length: u64) -> usize {
+    length.div_ceil(SECTOR_SIZE) as usize
+}
+
+impl RegionFile {
+    fn create(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(REGION_MAGIC)?;
+        file.write_all(&vec![0; HEADER_SIZE as usize - REGION_MAGIC.len()])?;
+        Ok(Self {
+            file,
+            entries: vec![(0, 0); REGION_CHUNKS],
+            used: vec![true; sectors(HEADER_SIZE)],
+        })
+    }
+
+    fn open(path: &Path) -> Result<Self, String> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| e.to_string())?;
+        let mut header = vec![0; HEADER_SIZE as usize];
+        file.read_exact(&mut header).map_err(|e| e.to_string())?;
+        if !header.starts_with(REGION_MAGIC) {
+            return Err("Not a region file".to_string());
+        }
+        let entries: Vec<(u32, u32)> = header[REGION_MAGIC.len()..]
+            .chunks_exact(8)
+            .map(|entry| {
+                (
+                    u32::from_le_bytes(entry[..4].try_into().unwrap()),
+                    u32::from_le_bytes(entry[4..].try_into().unwrap()),
+                )
+            })
+            .collect();
+        let mut region = Self {
+            file,
+            entries: Vec::new(),
+            used: vec![true; sectors(HEADER_SIZE)],
+        };
+        for (sector, length) in entries.iter() {
+            if *length > 0 {
+                region.mark(*sector, *length, true);
+            }
+        }
+        region.entries = entries;
+        Ok(region)
+    }
+
+    fn mark(&mut self, sector: u32, length: u32, used: bool) {
+        let end = sector as usize + sectors(length as u64);
+        if self.used.len() < end {
+            self.used.resize(end, false);
+        }
+        self.used[sector as usize..end].fill(used);
+    }
+
+    fn indices(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..REGION_CHUNKS as u16).filter(|index| self.entries[*index as usize].1 > 0)
+    }
+
+    fn read(&mut self, index: u16) -> Option<io::Result<Vec<u8>>> {
+        let (sector, length) = self.entries[index as usize];
+        if length == 0 {
+            return None;
+        }
+        let mut blob = vec![0; length as usize];
+        Some(
+            self.file
+                .seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE))
+                .and_then(|_| self.file.read_exact(&mut blob))
+                .map(|_| blob),
+        )
+    }
+
+    // The blob goes into free sectors before the header points at it, so a crash part way
+    // through leaves the old one
+    fn write(&mut self, index: u16, blob: &[u8]) -> io::Result<()> {
+        let needed = sectors(blob.len() as u64);
+        let mut free = 0;
+        let mut start = None;
+        for (sector, used) in self.used.iter().enumerate() {
+            free = if *used { 0 } else { free + 1 };
+            if free == needed {
+                start = Some(sector + 1 - needed);
+                break;
+            }
+        }
+        // Free sectors at the end are used before the file grows
+        let start = start.unwrap_or(self.used.len() - free);
+        self.file
+            .seek(SeekFrom::Start(start as u64 * SECTOR_SIZE))?;
+        self.file.write_all(blob)?;
+        self.mark(start as u32, blob.len() as u32, true);
+        self.set_entry(index, (start as u32, blob.len() as u32))
+    }
+
+    fn remove(&mut self, index: u16) -> io::Result<bool> {
+        if self.entries[index as usize].1 == 0 {
+            return Ok(false);
+        }
+        self.set_entry(index, (0, 0)).map(|_| true)
+    }
+
+    fn set_entry(&mut self, index: u16, entry: (u32, u32)) -> io::Result<()> {
+        let mut bytes = entry.0.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&entry.1.to_le_bytes());
+        self.file.seek(SeekFrom::Start(
+            REGION_MAGIC.len() as u64 + index as u64 * 8,
+        ))?;
+        self.file.write_all(&bytes)?;
+        let (sector, length) = std::mem::replace(&mut self.entries[index as usize], entry);
+        if length > 0 {
+            self.mark(sector, length, false);
+        }
+        Ok(())
+    }
+}
+
+// Open region files, the most recently used at the back
+#[derive(Default)]
+struct OpenRegions {
+    files: HashMap<IVec3, RegionFile>,
+    order: VecDeque<IVec3>,
+}
+
+// Everything that isn't a chunk, small enough to rewrite whenever it changes
+#[derive(Serialize, Deserialize, Default)]
+struct WorldMeta {
+    format_version: u32,
+    block_ids: HashMap<String, BlockId>,
+    // Saved player blob and hashed password
+    players: HashMap<String, (Vec<u8>, String)>,
+    ops: HashSet<String>,
+    whitelist: HashSet<String>,
+    bans: HashMap<String, String>,
+}
+
+// A <world> folder holding world.bin and regions/<x>.<y>.<z>.bin
+pub struct RegionStorage {
+    path: PathBuf,
+    info_path: PathBuf,
+    meta: Mutex<WorldMeta>,
+    regions: Mutex<OpenRegions>,
+}
+
+fn region_pos(chunk_pos: ChunkPos) -> (IVec3, u16) {
+    let region = IVec3::new(
+        chunk_pos.x.div_euclid(REGION_SIZE),
+        chunk_pos.y.div_euclid(REGION_SIZE),
+        chunk_pos.z.div_euclid(REGION_SIZE),
+    );
+    let local = *chunk_pos - region * REGION_SIZE;
+    let index = local.x + local.y * REGION_SIZE + local.z * REGION_SIZE * REGION_SIZE;
+    (region, index as u16)
+}
+
+fn read_meta(meta_path: &Path) -> Result<WorldMeta, String> {
+    let mut meta = if meta_path.exists() {
+        let bytes = fs::read(meta_path)
+            .map_err(|e| format!("Couldn't read {}: {e}", meta_path.display()))?;
+        bincode::deserialize::<WorldMeta>(&bytes)
+            .map_err(|e| format!("Couldn't decode {}: {e}", meta_path.display()))?
+    } else {
+        WorldMeta {
+            format_version: SAVE_FORMAT_VERSION,
+            ..default()
+        }
+    };
+    if meta.format_version > SAVE_FORMAT_VERSION {
+        return Err(format!(
+            "This world was saved with format {} but this version of the server only \
+             knows up to {SAVE_FORMAT_VERSION}",
+            meta.format_version
+        ));
+    }
+    // Chunks and players carry their own version, they are upgraded as they load
+    meta.format_version = SAVE_FORMAT_VERSION;
+    Ok(meta)
+}
+
+// Written next to the old file and moved over it so a crash can't leave half a file
+fn write_atomic(path: &Path, bytes: &[u8]) {
+    let temp_path = path.with_extension("tmp");
+    if let Err(e) = fs::write(&temp_path, bytes).and_then(|_| fs::rename(&temp_path, path)) {
+        println!("Failed to write {}: {e}", path.display());
+    }
+}
+
+impl RegionStorage {
+    pub fn open(world_path: &Path) -> Result<Self, String> {
+        fs::create_dir_all(world_path.join("regions"))
+            .map_err(|e| format!("Couldn't create {}: {e}", world_path.display()))?;
+        let meta = read_meta(&world_path.join("world.bin"))?;
+        let storage = Self {
+            path: world_path.to_path_buf(),
+            info_path: format!("{}.ron", world_path.display()).into(),
+            meta: Mutex::new(meta),
+            regions: Mutex::new(OpenRegions::default()),
+        };
+        storage.save_meta(&storage.meta.lock().unwrap());
+        Ok(storage)
+    }
+
+    fn save_meta(&self, meta: &WorldMeta) {
+        if let Ok(bytes) = bincode::serialize(meta) {
+            write_atomic(&self.path.join("world.bin"), &bytes);
+        }
+    }
+
+    fn region_path(&self, region: IVec3) -> PathBuf {
+        self.path
+            .join("regions")
+            .join(format!("{}.{}.{}.bin", region.x, region.y, region.z))
+    }
+
+    // None if the region has nothing saved in it, unless it's about to be written to
+    fn region<'a>(
+        &self,
+        regions: &'a mut OpenRegions,
+        region_pos: IVec3,
+        create: bool,
+    ) -> Option<&'a mut RegionFile> {
+        if !regions.files.contains_key(&region_pos) {
+            let region = self.open_region(region_pos, create)?;
+            if regions.files.len() >= OPEN_REGIONS {
+                if let Some(oldest) = regions.order.pop_front() {
+                    regions.files.remove(&oldest);
+                }
+            }
+            regions.files.insert(region_pos, region);
+        }
+        regions.order.retain(|pos| *pos != region_pos);
+        regions.order.push_back(region_pos);
+        regions.files.get_mut(&region_pos)
+    }
+
+    fn open_region(&self, region_pos: IVec3, create: bool) -> Option<RegionFile> {
+        let path = self.region_path(region_pos);
+        if path.exists() {
+            match RegionFile::open(&path) {
+                Ok(region) => return Some(region),
+                Err(e) => {
+                    // Moved aside so saving into this region doesn't overwrite it
+                    println!("Quarantined region {}: {e}", path.display());
+                    fs::rename(&path, path.with_extension("bin.quarantined")).ok();
+                }
+            }
+        }
+        if !create {
+            return None;
+        }
+        RegionFile::create(&path)
+            .map_err(|e| println!("Failed to create {}: {e}", path.display()))
+            .ok()
+    }
+
+    fn quarantine(&self, name: &str, blob: &[u8], reason: &str) {
+        println!("Quarantined {name}: {reason}");
+        let path = self.path.join("quarantine");
+        fs::create_dir_all(&path).ok();
+        fs::write(path.join(format!("{name}.bin")), blob).ok();
+    }
+}
+
+impl WorldStorage for RegionStorage {
+    fn load_world_info(&self) -> Option<WorldInfo> {
+        load_world_info(self.info_path.clone())
+    }
+
+    fn save_world_info(&self, world_info: &WorldInfo) {
+        save_world_info(world_info.clone(), self.info_path.clone());
+    }
+
+    fn load_block_registry(&self) -> BlockRegistry {
+        let meta = self.meta.lock().unwrap();
+        let mut block_registry = BlockRegistry::default();
+        for (identifier, id) in meta.block_ids.iter() {
+            block_registry.0.insert(identifier.clone(), *id);
+        }
+        block_registry
+    }
+
+    fn save_block_ids(&self, block_ids: &[(String, BlockId)]) {
+        let mut meta = self.meta.lock().unwrap();
+        meta.block_ids.extend(block_ids.iter().cloned());
+        self.save_meta(&meta);
+    }
+
+    fn load_chunk(
+        &self,
+        chunk_pos: ChunkPos,
+        block_registry: &BlockRegistry,
+    ) -> Option<SavedChunk> {
+        let (region_pos, index) = region_pos(chunk_pos);
+        let mut regions = self.regions.lock().unwrap();
+        let region = self.region(&mut regions, region_pos, false)?;
+        let blob = match region.read(index)? {
+            Ok(blob) => blob,
+            Err(e) => {
+                println!("Failed to read chunk {chunk_pos:?}: {e}");
+                return None;
+            }
+        };
+        match decode_chunk(&blob, block_registry) {
+            Ok((final_chunk, upgraded)) => {
+                if upgraded {
+                    if let Ok(blob) = encode_chunk(&final_chunk) {
+                        if let Err(e) = region.write(index, &blob) {
+                            println!("Failed to save chunk {chunk_pos:?}: {e}");
+                        }
+                    }
+                }
+                Some(final_chunk.unpack(block_registry))
+            }
+            Err(reason) => {
+                region.remove(index).ok();
+                self.quarantine(
+                    &format!("chunk.{}.{}.{}", chunk_pos.x, chunk_pos.y, chunk_pos.z),
+                    &blob,
+                    &reason,
+                );
+                None
+            }
+        }
+    }
+
+    fn save_chunks(&self, chunks: &[(ChunkPos, SavedChunk)], block_registry: &BlockRegistry) {
+        let mut regions = self.regions.lock().unwrap();
+        for (chunk_pos, saved_chunk) in chunks.iter() {
+            let Ok(blob) = encode_chunk(&saved_chunk.pack(block_registry)) else {
+                continue;
+            };
+            let (region_pos, index) = region_pos(*chunk_pos);
+            let Some(region) = self.region(&mut regions, region_pos, true) else {
+                continue;
+            };
+            if let Err(e) = region.write(index, &blob) {
+                println!("Failed to save chunk {chunk_pos:?}: {e}");
+            }
+        }
+    }
+
+    // Reads every region's header, only meant for tools
+    fn saved_chunks(&self) -> Vec<ChunkPos> {
+        let mut regions = self.regions.lock().unwrap();
+        let mut chunks = Vec::new();
+        for entry in fs::read_dir(self.path.join("regions"))
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(name) = name.strip_suffix(".bin") else {
+                continue;
+            };
+            let parts: Vec<i32> = name
+                .split('.')
+                .filter_map(|part| part.parse().ok())
+                .collect();
+            let &[x, y, z] = parts.as_slice() else {
+                continue;
+            };
+            let region_pos = IVec3::new(x, y, z);
+            let Some(region) = self.region(&mut regions, region_pos, false) else {
+                continue;
+            };
+            for index in region.indices() {
+                let index = index as i32;
+                let local = IVec3::new(
+                    index % REGION_SIZE,
+                    index / REGION_SIZE % REGION_SIZE,
+                    index / (REGION_SIZE * REGION_SIZE),
+                );
+                chunks.push(ChunkPos(region_pos * REGION_SIZE + local));
+            }
+        }
+        chunks
+    }
+
+    fn delete_chunks(&self, chunks: &[ChunkPos]) {
+        let mut regions = self.regions.lock().unwrap();
+        for chunk_pos in chunks.iter() {
+            let (region_pos, index) = region_pos(*chunk_pos);
+            if let Some(region) = self.region(&mut regions, region_pos, false) {
+                if let Err(e) = region.remove(index) {
+                    println!("Failed to delete chunk {chunk_pos:?}: {e}");
+                }
+            }
+        }
+    }
+
+    fn load_player(&self, name: &str) -> Option<(SavedPlayer, String)> {
+        let mut meta = self.meta.lock().unwrap();
+        let (blob, password) = meta.players.get(name)?.clone();
+        let player = match decode_player(&blob) {
+            Ok((player, upgraded)) => {
+                if upgraded {
+                    if let Ok(blob) = encode_player(&player) {
+                        meta.players
+                            .insert(name.to_string(), (blob, password.clone()));
+                        self.save_meta(&meta);
+                    }
+                }
+                player
+            }
+            // Losing where they were is better than not letting them back in
+            Err(reason) => {
+                self.quarantine(&format!("player.{name}"), &blob, &reason);
+                SavedPlayer {
+                    position: [0, 75, 0],
+                    ..default()
+                }
+            }
+        };
+        Some((player, password))
+    }
+
+    fn save_passwords(&self, players: &[(String, SavedPlayer, String)]) {
+        let mut meta = self.meta.lock().unwrap();
+        for (user_name, player, password) in players.iter() {
+            if let Ok(blob) = encode_player(player) {
+                meta.players
+                    .insert(user_name.clone(), (blob, password.clone()));
+            }
+        }
+        self.save_meta(&meta);
+    }
+
+    fn save_players(&self, players: &[(String, SavedPlayer)]) {
+        let mut meta = self.meta.lock().unwrap();
+        for (user_name, player) in players.iter() {
+            if let (Ok(blob), Some(saved)) =
+                (encode_player(player), meta.players.get_mut(user_name))
+            {
+                saved.0 = blob;
+            }
+        }
+        self.save_meta(&meta);
+    }
+
+    fn load_name_list(&self, list: NameList) -> HashSet<String> {
+        let meta = self.meta.lock().unwrap();
+        match list {
+            NameList::Ops => meta.ops.clone(),
+            NameList::Whitelist => meta.whitelist.clone(),
+        }
+    }
+
+    fn set_in_name_list(&self, list: NameList, name: &str, present: bool) {
+        let mut meta = self.meta.lock().unwrap();
+        let names = match list {
+            NameList::Ops => &mut meta.ops,
+            NameList::Whitelist => &mut meta.whitelist,
+        };
+        if present {
+            names.insert(name.to_string());
+        } else {
+            names.remove(name);
+        }
+        self.save_meta(&meta);
+    }
+
+    fn load_bans(&self) -> HashMap<String, String> {
+        self.meta.lock().unwrap().bans.clone()
+    }
+
+    fn save_ban(&self, name: &str, reason: Option<&str>) {
+        let mut meta = self.meta.lock().unwrap();
+        if let Some(reason) = reason {
+            meta.bans.insert(name.to_string(), reason.to_string());
+        } else {
+            meta.bans.remove(name);
+        }
+        self.save_meta(&meta);
+    }
+
+    // Every write happens while holding the lock so the copy can't catch a region half written.
+    // It's taken for one region at a time so the server isn't held up for the whole backup
+    fn backup(&self, folder: &Path) -> Result<(), String> {
+        let bytes = bincode::serialize(&*self.meta.lock().unwrap()).map_err(|e| e.to_string())?;
+        fs::write(folder.join("world.bin"), bytes).map_err(|e| e.to_string())?;
+        let regions_path = folder.join("regions");
+        fs::create_dir_all(&regions_path)
+            .map_err(|e| format!("Couldn't create {}: {e}", regions_path.display()))?;
+        let entries = fs::read_dir(self.path.join("regions"))
+            .map_err(|e| format!("Couldn't read the regions: {e}"))?;
+        for entry in entries.flatten() {
+            let _regions = self.regions.lock().unwrap();
+            fs::copy(entry.path(), regions_path.join(entry.file_name()))
+                .map_err(|e| format!("Couldn't copy {}: {e}", entry.path().display()))?;
+        }
+        Ok(())
+    }
+
+    fn restore(&self, folder: &Path) -> Result<(), String> {
+        let new_meta = read_meta(&folder.join("world.bin"))?;
+        if !folder.join("regions").is_dir() {
+            return Err(format!("{} has no regions", folder.display()));
+        }
+        let (mut meta, mut regions) = (self.meta.lock().unwrap(), self.regions.lock().unwrap());
+        // Closed first, some platforms can't remove files that are open
+        *regions = OpenRegions::default();
+        let regions_path = self.path.join("regions");
+        fs::remove_dir_all(&regions_path)
+            .map_err(|e| format!("Couldn't remove {}: {e}", regions_path.display()))?;
+        copy(folder.join("regions"), &self.path, &CopyOptions::new())
+            .map_err(|e| format!("Couldn't copy the regions: {e}"))?;
+        *meta = new_meta;
+        self.save_meta(&meta);
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::create_dir_all,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use bevy::prelude::*;
+use rusqlite::*;
+use vinox_common::world::chunks::{
+    positions::ChunkPos,
+    storage::{BlockId, BlockRegistry},
+};
+
+use super::{
+    migration::{
+        decode_chunk, decode_player, encode_chunk, encode_player, migrate_database,
+        quarantine_chunk, quarantine_player,
+    },
+    storage::{
+        load_world_info, save_world_info, NameList, SavedChunk, SavedPlayer, WorldInfo,
+        WorldStorage,
+    },
+};
+
+pub struct SqliteStorage {
+    pub connection: Pool<SqliteConnectionManager>,
+    database_path: PathBuf,
+    info_path: PathBuf,
+}
+
+// Backups copy this many pages at a time and wait in between so the server's writes get a turn
+const BACKUP_PAGES: i32 = 256;
+const BACKUP_PAUSE: Duration = Duration::from_millis(10);
+
+impl SqliteStorage {
+    pub fn open(world_path: &Path) -> Result<Self, String> {
+        if let Some(parent) = world_path.parent() {
+            create_dir_all(parent).ok();
+        }
+        let database_path = PathBuf::from(format!("{}.db", world_path.display()));
+        let manager = SqliteConnectionManager::file(&database_path);
+        let pool = Pool::builder()
+            .max_size(30)
+            .test_on_check_out(false)
+            .build(manager)
+            .map_err(|e| format!("Couldn't open {}.db: {e}", world_path.display()))?;
+        pool.get()
+            .unwrap()
+            .execute_batch(
+                "PRAGMA journal_mode=WAL;
+             PRAGMA synchronous=NORMAL;",
+            )
+            .ok();
+        create_database(&pool.get().unwrap());
+        migrate_database(&pool.get().unwrap())?;
+        Ok(Self {
+            connection: pool,
+            database_path,
+            info_path: format!("{}.ron", world_path.display()).into(),
+        })
+    }
+}
+
+pub fn create_database(database: &Connection) {
+    database
+        .execute(
+            " create table if not exists blocks (
+            posx integer not null,
+            posy integer not null,
+            posz integer not null,
+            data blob,
+            PRIMARY KEY (posx, posy, posz)
+        )",
+            [],
+        )
+        .unwrap();
+    database
+        .execute(
+            " create table if not exists players(
+            name varchar(255) not null,
+            password varchar(255) not null,
+            data blob,
+            PRIMARY KEY (name)
+        )",
+            [],
+        )
+        .unwrap();
+    database
+        .execute(
+            " create table if not exists block_ids(
+            identifier varchar(255) not null,
+            id integer not null unique,
+            PRIMARY KEY (identifier)
+        )",
+            [],
+        )
+        .unwrap();
+    for list in [NameList::Ops, NameList::Whitelist] {
+        database
+            .execute(
+                &format!(
+                    " create table if not exists {}(
+            name varchar(255) not null,
+            PRIMARY KEY (name)
+        )",
+                    table(list)
+                ),
+                [],
+            )
+            .unwrap();
+    }
+    database
+        .execute(
+            " create table if not exists bans(
+            name varchar(255) not null,
+            reason text not null,
+            PRIMARY KEY (name)
+        )",
+            [],
+        )
+        .unwrap();
+    database
+        .execute(
+            " create table if not exists meta(
+            key varchar(255) not null,
+            value integer not null,
+            PRIMARY KEY (key)
+        )",
+            [],
+        )
+        .unwrap();
+    // Saves that couldn't be decoded end up here instead of being thrown away
+    database
+        .execute(
+            " create table if not exists quarantine(
+            kind varchar(255) not null,
+            key varchar(255) not null,
+            data blob,
+            reason text not null,
+            PRIMARY KEY (kind, key)
+        )",
+            [],
+        )
+        .unwrap();
+}
+
+fn table(list: NameList) -> &'static str {
+    match list {
+        NameList::Ops => "ops",
+        NameList::Whitelist => "whitelist",
+    }
+}
+
+impl WorldStorage for SqliteStorage {
+    fn load_world_info(&self) -> Option<WorldInfo> {
+        load_world_info(self.info_path.clone())
+    }
+
+    fn save_world_info(&self, world_info: &WorldInfo) {
+        save_world_info(world_info.clone(), self.info_path.clone());
+    }
+
+    fn load_block_registry(&self) -> BlockRegistry {
+        let database = self.connection.get().unwrap();
+        let mut block_registry = BlockRegistry::default();
+        if let Ok(mut stmt) = database.prepare("SELECT identifier, id FROM block_ids;") {
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, BlockId>(1)?))
+                })
+                .unwrap();
+            for (identifier, id) in rows.flatten() {
+                block_registry.0.insert(identifier, id);
+            }
+        }
+        block_registry
+    }
+
+    fn save_block_ids(&self, block_ids: &[(String, BlockId)]) {
+        let database = self.connection.get().unwrap();
+        database.execute("BEGIN;", []).unwrap();
+        for (identifier, id) in block_ids.iter() {
+            database
+                .execute(
+                    "REPLACE INTO block_ids (identifier, id) values (?1, ?2)",
+                    params![&identifier, &id],
+                )
+                .unwrap();
+        }
+        database.execute("COMMIT;", []).unwrap();
+    }
+
+    fn load_chunk(
+        &self,
+        chunk_pos: ChunkPos,
+        block_registry: &BlockRegistry,
+    ) -> Option<SavedChunk> {
+        let database = self.connection.get().unwrap();
+        let stmt = database.prepare(
+            "SELECT posx, posy, posz, data FROM blocks WHERE posx=:posx AND posy=:posy AND posz=:posz;",
+        );
+        if let Ok(mut stmt) = stmt {
+            let chunk_result: Result<Vec<u8>, _> = stmt.query_row(
+                &[
+                    (":posx", &chunk_pos.x),
+                    (":posy", &chunk_pos.y),
+                    (":posz", &chunk_pos.z),
+                ],
+                |row| Ok(row.get(3).unwrap()),
+            );
+            if let Ok(chunk_row) = chunk_result {
+                match decode_chunk(&chunk_row, block_registry) {
+                    Ok((final_chunk, upgraded)) => {
+                        let saved_chunk = final_chunk.unpack(block_registry);
+                        // Write it back so it only gets upgraded once
+                        if upgraded {
+                            self.save_chunks(&[(chunk_pos, saved_chunk.clone())], block_registry);
+                        }
+                        return Some(saved_chunk);
+                    }
+                    // Gets generated again like it was never saved
+                    Err(reason) => quarantine_chunk(chunk_pos, &chunk_row, &reason, &database),
+                }
+            }
+        }
+
+        None
+    }
+
+    fn save_chunks(&self, chunks: &[(ChunkPos, SavedChunk)], block_registry: &BlockRegistry) {
+        let database = self.connection.get().unwrap();
+        database.execute("BEGIN;", []).unwrap();
+        for (chunk_pos, saved_chunk) in chunks.iter() {
+            if let Ok(blob) = encode_chunk(&saved_chunk.pack(block_registry)) {
+                database
+                    .execute(
+                        "REPLACE INTO blocks (posx, posy, posz, data) values (?1, ?2, ?3, ?4)",
+                        params![&chunk_pos.x, &chunk_pos.y, &chunk_pos.z, &blob],
+                    )
+                    .unwrap();
+            }
+        }
+        database.execute("COMMIT;", []).unwrap();
+    }
+
+    fn saved_chunks(&self) -> Vec<ChunkPos> {
+        let database = self.connection.get().unwrap();
+        let mut chunks = Vec::new();
+        if let Ok(mut stmt) = database.prepare("SELECT posx, posy, posz FROM blocks;") {
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok(ChunkPos(IVec3::new(row.get(0)?, row.get(1)?, row.get(2)?)))
+                })
+                .unwrap();
+            chunks.extend(rows.flatten());
+        }
+        chunks
+    }
+
+    fn delete_chunks(&self, chunks: &[ChunkPos]) {
+        let database = self.connection.get().unwrap();
+        database.execute("BEGIN;", []).unwrap();
+        for chunk_pos in chunks.iter() {
+            database
+                .execute(
+                    "DELETE FROM blocks WHERE posx = ?1 AND posy = ?2 AND posz = ?3",
+                    params![&chunk_pos.x, &chunk_pos.y, &chunk_pos.z],
+                )
+                .unwrap();
+        }
+        database.execute("COMMIT;", []).unwrap();
+    }
+
+    fn load_player(&self, name: &str) -> Option<(SavedPlayer, String)> {
+        let database = self.connection.get().unwrap();
+        let stmt = database.prepare("SELECT name, data, password FROM players WHERE name=:name;");
+        if let Ok(mut stmt) = stmt {
+            let name_result: Result<Vec<u8>, _> =
+                stmt.query_row(&[(":name", name)], |row| Ok(row.get(1).unwrap()));
+            let password_result: Result<String, _> =
+                stmt.query_row(&[(":name", name)], |row| Ok(row.get(2).unwrap()));
+            let final_player = if let Ok(name_row) = name_result {
+                match decode_player(&name_row) {
+                    Ok((player, upgraded)) => {
+                        if upgraded {
+                            self.save_players(&[(name.to_string(), player.clone())]);
+                        }
+                        Some(player)
+                    }
+                    // Losing where they were is better than not letting them back in
+                    Err(reason) => {
+                        quarantine_player(name, &name_row, &reason, &database);
+                        Some(SavedPlayer {
+                            position: [0, 75, 0],
+                            ..default()
+                        })
+                    }
+                }
+            } else {
+                None
+            };
+            let password = if let Ok(password) = password_result {
+                Some(password)
+            } else {
+                None
+            };
+
+            if let Some(final_player) = final_player {
+                if let Some(password) = password {
+                    return Some((final_player, password));
+                } else {
+                    return None;
+                }
+            } else {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    fn save_passwords(&self, players: &[(String, SavedPlayer, String)]) {
+        let database = self.connection.get().unwrap();
+        database.execute("BEGIN;", []).unwrap();
+        for (user_name, player, password) in players.iter() {
+            if let Ok(player_bin) = encode_player(player) {
+                database
+                    .execute(
+                        "REPLACE INTO players (name, data, password) values (?1, ?2, ?3)",
+                        params![&user_name, &player_bin, &password],
+                    )
+                    .unwrap();
+            }
+        }
+        database.execute("COMMIT;", []).unwrap();
+    }
+
+    fn save_players(&self, players: &[(String, SavedPlayer)]) {
+        let database = self.connection.get().unwrap();
+        database.execute("BEGIN;", []).unwrap();
+        for (user_name, player) in players.iter() {
+            if let Ok(player_bin) = encode_player(player) {
+                database
+                    .execute(
+                        "UPDATE players SET data = ?2 WHERE name = ?1",
+                        params![&user_name, &player_bin],
+                    )
+                    .unwrap();
+            }
+        }
+        database.execute("COMMIT;", []).unwrap();
+    }
+
+    fn load_name_list(&self, list: NameList) -> HashSet<String> {
+        let database = self.connection.get().unwrap();
+        let mut names = HashSet::new();
+        if let Ok(mut stmt) = database.prepare(&format!("SELECT name FROM {};", table(list))) {
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0)).unwrap();
+            names.extend(rows.flatten());
+        }
+        names
+    }
+
+    fn set_in_name_list(&self, list: NameList, name: &str, present: bool) {
+        let sql = if present {
+            format!("REPLACE INTO {} (name) values (?1)", table(list))
+        } else {
+            format!("DELETE FROM {} WHERE name = ?1", table(list))
+        };
+        let database = self.connection.get().unwrap();
+        database.execute(&sql, params![name]).unwrap();
+    }
+
+    fn load_bans(&self) -> HashMap<String, String> {
+        let database = self.connection.get().unwrap();
+        let mut bans = HashMap::new();
+        if let Ok(mut stmt) = database.prepare("SELECT name, reason FROM bans;") {
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })
+                .unwrap();
+            bans.extend(rows.flatten());
+        }
+        bans
+    }
+
+    fn save_ban(&self, name: &str, reason: Option<&str>) {
+        let database = self.connection.get().unwrap();
+        if let Some(reason) = reason {
+            database
+                .execute(
+                    "REPLACE INTO bans (name, reason) values (?1, ?2)",
+                    params![name, reason],
+                )
+                .unwrap();
+        } else {
+            database
+                .execute("DELETE FROM bans WHERE name = ?1", params![name])
+                .unwrap();
+        }
+    }
+
+    // The online backup API copies a consistent snapshot while other connections keep writing.
+    // Runs off the main thread on its own connection, if the world changes part way through
+    // SQLite starts the copy again
+    fn backup(&self, folder: &Path) -> Result<(), String> {
+        let source = Connection::open(&self.database_path)
+            .map_err(|e| format!("Couldn't open {}: {e}", self.database_path.display()))?;
+        let mut destination = Connection::open(folder.join("world.db"))
+            .map_err(|e| format!("Couldn't create the backup: {e}"))?;
+        let backup = backup::Backup::new(&source, &mut destination)
+            .map_err(|e| format!("Couldn't back up the database: {e}"))?;
+        loop {
+            let step = backup
+                .step(BACKUP_PAGES)
+                .map_err(|e| format!("Couldn't back up the database: {e}"))?;
+            match step {
+                backup::StepResult::Done => return Ok(()),
+                // More pages to copy, or the database is busy being written to
+                _ => thread::sleep(BACKUP_PAUSE),
+            }
+        }
+    }
+
+    fn restore(&self, folder: &Path) -> Result<(), String> {
+        let path = folder.join("world.db");
+        if !path.exists() {
+            return Err(format!("{} isn't there", path.display()));
+        }
+        let mut database = self.connection.get().unwrap();
+        database
+            .restore(DatabaseName::Main, path, None::<fn(backup::Progress)>)
+            .map_err(|e| format!("Couldn't restore the database: {e}"))?;
+        // The backup could be from before the last upgrade
+        migrate_database(&database)
+    }
+}
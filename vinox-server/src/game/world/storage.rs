@@ -1,16 +1,30 @@
-use std::io::Cursor;
-
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{create_dir_all, File},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 
 use bevy::prelude::*;
-use rusqlite::*;
+use ron::{
+    de::from_reader,
+    ser::{to_string_pretty, PrettyConfig},
+};
 use serde::{Deserialize, Serialize};
 use vinox_common::{
-    ecs::bundles::Inventory,
-    world::chunks::{positions::ChunkPos, storage::RawChunk},
+    ecs::bundles::{HeldItem, Inventory},
+    world::chunks::{
+        positions::{ChunkPos, RelativeVoxelPos, VoxelPos},
+        storage::{
+            name_to_identifier, BlockData, BlockId, BlockRegistry, ChunkData, ItemTable,
+            PackedBlock, PackedChunk, RawChunk,
+        },
+    },
 };
-use zstd::stream::{copy_decode, copy_encode};
+
+use super::{memory::MemoryStorage, region::RegionStorage, sqlite::SqliteStorage};
 
 #[derive(Component, Default, Serialize, Deserialize, Debug, Clone)]
 pub struct SavedPlayer {
@@ -18,15 +32,179 @@ pub struct SavedPlayer {
     pub position: [i32; 3],
 }
 
+impl SavedPlayer {
+    pub fn new(transform: &Transform, inventory: &Inventory) -> Self {
+        Self {
+            position: (*VoxelPos::from(transform.translation)).into(),
+            inventory: inventory.clone(),
+        }
+    }
+
+    // Saved as if the held stack had been put back so it isn't lost if the server stops while the
+    // inventory is open
+    pub fn holding(
+        transform: &Transform,
+        inventory: &Inventory,
+        held: &HeldItem,
+        item_table: &ItemTable,
+    ) -> Self {
+        let mut inventory = inventory.clone();
+        if let Some(stack) = held.0.clone() {
+            inventory.add_stack(stack, item_table);
+        }
+        Self::new(transform, &inventory)
+    }
+}
+
+// Chunks that were unloaded before they were saved, these are written on the next update
 #[derive(Resource, Deref, DerefMut, Default)]
-pub struct ChunksToSave(pub Vec<(ChunkPos, RawChunk)>);
+pub struct ChunksToSave(pub Vec<(ChunkPos, RawChunk, SpilledFrom)>);
+
+// Chunks that aren't what generation made anymore, only these get saved. Pastes into chunks that
+// aren't loaded yet are in here too so the blocks are saved once they land
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct ModifiedChunks(pub HashSet<ChunkPos>);
+
+// Neighbours whose features have spilled into a chunk, as offsets from it. Spill from these is
+// already in the chunk so it isn't placed again on top of whatever was changed since
+#[derive(Component, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SpilledFrom {
+    #[default]
+    Nothing,
+    Neighbours(HashSet<IVec3>),
+    // Saved before this was kept, whatever spilled into it then is already there
+    Everything,
+}
+
+impl SpilledFrom {
+    pub fn contains(&self, offset: IVec3) -> bool {
+        match self {
+            SpilledFrom::Nothing => false,
+            SpilledFrom::Neighbours(offsets) => offsets.contains(&offset),
+            SpilledFrom::Everything => true,
+        }
+    }
+
+    pub fn insert(&mut self, offset: IVec3) {
+        match self {
+            SpilledFrom::Nothing => *self = SpilledFrom::Neighbours([offset].into()),
+            SpilledFrom::Neighbours(offsets) => {
+                offsets.insert(offset);
+            }
+            SpilledFrom::Everything => {}
+        }
+    }
+}
+
+// What a chunk is saved as. A diff only has the blocks that are different from what the chunk
+// generates as on its own, so it's generated again when it loads
+#[derive(Clone)]
+pub enum SavedChunk {
+    Full(RawChunk, SpilledFrom),
+    Diff(Vec<(u16, BlockData)>, SpilledFrom),
+}
+
+impl SavedChunk {
+    pub fn pack(&self, block_registry: &BlockRegistry) -> PackedSavedChunk {
+        match self {
+            SavedChunk::Full(raw_chunk, spilled) => {
+                PackedSavedChunk::Full(raw_chunk.pack(block_registry), spilled.clone())
+            }
+            SavedChunk::Diff(blocks, spilled) => PackedSavedChunk::Diff(
+                blocks
+                    .iter()
+                    .map(|(idx, block)| (*idx, block.pack(block_registry)))
+                    .collect(),
+                spilled.clone(),
+            ),
+        }
+    }
+
+    pub fn identifiers(&self) -> Vec<String> {
+        match self {
+            SavedChunk::Full(raw_chunk, _) => raw_chunk.identifiers(),
+            SavedChunk::Diff(blocks, _) => blocks
+                .iter()
+                .map(|(_, block)| name_to_identifier(block.namespace.clone(), block.name.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn spilled(&self) -> &SpilledFrom {
+        match self {
+            SavedChunk::Full(_, spilled) | SavedChunk::Diff(_, spilled) => spilled,
+        }
+    }
+}
+
+// Blocks the world hasn't given an id yet get one before they are saved so they aren't lost
+pub fn register_blocks(
+    chunks: &[(ChunkPos, SavedChunk)],
+    block_registry: &mut BlockRegistry,
+    database: &dyn WorldStorage,
+) {
+    let added =
+        block_registry.register_missing(chunks.iter().flat_map(|(_, chunk)| chunk.identifiers()));
+    if !added.is_empty() {
+        database.save_block_ids(&added);
+    }
+}
+
+// Every block that isn't the same as in the baseline
+pub fn diff_blocks(chunk: &ChunkData, baseline: &ChunkData) -> Vec<(u16, BlockData)> {
+    (0..ChunkData::usize())
+        .filter_map(|idx| {
+            let (x, y, z) = ChunkData::delinearize(idx);
+            let pos = RelativeVoxelPos(UVec3::new(x, y, z));
+            let block = chunk.get(pos);
+            (block != baseline.get(pos)).then_some((idx as u16, block))
+        })
+        .collect()
+}
+
+pub fn apply_diff(chunk: &mut ChunkData, blocks: &[(u16, BlockData)]) {
+    for (idx, block) in blocks.iter() {
+        let (x, y, z) = ChunkData::delinearize(*idx as usize);
+        chunk.set(RelativeVoxelPos(UVec3::new(x, y, z)), block.clone());
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum PackedSavedChunk {
+    Full(PackedChunk, SpilledFrom),
+    Diff(Vec<(u16, PackedBlock)>, SpilledFrom),
+}
+
+impl PackedSavedChunk {
+    pub fn unpack(&self, block_registry: &BlockRegistry) -> SavedChunk {
+        match self {
+            PackedSavedChunk::Full(chunk, spilled) => {
+                SavedChunk::Full(chunk.unpack(block_registry), spilled.clone())
+            }
+            PackedSavedChunk::Diff(blocks, spilled) => SavedChunk::Diff(
+                blocks
+                    .iter()
+                    .map(|(idx, block)| (*idx, block.unpack(block_registry)))
+                    .collect(),
+                spilled.clone(),
+            ),
+        }
+    }
+}
 
 #[derive(Resource, Deref, DerefMut, Default)]
 pub struct FirstSaves(pub Vec<(String, SavedPlayer, String)>);
 
+// Players that left or crafted something, everyone online is added when the world autosaves
 #[derive(Resource, Deref, DerefMut, Default)]
 pub struct PlayersToSave(pub Vec<(String, SavedPlayer)>);
 
+// Writes everything out on the next update instead of waiting for the save interval
+#[derive(Resource, Default)]
+pub struct SaveRequest {
+    pub now: bool,
+}
+
 #[derive(Resource, Serialize, Deserialize, Clone)]
 pub struct WorldInfo {
     pub name: String,
@@ -34,142 +212,302 @@ pub struct WorldInfo {
     pub damage: bool,
 }
 
-#[derive(Resource)]
-pub struct WorldDatabase {
-    pub connection: Pool<SqliteConnectionManager>,
-}
-
-pub fn create_database(database: &Connection) {
-    database
-        .execute(
-            " create table if not exists blocks (
-            posx integer not null,
-            posy integer not null,
-            posz integer not null,
-            data blob,
-            PRIMARY KEY (posx, posy, posz)
-        )",
-            [],
-        )
-        .unwrap();
-    database
-        .execute(
-            " create table if not exists players(
-            name varchar(255) not null,
-            password varchar(255) not null,
-            data blob,
-            PRIMARY KEY (name)
-        )",
-            [],
-        )
-        .unwrap();
-}
-
-pub fn save_chunks(chunks: &ChunksToSave, database: &Connection) {
-    database.execute("BEGIN;", []).unwrap();
-    for (chunk_pos, raw_chunk) in chunks.iter() {
-        if let Ok(raw_chunk_bin) = bincode::serialize(raw_chunk) {
-            let mut final_chunk = Cursor::new(raw_chunk_bin);
-            let mut output = Cursor::new(Vec::new());
-            copy_encode(&mut final_chunk, &mut output, 0).unwrap();
-            database
-                .execute(
-                    "REPLACE INTO blocks (posx, posy, posz, data) values (?1, ?2, ?3, ?4)",
-                    params![
-                        &chunk_pos.x,
-                        &chunk_pos.y,
-                        &chunk_pos.z,
-                        &output.get_ref().clone(),
-                    ],
-                )
-                .unwrap();
+// Moderation lists that are only user names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameList {
+    Ops,
+    Whitelist,
+}
+
+// Everything a world keeps between runs. Saves are written straight away, batching them up is
+// left to whoever calls these
+pub trait WorldStorage: Send + Sync {
+    fn load_world_info(&self) -> Option<WorldInfo>;
+    fn save_world_info(&self, world_info: &WorldInfo);
+
+    fn load_block_registry(&self) -> BlockRegistry;
+    fn save_block_ids(&self, block_ids: &[(String, BlockId)]);
+
+    // None if the chunk was never saved or couldn't be read, either way it gets generated
+    fn load_chunk(&self, chunk_pos: ChunkPos, block_registry: &BlockRegistry)
+        -> Option<SavedChunk>;
+    fn save_chunks(&self, chunks: &[(ChunkPos, SavedChunk)], block_registry: &BlockRegistry);
+    fn saved_chunks(&self) -> Vec<ChunkPos>;
+    fn delete_chunks(&self, chunks: &[ChunkPos]);
+
+    // The player and their hashed password
+    fn load_player(&self, name: &str) -> Option<(SavedPlayer, String)>;
+    // First time a player joins, with their hashed password
+    fn save_passwords(&self, players: &[(String, SavedPlayer, String)]);
+    fn save_players(&self, players: &[(String, SavedPlayer)]);
+
+    fn load_name_list(&self, list: NameList) -> HashSet<String>;
+    fn set_in_name_list(&self, list: NameList, name: &str, present: bool);
+    // User names and why they were banned
+    fn load_bans(&self) -> HashMap<String, String>;
+    // No reason lifts the ban
+    fn save_ban(&self, name: &str, reason: Option<&str>);
+
+    // Copies everything but the world info into an empty folder without stopping the world, the
+    // server calls it from a background thread
+    fn backup(&self, folder: &Path) -> Result<(), String>;
+    // Replaces everything but the world info with what backup put in the folder
+    fn restore(&self, folder: &Path) -> Result<(), String>;
+}
+
+// Shared so backups can run in the background
+#[derive(Resource, Deref)]
+pub struct WorldDatabase(pub Arc<dyn WorldStorage>);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageBackend {
+    // <world>.db next to <world>.ron
+    #[default]
+    Sqlite,
+    // A <world> folder with a file for every 32x32x32 chunks
+    Region,
+    // Nothing is written to disk, for tests and throwaway servers
+    Memory,
+}
+
+impl FromStr for StorageBackend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sqlite" => Ok(StorageBackend::Sqlite),
+            "region" => Ok(StorageBackend::Region),
+            "memory" => Ok(StorageBackend::Memory),
+            _ => Err(()),
         }
     }
-    database.execute("COMMIT;", []).unwrap();
 }
 
-pub fn save_passwords(players_to_save: &FirstSaves, database: &Connection) {
-    database.execute("BEGIN;", []).unwrap();
-    for (user_name, player, password) in players_to_save.iter() {
-        if let Ok(player_bin) = bincode::serialize(player) {
-            database
-                .execute(
-                    "REPLACE INTO players (name, data, password) values (?1, ?2, ?3)",
-                    params![&user_name, &player_bin.clone(), &password],
-                )
-                .unwrap();
-        }
+// world_path is the world's name in the worlds folder without an extension. Creates the world if
+// it isn't there and upgrades it if it was saved by an older version
+pub fn open_world_storage(
+    backend: StorageBackend,
+    world_path: &Path,
+) -> Result<Box<dyn WorldStorage>, String> {
+    let storage: Box<dyn WorldStorage> = match backend {
+        StorageBackend::Sqlite => Box::new(SqliteStorage::open(world_path)?),
+        StorageBackend::Region => Box::new(RegionStorage::open(world_path)?),
+        StorageBackend::Memory => Box::<MemoryStorage>::default(),
+    };
+    Ok(storage)
+}
+
+pub fn save_world_info(world_info: WorldInfo, path: PathBuf) {
+    if create_dir_all(path.parent().unwrap()).is_err() {
+        println!("Failed to create {:?} directory!", path.parent());
+        return;
+    }
+    if let Ok(mut output) = File::create(path.clone()) {
+        let pretty = PrettyConfig::new()
+            .depth_limit(2)
+            .separate_tuple_members(true)
+            .enumerate_arrays(true);
+        let s = to_string_pretty(&world_info, pretty).ok().unwrap();
+        write!(output, "{s}").ok();
+    } else {
+        println!("Failed to save world at path {path:?}!");
     }
-    database.execute("COMMIT;", []).unwrap();
 }
 
-pub fn save_players(players_to_save: &PlayersToSave, database: &Connection) {
-    database.execute("BEGIN;", []).unwrap();
-    for (user_name, player) in players_to_save.iter() {
-        if let Ok(player_bin) = bincode::serialize(player) {
-            database
-                .execute(
-                    "UPDATE players SET data = ?2 WHERE name = ?1",
-                    params![&user_name, &player_bin.clone(),],
-                )
-                .unwrap();
-        }
+pub fn load_world_info(path: PathBuf) -> Option<WorldInfo> {
+    if let Ok(f) = File::open(path) {
+        let world_info: Option<WorldInfo> = match from_reader(f) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                println!("Failed to load world_info: {e}");
+                None
+            }
+        };
+        world_info
+    } else {
+        println!("No such directory!");
+        None
     }
-    database.execute("COMMIT;", []).unwrap();
 }
 
-pub fn load_player(name: String, database: &Connection) -> Option<(SavedPlayer, String)> {
-    let stmt = database.prepare("SELECT name, data, password FROM players WHERE name=:name;");
-    if let Ok(mut stmt) = stmt {
-        let name_result: Result<Vec<u8>, _> =
-            stmt.query_row(&[(":name", &name)], |row| Ok(row.get(1).unwrap()));
-        let password_result: Result<String, _> =
-            stmt.query_row(&[(":name", &name)], |row| Ok(row.get(2).unwrap()));
-        let final_player = if let Ok(name_row) = name_result {
-            Some(bincode::deserialize(&name_row).unwrap())
-        } else {
-            None
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use bevy::prelude::IVec3;
+    use vinox_common::world::chunks::{
+        positions::{ChunkPos, RelativeVoxelPos},
+        storage::{BlockData, BlockRegistry, ChunkData},
+    };
+
+    use super::{NameList, SavedChunk, SavedPlayer, SpilledFrom, WorldStorage};
+    use crate::game::world::{memory::MemoryStorage, region::RegionStorage, sqlite::SqliteStorage};
+
+    fn grass() -> BlockData {
+        BlockData::new("vinox".to_string(), "grass".to_string())
+    }
+
+    fn test_registry() -> BlockRegistry {
+        let mut block_registry = BlockRegistry::default();
+        block_registry.register("vinox:grass".to_string());
+        block_registry
+    }
+
+    // Spread over more regions than are kept open so some are closed and opened again
+    fn test_positions() -> Vec<ChunkPos> {
+        (0..20)
+            .map(|i| ChunkPos::new(i * 32 - 320, i, -i))
+            .collect()
+    }
+
+    fn test_world(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("vinox-{name}-{}", std::process::id()));
+        fs::remove_dir_all(&folder).ok();
+        folder.join("world")
+    }
+
+    fn fill(storage: &dyn WorldStorage) {
+        let block_registry = test_registry();
+        let mut chunk = ChunkData::default();
+        chunk.set(RelativeVoxelPos::new(1, 2, 3), grass());
+        let spilled = SpilledFrom::Neighbours([IVec3::new(-1, 0, 2)].into());
+        // Diffs first so the full chunks have to be written somewhere bigger
+        let diffs: Vec<_> = test_positions()
+            .into_iter()
+            .map(|pos| {
+                (
+                    pos,
+                    SavedChunk::Diff(vec![(5, grass())], SpilledFrom::Everything),
+                )
+            })
+            .collect();
+        storage.save_chunks(&diffs, &block_registry);
+        let chunks: Vec<_> = test_positions()
+            .into_iter()
+            .step_by(2)
+            .map(|pos| (pos, SavedChunk::Full(chunk.to_raw(), spilled.clone())))
+            .collect();
+        storage.save_chunks(&chunks, &block_registry);
+        storage.delete_chunks(&[test_positions()[1]]);
+
+        let player = SavedPlayer {
+            position: [1, 2, 3],
+            ..Default::default()
         };
-        let password = if let Ok(password) = password_result {
-            Some(password)
-        } else {
-            None
+        storage.save_passwords(&[
+            ("alice".to_string(), player.clone(), "hash".to_string()),
+            ("bob".to_string(), player, "other hash".to_string()),
+        ]);
+        let moved = SavedPlayer {
+            position: [4, 5, 6],
+            ..Default::default()
         };
+        storage.save_players(&[("alice".to_string(), moved)]);
+
+        storage.set_in_name_list(NameList::Ops, "alice", true);
+        storage.set_in_name_list(NameList::Whitelist, "alice", true);
+        storage.set_in_name_list(NameList::Whitelist, "bob", true);
+        storage.set_in_name_list(NameList::Whitelist, "bob", false);
+        storage.save_ban("carol", Some("griefing"));
+        storage.save_ban("dave", Some("spam"));
+        storage.save_ban("dave", None);
+    }
 
-        if let Some(final_player) = final_player {
-            if let Some(password) = password {
-                return Some((final_player, password));
-            } else {
-                return None;
+    fn check(storage: &dyn WorldStorage) {
+        let block_registry = test_registry();
+        for (i, pos) in test_positions().into_iter().enumerate() {
+            match storage.load_chunk(pos, &block_registry) {
+                Some(SavedChunk::Full(raw_chunk, spilled)) if i % 2 == 0 => {
+                    assert!(spilled.contains(IVec3::new(-1, 0, 2)));
+                    assert!(!spilled.contains(IVec3::new(1, 0, 0)));
+                    let chunk = ChunkData::from_raw(raw_chunk);
+                    assert_eq!(
+                        chunk.get_identifier(RelativeVoxelPos::new(1, 2, 3)),
+                        "vinox:grass"
+                    );
+                    assert_eq!(
+                        chunk.get_identifier(RelativeVoxelPos::new(0, 0, 0)),
+                        "vinox:air"
+                    );
+                }
+                Some(SavedChunk::Diff(blocks, spilled)) if i % 2 == 1 && i != 1 => {
+                    assert_eq!(blocks, vec![(5, grass())]);
+                    assert_eq!(spilled, SpilledFrom::Everything);
+                }
+                None if i == 1 => {}
+                _ => panic!("Chunk {pos:?} didn't load as it was saved"),
             }
-        } else {
-            return None;
         }
-    }
+        let mut saved_chunks = storage.saved_chunks();
+        saved_chunks.sort_by_key(|pos| pos.x);
+        let mut expected = test_positions();
+        expected.remove(1);
+        assert_eq!(saved_chunks, expected);
 
-    None
-}
+        let (alice, password) = storage.load_player("alice").unwrap();
+        assert_eq!(alice.position, [4, 5, 6]);
+        assert_eq!(password, "hash");
+        let (bob, password) = storage.load_player("bob").unwrap();
+        assert_eq!(bob.position, [1, 2, 3]);
+        assert_eq!(password, "other hash");
+        assert!(storage.load_player("carol").is_none());
 
-pub fn load_chunk(chunk_pos: ChunkPos, database: &Connection) -> Option<RawChunk> {
-    let stmt = database.prepare(
-        "SELECT posx, posy, posz, data FROM blocks WHERE posx=:posx AND posy=:posy AND posz=:posz;",
-    );
-    if let Ok(mut stmt) = stmt {
-        let chunk_result: Result<Vec<u8>, _> = stmt.query_row(
-            &[
-                (":posx", &chunk_pos.x),
-                (":posy", &chunk_pos.y),
-                (":posz", &chunk_pos.z),
-            ],
-            |row| Ok(row.get(3).unwrap()),
+        assert_eq!(
+            storage.load_name_list(NameList::Ops),
+            ["alice".to_string()].into()
+        );
+        assert_eq!(
+            storage.load_name_list(NameList::Whitelist),
+            ["alice".to_string()].into()
+        );
+        assert_eq!(
+            storage.load_bans(),
+            [("carol".to_string(), "griefing".to_string())].into()
         );
-        if let Ok(chunk_row) = chunk_result {
-            let mut temp_output = Cursor::new(Vec::new());
-            copy_decode(&chunk_row[..], &mut temp_output).unwrap();
-            let final_chunk = bincode::deserialize(temp_output.get_ref()).unwrap();
-            return Some(final_chunk);
-        }
     }
 
-    None
+    // A world that lost a block's id still saves it back the same
+    #[test]
+    fn unknown_ids_survive() {
+        let mut chunk = ChunkData::default();
+        chunk.set(RelativeVoxelPos::new(1, 2, 3), grass());
+        let packed = SavedChunk::Full(chunk.to_raw(), SpilledFrom::Nothing).pack(&test_registry());
+        let SavedChunk::Full(raw_chunk, _) = packed.unpack(&BlockRegistry::default()) else {
+            panic!("A full chunk should unpack as a full chunk");
+        };
+        let SavedChunk::Full(raw_chunk, _) = SavedChunk::Full(raw_chunk, SpilledFrom::Nothing)
+            .pack(&BlockRegistry::default())
+            .unpack(&test_registry())
+        else {
+            panic!("A full chunk should unpack as a full chunk");
+        };
+        assert_eq!(
+            ChunkData::from_raw(raw_chunk).get_identifier(RelativeVoxelPos::new(1, 2, 3)),
+            "vinox:grass"
+        );
+    }
+
+    #[test]
+    fn memory_round_trip() {
+        let storage = MemoryStorage::default();
+        fill(&storage);
+        check(&storage);
+    }
+
+    #[test]
+    fn region_round_trip() {
+        let world_path = test_world("region");
+        fill(&RegionStorage::open(&world_path).unwrap());
+        // Opened again so everything is read back from disk
+        check(&RegionStorage::open(&world_path).unwrap());
+        fs::remove_dir_all(world_path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn sqlite_round_trip() {
+        let world_path = test_world("sqlite");
+        fill(&SqliteStorage::open(&world_path).unwrap());
+        check(&SqliteStorage::open(&world_path).unwrap());
+        fs::remove_dir_all(world_path.parent().unwrap()).ok();
+    }
 }
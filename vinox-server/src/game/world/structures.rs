@@ -0,0 +1,218 @@
+use std::{collections::HashMap, fs};
+
+use bevy::prelude::*;
+use bevy_quinnet::server::Server;
+use directories::ProjectDirs;
+use vinox_common::{
+    storage::structures::descriptor::StructureBlocks,
+    world::chunks::{
+        ecs::ChunkManager,
+        positions::VoxelPos,
+        storage::{identifier_to_name, name_to_identifier, BlockData, StructureTable},
+    },
+};
+
+use crate::game::commands::dispatcher::{
+    argument, literal, ArgumentType, CommandRegistry, PermissionLevel, RunCommand,
+};
+
+use super::storage::ModifiedChunks;
+
+// Anything bigger than this should be split up, saving reads every block in one frame
+const MAX_STRUCTURE_SIZE: i32 = 64;
+
+// Pasted blocks waiting for their chunk to have data. Unlike spill from generation these replace
+// whatever is there
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ToBePasted(pub HashMap<IVec3, Vec<(UVec3, BlockData)>>);
+
+// Structures without a namespace go in vinox
+fn parse_identifier(identifier: &str) -> (String, String) {
+    identifier_to_name(identifier.to_string())
+        .unwrap_or_else(|| ("vinox".to_string(), identifier.to_string()))
+}
+
+// Reads a region out of loaded chunks with the minimum corner as the anchor
+pub fn save_structure(
+    chunk_manager: &ChunkManager,
+    namespace: String,
+    name: String,
+    start: IVec3,
+    end: IVec3,
+) -> Result<StructureBlocks, String> {
+    let (min, max) = (start.min(end), start.max(end));
+    let size = max - min + IVec3::ONE;
+    if size.max_element() > MAX_STRUCTURE_SIZE {
+        return Err(format!(
+            "Structures can be at most {MAX_STRUCTURE_SIZE} blocks on each side"
+        ));
+    }
+    let mut palette: Vec<BlockData> = Vec::new();
+    let mut indices: HashMap<BlockData, u16> = HashMap::new();
+    let mut blocks = Vec::new();
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                let Some(block) = chunk_manager.get_block(VoxelPos::new(x, y, z)) else {
+                    return Err("The whole region has to be loaded".to_string());
+                };
+                let index = *indices.entry(block.clone()).or_insert_with(|| {
+                    palette.push(block);
+                    palette.len() as u16 - 1
+                });
+                let relative = IVec3::new(x, y, z) - min;
+                blocks.push((
+                    (relative.x as u32, relative.y as u32, relative.z as u32),
+                    index,
+                ));
+            }
+        }
+    }
+    Ok(StructureBlocks {
+        namespace,
+        name,
+        size: (size.x as u32, size.y as u32, size.z as u32),
+        anchor: (0, 0, 0),
+        palette,
+        blocks,
+        rotate: None,
+        mirror: None,
+    })
+}
+
+// Blocks go through ToBePasted so loaded chunks get resent and unloaded ones are filled in later
+pub fn paste_structure(
+    to_be_pasted: &mut ToBePasted,
+    modified_chunks: &mut ModifiedChunks,
+    structure: &StructureBlocks,
+    origin: IVec3,
+    rotation: u8,
+    mirror: bool,
+) {
+    for (pos, block) in structure.transformed_blocks(rotation, mirror) {
+        let (relative_pos, chunk_pos) = VoxelPos(origin + pos).to_offsets();
+        modified_chunks.insert(chunk_pos);
+        to_be_pasted
+            .entry(*chunk_pos)
+            .or_default()
+            .push((*relative_pos, block));
+    }
+}
+
+// Both parts end up in the path so anything like ../ has to be refused
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+// Saved to structures/<namespace>/<name>.ron
+fn write_structure(structure: &StructureBlocks) -> Result<(), String> {
+    if !valid_name(&structure.namespace) || !valid_name(&structure.name) {
+        return Err("Names can only use a-z, 0-9 and _".to_string());
+    }
+    let Some(proj_dirs) = ProjectDirs::from("com", "vinox", "vinox") else {
+        return Err("Couldn't find the data directory".to_string());
+    };
+    let folder = proj_dirs
+        .data_dir()
+        .join("assets/structures")
+        .join(&structure.namespace);
+    let ron_string = ron::ser::to_string_pretty(structure, ron::ser::PrettyConfig::default())
+        .map_err(|e| e.to_string())?;
+    fs::create_dir_all(&folder).map_err(|e| e.to_string())?;
+    fs::write(folder.join(format!("{}.ron", structure.name)), ron_string).map_err(|e| e.to_string())
+}
+
+pub fn register_structure_commands(mut registry: ResMut<CommandRegistry>) {
+    registry.register(
+        literal("structure")
+            .then(
+                literal("save").then(
+                    argument("name", ArgumentType::Word).then(
+                        argument("start", ArgumentType::Position)
+                            .then(argument("end", ArgumentType::Position).executes()),
+                    ),
+                ),
+            )
+            .then(
+                literal("paste").then(
+                    argument("name", ArgumentType::Word).then(
+                        argument("pos", ArgumentType::Position).executes().then(
+                            argument("rotation", ArgumentType::Integer)
+                                .executes()
+                                .then(argument("mirror", ArgumentType::Boolean).executes()),
+                        ),
+                    ),
+                ),
+            ),
+        PermissionLevel::Operator,
+        "Save a region of the world as a structure or paste one",
+    );
+}
+
+pub fn structure_commands(
+    mut run_commands: EventReader<RunCommand>,
+    mut server: ResMut<Server>,
+    chunk_manager: ChunkManager,
+    mut structure_table: ResMut<StructureTable>,
+    mut to_be_pasted: ResMut<ToBePasted>,
+    mut modified_chunks: ResMut<ModifiedChunks>,
+) {
+    for command in run_commands
+        .iter()
+        .filter(|command| command.name == "structure")
+    {
+        let Some(identifier) = command.text("name") else {
+            continue;
+        };
+        let (namespace, name) = parse_identifier(identifier);
+        let message = match command.literals().as_slice() {
+            ["save"] => {
+                let (Some(start), Some(end)) = (command.position("start"), command.position("end"))
+                else {
+                    continue;
+                };
+                match save_structure(&chunk_manager, namespace, name, start, end).and_then(
+                    |structure| {
+                        write_structure(&structure)?;
+                        Ok(structure)
+                    },
+                ) {
+                    Ok(structure) => {
+                        let identifier =
+                            name_to_identifier(structure.namespace.clone(), structure.name.clone());
+                        let message =
+                            format!("Saved {identifier} with {} blocks", structure.blocks.len());
+                        println!("{} saved structure {identifier}", command.source.user_name);
+                        structure_table.insert(identifier, structure);
+                        message
+                    }
+                    Err(e) => format!("Failed to save structure: {e}"),
+                }
+            }
+            ["paste"] => {
+                let identifier = name_to_identifier(namespace, name);
+                let Some(origin) = command.position("pos") else {
+                    continue;
+                };
+                if let Some(structure) = structure_table.get(&identifier) {
+                    paste_structure(
+                        &mut to_be_pasted,
+                        &mut modified_chunks,
+                        structure,
+                        origin,
+                        command.integer("rotation").unwrap_or(0).rem_euclid(4) as u8,
+                        command.boolean("mirror").unwrap_or(false),
+                    );
+                    format!("Pasted {identifier} at {origin}")
+                } else {
+                    format!("No structure called {identifier}")
+                }
+            }
+            _ => continue,
+        };
+        command.reply(&mut server, message);
+    }
+}
@@ -1,26 +1,17 @@
-mod game;
+pub mod game;
 use bevy::{
     app::ScheduleRunnerSettings, diagnostic::DiagnosticsPlugin, log::LogPlugin, prelude::*,
 };
 use bevy_quinnet::server::QuinnetServerPlugin;
 use directories::*;
 use game::{
-    networking::components::{ChunkLimit, LocalGame, SaveGame},
+    config::ServerConfig,
+    networking::components::{ChunkBandwidth, ChunkLimit, LocalGame, SaveGame},
     plugin::GamePlugin,
-    world::storage::{create_database, WorldDatabase, WorldInfo},
+    world::storage::{open_world_storage, StorageBackend, WorldDatabase, WorldInfo},
 };
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
 use rand::Rng;
-use ron::de::from_reader;
-use ron::ser::{to_string_pretty, PrettyConfig};
-use std::io::Write;
-use std::{
-    env,
-    fs::{create_dir_all, File},
-    path::PathBuf,
-    time::Duration,
-};
+use std::{env, fs::create_dir_all, path::PathBuf, time::Duration};
 use vinox_common::networking::protocol::NetworkIP;
 
 // Server should always keep spawn chunks loaded and any chunks near players
@@ -56,9 +47,14 @@ pub fn create_server() {
     let mut final_world_name = "worlds/".to_string();
     final_world_name.push_str(&world_name);
     asset_path.push(final_world_name);
-    let final_world_info = if let Some(world_info) =
-        load_world_info(format!("{}.ron", asset_path.clone().display()).into())
-    {
+    let database = match open_world_storage(StorageBackend::Sqlite, &asset_path) {
+        Ok(database) => database,
+        Err(e) => {
+            println!("{e}");
+            return;
+        }
+    };
+    let final_world_info = if let Some(world_info) = database.load_world_info() {
         world_info
     } else {
         let world = WorldInfo {
@@ -66,36 +62,25 @@ pub fn create_server() {
             seed: rand::thread_rng().gen_range(0..=u32::MAX),
             damage: false,
         };
-        save_world_info(
-            world.clone(),
-            format!("{}.ron", asset_path.clone().display()).into(),
-        );
+        database.save_world_info(&world);
         world
     };
-    let manager = SqliteConnectionManager::file(format!("{}.db", asset_path.display()));
-    let pool = Pool::builder()
-        .max_size(30)
-        .test_on_check_out(false)
-        .build(manager)
-        .unwrap();
-    pool.get()
-        .unwrap()
-        .execute_batch(
-            "PRAGMA journal_mode=WAL;
-             PRAGMA synchronous=NORMAL;",
-        )
-        .ok();
-    create_database(&pool.get().unwrap());
     App::new()
         .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
             1.0 / 60.0,
         )))
         .insert_resource(final_world_info)
-        .insert_resource(WorldDatabase { connection: pool })
+        .insert_resource(WorldDatabase(database.into()))
         .insert_resource(NetworkIP(ip))
         .insert_resource(ChunkLimit(64))
+        .insert_resource(ServerConfig {
+            world_name,
+            chunk_limit: 64,
+            ..default()
+        })
+        .insert_resource(ChunkBandwidth(1024 * 1024))
         .insert_resource(LocalGame(true))
-        .insert_resource(SaveGame(false))
+        .insert_resource(SaveGame(true))
         .add_plugins(MinimalPlugins)
         .add_plugin(DiagnosticsPlugin)
         .add_plugin(LogPlugin::default())
@@ -103,36 +88,3 @@ pub fn create_server() {
         .add_plugin(GamePlugin)
         .run();
 }
-
-pub fn save_world_info(world_info: WorldInfo, path: PathBuf) {
-    if create_dir_all(path.parent().unwrap()).is_err() {
-        println!("Failed to create {:?} directory!", path.parent());
-        return;
-    }
-    if let Ok(mut output) = File::create(path.clone()) {
-        let pretty = PrettyConfig::new()
-            .depth_limit(2)
-            .separate_tuple_members(true)
-            .enumerate_arrays(true);
-        let s = to_string_pretty(&world_info, pretty).ok().unwrap();
-        write!(output, "{s}").ok();
-    } else {
-        println!("Failed to save world at path {path:?}!");
-    }
-}
-
-fn load_world_info(path: PathBuf) -> Option<WorldInfo> {
-    if let Ok(f) = File::open(path) {
-        let world_info: Option<WorldInfo> = match from_reader(f) {
-            Ok(x) => Some(x),
-            Err(e) => {
-                println!("Failed to load world_info: {e}");
-                None
-            }
-        };
-        world_info
-    } else {
-        println!("No such directory!");
-        None
-    }
-}
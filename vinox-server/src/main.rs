@@ -1,28 +1,47 @@
 mod game;
 use bevy::{
-    app::ScheduleRunnerSettings, diagnostic::DiagnosticsPlugin, log::LogPlugin, prelude::*,
+    app::{AppExit, ScheduleRunnerSettings},
+    diagnostic::DiagnosticsPlugin,
+    log::LogPlugin,
+    prelude::*,
 };
 use bevy_quinnet::server::QuinnetServerPlugin;
 
 use directories::*;
 use game::{
-    networking::components::{ChunkLimit, LocalGame, SaveGame},
+    config::{ServerConfig, DEFAULT_CONFIG_PATH, USAGE},
+    networking::components::{ChunkBandwidth, ChunkLimit, LocalGame, SaveGame},
     plugin::GamePlugin,
-    world::storage::{create_database, WorldDatabase, WorldInfo},
+    world::{
+        backup::restore_backup,
+        storage::{open_world_storage, WorldDatabase, WorldInfo},
+    },
 };
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
 use rand::Rng;
-use ron::de::from_reader;
-use ron::ser::{to_string_pretty, PrettyConfig};
-use std::io::Write;
 use std::{
     env,
-    fs::{create_dir_all, File},
-    path::PathBuf,
+    fs::create_dir_all,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
-use vinox_common::networking::protocol::NetworkIP;
+
+// Set when the process is asked to stop, ie Ctrl+C or SIGTERM
+#[derive(Resource, Clone, Default, Deref)]
+struct StopSignal(Arc<AtomicBool>);
+
+// Stops the same way `stop` does so the last save still happens
+fn stop_on_signal(signal: Res<StopSignal>, mut exit: EventWriter<AppExit>, mut sent: Local<bool>) {
+    if signal.load(Ordering::Relaxed) && !*sent {
+        *sent = true;
+        println!("Stopping the server");
+        exit.send(AppExit);
+    }
+}
 
 // Server should always keep spawn chunks loaded and any chunks near players
 fn main() {
@@ -38,63 +57,72 @@ fn main() {
         path
     };
 
-    let args: Vec<String> = env::args().collect();
-
-    let mut ip = "127.0.0.1".to_string();
-    let mut world_name = "world".to_string();
-    // TODO: Better arg parser eventually something like clap
-    match args.len() {
-        1 => {}
-        2 => {
-            ip = args[1].to_string();
-        }
-        3 => {
-            ip = args[1].to_string();
-            world_name = args[2].to_string();
-        }
-        _ => {}
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{USAGE}");
+        return;
     }
+    let config = load_config(&args);
+
     let mut final_world_name = "worlds/".to_string();
-    final_world_name.push_str(&world_name);
+    final_world_name.push_str(&config.world_name);
     asset_path.push(final_world_name);
-    let final_world_info = if let Some(world_info) =
-        load_world_info(format!("{}.ron", asset_path.clone().display()).into())
+    let database = match open_world_storage(config.storage, &asset_path) {
+        Ok(database) => database,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    if let Some(backup) = args
+        .iter()
+        .position(|arg| arg == "--restore")
+        .and_then(|i| args.get(i + 1))
     {
+        if let Err(e) = restore_backup(database.as_ref(), &config.world_name, backup) {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+        println!("Restored {} from {backup}", config.world_name);
+    }
+    let final_world_info = if let Some(world_info) = database.load_world_info() {
+        if config.seed.map_or(false, |seed| seed != world_info.seed) {
+            println!(
+                "World {} already exists, keeping its seed {}",
+                world_info.name, world_info.seed
+            );
+        }
         world_info
     } else {
         let world = WorldInfo {
-            name: world_name.clone(),
-            seed: rand::thread_rng().gen_range(0..=u32::MAX),
+            name: config.world_name.clone(),
+            seed: config
+                .seed
+                .unwrap_or_else(|| rand::thread_rng().gen_range(0..=u32::MAX)),
             damage: false,
         };
-        save_world_info(
-            world.clone(),
-            format!("{}.ron", asset_path.clone().display()).into(),
-        );
+        database.save_world_info(&world);
         world
     };
-    let manager = SqliteConnectionManager::file(format!("{}.db", asset_path.display()));
-    let pool = Pool::builder()
-        .max_size(30)
-        .test_on_check_out(false)
-        .build(manager)
-        .unwrap();
-    pool.get()
-        .unwrap()
-        .execute_batch(
-            "PRAGMA journal_mode=WAL;
-             PRAGMA synchronous=NORMAL;",
-        )
-        .ok();
-    create_database(&pool.get().unwrap());
+    // A second signal means the save is taking too long, leave without it
+    let stop_signal = StopSignal::default();
+    let signal = stop_signal.clone();
+    if let Err(e) = ctrlc::set_handler(move || {
+        if signal.swap(true, Ordering::Relaxed) {
+            process::exit(130);
+        }
+    }) {
+        println!("Couldn't listen for Ctrl+C, only stop saves on the way out: {e}");
+    }
     App::new()
         .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
-            1.0 / 60.0,
+            1.0 / config.tick_rate,
         )))
         .insert_resource(final_world_info)
-        .insert_resource(WorldDatabase { connection: pool })
-        .insert_resource(ChunkLimit(16))
-        .insert_resource(NetworkIP(ip))
+        .insert_resource(WorldDatabase(database.into()))
+        .insert_resource(ChunkLimit(config.chunk_limit))
+        .insert_resource(ChunkBandwidth(64 * 1024))
+        .insert_resource(config)
         .insert_resource(LocalGame(false))
         .insert_resource(SaveGame(true))
         // .add_plugins(MinimalPlugins.build().disable::<TransformPlugin>())
@@ -104,38 +132,35 @@ fn main() {
         // .add_plugin(FloatingOriginPlugin::default())
         .add_plugin(QuinnetServerPlugin::default())
         .add_plugin(GamePlugin)
+        .insert_resource(stop_signal)
+        .add_system(stop_on_signal)
         .run();
 }
 
-pub fn save_world_info(world_info: WorldInfo, path: PathBuf) {
-    if create_dir_all(path.parent().unwrap()).is_err() {
-        println!("Failed to create {:?} directory!", path.parent());
-        return;
-    }
-    if let Ok(mut output) = File::create(path.clone()) {
-        let pretty = PrettyConfig::new()
-            .depth_limit(2)
-            .separate_tuple_members(true)
-            .enumerate_arrays(true);
-        let s = to_string_pretty(&world_info, pretty).ok().unwrap();
-        write!(output, "{s}").ok();
-    } else {
-        println!("Failed to save world at path {path:?}!");
+// Defaults, then the config file, then flags. Anything wrong stops the server before it starts
+fn load_config(args: &[String]) -> ServerConfig {
+    let config_path = args
+        .iter()
+        .position(|arg| arg == "--config")
+        .and_then(|i| args.get(i + 1));
+    let path = config_path.map_or(DEFAULT_CONFIG_PATH, |path| path.as_str());
+    let mut config = match ServerConfig::load(Path::new(path), config_path.is_some()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    if let Err(e) = config.apply_args(args) {
+        eprintln!("{e}\n\n{USAGE}");
+        process::exit(1);
     }
-}
-
-fn load_world_info(path: PathBuf) -> Option<WorldInfo> {
-    if let Ok(f) = File::open(path) {
-        let world_info: Option<WorldInfo> = match from_reader(f) {
-            Ok(x) => Some(x),
-            Err(e) => {
-                println!("Failed to load world_info: {e}");
-                None
-            }
-        };
-        world_info
-    } else {
-        println!("No such directory!");
-        None
+    if let Err(errors) = config.validate() {
+        eprintln!("Invalid server config:");
+        for e in errors {
+            eprintln!("  {e}");
+        }
+        process::exit(1);
     }
+    config
 }